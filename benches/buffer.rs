@@ -0,0 +1,182 @@
+//! Measures reading and writing the integers and byte runs that make up most of a message,
+//! comparing the buffer's slice operations with a reference reading and writing one byte at
+//! a time, bounds-checked for each byte, the way the buffer used to.
+//!
+//! Run with `cargo bench --bench buffer`.
+
+use std::hint::black_box;
+use std::time::Instant;
+
+use vodo::buffer::{Buffer, BufferError, MAX_SIZE};
+
+const ITERATIONS: usize = 200;
+/// Number of times each measurement is repeated, keeping the fastest to leave out noise
+const ROUNDS: usize = 10;
+/// Length of the byte runs written, about that of an IPv6 address or a short name
+const RUN_LEN: usize = 16;
+
+/// A buffer reading and writing one byte at a time
+struct Bytewise {
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl Bytewise {
+    fn new() -> Bytewise {
+        Bytewise {
+            buf: vec![0; MAX_SIZE],
+            pos: 0,
+        }
+    }
+
+    fn read(&mut self) -> Result<u8, BufferError> {
+        let res = *self.buf.get(self.pos).ok_or(BufferError::EndOfBuffer)?;
+        self.pos += 1;
+        Ok(res)
+    }
+
+    fn read_u16(&mut self) -> Result<u16, BufferError> {
+        Ok((u16::from(self.read()?) << 8) | u16::from(self.read()?))
+    }
+
+    fn read_u32(&mut self) -> Result<u32, BufferError> {
+        Ok((u32::from(self.read()?) << 24)
+            | (u32::from(self.read()?) << 16)
+            | (u32::from(self.read()?) << 8)
+            | u32::from(self.read()?))
+    }
+
+    fn write(&mut self, val: u8) -> Result<(), BufferError> {
+        *self.buf.get_mut(self.pos).ok_or(BufferError::EndOfBuffer)? = val;
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn write_u16(&mut self, val: u16) -> Result<(), BufferError> {
+        self.write((val >> 8) as u8)?;
+        self.write((val & 0xFF) as u8)
+    }
+
+    fn write_u32(&mut self, val: u32) -> Result<(), BufferError> {
+        self.write(((val >> 24) & 0xFF) as u8)?;
+        self.write(((val >> 16) & 0xFF) as u8)?;
+        self.write(((val >> 8) & 0xFF) as u8)?;
+        self.write((val & 0xFF) as u8)
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), BufferError> {
+        for b in bytes {
+            self.write(*b)?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs an operation over a whole buffer, returning the nanoseconds it takes for each value
+/// in the fastest round
+fn per_value(values: usize, mut operation: impl FnMut()) -> f64 {
+    let fastest = (0..ROUNDS)
+        .map(|_| {
+            let start = Instant::now();
+            for _ in 0..ITERATIONS {
+                operation();
+            }
+            start.elapsed()
+        })
+        .min()
+        .unwrap();
+    fastest.as_nanos() as f64 / (ITERATIONS * values) as f64
+}
+
+fn main() {
+    let run = [0xab; RUN_LEN];
+    let u16s = MAX_SIZE / 2;
+    let u32s = MAX_SIZE / 4;
+    let runs = MAX_SIZE / RUN_LEN;
+
+    println!("{:<12} {:>10} {:>10}", "OPERATION", "SLICES", "BYTEWISE");
+    let report = |name: &str, slices: f64, bytewise: f64| {
+        println!("{:<12} {:>8.2}ns {:>8.2}ns", name, slices, bytewise);
+    };
+
+    let mut buffer = Buffer::with_size(MAX_SIZE);
+    let mut reference = Bytewise::new();
+
+    report(
+        "write_u16",
+        per_value(u16s, || {
+            buffer.pos = 0;
+            for i in 0..u16s {
+                black_box(&mut buffer).write_u16(i as u16).unwrap();
+            }
+        }),
+        per_value(u16s, || {
+            reference.pos = 0;
+            for i in 0..u16s {
+                black_box(&mut reference).write_u16(i as u16).unwrap();
+            }
+        }),
+    );
+    report(
+        "read_u16",
+        per_value(u16s, || {
+            buffer.pos = 0;
+            for _ in 0..u16s {
+                black_box(black_box(&mut buffer).read_u16().unwrap());
+            }
+        }),
+        per_value(u16s, || {
+            reference.pos = 0;
+            for _ in 0..u16s {
+                black_box(black_box(&mut reference).read_u16().unwrap());
+            }
+        }),
+    );
+    report(
+        "write_u32",
+        per_value(u32s, || {
+            buffer.pos = 0;
+            for i in 0..u32s {
+                black_box(&mut buffer).write_u32(i as u32).unwrap();
+            }
+        }),
+        per_value(u32s, || {
+            reference.pos = 0;
+            for i in 0..u32s {
+                black_box(&mut reference).write_u32(i as u32).unwrap();
+            }
+        }),
+    );
+    report(
+        "read_u32",
+        per_value(u32s, || {
+            buffer.pos = 0;
+            for _ in 0..u32s {
+                black_box(black_box(&mut buffer).read_u32().unwrap());
+            }
+        }),
+        per_value(u32s, || {
+            reference.pos = 0;
+            for _ in 0..u32s {
+                black_box(black_box(&mut reference).read_u32().unwrap());
+            }
+        }),
+    );
+    report(
+        "write_bytes",
+        per_value(runs, || {
+            buffer.pos = 0;
+            for _ in 0..runs {
+                black_box(&mut buffer).write_bytes(black_box(&run)).unwrap();
+            }
+        }),
+        per_value(runs, || {
+            reference.pos = 0;
+            for _ in 0..runs {
+                black_box(&mut reference)
+                    .write_bytes(black_box(&run))
+                    .unwrap();
+            }
+        }),
+    );
+}
@@ -0,0 +1,84 @@
+//! Floods the cache with names in the shape an attacker would use (random labels under a
+//! single domain), and reports the time each insertion and lookup takes as the cache grows.
+//! The same names are stored in a map whose hashes all collide, for comparison: that's what
+//! the cache would degrade to if attackers could choose names colliding under its hasher.
+//!
+//! Run with `cargo bench --bench cache`.
+
+use std::collections::HashMap;
+use std::hash::{BuildHasherDefault, Hasher};
+use std::hint::black_box;
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
+
+use vodo::cache::Cache;
+use vodo::packet::DnsPacket;
+use vodo::question::{QueryClass, QueryType};
+use vodo::record::DnsRecord;
+
+const SIZES: [usize; 3] = [1_000, 4_000, 16_000];
+
+/// A hasher giving every key the same hash
+#[derive(Default)]
+struct Colliding;
+
+impl Hasher for Colliding {
+    fn finish(&self) -> u64 {
+        0
+    }
+
+    fn write(&mut self, _: &[u8]) {}
+}
+
+fn names(count: usize) -> Vec<String> {
+    (0..count)
+        .map(|_| format!("{:016x}.attacker.example", rand::random::<u64>()))
+        .collect()
+}
+
+/// Returns the time an operation takes for each of the names, on average
+fn per_name(names: &[String], mut operation: impl FnMut(&str)) -> Duration {
+    let start = Instant::now();
+    for name in names {
+        operation(name);
+    }
+    start.elapsed() / names.len() as u32
+}
+
+fn main() {
+    println!(
+        "{:>8} {:>12} {:>12} {:>18} {:>18}",
+        "NAMES", "INSERT", "LOOKUP", "COLLIDING INSERT", "COLLIDING LOOKUP"
+    );
+
+    for size in SIZES {
+        let names = names(size);
+
+        let cache = Cache::new();
+        let insert = per_name(&names, |name| {
+            let mut packet = DnsPacket::new();
+            packet.answers.push(DnsRecord::A {
+                domain: name.to_string(),
+                addr: Ipv4Addr::new(192, 0, 2, 1),
+                ttl: 300,
+            });
+            cache.insert_response(&packet);
+        });
+        let lookup = per_name(&names, |name| {
+            black_box(cache.lookup(name, QueryType::A, QueryClass::IN));
+        });
+
+        let mut colliding: HashMap<String, u32, BuildHasherDefault<Colliding>> = HashMap::default();
+        let colliding_insert = per_name(&names, |name| {
+            colliding.insert(name.to_string(), 300);
+        });
+        let colliding_lookup = per_name(&names, |name| {
+            black_box(colliding.get(name));
+        });
+
+        println!(
+            "{:>8} {:>12.1?} {:>12.1?} {:>18.1?} {:>18.1?}",
+            size, insert, lookup, colliding_insert, colliding_lookup
+        );
+    }
+}
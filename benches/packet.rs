@@ -0,0 +1,97 @@
+//! Measures the parsing and serialization of a typical response (one question, a CNAME and
+//! an address in the answer, and an OPT record), counting the allocations they make.
+//!
+//! Run with `cargo bench --bench packet`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::net::Ipv4Addr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use vodo::opt::OptRecord;
+use vodo::packet::DnsPacket;
+use vodo::question::{DnsQuestion, QueryType};
+use vodo::record::DnsRecord;
+
+const ITERATIONS: usize = 200_000;
+
+/// The system allocator, counting the allocations made through it
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+fn response() -> DnsPacket {
+    let mut packet = DnsPacket::new();
+    packet.header.id = 4242;
+    packet.header.response = true;
+    packet.questions.push(DnsQuestion::new(
+        "www.example.com".to_string(),
+        QueryType::A,
+    ));
+    packet.answers.push(DnsRecord::CNAME {
+        domain: "www.example.com".to_string(),
+        host: "example.com".to_string(),
+        ttl: 300,
+    });
+    packet.answers.push(DnsRecord::A {
+        domain: "example.com".to_string(),
+        addr: Ipv4Addr::new(192, 0, 2, 1),
+        ttl: 300,
+    });
+    packet.set_opt(OptRecord::new(1232));
+    packet
+}
+
+/// Runs an operation over and over, and prints the time and allocations it takes each time
+fn measure(name: &str, mut operation: impl FnMut()) {
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        operation();
+    }
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+
+    println!(
+        "{:<10} {:>10.1?} {:>12.1}",
+        name,
+        elapsed / ITERATIONS as u32,
+        allocations as f64 / ITERATIONS as f64
+    );
+}
+
+fn main() {
+    let packet = response();
+    let bytes = packet.to_bytes().unwrap();
+
+    println!("{:<10} {:>10} {:>12}", "OPERATION", "TIME", "ALLOCATIONS");
+    measure("parse", || {
+        black_box(DnsPacket::parse(black_box(&bytes)).unwrap());
+    });
+    measure("serialize", || {
+        black_box(black_box(&packet).to_bytes().unwrap());
+    });
+    measure("clone", || {
+        black_box(black_box(&packet).clone());
+    });
+}
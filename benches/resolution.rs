@@ -0,0 +1,60 @@
+//! Measures end-to-end recursive resolution against a simulated hierarchy of servers
+//! (see `vodo::simulation`), under increasing packet loss.
+//!
+//! Run with `cargo bench --bench resolution`.
+
+use std::time::{Duration, Instant};
+
+use vodo::cache::Cache;
+use vodo::handler::recursive_lookup;
+use vodo::infra::InfraCache;
+use vodo::question::QueryType;
+use vodo::simulation::{Conditions, Hierarchy};
+
+/// Names resolved for each scenario, each one needing a query to the authoritative server
+const NAMES: usize = 50;
+const LATENCY: Duration = Duration::from_millis(5);
+const LOSS: [f64; 4] = [0.0, 0.01, 0.05, 0.1];
+
+fn main() {
+    println!(
+        "{:>6} {:>8} {:>10} {:>10} {:>10} {:>10}",
+        "LOSS", "FAILED", "P50", "P95", "MAX", "TOTAL"
+    );
+
+    for loss in LOSS {
+        let hierarchy = Hierarchy::start(Conditions {
+            latency: LATENCY,
+            loss,
+        })
+        .expect("failed to start the simulated hierarchy");
+        let infra = InfraCache::new(1232).with_root(hierarchy.root, hierarchy.port);
+        let cache = Cache::new();
+
+        let start = Instant::now();
+        let mut latencies = Vec::with_capacity(NAMES);
+        let mut failed = 0;
+        for i in 0..NAMES {
+            let name = format!("host{}.example.com", i);
+            let lookup_start = Instant::now();
+            match recursive_lookup(&name, QueryType::A, &cache, &infra) {
+                Ok(response) if !response.answers.is_empty() => {}
+                _ => failed += 1,
+            }
+            latencies.push(lookup_start.elapsed());
+        }
+        let total = start.elapsed();
+
+        latencies.sort();
+        let percentile = |p: usize| latencies[(latencies.len() - 1) * p / 100];
+        println!(
+            "{:>5.0}% {:>8} {:>10.1?} {:>10.1?} {:>10.1?} {:>10.1?}",
+            loss * 100.0,
+            failed,
+            percentile(50),
+            percentile(95),
+            percentile(100),
+            total
+        );
+    }
+}
@@ -0,0 +1,171 @@
+use crate::record::DnsRecord;
+use crate::validate::{validate_name, ValidationError};
+use crate::zone::Authority;
+
+/// Label prefixed to a domain to publish its DNS-01 challenges:
+/// see https://tools.ietf.org/html/rfc8555#section-8.4
+const CHALLENGE_LABEL: &str = "_acme-challenge";
+/// TTL of challenge records, short so that validation servers don't see stale values
+const CHALLENGE_TTL: u32 = 60;
+
+/// `AcmeError` is an enum that represents the reasons a challenge can't be published
+#[derive(thiserror::Error, Debug)]
+pub enum AcmeError {
+    #[error("Invalid domain \"{0}\": {1}")]
+    InvalidDomain(String, ValidationError),
+    #[error("Invalid challenge value \"{0}\"")]
+    InvalidValue(String),
+    #[error("{0} is not in a hosted zone")]
+    NotHosted(String),
+}
+
+/// Returns the name the challenges of a domain are published at. The domain can be given
+/// with or without the `_acme-challenge` label.
+pub fn challenge_name(domain: &str) -> Result<String, AcmeError> {
+    let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+    let name = match domain.starts_with(CHALLENGE_LABEL) {
+        true => domain,
+        false => format!("{}.{}", CHALLENGE_LABEL, domain),
+    };
+    validate_name(&name).map_err(|e| AcmeError::InvalidDomain(name.clone(), e))?;
+
+    Ok(name)
+}
+
+/// Publishes a DNS-01 challenge for a domain as a TXT record in the hosted zone containing
+/// it, bumping the serial of the zone. Returns the name of the record.
+pub fn add_challenge(
+    authority: &mut Authority,
+    domain: &str,
+    value: &str,
+) -> Result<String, AcmeError> {
+    // Challenge values are base64url digests, which never need escaping.
+    if value.is_empty() || value.len() > 255 || !value.chars().all(|c| c.is_ascii_graphic()) {
+        return Err(AcmeError::InvalidValue(value.to_string()));
+    }
+
+    let name = challenge_name(domain)?;
+    let record = DnsRecord::TXT {
+        domain: name.clone(),
+        data: vec![value.to_string()],
+        ttl: CHALLENGE_TTL,
+    };
+    update_zone(authority, &name, |records| {
+        if !records.contains(&record) {
+            records.push(record.clone());
+        }
+    })?;
+
+    Ok(name)
+}
+
+/// Removes the DNS-01 challenges of a domain from the hosted zone containing it: the one with
+/// the given value, or all of them. Returns the number of records removed.
+pub fn remove_challenges(
+    authority: &mut Authority,
+    domain: &str,
+    value: Option<&str>,
+) -> Result<usize, AcmeError> {
+    let name = challenge_name(domain)?;
+
+    let mut removed = 0;
+    update_zone(authority, &name, |records| {
+        let before = records.len();
+        records.retain(|record| match record {
+            DnsRecord::TXT { domain, data, .. } if *domain == name => {
+                value.is_some_and(|value| data.first().map(String::as_str) != Some(value))
+            }
+            _ => true,
+        });
+        removed = before - records.len();
+    })?;
+
+    Ok(removed)
+}
+
+/// Changes the records of the zone containing a name, as a new version of the zone so that
+/// secondaries pick up the change
+fn update_zone(
+    authority: &mut Authority,
+    name: &str,
+    update: impl FnOnce(&mut Vec<DnsRecord>),
+) -> Result<(), AcmeError> {
+    let mut zone = authority
+        .find_zone(name)
+        .cloned()
+        .ok_or_else(|| AcmeError::NotHosted(name.to_string()))?;
+
+    let before = zone.records.len();
+    update(&mut zone.records);
+    if zone.records.len() != before {
+        zone.increment_serial();
+        authority.insert_zone(zone);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::question::{QueryClass, QueryType};
+    use crate::zone::Zone;
+
+    fn authority() -> Authority {
+        let zone = Zone {
+            origin: "home.lan".to_string(),
+            records: vec![DnsRecord::SOA {
+                domain: "home.lan".to_string(),
+                mname: "ns1.home.lan".to_string(),
+                rname: "admin.home.lan".to_string(),
+                serial: 1,
+                refresh: 3600,
+                retry: 600,
+                expire: 86400,
+                minimum: 300,
+                ttl: 3600,
+            }],
+        };
+        Authority::new(Vec::new(), vec![zone])
+    }
+
+    #[test]
+    fn publishes_and_removes_challenges() {
+        let mut authority = authority();
+
+        let name = add_challenge(&mut authority, "www.home.lan", "token-1").unwrap();
+        add_challenge(&mut authority, "_acme-challenge.www.home.lan.", "token-2").unwrap();
+        assert_eq!(name, "_acme-challenge.www.home.lan");
+
+        let answer = authority
+            .lookup(&name, QueryType::TXT, QueryClass::IN)
+            .unwrap();
+        assert_eq!(answer.answers.len(), 2);
+        assert_eq!(authority.zones[0].serial(), Some(3));
+        assert!(authority.journal.changes_since("home.lan", 1).is_some());
+
+        assert_eq!(
+            remove_challenges(&mut authority, "www.home.lan", Some("token-1")).unwrap(),
+            1
+        );
+        assert_eq!(
+            remove_challenges(&mut authority, "www.home.lan", None).unwrap(),
+            1
+        );
+        assert_eq!(authority.zones[0].serial(), Some(5));
+    }
+
+    #[test]
+    fn refuses_names_outside_hosted_zones() {
+        let mut authority = authority();
+
+        assert!(matches!(
+            add_challenge(&mut authority, "example.com", "token"),
+            Err(AcmeError::NotHosted(_))
+        ));
+        assert!(matches!(
+            add_challenge(&mut authority, "www.home.lan", "two words"),
+            Err(AcmeError::InvalidValue(_))
+        ));
+    }
+}
@@ -0,0 +1,213 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::{info, warn, LevelFilter};
+
+use crate::acme::{self, AcmeError};
+use crate::handler::Context;
+use crate::mode::Maintenance;
+use crate::verbosity;
+
+/// Number of names listed by `GET /top`
+const TOP_NAMES: usize = 100;
+/// Time a client has to send its request and read the response, after which it's dropped so
+/// that it doesn't hold up the requests of others
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Content type of the Prometheus text exposition format
+const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+const TEXT_CONTENT_TYPE: &str = "text/plain; charset=utf-8";
+
+/// The admin API is a minimal HTTP server meant to be bound to a local address.
+/// It exposes the following endpoints:
+/// - `GET /metrics`: statistics in the Prometheus text exposition format
+/// - `GET /stats`: statistics as a human readable table
+/// - `GET /top`: the most queried names and the names most blocked by response policies,
+///   approximately counted
+/// - `PUT /acme-challenge/<domain>/<value>`: publishes a DNS-01 challenge for a domain of a
+///   hosted zone, as a TXT record at `_acme-challenge.<domain>`
+/// - `DELETE /acme-challenge/<domain>[/<value>]`: removes the challenge with the given
+///   value, or all the challenges of the domain
+/// - `GET /clients`: the devices LAN clients are leased to, from the DHCP leases file
+/// - `GET /modes`: the modes toggled at runtime
+/// - `PUT /maintenance/<servfail|refused>`, `DELETE /maintenance`: enters or leaves
+///   maintenance mode
+/// - `PUT /read-only`, `DELETE /read-only`: enters or leaves read-only mode
+/// - `GET /log-level`: the level of the logs, then the modules with a level of their own
+/// - `PUT /log-level/<level>`, `PUT /log-level/<module>/<level>`: sets the level of the logs,
+///   or of a module (e.g. `handler` or `cache`) and its submodules
+/// - `DELETE /log-level/<module>`: makes a module log at the level of the others
+pub struct Admin {
+    context: Arc<Context>,
+}
+
+impl Admin {
+    pub fn new(context: Arc<Context>) -> Admin {
+        Admin { context }
+    }
+
+    /// Serves requests sequentially, forever. Clients that are too slow to send their request
+    /// or to read the response are dropped after a timeout.
+    pub fn serve(&self, listener: TcpListener) {
+        for stream in listener.incoming() {
+            let result = stream.and_then(|stream| self.handle(stream));
+            if let Err(e) = result {
+                warn!("Admin API error: {}", e);
+            }
+        }
+    }
+
+    /// Answers a single request, closing the connection afterwards
+    fn handle(&self, mut stream: TcpStream) -> std::io::Result<()> {
+        stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+        stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+        let mut request_line = String::new();
+        BufReader::new(&stream).read_line(&mut request_line)?;
+
+        let mut parts = request_line.split_whitespace();
+        let method = parts.next().unwrap_or_default();
+        let path = parts.next().unwrap_or_default();
+
+        let stats = &self.context.stats;
+        let (status, body) = match (method, path) {
+            ("GET", "/metrics") => ("200 OK", stats.to_prometheus()),
+            ("GET", "/stats") => ("200 OK", stats.to_table()),
+            ("GET", "/top") => ("200 OK", stats.to_top(TOP_NAMES)),
+            ("GET", "/clients") => ("200 OK", self.clients()),
+            ("GET", "/modes") => ("200 OK", format!("{}\n", self.context.modes)),
+            ("DELETE", "/maintenance") => self.set_maintenance(Maintenance::Off),
+            ("PUT", path) if path.starts_with("/maintenance/") => {
+                match path["/maintenance/".len()..].parse() {
+                    Ok(maintenance) => self.set_maintenance(maintenance),
+                    Err(e) => ("400 Bad Request", format!("{}\n", e)),
+                }
+            }
+            ("PUT", "/read-only") => self.set_read_only(true),
+            ("DELETE", "/read-only") => self.set_read_only(false),
+            ("GET", "/log-level") => ("200 OK", verbosity::describe()),
+            (method, path) if path.starts_with("/log-level/") => {
+                self.log_level(method, &path["/log-level/".len()..])
+            }
+            (method, path) if path.starts_with("/acme-challenge/") => {
+                self.acme_challenge(method, &path["/acme-challenge/".len()..])
+            }
+            _ => ("404 Not Found", "Not found\n".to_string()),
+        };
+
+        let content_type = match (method, path) {
+            ("GET", "/metrics") => PROMETHEUS_CONTENT_TYPE,
+            _ => TEXT_CONTENT_TYPE,
+        };
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            content_type,
+            body.len(),
+            body
+        )
+    }
+
+    /// Publishes or removes DNS-01 challenges, given a `<domain>[/<value>]` path
+    fn acme_challenge(&self, method: &str, path: &str) -> (&'static str, String) {
+        let (domain, value) = match path.split_once('/') {
+            Some((domain, value)) => (domain, Some(value)),
+            None => (path, None),
+        };
+
+        if self.context.modes.read_only() {
+            return (
+                "403 Forbidden",
+                "Zones can't be updated in read-only mode\n".to_string(),
+            );
+        }
+
+        let mut authority = self.context.authority.write().unwrap();
+        let result = match (method, value) {
+            ("PUT", Some(value)) => acme::add_challenge(&mut authority, domain, value)
+                .map(|name| format!("Published challenge at {}\n", name)),
+            ("DELETE", value) => acme::remove_challenges(&mut authority, domain, value)
+                .map(|removed| format!("Removed {} challenges\n", removed)),
+            _ => return ("404 Not Found", "Not found\n".to_string()),
+        };
+
+        match result {
+            Ok(message) => {
+                info!("Admin API: {}", message.trim_end());
+                ("200 OK", message)
+            }
+            Err(e @ AcmeError::NotHosted(_)) => ("404 Not Found", format!("{}\n", e)),
+            Err(e) => ("400 Bad Request", format!("{}\n", e)),
+        }
+    }
+
+    /// Lists the leases, one per line
+    fn clients(&self) -> String {
+        self.context
+            .leases
+            .all()
+            .into_iter()
+            .map(|(addr, lease)| {
+                format!(
+                    "{} {} {}\n",
+                    addr,
+                    lease.mac,
+                    lease.hostname.as_deref().unwrap_or("-")
+                )
+            })
+            .collect()
+    }
+
+    /// Sets or removes the level of the logs or of a module, given as `[<module>/]<level>`
+    fn log_level(&self, method: &str, path: &str) -> (&'static str, String) {
+        let (module, level) = match path.rsplit_once('/') {
+            Some((module, level)) => (Some(module), Some(level)),
+            None if method == "DELETE" => (Some(path), None),
+            None => (None, Some(path)),
+        };
+        let level = match level.map(str::parse::<LevelFilter>) {
+            Some(Ok(level)) => Some(level),
+            Some(Err(_)) => {
+                return (
+                    "400 Bad Request",
+                    "expected off, error, warn, info, debug or trace\n".to_string(),
+                )
+            }
+            None => None,
+        };
+
+        match (method, module, level) {
+            ("PUT", None, Some(level)) => verbosity::set_level(level),
+            ("PUT", Some(module), Some(level)) => verbosity::set_module_level(module, level),
+            ("DELETE", Some(module), None) => {
+                if !verbosity::clear_module_level(module) {
+                    return ("404 Not Found", format!("No level set for {}\n", module));
+                }
+            }
+            _ => return ("404 Not Found", "Not found\n".to_string()),
+        }
+        info!(
+            "Admin API: log level of {} set to {}",
+            module.unwrap_or("all modules"),
+            level.map_or("the default".to_string(), |level| level.to_string())
+        );
+        ("200 OK", verbosity::describe())
+    }
+
+    fn set_maintenance(&self, maintenance: Maintenance) -> (&'static str, String) {
+        self.context.modes.set_maintenance(maintenance);
+        info!("Admin API: maintenance mode {}", maintenance);
+        ("200 OK", format!("{}\n", self.context.modes))
+    }
+
+    fn set_read_only(&self, read_only: bool) -> (&'static str, String) {
+        self.context.modes.set_read_only(read_only);
+        self.context.cache.set_read_only(read_only);
+        info!(
+            "Admin API: read-only mode {}",
+            if read_only { "on" } else { "off" }
+        );
+        ("200 OK", format!("{}\n", self.context.modes))
+    }
+}
@@ -0,0 +1,264 @@
+use std::fmt;
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, TcpStream, ToSocketAddrs};
+use std::process::Command;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use log::{info, warn};
+
+use crate::stats::Stats;
+
+/// Time to wait for a webhook to accept a connection or answer
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+/// Minimum number of queries in a window for its SERVFAIL rate to be meaningful
+const MIN_WINDOW_QUERIES: u64 = 20;
+
+/// `AlertError` is an enum that represents the errors that can occur while
+/// configuring or delivering alerts
+#[derive(thiserror::Error, Debug)]
+pub enum AlertError {
+    #[error("Invalid webhook URL \"{0}\": expected http://<HOST>[:<PORT>][/<PATH>]")]
+    InvalidUrl(String),
+    #[error("Webhook answered {0}")]
+    WebhookFailed(String),
+    #[error("Command exited with {0}")]
+    CommandFailed(std::process::ExitStatus),
+    #[error("I/O error: {0}")]
+    IoError(#[from] std::io::Error),
+}
+
+/// Something operators should know about promptly
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    /// An upstream server stopped answering
+    ServerDown(Ipv4Addr),
+    /// An upstream server answers again after being down
+    ServerUp(Ipv4Addr),
+    /// The ratio of queries answered with SERVFAIL crossed the threshold
+    ServfailRateHigh(f64),
+    /// The ratio of queries answered with SERVFAIL went back below the threshold
+    ServfailRateNormal(f64),
+}
+
+impl Event {
+    /// Short name of the kind of event, e.g. `server-down`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::ServerDown(_) => "server-down",
+            Event::ServerUp(_) => "server-up",
+            Event::ServfailRateHigh(_) => "servfail-rate-high",
+            Event::ServfailRateNormal(_) => "servfail-rate-normal",
+        }
+    }
+
+    /// What the event is about: the server address, or the SERVFAIL rate
+    pub fn detail(&self) -> String {
+        match self {
+            Event::ServerDown(server) | Event::ServerUp(server) => server.to_string(),
+            Event::ServfailRateHigh(rate) | Event::ServfailRateNormal(rate) => {
+                format!("{:.3}", rate)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.name(), self.detail())
+    }
+}
+
+/// An HTTP endpoint alerts are posted to as JSON, e.g. `{"event":"server-down","detail":"192.0.2.1"}`
+#[derive(Clone, Debug)]
+pub struct Webhook {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl FromStr for Webhook {
+    type Err = AlertError;
+
+    fn from_str(url: &str) -> Result<Webhook, AlertError> {
+        let invalid = || AlertError::InvalidUrl(url.to_string());
+
+        let rest = url.strip_prefix("http://").ok_or_else(invalid)?;
+        let (authority, path) = match rest.find('/') {
+            Some(i) => rest.split_at(i),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| invalid())?),
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(invalid());
+        }
+
+        Ok(Webhook {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for Webhook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "http://{}:{}{}", self.host, self.port, self.path)
+    }
+}
+
+impl Webhook {
+    /// Posts an event, failing unless the endpoint answers with a 2xx status
+    fn post(&self, event: &Event) -> Result<(), AlertError> {
+        let addr = (self.host.as_str(), self.port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| AlertError::InvalidUrl(self.host.clone()))?;
+        let mut stream = TcpStream::connect_timeout(&addr, WEBHOOK_TIMEOUT)?;
+        stream.set_read_timeout(Some(WEBHOOK_TIMEOUT))?;
+        stream.set_write_timeout(Some(WEBHOOK_TIMEOUT))?;
+
+        let body = format!(
+            "{{\"event\":\"{}\",\"detail\":\"{}\"}}",
+            event.name(),
+            event.detail()
+        );
+        write!(
+            stream,
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.path,
+            self.host,
+            body.len(),
+            body
+        )?;
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        let status = response.lines().next().unwrap_or_default();
+        match status.split_whitespace().nth(1) {
+            Some(code) if code.starts_with('2') => Ok(()),
+            _ => Err(AlertError::WebhookFailed(status.to_string())),
+        }
+    }
+}
+
+/// The `Alerter` struct notifies operators of resolution outages, by running a command
+/// and/or posting to a webhook. The command is run by `sh -c` with the event in the
+/// `VODO_EVENT` and `VODO_DETAIL` environment variables.
+/// Notifications are delivered in the background, so that they never slow down resolution.
+#[derive(Clone, Debug, Default)]
+pub struct Alerter {
+    command: Option<String>,
+    webhook: Option<Webhook>,
+}
+
+impl Alerter {
+    pub fn new(command: Option<String>, webhook: Option<Webhook>) -> Alerter {
+        Alerter { command, webhook }
+    }
+
+    /// Returns true if alerts are delivered anywhere
+    pub fn is_enabled(&self) -> bool {
+        self.command.is_some() || self.webhook.is_some()
+    }
+
+    /// Notifies operators of an event
+    pub fn notify(&self, event: Event) {
+        info!("Alert: {}", event);
+
+        if let Some(command) = self.command.clone() {
+            let event = event.clone();
+            thread::spawn(move || {
+                if let Err(e) = run_command(&command, &event) {
+                    warn!("Failed to run the alert command for {}: {}", event, e);
+                }
+            });
+        }
+
+        if let Some(webhook) = self.webhook.clone() {
+            thread::spawn(move || {
+                if let Err(e) = webhook.post(&event) {
+                    warn!("Failed to post {} to the webhook: {}", event, e);
+                }
+            });
+        }
+    }
+
+    /// Checks the ratio of queries answered with SERVFAIL every `interval`, forever, and
+    /// notifies when it crosses `threshold` in either direction. It blocks the calling thread,
+    /// so the server gives it one of its own, only when alerts have somewhere to go.
+    pub fn watch_servfail_rate(&self, stats: Arc<Stats>, threshold: f64, interval: Duration) {
+        let mut last = stats.global();
+        let mut high = false;
+
+        loop {
+            thread::sleep(interval);
+
+            let now = stats.global();
+            let queries = now.queries - last.queries;
+            let servfail = now.servfail - last.servfail;
+            last = now;
+            if queries < MIN_WINDOW_QUERIES {
+                continue;
+            }
+
+            let rate = servfail as f64 / queries as f64;
+            if !high && rate >= threshold {
+                high = true;
+                self.notify(Event::ServfailRateHigh(rate));
+            } else if high && rate < threshold {
+                high = false;
+                self.notify(Event::ServfailRateNormal(rate));
+            }
+        }
+    }
+}
+
+/// Runs the alert command for an event, waiting for it to exit
+fn run_command(command: &str, event: &Event) -> Result<(), AlertError> {
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("VODO_EVENT", event.name())
+        .env("VODO_DETAIL", event.detail())
+        .status()?;
+
+    if !status.success() {
+        return Err(AlertError::CommandFailed(status));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_webhook_urls() {
+        let webhook: Webhook = "http://alerts.example.com:8080/hooks/dns".parse().unwrap();
+        assert_eq!(webhook.host, "alerts.example.com");
+        assert_eq!(webhook.port, 8080);
+        assert_eq!(webhook.path, "/hooks/dns");
+
+        let webhook: Webhook = "http://127.0.0.1".parse().unwrap();
+        assert_eq!(webhook.port, 80);
+        assert_eq!(webhook.path, "/");
+    }
+
+    #[test]
+    fn rejects_invalid_webhook_urls() {
+        for url in [
+            "https://example.com/",
+            "http://",
+            "http://example.com:port/",
+        ] {
+            assert!(url.parse::<Webhook>().is_err(), "{}", url);
+        }
+    }
+}
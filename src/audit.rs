@@ -0,0 +1,42 @@
+use std::cell::RefCell;
+use std::net::Ipv4Addr;
+
+use crate::infra::EdnsMode;
+use crate::question::QueryType;
+
+thread_local! {
+    /// Upstream queries sent by the current thread while it is being traced
+    static TRACE: RefCell<Option<Vec<UpstreamQuery>>> = const { RefCell::new(None) };
+}
+
+/// A query sent to an upstream server while resolving a client query
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UpstreamQuery {
+    pub server: Ipv4Addr,
+    pub qname: String,
+    pub qtype: QueryType,
+    pub edns: EdnsMode,
+    pub tcp: bool,
+}
+
+/// Runs `f`, returning its result along with the upstream queries it sent, in order.
+/// This covers the whole resolution: the walk down the delegations, the lookups of the
+/// name servers without glue, and the retries without EDNS or over TCP.
+pub fn trace<R>(f: impl FnOnce() -> R) -> (R, Vec<UpstreamQuery>) {
+    TRACE.with(|trace| *trace.borrow_mut() = Some(Vec::new()));
+    let result = f();
+    let queries = TRACE
+        .with(|trace| trace.borrow_mut().take())
+        .unwrap_or_default();
+
+    (result, queries)
+}
+
+/// Records an upstream query, if the current thread is being traced
+pub fn record(query: UpstreamQuery) {
+    TRACE.with(|trace| {
+        if let Some(queries) = trace.borrow_mut().as_mut() {
+            queries.push(query);
+        }
+    });
+}
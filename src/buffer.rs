@@ -7,24 +7,72 @@ pub enum BufferError {
     JumpsLimitExceeded(i32),
     #[error("Single label exceeds 63 characters of length")]
     LabelTooLong,
+    #[error("Character string exceeds 255 characters of length")]
+    StringTooLong,
+    #[error("Record data of {0} bytes is too short for its fields")]
+    DataTooShort(u16),
+    #[error("Record data of {0} bytes is longer than its fields")]
+    TrailingData(u16),
+    #[error("Record data exceeds 65535 bytes of length")]
+    DataTooLong,
     #[error("I/O error: {0}")]
     IoError(#[from] std::io::Error),
 }
 
+/// Size of a classic DNS message over UDP, as defined in RFC 1035
+pub const DEFAULT_SIZE: usize = 512;
+/// Maximum size of a DNS message, bounded by the two bytes length prefix used over TCP
+pub const MAX_SIZE: usize = 65535;
+
 /// The `Buffer` struct is used to hold the contents of a DNS packet as a byte buffer,
 /// and provides methods for reading and manipulating the buffer contents.
 pub struct Buffer {
-    pub buf: [u8; 512],
+    pub buf: Vec<u8>,
     pub pos: usize,
+    /// Whether writes past the end grow the buffer, up to the maximum size of a message
+    growable: bool,
+}
+
+impl Default for Buffer {
+    fn default() -> Self {
+        Buffer::new()
+    }
 }
 
 impl Buffer {
     /// This gives us a fresh buffer for holding the packet contents, and a
     /// field for keeping track of where we are.
     pub fn new() -> Buffer {
+        Buffer::with_size(DEFAULT_SIZE)
+    }
+
+    /// Creates a fresh buffer of a specific size, used when EDNS allows
+    /// for messages bigger than 512 bytes.
+    pub fn with_size(size: usize) -> Buffer {
         Buffer {
-            buf: [0; 512],
+            buf: vec![0; size],
             pos: 0,
+            growable: false,
+        }
+    }
+
+    /// Creates an empty buffer that grows as it is written to, up to the maximum size of a
+    /// message, to serialize messages without knowing their length beforehand. Room for a
+    /// classic 512 bytes message is reserved up front.
+    pub fn growable() -> Buffer {
+        Buffer {
+            buf: Vec::with_capacity(DEFAULT_SIZE),
+            pos: 0,
+            growable: true,
+        }
+    }
+
+    /// Creates a buffer holding a copy of the given bytes, positioned at the start
+    pub fn from_bytes(bytes: &[u8]) -> Buffer {
+        Buffer {
+            buf: bytes.to_vec(),
+            pos: 0,
+            growable: false,
         }
     }
 
@@ -48,8 +96,8 @@ impl Buffer {
     }
 
     /// Read a single byte and move the position one step forward
-    fn read(&mut self) -> Result<u8, BufferError> {
-        if self.pos >= 512 {
+    pub fn read(&mut self) -> Result<u8, BufferError> {
+        if self.pos >= self.buf.len() {
             return Err(BufferError::EndOfBuffer);
         }
         let res = self.buf[self.pos];
@@ -60,43 +108,69 @@ impl Buffer {
 
     /// Get a single byte, without changing the buffer position
     fn get(&mut self, pos: usize) -> Result<u8, BufferError> {
-        if pos >= 512 {
+        if pos >= self.buf.len() {
             return Err(BufferError::EndOfBuffer);
         }
         Ok(self.buf[pos])
     }
 
     /// Get a range of bytes
-    pub fn get_range(&mut self, start: usize, len: usize) -> Result<&[u8], BufferError> {
-        if start + len >= 512 {
-            return Err(BufferError::EndOfBuffer);
-        }
-        Ok(&self.buf[start..start + len])
+    pub fn get_range(&self, start: usize, len: usize) -> Result<&[u8], BufferError> {
+        start
+            .checked_add(len)
+            .and_then(|end| self.buf.get(start..end))
+            .ok_or(BufferError::EndOfBuffer)
     }
 
-    /// Read two bytes, stepping two steps forward
-    pub fn read_u16(&mut self) -> Result<u16, BufferError> {
-        let res = (u16::from(self.read()?) << 8) | u16::from(self.read()?);
+    /// Read a fixed size array of bytes, stepping forward by its size.
+    /// The whole range is bounds-checked once, rather than once per byte.
+    #[inline]
+    pub fn read_array<const N: usize>(&mut self) -> Result<[u8; N], BufferError> {
+        let res = self
+            .buf
+            .get(self.pos..self.pos + N)
+            .and_then(|bytes| <[u8; N]>::try_from(bytes).ok())
+            .ok_or(BufferError::EndOfBuffer)?;
+        self.pos += N;
 
         Ok(res)
     }
 
+    /// Read two bytes, stepping two steps forward
+    #[inline]
+    pub fn read_u16(&mut self) -> Result<u16, BufferError> {
+        Ok(u16::from_be_bytes(self.read_array()?))
+    }
+
     /// Read four bytes, stepping four steps forward
-    #[allow(clippy::identity_op)]
+    #[inline]
     pub fn read_u32(&mut self) -> Result<u32, BufferError> {
-        let res = (u32::from(self.read()?) << 24)
-            | (u32::from(self.read()?) << 16)
-            | (u32::from(self.read()?) << 8)
-            | (u32::from(self.read()?) << 0);
+        Ok(u32::from_be_bytes(self.read_array()?))
+    }
+
+    /// Read a fixed number of bytes, stepping forward by the same amount
+    pub fn read_bytes(&mut self, len: usize) -> Result<Vec<u8>, BufferError> {
+        let res = self.get_range(self.pos, len)?.to_vec();
+        self.pos += len;
 
         Ok(res)
     }
 
+    /// Read a character string, which is a single length byte followed by that many bytes:
+    /// see https://tools.ietf.org/html/rfc1035#section-3.3
+    pub fn read_character_string(&mut self) -> Result<String, BufferError> {
+        let len = self.read()?;
+        let bytes = self.read_bytes(len as usize)?;
+
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+
     /// Read a qname
     ///
     /// The tricky part: Reading domain names, taking labels into consideration.
     /// Will take something like [3]www[6]google[3]com[0] and append
-    /// www.google.com to outstr.
+    /// www.google.com to outstr. The case of the labels is preserved, so that questions can be
+    /// echoed exactly as asked; see `read_normalized_qname` for names compared with others.
     pub fn read_qname(&mut self, outstr: &mut String) -> Result<(), BufferError> {
         // Since we might encounter jumps, we'll keep track of our position
         // locally as opposed to using the position within the struct. This
@@ -165,7 +239,7 @@ impl Buffer {
                 // Extract the actual ASCII bytes for this label and append them
                 // to the output buffer.
                 let str_buffer = self.get_range(pos, len as usize)?;
-                outstr.push_str(&String::from_utf8_lossy(str_buffer).to_lowercase());
+                outstr.push_str(&String::from_utf8_lossy(str_buffer));
 
                 delim = ".";
 
@@ -181,15 +255,21 @@ impl Buffer {
         Ok(())
     }
 
+    /// Read a qname, lowercased. Names are case-insensitive (see
+    /// https://tools.ietf.org/html/rfc4343), so they are stored lowercased wherever they are
+    /// compared, such as in records.
+    pub fn read_normalized_qname(&mut self, outstr: &mut String) -> Result<(), BufferError> {
+        let start = outstr.len();
+        self.read_qname(outstr)?;
+        outstr[start..].make_ascii_lowercase();
+
+        Ok(())
+    }
+
     /// The write function writes a single byte to the buffer at the current position.
     /// If the buffer is already full, it returns an `EndOfBuffer` error.
     pub fn write(&mut self, val: u8) -> Result<(), BufferError> {
-        if self.pos >= 512 {
-            return Err(BufferError::EndOfBuffer);
-        }
-        self.buf[self.pos] = val;
-        self.pos += 1;
-        Ok(())
+        self.write_bytes(&[val])
     }
 
     /// `write_u8` writes a single byte to the buffer at the current position.
@@ -201,28 +281,58 @@ impl Buffer {
 
     /// `write_u16` writes two bytes to the buffer at the current position.
     /// The most significant byte is written first.
+    #[inline]
     pub fn write_u16(&mut self, val: u16) -> Result<(), BufferError> {
-        self.write((val >> 8) as u8)?;
-        self.write((val & 0xFF) as u8)?;
-
-        Ok(())
+        self.write_bytes(&val.to_be_bytes())
     }
 
     /// `write_u32` writes four bytes to the buffer at the current position.
     /// The most significant byte is written first.
     /// This is useful for writing IPv4 addresses.
-    /// #[`allow(clippy::identity_op`)]
+    #[inline]
     pub fn write_u32(&mut self, val: u32) -> Result<(), BufferError> {
-        self.write(((val >> 24) & 0xFF) as u8)?;
-        self.write(((val >> 16) & 0xFF) as u8)?;
-        self.write(((val >> 8) & 0xFF) as u8)?;
-        self.write((val & 0xFF) as u8)?;
+        self.write_bytes(&val.to_be_bytes())
+    }
+
+    /// `write_bytes` writes a slice of bytes to the buffer at the current position.
+    /// If the bytes don't fit in the buffer, nothing is written and an `EndOfBuffer`
+    /// error is returned.
+    #[inline]
+    pub fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), BufferError> {
+        let end = self.pos + bytes.len();
+        if self.growable && end > self.buf.len() && end <= MAX_SIZE {
+            self.buf.resize(end, 0);
+        }
+        self.buf
+            .get_mut(self.pos..end)
+            .ok_or(BufferError::EndOfBuffer)?
+            .copy_from_slice(bytes);
+        self.pos = end;
+
+        Ok(())
+    }
+
+    /// `write_character_string` writes a length byte followed by the string itself.
+    /// Strings longer than 255 bytes cannot be represented.
+    pub fn write_character_string(&mut self, value: &str) -> Result<(), BufferError> {
+        let len = value.len();
+        if len > 0xFF {
+            return Err(BufferError::StringTooLong);
+        }
+
+        self.write_u8(len as u8)?;
+        self.write_bytes(value.as_bytes())?;
 
         Ok(())
     }
 
     /// `write_qname` writes query names in labeled form
     pub fn write_qname(&mut self, qname: &str) -> Result<(), BufferError> {
+        // The root name, e.g. the target of a null MX record, is only the terminating label.
+        if qname.is_empty() {
+            return self.write_u8(0);
+        }
+
         for label in qname.split('.') {
             // ox3f is 0011 1111 in binary, so we can use it to check if the label is longer than 63 characters
             let len = label.len();
@@ -231,9 +341,7 @@ impl Buffer {
             }
 
             self.write_u8(len as u8)?;
-            for b in label.as_bytes() {
-                self.write_u8(*b)?;
-            }
+            self.write_bytes(label.as_bytes())?;
         }
 
         self.write_u8(0)?;
@@ -241,21 +349,34 @@ impl Buffer {
         Ok(())
     }
 
-    // set writes a single byte to the buffer at the specified position.
-    fn set(&mut self, pos: usize, val: u8) -> Result<(), BufferError> {
-        self.buf[pos] = val;
-
-        Ok(())
-    }
-
     /// `set_u16` writes two bytes to the buffer at the specified position.
     /// The most significant byte is written first.
     pub fn set_u16(&mut self, pos: usize, val: u16) -> Result<(), BufferError> {
-        self.set(pos, (val >> 8) as u8)?;
-        // The bitwise AND operation with 0xFF then clears all bits except for the least significant byte,
-        // which contains the value of the most significant byte of raw_addr.
-        self.set(pos + 1, (val & 0xFF) as u8)?;
+        self.buf
+            .get_mut(pos..pos + 2)
+            .ok_or(BufferError::EndOfBuffer)?
+            .copy_from_slice(&val.to_be_bytes());
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_the_root_name_as_a_single_label() {
+        let mut buffer = Buffer::new();
+        buffer.write_qname("").unwrap();
+        buffer.write_qname("example.com").unwrap();
+        assert_eq!(buffer.pos(), 1 + 13);
+        assert_eq!(buffer.buf[0], 0);
+
+        buffer.pos = 0;
+        let (mut root, mut name) = (String::new(), String::new());
+        buffer.read_qname(&mut root).unwrap();
+        buffer.read_qname(&mut name).unwrap();
+        assert_eq!((root.as_str(), name.as_str()), ("", "example.com"));
+    }
+}
@@ -0,0 +1,471 @@
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::mem::size_of;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::clock::{Clock, SystemClock};
+use crate::packet::{DnsPacket, Records};
+use crate::question::{QueryClass, QueryType};
+use crate::record::DnsRecord;
+
+/// Longest chain of cached CNAMEs followed to complete an answer
+const MAX_SPLICED_CNAMES: usize = 8;
+
+/// How much a set of records can be trusted, depending on where it was found
+/// in a response, as ranked by RFC 2181 (lowest first):
+/// see https://tools.ietf.org/html/rfc2181#section-5.4.1
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Credibility {
+    /// Data from the additional section, such as glue records
+    Additional,
+    /// Data from the authority section
+    Authority,
+    /// Data from the answer section of a non-authoritative answer
+    Answer,
+    /// Data from the answer section of an authoritative answer
+    AuthoritativeAnswer,
+}
+
+/// Name, type and class of the records of a set
+type Key = (String, QueryType, QueryClass);
+
+/// A set of records sharing the same name, type and class
+#[derive(Clone, Debug)]
+struct CachedRRset {
+    records: Vec<DnsRecord>,
+    credibility: Credibility,
+    inserted: Instant,
+    expires: Instant,
+}
+
+impl CachedRRset {
+    /// Returns an estimate of the memory used by the set, along with its key
+    fn approximate_size(&self, key: &Key) -> usize {
+        size_of::<(Key, CachedRRset)>()
+            + key.0.len()
+            + self
+                .records
+                .iter()
+                .map(DnsRecord::approximate_size)
+                .sum::<usize>()
+    }
+}
+
+/// The `Cache` struct holds the records learned from upstream servers until their TTL expires.
+/// Records are stored as sets sharing the same name, type and class, each tagged with its
+/// credibility, so that data of lower credibility never replaces data of higher credibility
+/// that is still valid (a basic defense against cache poisoning), and is never answered to
+/// clients. The cache can be shared between threads, each operation holding its lock only
+/// briefly.
+///
+/// Names are hashed with SipHash keyed by random keys drawn for each cache, so that clients
+/// can't choose names that collide in the map to degrade lookups to linear scans
+/// (hash flooding): without the keys, collisions can't be predicted.
+pub struct Cache {
+    entries: Mutex<HashMap<Key, CachedRRset, RandomState>>,
+    clock: Arc<dyn Clock>,
+    /// Estimate of the memory used by the entries
+    size: AtomicUsize,
+    /// Number of entries evicted before they expired
+    evictions: AtomicUsize,
+    /// Whether new records are ignored rather than stored
+    read_only: AtomicBool,
+}
+
+impl Default for Cache {
+    fn default() -> Cache {
+        Cache::new()
+    }
+}
+
+impl Cache {
+    pub fn new() -> Cache {
+        Cache::with_clock(Arc::new(SystemClock))
+    }
+
+    /// Creates a cache whose records expire according to the given clock
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Cache {
+        Cache {
+            entries: Mutex::new(HashMap::with_hasher(RandomState::new())),
+            clock,
+            size: AtomicUsize::new(0),
+            evictions: AtomicUsize::new(0),
+            read_only: AtomicBool::new(false),
+        }
+    }
+
+    /// Stops storing new records, or resumes storing them. Cached records are still
+    /// served until they expire.
+    pub fn set_read_only(&self, read_only: bool) {
+        self.read_only.store(read_only, Ordering::Relaxed);
+    }
+
+    /// Returns an estimate of the memory used by the cached records
+    pub fn memory_usage(&self) -> usize {
+        self.size.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of entries evicted before they expired, to save memory
+    pub fn evictions(&self) -> usize {
+        self.evictions.load(Ordering::Relaxed)
+    }
+
+    /// Number of sets of records in the cache, expired ones included
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Shrinks the cache to use at most `max_size` bytes, by removing the expired entries and
+    /// then, if that isn't enough, the entries closest to expiring. Returns the number of
+    /// entries removed.
+    pub fn evict(&self, max_size: usize) -> usize {
+        let now = self.clock.now();
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+
+        entries.retain(|key, entry| {
+            let keep = entry.expires > now;
+            if !keep {
+                self.size
+                    .fetch_sub(entry.approximate_size(key), Ordering::Relaxed);
+            }
+            keep
+        });
+
+        if self.size.load(Ordering::Relaxed) > max_size {
+            let mut by_expiry: Vec<(Key, Instant)> = entries
+                .iter()
+                .map(|(key, entry)| (key.clone(), entry.expires))
+                .collect();
+            by_expiry.sort_by_key(|(_, expires)| *expires);
+
+            for (key, _) in by_expiry {
+                if self.size.load(Ordering::Relaxed) <= max_size {
+                    break;
+                }
+                if let Some(entry) = entries.remove(&key) {
+                    self.size
+                        .fetch_sub(entry.approximate_size(&key), Ordering::Relaxed);
+                    self.evictions.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+
+        before - entries.len()
+    }
+
+    /// Returns the cached records for a name, type and class, with their TTLs reduced
+    /// by the time they spent in the cache. Records of another class never match.
+    /// Records of any credibility are returned, glue included, to follow delegations and fill
+    /// additional sections: answers to clients come from `lookup_answer`.
+    pub fn lookup(&self, qname: &str, qtype: QueryType, qclass: QueryClass) -> Option<Records> {
+        self.lookup_ranked(qname, qtype, qclass, Credibility::Additional)
+    }
+
+    /// Returns the cached records for a name, type and class that can be answered to clients,
+    /// those found in the answer section of a response. Data of the authority and additional
+    /// sections is never returned as an answer: see
+    /// https://tools.ietf.org/html/rfc2181#section-5.4.1
+    pub fn lookup_answer(
+        &self,
+        qname: &str,
+        qtype: QueryType,
+        qclass: QueryClass,
+    ) -> Option<Records> {
+        self.lookup_ranked(qname, qtype, qclass, Credibility::Answer)
+    }
+
+    /// Returns the cached records for a name, type and class, if they are at least as
+    /// credible as `min`
+    fn lookup_ranked(
+        &self,
+        qname: &str,
+        qtype: QueryType,
+        qclass: QueryClass,
+        min: Credibility,
+    ) -> Option<Records> {
+        let now = self.clock.now();
+        let entries = self.entries.lock().unwrap();
+        let entry = entries
+            .get(&(qname.to_string(), qtype, qclass))
+            .filter(|entry| entry.expires > now && entry.credibility >= min)?;
+
+        let elapsed = now.duration_since(entry.inserted).as_secs() as u32;
+
+        Some(
+            entry
+                .records
+                .iter()
+                .map(|record| record.with_ttl(record.ttl().saturating_sub(elapsed)))
+                .collect(),
+        )
+    }
+
+    /// Completes an answer whose CNAME chain leads out of it with the cached records of the
+    /// target, following the aliases cached along the way, so that the target doesn't have
+    /// to be resolved again. Returns whether the answer was completed, and leaves it as it
+    /// is otherwise.
+    pub fn splice_cname_target(
+        &self,
+        packet: &mut DnsPacket,
+        qname: &str,
+        qtype: QueryType,
+    ) -> bool {
+        let mut target = packet.cname_target(qname);
+        if qtype == QueryType::CNAME
+            || target == qname
+            || packet
+                .answers
+                .iter()
+                .any(|record| record.domain() == Some(&target) && record.qtype() == qtype)
+        {
+            return false;
+        }
+
+        let mut spliced = Vec::new();
+        for _ in 0..MAX_SPLICED_CNAMES {
+            if let Some(records) = self.lookup_answer(&target, qtype, QueryClass::IN) {
+                spliced.extend(records);
+                packet.answers.extend(spliced);
+                return true;
+            }
+            let Some(aliases) = self.lookup_answer(&target, QueryType::CNAME, QueryClass::IN)
+            else {
+                return false;
+            };
+            match aliases.first() {
+                Some(DnsRecord::CNAME { host, .. }) => target = host.clone(),
+                _ => return false,
+            }
+            spliced.extend(aliases);
+        }
+        false
+    }
+
+    /// Stores all the records of a response, ranking them by the section they were found in.
+    /// The records are in the class of the question they answer.
+    pub fn insert_response(&self, packet: &DnsPacket) {
+        if self.read_only.load(Ordering::Relaxed) {
+            return;
+        }
+        let qclass = packet
+            .questions
+            .first()
+            .map_or(QueryClass::IN, |question| question.qclass);
+        let answer = if packet.header.authoritative_answer {
+            Credibility::AuthoritativeAnswer
+        } else {
+            Credibility::Answer
+        };
+
+        self.insert_section(&packet.answers, qclass, answer);
+        self.insert_section(&packet.authorities, qclass, Credibility::Authority);
+        self.insert_section(&packet.resources, qclass, Credibility::Additional);
+    }
+
+    /// Stores the records of a section, grouped in sets sharing the same name and type
+    fn insert_section(&self, records: &[DnsRecord], qclass: QueryClass, credibility: Credibility) {
+        let mut rrsets: HashMap<Key, Vec<DnsRecord>> = HashMap::new();
+        for record in records {
+            // Pseudo-records like OPT only apply to the message carrying them.
+            let Some(domain) = record.domain() else {
+                continue;
+            };
+            rrsets
+                .entry((domain.to_string(), record.qtype(), qclass))
+                .or_default()
+                .push(record.clone());
+        }
+
+        for (key, records) in rrsets {
+            self.insert_rrset(key, records, credibility);
+        }
+    }
+
+    /// Stores a set of records, unless a set of higher credibility is already cached
+    fn insert_rrset(&self, key: Key, records: Vec<DnsRecord>, credibility: Credibility) {
+        let now = self.clock.now();
+        let mut entries = self.entries.lock().unwrap();
+
+        if let Some(existing) = entries.get(&key) {
+            if existing.expires > now && existing.credibility > credibility {
+                return;
+            }
+        }
+
+        // All the records of a set are expected to share the same TTL, when they
+        // don't, the lowest one is used for the whole set:
+        // see https://tools.ietf.org/html/rfc2181#section-5.2
+        let ttl = records.iter().map(DnsRecord::ttl).min().unwrap_or(0);
+        if ttl == 0 {
+            return;
+        }
+
+        let entry = CachedRRset {
+            records,
+            credibility,
+            inserted: now,
+            expires: now + Duration::from_secs(u64::from(ttl)),
+        };
+        self.size
+            .fetch_add(entry.approximate_size(&key), Ordering::Relaxed);
+        if let Some(replaced) = entries.insert(key.clone(), entry) {
+            self.size
+                .fetch_sub(replaced.approximate_size(&key), Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::question::DnsQuestion;
+    use std::net::Ipv4Addr;
+
+    fn response(names: impl Iterator<Item = String>) -> DnsPacket {
+        let mut packet = DnsPacket::new();
+        packet.answers = names
+            .map(|domain| DnsRecord::A {
+                domain,
+                addr: Ipv4Addr::new(192, 0, 2, 1),
+                ttl: 300,
+            })
+            .collect();
+        packet
+    }
+
+    #[test]
+    fn splices_cached_targets_of_aliases() {
+        let cname = |domain: &str, host: &str| DnsRecord::CNAME {
+            domain: domain.to_string(),
+            host: host.to_string(),
+            ttl: 300,
+        };
+        let cache = Cache::new();
+        let mut cached = response(std::iter::once("edge.example.org".to_string()));
+        cached
+            .answers
+            .push(cname("cdn.example.net", "edge.example.org"));
+        cache.insert_response(&cached);
+
+        let mut packet = DnsPacket::new();
+        packet
+            .answers
+            .push(cname("www.example.com", "cdn.example.net"));
+        assert!(!cache.splice_cname_target(&mut packet, "www.example.com", QueryType::AAAA));
+        assert_eq!(packet.answers.len(), 1);
+
+        assert!(cache.splice_cname_target(&mut packet, "www.example.com", QueryType::A));
+        assert_eq!(packet.answers.len(), 3);
+        assert_eq!(
+            packet.answers[1],
+            cname("cdn.example.net", "edge.example.org")
+        );
+        assert_eq!(packet.answers[2].domain(), Some("edge.example.org"));
+
+        assert!(!cache.splice_cname_target(&mut packet, "www.example.com", QueryType::A));
+    }
+
+    #[test]
+    fn records_expire_with_their_ttl() {
+        let clock = Arc::new(ManualClock::new());
+        let cache = Cache::with_clock(clock.clone());
+        cache.insert_response(&response(std::iter::once("example.com".to_string())));
+
+        clock.advance(Duration::from_secs(100));
+        let records = cache
+            .lookup("example.com", QueryType::A, QueryClass::IN)
+            .unwrap();
+        assert_eq!(records[0].ttl(), 200);
+
+        clock.advance(Duration::from_secs(200));
+        assert!(cache
+            .lookup("example.com", QueryType::A, QueryClass::IN)
+            .is_none());
+    }
+
+    #[test]
+    fn evicts_entries_closest_to_expiring() {
+        let clock = Arc::new(ManualClock::new());
+        let cache = Cache::with_clock(clock.clone());
+        let mut packet = response((0..10).map(|i| format!("{}.example.com", i)));
+        for (i, record) in packet.answers.iter_mut().enumerate() {
+            *record = record.with_ttl(100 + i as u32);
+        }
+        cache.insert_response(&packet);
+        let size = cache.memory_usage();
+
+        assert_eq!(cache.evict(size / 2), 5);
+        assert!(cache.memory_usage() <= size / 2);
+        assert!(cache
+            .lookup("4.example.com", QueryType::A, QueryClass::IN)
+            .is_none());
+        assert!(cache
+            .lookup("5.example.com", QueryType::A, QueryClass::IN)
+            .is_some());
+
+        clock.advance(Duration::from_secs(200));
+        assert_eq!(cache.evict(usize::MAX), 5);
+        assert_eq!(cache.memory_usage(), 0);
+        assert_eq!(cache.evictions(), 5);
+    }
+
+    #[test]
+    fn never_answers_glue() {
+        let cache = Cache::new();
+        let mut referral = DnsPacket::new();
+        referral.authorities.push(DnsRecord::NS {
+            domain: "example.com".to_string(),
+            host: "ns1.example.com".to_string(),
+            ttl: 300,
+        });
+        referral.resources.push(DnsRecord::A {
+            domain: "ns1.example.com".to_string(),
+            addr: Ipv4Addr::new(192, 0, 2, 53),
+            ttl: 300,
+        });
+        cache.insert_response(&referral);
+
+        assert!(cache
+            .lookup("ns1.example.com", QueryType::A, QueryClass::IN)
+            .is_some());
+        assert!(cache
+            .lookup_answer("ns1.example.com", QueryType::A, QueryClass::IN)
+            .is_none());
+        assert!(cache
+            .lookup_answer("example.com", QueryType::NS, QueryClass::IN)
+            .is_none());
+
+        // Once the address is found in an answer, it replaces the glue and is answered.
+        cache.insert_response(&response(std::iter::once("ns1.example.com".to_string())));
+        assert!(cache
+            .lookup_answer("ns1.example.com", QueryType::A, QueryClass::IN)
+            .is_some());
+    }
+
+    #[test]
+    fn records_only_match_their_class() {
+        let cache = Cache::new();
+        let mut packet = response(std::iter::once("example.com".to_string()));
+        packet.questions.push(DnsQuestion {
+            name: "example.com".to_string(),
+            qtype: QueryType::A,
+            qclass: QueryClass::CH,
+        });
+        cache.insert_response(&packet);
+
+        assert!(cache
+            .lookup("example.com", QueryType::A, QueryClass::CH)
+            .is_some());
+        assert!(cache
+            .lookup("example.com", QueryType::A, QueryClass::IN)
+            .is_none());
+    }
+}
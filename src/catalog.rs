@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use log::{info, warn};
+
+use crate::handler::Context;
+use crate::record::DnsRecord;
+use crate::transfer::{axfr, ixfr, query_serial, TransferError};
+use crate::zone::Zone;
+
+/// Time between two checks of the catalogs, when their SOA doesn't say
+const DEFAULT_REFRESH: Duration = Duration::from_secs(300);
+/// Bounds of the time between two checks of the catalogs
+const MIN_REFRESH: Duration = Duration::from_secs(60);
+const MAX_REFRESH: Duration = Duration::from_secs(86400);
+
+/// A catalog zone, listing the zones to serve as a secondary of the same primary:
+/// see https://datatracker.ietf.org/doc/html/rfc9432
+#[derive(Clone, Debug)]
+pub struct Catalog {
+    pub origin: String,
+    pub primary: SocketAddr,
+}
+
+/// Returns the member zones listed in a catalog zone, i.e. the targets of the PTR records
+/// owned by `<unique-id>.zones.<catalog>`
+pub fn member_zones(catalog: &Zone) -> Vec<String> {
+    let suffix = format!(".zones.{}", catalog.origin);
+
+    let mut members: Vec<String> = catalog
+        .records
+        .iter()
+        .filter_map(|record| match record {
+            DnsRecord::PTR { domain, host, .. } => {
+                let id = domain.strip_suffix(&suffix)?;
+                (!id.is_empty() && !id.contains('.')).then(|| host.clone())
+            }
+            _ => None,
+        })
+        .collect();
+    members.sort();
+    members.dedup();
+
+    members
+}
+
+/// The `CatalogConsumer` struct keeps the hosted zones in sync with catalog zones: member
+/// zones are transferred from the primary when they are added to a catalog or their serial
+/// changes, and stop being served when they are removed from it.
+pub struct CatalogConsumer {
+    catalogs: Vec<Catalog>,
+    context: Arc<Context>,
+    /// Serial of each transferred zone, catalogs included
+    serials: HashMap<String, u32>,
+    /// Member zones of each catalog
+    members: HashMap<String, Vec<String>>,
+}
+
+impl CatalogConsumer {
+    pub fn new(catalogs: Vec<Catalog>, context: Arc<Context>) -> CatalogConsumer {
+        CatalogConsumer {
+            catalogs,
+            context,
+            serials: HashMap::new(),
+            members: HashMap::new(),
+        }
+    }
+
+    /// Syncs the catalogs forever, as often as their SOA refresh says. A single thread spawned
+    /// on startup runs it for all the catalogs, so transfers of members happen one at a time.
+    pub fn run(mut self) {
+        loop {
+            let mut refresh = MAX_REFRESH;
+            for catalog in self.catalogs.clone() {
+                match self.sync(&catalog) {
+                    Ok(catalog_refresh) => refresh = refresh.min(catalog_refresh),
+                    Err(e) => {
+                        warn!("Failed to sync catalog {}: {}", catalog.origin, e);
+                        refresh = refresh.min(DEFAULT_REFRESH);
+                    }
+                }
+            }
+
+            thread::sleep(refresh.clamp(MIN_REFRESH, MAX_REFRESH));
+        }
+    }
+
+    /// Syncs the member zones of a catalog, returning when to check it again
+    fn sync(&mut self, catalog: &Catalog) -> Result<Duration, TransferError> {
+        let mut refresh = DEFAULT_REFRESH;
+
+        if let Some(zone) = self.transfer_if_changed(catalog.primary, &catalog.origin)? {
+            refresh = soa_refresh(&zone).unwrap_or(DEFAULT_REFRESH);
+
+            let members = member_zones(&zone);
+            let removed: Vec<String> = self
+                .members
+                .get(&catalog.origin)
+                .into_iter()
+                .flatten()
+                .filter(|origin| !members.contains(origin))
+                .cloned()
+                .collect();
+
+            for origin in removed {
+                info!("Zone {} removed from catalog {}", origin, catalog.origin);
+                self.context.authority.write().unwrap().remove_zone(&origin);
+                self.serials.remove(&origin);
+            }
+
+            self.members.insert(catalog.origin.clone(), members);
+        }
+
+        let members = self
+            .members
+            .get(&catalog.origin)
+            .cloned()
+            .unwrap_or_default();
+        for origin in members {
+            match self.transfer_if_changed(catalog.primary, &origin) {
+                Ok(Some(zone)) => {
+                    info!(
+                        "Transferred zone {} with {} records",
+                        origin,
+                        zone.records.len()
+                    );
+                    self.context.authority.write().unwrap().insert_zone(zone);
+                }
+                Ok(None) => {}
+                // A broken member zone doesn't prevent the others from being served.
+                Err(e) => warn!("Failed to transfer zone {}: {}", origin, e),
+            }
+        }
+
+        Ok(refresh)
+    }
+
+    /// Transfers a zone if its serial changed since it was last transferred, incrementally
+    /// when a version of it is already served
+    fn transfer_if_changed(
+        &mut self,
+        primary: SocketAddr,
+        origin: &str,
+    ) -> Result<Option<Zone>, TransferError> {
+        let serial = query_serial(primary, origin)?;
+        if self.serials.get(origin) == Some(&serial) {
+            return Ok(None);
+        }
+
+        let current = self
+            .context
+            .authority
+            .read()
+            .unwrap()
+            .zones
+            .iter()
+            .find(|zone| zone.origin == origin)
+            .cloned();
+        let zone = match current {
+            Some(current) => ixfr(primary, &current).or_else(|e| {
+                warn!("Incremental transfer of zone {} failed: {}", origin, e);
+                axfr(primary, origin)
+            })?,
+            None => axfr(primary, origin)?,
+        };
+        self.serials.insert(origin.to_string(), serial);
+
+        Ok(Some(zone))
+    }
+}
+
+/// Returns the refresh interval of the SOA record of a zone
+fn soa_refresh(zone: &Zone) -> Option<Duration> {
+    zone.records.iter().find_map(|record| match record {
+        DnsRecord::SOA { refresh, .. } => Some(Duration::from_secs(u64::from(*refresh))),
+        _ => None,
+    })
+}
@@ -0,0 +1,154 @@
+use std::fmt;
+use std::io::{self, ErrorKind};
+use std::str::FromStr;
+use std::sync::OnceLock;
+use std::thread;
+use std::time::Duration;
+
+use log::warn;
+use rand::Rng;
+
+/// Faults injected into the upstream responses, set once on startup
+static CHAOS: OnceLock<Chaos> = OnceLock::new();
+
+/// Faults that can be injected into an upstream response
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fault {
+    /// The response arrives late
+    Delay,
+    /// The response never arrives, and the query times out
+    Drop,
+    /// Random bytes of the response are flipped
+    Corrupt,
+}
+
+impl Fault {
+    pub const ALL: [Fault; 3] = [Fault::Delay, Fault::Drop, Fault::Corrupt];
+}
+
+impl FromStr for Fault {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Fault, String> {
+        match value.to_ascii_lowercase().as_str() {
+            "delay" => Ok(Fault::Delay),
+            "drop" => Ok(Fault::Drop),
+            "corrupt" => Ok(Fault::Corrupt),
+            _ => Err(format!(
+                "expected delay, drop or corrupt, got \"{}\"",
+                value
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fault::Delay => write!(f, "delay"),
+            Fault::Drop => write!(f, "drop"),
+            Fault::Corrupt => write!(f, "corrupt"),
+        }
+    }
+}
+
+/// The `Chaos` struct injects faults into a fraction of the responses of upstream servers,
+/// as if the network or the servers misbehaved, so that operators can see how clients and
+/// dashboards behave when resolution degrades. It's only built with the `chaos` feature,
+/// and never meant for production.
+#[derive(Clone, Debug)]
+pub struct Chaos {
+    /// Fraction of the responses a fault is injected into, between 0 and 1
+    pub fraction: f64,
+    /// Faults to pick from, at random
+    pub faults: Vec<Fault>,
+    /// How late delayed responses arrive
+    pub delay: Duration,
+}
+
+impl Chaos {
+    /// Picks the fault to inject into a response, if any
+    fn pick(&self) -> Option<Fault> {
+        let mut rng = rand::thread_rng();
+        if self.faults.is_empty() || !rng.gen_bool(self.fraction.clamp(0.0, 1.0)) {
+            return None;
+        }
+        Some(self.faults[rng.gen_range(0..self.faults.len())])
+    }
+}
+
+impl fmt::Display for Chaos {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let faults: Vec<String> = self.faults.iter().map(Fault::to_string).collect();
+        write!(
+            f,
+            "{} of the upstream responses faulted ({}), delayed by {}ms",
+            self.fraction,
+            faults.join(", "),
+            self.delay.as_millis()
+        )
+    }
+}
+
+/// Sets the faults to inject. Only the first call has an effect.
+pub fn init(chaos: Chaos) {
+    let _ = CHAOS.set(chaos);
+}
+
+/// Injects a fault into the response of an upstream server, if one is picked: delayed
+/// responses are returned late, corrupted ones have random bytes flipped, and dropped ones
+/// fail with a timeout once `timeout` has passed.
+pub fn inject(response: &mut [u8], timeout: Duration) -> io::Result<()> {
+    let Some((chaos, fault)) = CHAOS.get().and_then(|chaos| Some((chaos, chaos.pick()?))) else {
+        return Ok(());
+    };
+    warn!("Chaos: injecting {} into an upstream response", fault);
+
+    match fault {
+        Fault::Delay => thread::sleep(chaos.delay),
+        Fault::Drop => {
+            thread::sleep(timeout);
+            return Err(io::Error::new(ErrorKind::TimedOut, "response dropped"));
+        }
+        Fault::Corrupt => corrupt(response),
+    }
+    Ok(())
+}
+
+/// Flips a few random bytes of a message
+fn corrupt(message: &mut [u8]) {
+    if message.is_empty() {
+        return;
+    }
+    let mut rng = rand::thread_rng();
+    let count = rng.gen_range(1..=4).min(message.len());
+    for i in rand::seq::index::sample(&mut rng, message.len(), count) {
+        message[i] ^= rng.gen_range(1..=u8::MAX);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_faults_for_a_fraction_of_responses() {
+        let chaos = Chaos {
+            fraction: 0.5,
+            faults: vec![Fault::Corrupt],
+            delay: Duration::ZERO,
+        };
+        let picked = (0..1000).filter_map(|_| chaos.pick()).count();
+        assert!((350..650).contains(&picked));
+
+        let never = Chaos {
+            fraction: 0.0,
+            ..chaos
+        };
+        assert_eq!((0..100).filter_map(|_| never.pick()).count(), 0);
+
+        let mut message = [0; 12];
+        corrupt(&mut message);
+        assert_ne!(message, [0; 12]);
+    }
+}
@@ -0,0 +1,368 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::{SocketAddr, SocketAddrV4};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::alert::Webhook;
+#[cfg(feature = "catalog")]
+use crate::catalog::Catalog;
+#[cfg(feature = "chaos")]
+use crate::chaos::Chaos;
+use crate::filter::AddressFilter;
+use crate::inflight::LimitAction;
+use crate::listener::Listener;
+use crate::mode::Maintenance;
+use crate::opcode::{Dispatcher, NotifyHandler, Opcode};
+use crate::policy::BlockedTtl;
+use crate::privacy::Profile;
+use crate::retry::RetryPolicy;
+use crate::subnet::{Subnet, SubnetMap};
+use crate::transfer::{TransferLimits, ZoneTransfers};
+
+/// The `Config` struct holds the runtime configuration of the server, once resolved from the
+/// command line (units converted, defaults applied), so that the rest of the server and the
+/// configuration report read from a single place.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Sockets the DNS server receives queries on
+    pub listeners: Vec<Listener>,
+    /// Address clients reach the server at through a port mapping, if any
+    pub advertise: Option<SocketAddr>,
+    /// Port of the admin API on localhost, if enabled
+    #[cfg(feature = "admin")]
+    pub admin_port: Option<u16>,
+    pub search: Vec<String>,
+    /// UDP payload size advertised to upstream servers
+    pub edns_size: u16,
+    /// Maximum size of UDP responses, by client subnet
+    pub max_udp_size: SubnetMap<u16>,
+    /// Address records withheld from the answers, by client subnet
+    pub address_filter: SubnetMap<AddressFilter>,
+    pub flatten_cnames: bool,
+    /// Origins and paths of the hosted zones
+    pub zones: Vec<(String, PathBuf)>,
+    #[cfg(feature = "catalog")]
+    pub catalogs: Vec<Catalog>,
+    pub hosts: Option<PathBuf>,
+    /// Origins and paths of the response policy zones, in order of precedence
+    pub rpz: Vec<(String, PathBuf)>,
+    /// Origins and primaries of the response policy zones transferred with AXFR, which
+    /// apply after those loaded from files
+    pub rpz_primaries: Vec<(String, SocketAddr)>,
+    pub allowlist: Option<PathBuf>,
+    pub blocked_ttl: BlockedTtl,
+    pub warmup: Option<PathBuf>,
+    pub transfer_limits: TransferLimits,
+    /// Transfer settings of the hosted zones that have some, by origin
+    pub zone_transfers: BTreeMap<String, ZoneTransfers>,
+    /// Primaries allowed to send NOTIFY messages, none by default
+    pub allow_notify: Vec<Subnet>,
+    pub always_recurse: bool,
+    /// Forward the reverse lookups of private addresses rather than answering them locally
+    pub forward_private_reverse: bool,
+    /// Maximum time failed questions are answered SERVFAIL without recursing
+    pub servfail_hold_down: Duration,
+    /// How unanswered queries to the upstream servers are retried
+    pub retry: RetryPolicy,
+    /// Root server resolutions start from, and port of all the upstream servers, if not
+    /// the actual root servers
+    pub root_server: Option<SocketAddrV4>,
+    /// Maximum number of resolutions in progress for the same client, unlimited if zero
+    pub max_resolutions_per_client: usize,
+    /// Maximum number of resolutions in progress overall, unlimited if zero
+    pub max_resolutions: usize,
+    /// What to do with queries beyond the resolution limits
+    pub resolution_limit_action: LimitAction,
+    /// Ratio of NXDOMAIN answers under a domain above which it is mitigated, disabled if zero
+    pub nxdomain_storm_ratio: f64,
+    /// Resolutions under a domain in a window below which it is never mitigated
+    pub nxdomain_storm_min: u32,
+    pub maintenance: Maintenance,
+    pub read_only: bool,
+    /// Names whose queries are answered even in maintenance mode
+    pub health_checks: Vec<String>,
+    pub audit: bool,
+    /// Time the stages of answering queries, for the statistics
+    pub profile_stages: bool,
+    /// Code of the EDNS option asking for the origin of the answer, if enabled
+    pub debug_option: Option<u16>,
+    /// Faults injected into the upstream responses, if any
+    #[cfg(feature = "chaos")]
+    pub chaos: Option<Chaos>,
+    pub infra_cache: Option<PathBuf>,
+    /// Leases file of the DHCP server, mapping client addresses to devices
+    pub dhcp_leases: Option<PathBuf>,
+    pub anonymize_clients: bool,
+    pub hash_names: bool,
+    /// How much the queries sent upstream reveal
+    pub privacy: Profile,
+    /// Maximum memory used by the cache and the other data, in bytes
+    pub memory_limit: Option<usize>,
+    pub alert_command: Option<String>,
+    pub alert_webhook: Option<Webhook>,
+    pub alert_servfail_rate: Option<f64>,
+}
+
+impl Config {
+    /// Builds the dispatcher routing the messages other than standard queries to the handlers
+    /// of their opcode
+    pub fn dispatcher(&self) -> Dispatcher {
+        Dispatcher::new()
+            .with_handler(Opcode::Notify, NotifyHandler)
+            .with_allowed(Opcode::Notify, self.allow_notify.clone())
+    }
+
+    /// Summarizes the subsystems and their settings, as (subsystem, summary) pairs
+    pub fn report(&self) -> Vec<(&'static str, String)> {
+        let mut report = Vec::new();
+
+        let mut transports: Vec<String> = self.listeners.iter().map(Listener::to_string).collect();
+        if let Some(advertised) = self.advertise {
+            transports.push(format!("advertised as {}", advertised));
+        }
+        #[cfg(feature = "admin")]
+        if let Some(port) = self.admin_port {
+            transports.push(format!("admin API on 127.0.0.1:{}", port));
+        }
+        report.push(("Transports", list(transports)));
+        report.push((
+            "UDP",
+            format!(
+                "responses up to {} bytes{}, EDNS size {}",
+                self.max_udp_size.default,
+                subnets(&self.max_udp_size.subnets),
+                self.edns_size
+            ),
+        ));
+
+        let mut resolution = match self.root_server {
+            Some(root) => format!("recursive from the root server {}", root),
+            None => "recursive from the root servers".to_string(),
+        };
+        if self.always_recurse {
+            resolution.push_str(", also for queries without RD");
+        }
+        if self.flatten_cnames {
+            resolution.push_str(", CNAME chains flattened");
+        }
+        resolution.push_str(&format!(", upstream queries retried with {}", self.retry));
+        match self.servfail_hold_down.as_secs() {
+            0 => resolution.push_str(", failures not held down"),
+            secs => resolution.push_str(&format!(", failures held down up to {}s", secs)),
+        }
+        if self.forward_private_reverse {
+            resolution.push_str(", private reverse lookups forwarded");
+        }
+        let mut limits = Vec::new();
+        if self.max_resolutions_per_client > 0 {
+            limits.push(format!("{} per client", self.max_resolutions_per_client));
+        }
+        if self.max_resolutions > 0 {
+            limits.push(format!("{} overall", self.max_resolutions));
+        }
+        if !limits.is_empty() {
+            resolution.push_str(&format!(
+                ", resolutions limited to {} ({} the others)",
+                limits.join(" and "),
+                self.resolution_limit_action
+            ));
+        }
+        if self.nxdomain_storm_ratio > 0.0 {
+            resolution.push_str(&format!(
+                ", NXDOMAIN storms mitigated (over {:.0}% of {}+ resolutions in 10s)",
+                self.nxdomain_storm_ratio * 100.0,
+                self.nxdomain_storm_min
+            ));
+        }
+        if !self.search.is_empty() {
+            resolution.push_str(&format!(", search suffixes {}", self.search.join(", ")));
+        }
+        if self.audit {
+            resolution.push_str(", audit mode");
+        }
+        if let Some(code) = self.debug_option {
+            resolution.push_str(&format!(
+                ", origin of answers described on EDNS option {}",
+                code
+            ));
+        }
+        report.push(("Resolution", resolution));
+        report.push(("DNSSEC", "records relayed, not validated".to_string()));
+        report.push((
+            "Modes",
+            format!(
+                "maintenance {}, read-only {}, health checks {}",
+                self.maintenance,
+                if self.read_only { "on" } else { "off" },
+                list(self.health_checks.clone())
+            ),
+        ));
+        report.push((
+            "Address filter",
+            format!(
+                "{}{}",
+                self.address_filter.default,
+                subnets(&self.address_filter.subnets)
+            ),
+        ));
+
+        let mut cache = match self.memory_limit {
+            Some(limit) => format!("limited to {} MiB", limit / (1024 * 1024)),
+            None => "unlimited".to_string(),
+        };
+        if let Some(path) = &self.warmup {
+            cache.push_str(&format!(", warmed up from {}", path.display()));
+        }
+        if let Some(path) = &self.infra_cache {
+            cache.push_str(&format!(
+                ", infrastructure cache saved to {}",
+                path.display()
+            ));
+        }
+        report.push(("Cache", cache));
+
+        let mut zones: Vec<String> = self
+            .zones
+            .iter()
+            .map(|(origin, path)| format!("{} ({})", origin, path.display()))
+            .collect();
+        #[cfg(feature = "catalog")]
+        zones.extend(
+            self.catalogs
+                .iter()
+                .map(|catalog| format!("catalog {} from {}", catalog.origin, catalog.primary)),
+        );
+        if let Some(path) = &self.hosts {
+            zones.push(format!("hosts file {}", path.display()));
+        }
+        report.push(("Zones", list(zones)));
+
+        let transfer = &self.transfer_limits;
+        let rate = match transfer.rate {
+            Some(rate) => format!("{} KiB/s", rate / 1024),
+            None => "unlimited rate".to_string(),
+        };
+        let mut transfers = format!(
+            "{} at a time, {} per client, {}, up to {} MiB",
+            transfer.max_transfers,
+            transfer.max_transfers_per_client,
+            rate,
+            transfer.max_size / (1024 * 1024)
+        );
+        for (origin, zone) in &self.zone_transfers {
+            let mut settings = Vec::new();
+            if !zone.allow_transfer.is_empty() {
+                let allow: Vec<String> = zone
+                    .allow_transfer
+                    .iter()
+                    .map(|subnet| format!("{}/{}", subnet.addr, subnet.prefix))
+                    .collect();
+                settings.push(format!("allowed to {}", allow.join(" ")));
+            }
+            if !zone.also_notify.is_empty() {
+                let notify: Vec<String> =
+                    zone.also_notify.iter().map(ToString::to_string).collect();
+                settings.push(format!("notifying {}", notify.join(" ")));
+            }
+            transfers.push_str(&format!(", {} {}", origin, settings.join(" and ")));
+        }
+        report.push(("Transfers", transfers));
+        report.push(("Opcodes", self.dispatcher().describe()));
+
+        let mut policies: Vec<String> = self
+            .rpz
+            .iter()
+            .map(|(origin, path)| format!("RPZ {} ({})", origin, path.display()))
+            .chain(
+                self.rpz_primaries
+                    .iter()
+                    .map(|(origin, primary)| format!("RPZ {} (from {})", origin, primary)),
+            )
+            .collect();
+        if let Some(path) = &self.allowlist {
+            policies.push(format!("allowlist {}", path.display()));
+        }
+        if !policies.is_empty() {
+            policies.push(format!(
+                "blocked answers cached {}s",
+                self.blocked_ttl.default
+            ));
+        }
+        report.push(("Policies", list(policies)));
+
+        let clients = match &self.dhcp_leases {
+            Some(path) => format!("identified from the DHCP leases in {}", path.display()),
+            None => "identified by address".to_string(),
+        };
+        report.push(("Clients", clients));
+
+        let mut privacy = Vec::new();
+        if self.anonymize_clients {
+            privacy.push("client addresses anonymized".to_string());
+        }
+        if self.hash_names {
+            privacy.push("names hashed".to_string());
+        }
+        if self.privacy == Profile::Strict {
+            privacy.push("upstream queries minimized".to_string());
+        }
+        report.push(("Privacy", list(privacy)));
+        report.push((
+            "Profiling",
+            if self.profile_stages {
+                "stages timed".to_string()
+            } else {
+                "off".to_string()
+            },
+        ));
+        #[cfg(feature = "chaos")]
+        report.push((
+            "Chaos",
+            match &self.chaos {
+                Some(chaos) => chaos.to_string(),
+                None => "off".to_string(),
+            },
+        ));
+
+        let mut alerts = Vec::new();
+        if let Some(command) = &self.alert_command {
+            alerts.push(format!("command {}", command));
+        }
+        if let Some(webhook) = &self.alert_webhook {
+            alerts.push(format!("webhook {}", webhook));
+        }
+        if let Some(rate) = self.alert_servfail_rate {
+            alerts.push(format!("SERVFAIL rate above {}", rate));
+        }
+        report.push(("Alerts", list(alerts)));
+
+        report
+    }
+}
+
+impl fmt::Display for Config {
+    /// Writes the report, one subsystem per line
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (subsystem, summary) in self.report() {
+            writeln!(f, "{}: {}", subsystem, summary)?;
+        }
+        Ok(())
+    }
+}
+
+/// Joins the items of a report line, or says there are none
+fn list(items: Vec<String>) -> String {
+    match items.is_empty() {
+        true => "none".to_string(),
+        false => items.join(", "),
+    }
+}
+
+/// Describes the per-subnet overrides of a setting
+fn subnets<T: fmt::Display>(overrides: &[(Subnet, T)]) -> String {
+    overrides
+        .iter()
+        .map(|(subnet, value)| format!(" ({} for {}/{})", value, subnet.addr, subnet.prefix))
+        .collect()
+}
@@ -0,0 +1,193 @@
+use crate::packet::DnsPacket;
+use crate::question::QueryType;
+use crate::record::DnsRecord;
+
+/// What to do with a message received as a query, before trying to answer it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    /// The query is well formed and can be answered
+    Accept,
+    /// The message must not be answered, for the given reason
+    Ignore(&'static str),
+    /// The query is malformed and must be answered with FORMERR, for the given reason
+    FormErr(&'static str),
+}
+
+/// Checks that a message is a query that can be answered, following RFC 1035 and RFC 6891:
+/// - responses (QR bit set) are never answered, so that two servers can't loop on each other;
+/// - exactly one question is expected, as no server supports more
+///   (see https://datatracker.ietf.org/doc/html/rfc9619);
+/// - queries carry no answer or authority records, except for the SOA record of IXFR queries
+///   (see https://datatracker.ietf.org/doc/html/rfc1995#section-3);
+/// - there is at most one OPT record
+///   (see https://datatracker.ietf.org/doc/html/rfc6891#section-6.1.1).
+///
+/// The Z bit is ignored: it is reserved, and responses never set it.
+pub fn check(request: &DnsPacket) -> Verdict {
+    if request.header.response {
+        return Verdict::Ignore("QR bit set in a query");
+    }
+
+    match request.questions.len() {
+        0 => return Verdict::FormErr("no question"),
+        1 => {}
+        _ => return Verdict::FormErr("more than one question"),
+    }
+
+    let ixfr = request.questions[0].qtype == QueryType::IXFR;
+    let expected_authorities = usize::from(ixfr);
+    if !request.answers.is_empty() || request.authorities.len() != expected_authorities {
+        return Verdict::FormErr("records in the answer or authority section");
+    }
+    if ixfr && request.authorities[0].qtype() != QueryType::SOA {
+        return Verdict::FormErr("IXFR query without an SOA record");
+    }
+
+    let opt_records = request
+        .resources
+        .iter()
+        .filter(|record| matches!(record, DnsRecord::OPT { .. }))
+        .count();
+    if opt_records > 1 {
+        return Verdict::FormErr("more than one OPT record");
+    }
+
+    Verdict::Accept
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::question::DnsQuestion;
+
+    /// Header of a query with the given flags and section counts
+    fn header(flags: u16, questions: u16, answers: u16, additional: u16) -> Vec<u8> {
+        let mut bytes = vec![0x12, 0x34];
+        for field in [flags, questions, answers, 0, additional] {
+            bytes.extend_from_slice(&field.to_be_bytes());
+        }
+        bytes
+    }
+
+    /// Question for `example.com` of type A
+    fn question() -> Vec<u8> {
+        let mut bytes = b"\x07example\x03com\x00".to_vec();
+        bytes.extend_from_slice(&[0, 1, 0, 1]);
+        bytes
+    }
+
+    /// A record for `example.com`, with the name compressed to the question
+    fn answer() -> Vec<u8> {
+        vec![0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 127, 0, 0, 1]
+    }
+
+    /// OPT record advertising a payload size of 1232 bytes
+    fn opt() -> Vec<u8> {
+        vec![0, 0, 41, 0x04, 0xd0, 0, 0, 0, 0, 0, 0]
+    }
+
+    fn verdict(parts: &[Vec<u8>]) -> Verdict {
+        check(&DnsPacket::parse(&parts.concat()).unwrap())
+    }
+
+    #[test]
+    fn accepts_standard_query() {
+        assert_eq!(
+            verdict(&[header(0x0100, 1, 0, 0), question()]),
+            Verdict::Accept
+        );
+    }
+
+    #[test]
+    fn accepts_query_with_opt_record() {
+        let query = [header(0x0100, 1, 0, 1), question(), opt()];
+        assert_eq!(verdict(&query), Verdict::Accept);
+    }
+
+    #[test]
+    fn ignores_z_bit() {
+        let query = [header(0x0140, 1, 0, 0), question()];
+        let packet = DnsPacket::parse(&query.concat()).unwrap();
+
+        assert!(packet.header.z);
+        assert_eq!(check(&packet), Verdict::Accept);
+    }
+
+    #[test]
+    fn ignores_responses() {
+        let query = [header(0x8100, 1, 0, 0), question()];
+        assert!(matches!(verdict(&query), Verdict::Ignore(_)));
+    }
+
+    #[test]
+    fn rejects_no_question() {
+        assert!(matches!(
+            verdict(&[header(0x0100, 0, 0, 0)]),
+            Verdict::FormErr(_)
+        ));
+    }
+
+    #[test]
+    fn rejects_answers_without_question() {
+        let mut answer = answer();
+        // Without a question to point to, the owner is the root.
+        answer.splice(0..2, [0]);
+
+        let query = [header(0x0100, 0, 1, 0), answer];
+        assert!(matches!(verdict(&query), Verdict::FormErr(_)));
+    }
+
+    #[test]
+    fn rejects_answers_in_query() {
+        let query = [header(0x0100, 1, 1, 0), question(), answer()];
+        assert!(matches!(verdict(&query), Verdict::FormErr(_)));
+    }
+
+    #[test]
+    fn rejects_multiple_questions() {
+        let query = [header(0x0100, 2, 0, 0), question(), question()];
+        assert!(matches!(verdict(&query), Verdict::FormErr(_)));
+    }
+
+    #[test]
+    fn rejects_multiple_opt_records() {
+        let query = [header(0x0100, 1, 0, 2), question(), opt(), opt()];
+        assert!(matches!(verdict(&query), Verdict::FormErr(_)));
+    }
+
+    /// IXFR query for `example.com`, with the given record in the authority section
+    fn ixfr(authority: DnsRecord) -> DnsPacket {
+        let mut packet = DnsPacket::new();
+        packet
+            .questions
+            .push(DnsQuestion::new("example.com".to_string(), QueryType::IXFR));
+        packet.authorities.push(authority);
+        packet
+    }
+
+    #[test]
+    fn accepts_ixfr_with_soa() {
+        let query = ixfr(DnsRecord::SOA {
+            domain: "example.com".to_string(),
+            mname: "ns.example.com".to_string(),
+            rname: "admin.example.com".to_string(),
+            serial: 1,
+            refresh: 3600,
+            retry: 600,
+            expire: 86400,
+            minimum: 300,
+            ttl: 3600,
+        });
+        assert_eq!(check(&query), Verdict::Accept);
+    }
+
+    #[test]
+    fn rejects_ixfr_without_soa() {
+        let query = ixfr(DnsRecord::A {
+            domain: "example.com".to_string(),
+            addr: "127.0.0.1".parse().unwrap(),
+            ttl: 60,
+        });
+        assert!(matches!(check(&query), Verdict::FormErr(_)));
+    }
+}
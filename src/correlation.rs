@@ -0,0 +1,104 @@
+use std::cell::Cell;
+
+use log::{Log, Metadata, Record};
+
+use crate::verbosity;
+
+thread_local! {
+    /// Correlation ID of the client query the current thread is handling
+    static CURRENT: Cell<Option<u32>> = const { Cell::new(None) };
+}
+
+/// Returns a new correlation ID, for a query received from a client
+pub fn new_id() -> u32 {
+    rand::random()
+}
+
+/// Returns the correlation ID of the client query the current thread is handling, if any
+pub fn current() -> Option<u32> {
+    CURRENT.with(Cell::get)
+}
+
+/// Tags what the current thread does with a correlation ID, until the returned guard is
+/// dropped: the lines it logs through `CorrelatedLogger`, the lookups it sends upstream
+/// and the queries it traces in audit mode. This ties the handling of a client query to
+/// the upstream queries derived from it.
+pub fn enter(id: u32) -> Guard {
+    Guard {
+        previous: CURRENT.with(|current| current.replace(Some(id))),
+    }
+}
+
+/// Restores the previous correlation ID of the thread when dropped
+pub struct Guard {
+    previous: Option<u32>,
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        CURRENT.with(|current| current.set(self.previous));
+    }
+}
+
+/// The `CorrelatedLogger` struct prefixes the lines logged while handling a client query
+/// with its correlation ID, e.g. `[1f3a9c02] Received query for A example.com`, so that the
+/// lines of concurrent queries can be told apart. Lines are only logged at the levels set
+/// at runtime (see `verbosity`).
+pub struct CorrelatedLogger<L> {
+    inner: L,
+}
+
+impl<L: Log> CorrelatedLogger<L> {
+    pub fn new(inner: L) -> CorrelatedLogger<L> {
+        CorrelatedLogger { inner }
+    }
+}
+
+impl<L: Log> Log for CorrelatedLogger<L> {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        verbosity::enabled(metadata) && self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !verbosity::enabled(record.metadata()) {
+            return;
+        }
+        let Some(id) = current() else {
+            self.inner.log(record);
+            return;
+        };
+
+        self.inner.log(
+            &Record::builder()
+                .args(format_args!("[{:08x}] {}", id, record.args()))
+                .metadata(record.metadata().clone())
+                .module_path(record.module_path())
+                .file(record.file())
+                .line(record.line())
+                .build(),
+        );
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restores_the_previous_id() {
+        assert_eq!(current(), None);
+        {
+            let _outer = enter(1);
+            {
+                let _inner = enter(2);
+                assert_eq!(current(), Some(2));
+            }
+            assert_eq!(current(), Some(1));
+        }
+        assert_eq!(current(), None);
+    }
+}
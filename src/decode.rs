@@ -0,0 +1,345 @@
+use crate::packet::DnsPacket;
+use crate::record::DnsRecord;
+
+/// Magic numbers of pcap files, with microsecond and nanosecond timestamps,
+/// see https://datatracker.ietf.org/doc/html/draft-ietf-opsawg-pcap
+const PCAP_MAGIC: [u32; 2] = [0xa1b2_c3d4, 0xa1b2_3c4d];
+/// Length of the header at the start of pcap files
+const PCAP_HEADER_LEN: usize = 24;
+/// Length of the header before each captured packet
+const PCAP_RECORD_LEN: usize = 16;
+
+/// Link types of the supported captures: BSD loopback, Ethernet, raw IP,
+/// Linux cooked capture, raw IPv4 and raw IPv6
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_IPV6: u32 = 229;
+
+/// IP protocol numbers of TCP and UDP
+const PROTOCOL_TCP: u8 = 6;
+const PROTOCOL_UDP: u8 = 17;
+
+/// `DecodeError` is an enum that represents the ways in which a file of DNS messages can be malformed
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    #[error("Invalid hex at \"{0}\"")]
+    InvalidHex(String),
+    #[error("Capture ends in the middle of a packet")]
+    TruncatedCapture,
+    #[error("Unsupported link type {0} in capture")]
+    UnsupportedLinkType(u32),
+}
+
+/// Reads the DNS messages of a file, which is either a pcap capture or the hex dump of
+/// messages separated by blank lines. Comments, from `;` or `#` to the end of the line,
+/// are ignored in hex dumps.
+///
+/// All the UDP and TCP payloads of a capture are taken as DNS messages (after the length
+/// prefix for TCP), so captures are best filtered on the DNS port when they are taken.
+/// Fragmented IP packets, and TCP messages split across segments, are left out.
+pub fn read_messages(bytes: &[u8]) -> Result<Vec<Vec<u8>>, DecodeError> {
+    match bytes
+        .get(..4)
+        .map(|magic| pcap_endianness(magic.try_into().unwrap()))
+    {
+        Some(Some(big_endian)) => read_pcap(bytes, big_endian),
+        _ => read_hex(&String::from_utf8_lossy(bytes)),
+    }
+}
+
+/// Returns whether a pcap file is big endian, from its magic number,
+/// or `None` if the file is not a capture
+fn pcap_endianness(magic: [u8; 4]) -> Option<bool> {
+    if PCAP_MAGIC.contains(&u32::from_be_bytes(magic)) {
+        Some(true)
+    } else if PCAP_MAGIC.contains(&u32::from_le_bytes(magic)) {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+fn read_hex(text: &str) -> Result<Vec<Vec<u8>>, DecodeError> {
+    let mut messages = Vec::new();
+    let mut message = Vec::new();
+
+    for line in text.lines() {
+        let line = line.split([';', '#']).next().unwrap_or_default();
+        if line.trim().is_empty() {
+            if !message.is_empty() {
+                messages.push(std::mem::take(&mut message));
+            }
+            continue;
+        }
+
+        let digits: String = line.split_whitespace().collect();
+        if !digits.len().is_multiple_of(2) {
+            return Err(DecodeError::InvalidHex(line.trim().to_string()));
+        }
+        for pair in digits.as_bytes().chunks(2) {
+            let pair = std::str::from_utf8(pair).unwrap_or_default();
+            let byte = u8::from_str_radix(pair, 16)
+                .map_err(|_| DecodeError::InvalidHex(line.trim().to_string()))?;
+            message.push(byte);
+        }
+    }
+    if !message.is_empty() {
+        messages.push(message);
+    }
+
+    Ok(messages)
+}
+
+fn read_pcap(bytes: &[u8], big_endian: bool) -> Result<Vec<Vec<u8>>, DecodeError> {
+    let u32_at = |pos: usize| -> Result<u32, DecodeError> {
+        let field = bytes
+            .get(pos..pos + 4)
+            .ok_or(DecodeError::TruncatedCapture)?
+            .try_into()
+            .unwrap();
+        Ok(match big_endian {
+            true => u32::from_be_bytes(field),
+            false => u32::from_le_bytes(field),
+        })
+    };
+
+    let link_type = u32_at(20)?;
+    let mut messages = Vec::new();
+    let mut pos = PCAP_HEADER_LEN;
+    while pos < bytes.len() {
+        let len = u32_at(pos + 8)? as usize;
+        let start = pos + PCAP_RECORD_LEN;
+        let frame = bytes
+            .get(start..start + len)
+            .ok_or(DecodeError::TruncatedCapture)?;
+        pos = start + len;
+
+        let ip = match link_type {
+            // The address family is in the byte order of the capturing host: skip it.
+            LINKTYPE_NULL => frame.get(4..),
+            LINKTYPE_ETHERNET => ethernet_payload(frame),
+            LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => Some(frame),
+            LINKTYPE_LINUX_SLL => frame.get(16..),
+            _ => return Err(DecodeError::UnsupportedLinkType(link_type)),
+        };
+        if let Some((protocol, payload)) = ip.and_then(ip_payload) {
+            messages.extend(transport_messages(protocol, payload));
+        }
+    }
+
+    Ok(messages)
+}
+
+/// Returns the IP packet of an Ethernet frame, behind VLAN tags if any
+fn ethernet_payload(frame: &[u8]) -> Option<&[u8]> {
+    let mut pos = 12;
+    while u16_at(frame, pos)? == 0x8100 {
+        pos += 4;
+    }
+    frame.get(pos + 2..)
+}
+
+/// Returns the protocol and the payload of an IPv4 or IPv6 packet, unless it is a fragment
+fn ip_payload(packet: &[u8]) -> Option<(u8, &[u8])> {
+    match packet.first()? >> 4 {
+        4 => {
+            let header_len = ((packet[0] & 0x0F) as usize) * 4;
+            let total_len = u16_at(packet, 2)? as usize;
+            // More fragments flag, and fragment offset
+            if u16_at(packet, 6)? & 0x3FFF != 0 {
+                return None;
+            }
+            Some((*packet.get(9)?, packet.get(header_len..total_len)?))
+        }
+        6 => {
+            let payload_len = u16_at(packet, 4)? as usize;
+            Some((*packet.get(6)?, packet.get(40..40 + payload_len)?))
+        }
+        _ => None,
+    }
+}
+
+/// Returns the DNS messages carried by a UDP datagram or a TCP segment
+fn transport_messages(protocol: u8, segment: &[u8]) -> Vec<Vec<u8>> {
+    match protocol {
+        PROTOCOL_UDP => segment
+            .get(8..)
+            .filter(|payload| !payload.is_empty())
+            .map(|payload| vec![payload.to_vec()])
+            .unwrap_or_default(),
+        PROTOCOL_TCP => {
+            let mut messages = Vec::new();
+            let Some(offset) = segment.get(12).map(|byte| ((byte >> 4) as usize) * 4) else {
+                return messages;
+            };
+            // Each message is prefixed with its length.
+            let mut pos = offset;
+            while let Some(len) = u16_at(segment, pos) {
+                let Some(message) = segment.get(pos + 2..pos + 2 + len as usize) else {
+                    break;
+                };
+                messages.push(message.to_vec());
+                pos += 2 + len as usize;
+            }
+            messages
+        }
+        _ => Vec::new(),
+    }
+}
+
+fn u16_at(bytes: &[u8], pos: usize) -> Option<u16> {
+    let field = bytes.get(pos..pos + 2)?;
+    Some(u16::from_be_bytes([field[0], field[1]]))
+}
+
+/// This function compares two DNS messages field by field, and returns a line for each
+/// difference: header fields first, then the questions and records of each section,
+/// compared in order.
+pub fn diff(a: &DnsPacket, b: &DnsPacket) -> Vec<String> {
+    let mut lines = Vec::new();
+
+    for ((field, a), (_, b)) in header_fields(a).into_iter().zip(header_fields(b)) {
+        if a != b {
+            lines.push(format!("header {}: {} != {}", field, a, b));
+        }
+    }
+
+    let questions = |packet: &DnsPacket| -> Vec<String> {
+        packet
+            .questions
+            .iter()
+            .map(|question| format!("{:?}", question))
+            .collect()
+    };
+    diff_section(&mut lines, "question", &questions(a), &questions(b));
+
+    let records = |records: &[DnsRecord]| -> Vec<String> {
+        records
+            .iter()
+            .map(|record| format!("{:?}", record))
+            .collect()
+    };
+    let sections = [
+        ("answer", &a.answers, &b.answers),
+        ("authority", &a.authorities, &b.authorities),
+        ("additional", &a.resources, &b.resources),
+    ];
+    for (section, a, b) in sections {
+        diff_section(&mut lines, section, &records(a), &records(b));
+    }
+
+    lines
+}
+
+fn header_fields(packet: &DnsPacket) -> [(&'static str, String); 11] {
+    let header = &packet.header;
+    [
+        ("ID", header.id.to_string()),
+        ("QR", header.response.to_string()),
+        ("opcode", header.opcode.to_string()),
+        ("AA", header.authoritative_answer.to_string()),
+        ("TC", header.truncated_message.to_string()),
+        ("RD", header.recursion_desired.to_string()),
+        ("RA", header.recursion_available.to_string()),
+        ("Z", header.z.to_string()),
+        ("AD", header.authed_data.to_string()),
+        ("CD", header.checking_disabled.to_string()),
+        ("rcode", format!("{:?}", header.rescode)),
+    ]
+}
+
+fn diff_section(lines: &mut Vec<String>, section: &str, a: &[String], b: &[String]) {
+    for i in 0..a.len().max(b.len()) {
+        let a = a.get(i).map_or("none", String::as_str);
+        let b = b.get(i).map_or("none", String::as_str);
+        if a != b {
+            lines.push(format!("{} {}: {} != {}", section, i + 1, a, b));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Response for `example.com` of type A, as a hex dump
+    const RESPONSE: &str = "
+        be ef 81 80 00 01 00 01 00 00 00 00 ; header
+        07 65 78 61 6d 70 6c 65 03 63 6f 6d 00 00 01 00 01
+        c0 0c 00 01 00 01 00 00 0e 10 00 04 5d b8 d7 0e
+    ";
+
+    /// Wraps a DNS message into a pcap capture of a single Ethernet frame, over UDP and IPv4
+    fn capture(message: &[u8]) -> Vec<u8> {
+        let udp_len = 8 + message.len() as u16;
+        let ip_len = 20 + udp_len;
+
+        let mut frame = vec![0; 12];
+        frame.extend_from_slice(&[0x08, 0x00]);
+        frame.extend_from_slice(&[0x45, 0, 0, 0, 0, 0, 0x40, 0, 64, PROTOCOL_UDP, 0, 0]);
+        frame[16..18].copy_from_slice(&ip_len.to_be_bytes());
+        frame.extend_from_slice(&[192, 0, 2, 1, 192, 0, 2, 2]);
+        frame.extend_from_slice(&[0, 53, 0xd4, 0x31]);
+        frame.extend_from_slice(&udp_len.to_be_bytes());
+        frame.extend_from_slice(&[0, 0]);
+        frame.extend_from_slice(message);
+
+        let mut bytes = Vec::new();
+        for field in [PCAP_MAGIC[0], 0x0004_0002, 0, 0, 65535, LINKTYPE_ETHERNET] {
+            bytes.extend_from_slice(&field.to_le_bytes());
+        }
+        let len = frame.len() as u32;
+        for field in [0, 0, len, len] {
+            bytes.extend_from_slice(&field.to_le_bytes());
+        }
+        bytes.extend_from_slice(&frame);
+        bytes
+    }
+
+    #[test]
+    fn reads_hex_dumps() {
+        let messages = read_messages(format!("{}\n\n00 01", RESPONSE).as_bytes()).unwrap();
+
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].len(), 45);
+        assert_eq!(messages[1], vec![0, 1]);
+        assert!(matches!(
+            read_messages(b"12 3g"),
+            Err(DecodeError::InvalidHex(_))
+        ));
+    }
+
+    #[test]
+    fn reads_pcap_captures() {
+        let message = read_messages(RESPONSE.as_bytes()).unwrap().remove(0);
+        let capture = capture(&message);
+
+        assert_eq!(read_messages(&capture).unwrap(), vec![message]);
+        assert_eq!(
+            read_messages(&capture[..capture.len() - 1]),
+            Err(DecodeError::TruncatedCapture)
+        );
+    }
+
+    #[test]
+    fn diffs_packets_field_by_field() {
+        let bytes = read_messages(RESPONSE.as_bytes()).unwrap().remove(0);
+        let a = DnsPacket::parse(&bytes).unwrap();
+        let mut b = a.clone();
+
+        assert!(diff(&a, &b).is_empty());
+
+        b.header.truncated_message = true;
+        b.answers.clear();
+        assert_eq!(
+            diff(&a, &b),
+            vec![
+                "header TC: false != true".to_string(),
+                format!("answer 1: {:?} != none", a.answers[0]),
+            ]
+        );
+    }
+}
@@ -0,0 +1,179 @@
+use std::fmt::Write;
+
+use crate::buffer::{Buffer, BufferError};
+use crate::question::{QueryClass, QueryType};
+
+/// Number of bytes shown on each line of a dump
+const BYTES_PER_LINE: usize = 16;
+
+/// Flags of the second header field, with their names
+const FLAGS: [(u16, &str); 7] = [
+    (0x8000, "QR"),
+    (0x0400, "AA"),
+    (0x0200, "TC"),
+    (0x0100, "RD"),
+    (0x0080, "RA"),
+    (0x0020, "AD"),
+    (0x0010, "CD"),
+];
+
+/// This function takes the bytes of a DNS message as input, and returns an annotated hex
+/// dump of it: each field is shown at its offset, next to what it decodes to (header
+/// fields, questions and the boundaries of records). Decoding is lenient and stops at the
+/// first malformed field, which is pointed out, followed by the remaining bytes.
+pub fn dump(bytes: &[u8]) -> String {
+    let mut dump = Dump {
+        buffer: Buffer::from_bytes(bytes),
+        done: 0,
+        out: String::new(),
+    };
+
+    if let Err(e) = dump.message() {
+        let _ = writeln!(dump.out, "; {} at offset {}", e, dump.done);
+    }
+    if dump.done < bytes.len() {
+        dump.emit(bytes.len(), "not decoded");
+    }
+
+    dump.out
+}
+
+struct Dump {
+    buffer: Buffer,
+    /// Offset of the first byte not dumped yet
+    done: usize,
+    out: String,
+}
+
+impl Dump {
+    fn message(&mut self) -> Result<(), BufferError> {
+        let id = self.buffer.read_u16()?;
+        let flags = self.buffer.read_u16()?;
+        let mut names: Vec<&str> = FLAGS
+            .iter()
+            .filter(|(bit, _)| flags & bit != 0)
+            .map(|(_, name)| *name)
+            .collect();
+        if names.is_empty() {
+            names.push("none");
+        }
+        self.emit(
+            self.buffer.pos,
+            &format!(
+                "ID {}, flags {}, opcode {}, rcode {}",
+                id,
+                names.join(" "),
+                (flags >> 11) & 0xF,
+                flags & 0xF
+            ),
+        );
+
+        let mut counts = [0; 4];
+        for count in &mut counts {
+            *count = self.buffer.read_u16()?;
+        }
+        self.emit(
+            self.buffer.pos,
+            &format!(
+                "{} questions, {} answers, {} authorities, {} additional",
+                counts[0], counts[1], counts[2], counts[3]
+            ),
+        );
+
+        for _ in 0..counts[0] {
+            let name = self.name()?;
+            let qtype = QueryType::from_num(self.buffer.read_u16()?);
+            let qclass = QueryClass::from_num(self.buffer.read_u16()?);
+            self.emit(
+                self.buffer.pos,
+                &format!("question {:?} {:?} {}", qclass, qtype, name),
+            );
+        }
+
+        let sections = ["answer", "authority", "additional"];
+        for (section, count) in sections.iter().zip(&counts[1..]) {
+            for _ in 0..*count {
+                let name = self.name()?;
+                let qtype = QueryType::from_num(self.buffer.read_u16()?);
+                let class = self.buffer.read_u16()?;
+                let ttl = self.buffer.read_u32()?;
+                let len = self.buffer.read_u16()? as usize;
+                self.emit(
+                    self.buffer.pos,
+                    &format!(
+                        "{} {:?} {}, class {}, TTL {}, {} bytes of data",
+                        section, qtype, name, class, ttl, len
+                    ),
+                );
+
+                self.buffer.get_range(self.buffer.pos, len)?;
+                self.buffer.step(len)?;
+                self.emit(self.buffer.pos, "data");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads a name, and dumps it on its own line
+    fn name(&mut self) -> Result<String, BufferError> {
+        let mut name = String::new();
+        self.buffer.read_qname(&mut name)?;
+        if name.is_empty() {
+            name.push('.');
+        }
+        self.emit(self.buffer.pos, "name");
+        Ok(name)
+    }
+
+    /// Dumps the bytes up to `end` with a comment, which is only written on the first line
+    /// when they span several
+    fn emit(&mut self, end: usize, comment: &str) {
+        let bytes = &self.buffer.buf[self.done..end];
+        if bytes.is_empty() {
+            return;
+        }
+
+        for (i, line) in bytes.chunks(BYTES_PER_LINE).enumerate() {
+            let hex: Vec<String> = line.iter().map(|byte| format!("{:02x}", byte)).collect();
+            let _ = write!(
+                self.out,
+                "{:04x}  {:<width$}",
+                self.done + i * BYTES_PER_LINE,
+                hex.join(" "),
+                width = BYTES_PER_LINE * 3 - 1
+            );
+            match i {
+                0 => {
+                    let _ = writeln!(self.out, "  ; {}", comment);
+                }
+                _ => self.out.push('\n'),
+            }
+        }
+        self.done = end;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn annotates_fields_and_points_out_malformed_ones() {
+        let mut query = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 1];
+        query.extend_from_slice(b"\x07example\x03com\x00\x00\x01\x00\x01");
+        // The additional record claims more data than the message holds.
+        query.extend_from_slice(&[0, 0, 41, 0x04, 0xd0, 0, 0, 0, 0, 0, 8, 0xaa]);
+
+        let dump = dump(&query);
+        let lines: Vec<&str> = dump.lines().collect();
+
+        assert!(lines[0].ends_with("; ID 4660, flags RD, opcode 0, rcode 0"));
+        assert!(lines[2].ends_with("; name"));
+        assert!(lines[3].ends_with("; question IN A example.com"));
+        assert!(lines[5].ends_with("; additional OPT ., class 1232, TTL 0, 8 bytes of data"));
+        assert!(lines[6].starts_with("; End of buffer at offset"));
+        assert!(lines[7].starts_with("0028  aa"));
+        assert!(lines[7].ends_with("; not decoded"));
+    }
+}
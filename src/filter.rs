@@ -0,0 +1,207 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::handler::local_soa;
+use crate::packet::DnsPacket;
+use crate::question::QueryType;
+use crate::record::DnsRecord;
+
+/// Address records withheld from the answers to some clients, to work around applications
+/// that try the other address family first and hang on networks lacking it (like BIND's
+/// `filter-aaaa-on-v4`)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AddressFilter {
+    /// Answer with all the address records
+    #[default]
+    None,
+    /// Withhold AAAA records, for clients on IPv4-only networks
+    Aaaa,
+    /// Withhold A records, for clients on IPv6-only networks
+    A,
+}
+
+impl AddressFilter {
+    /// Removes the filtered address records from a response, along with their signatures,
+    /// for the names that have addresses of the other family: those in the response, or
+    /// those `has_records` finds locally. Names with only filtered addresses keep them, as
+    /// the client couldn't reach them otherwise. Queries for the filtered type get an empty
+    /// answer (NODATA), as if the name had no such records.
+    pub fn apply(
+        self,
+        packet: &mut DnsPacket,
+        qtype: QueryType,
+        has_records: impl Fn(&str, QueryType) -> bool,
+    ) {
+        let (filtered, other) = match self {
+            AddressFilter::None => return,
+            AddressFilter::Aaaa => (QueryType::AAAA, QueryType::A),
+            AddressFilter::A => (QueryType::A, QueryType::AAAA),
+        };
+
+        let records = || packet.answers.iter().chain(packet.resources.iter());
+        let mut names: Vec<String> = Vec::new();
+        for record in records().filter(|record| record.qtype() == filtered) {
+            let Some(domain) = record.domain() else {
+                continue;
+            };
+            if names.iter().any(|name| name.eq_ignore_ascii_case(domain)) {
+                continue;
+            }
+            let has_other = records().any(|record| {
+                record.qtype() == other
+                    && record
+                        .domain()
+                        .is_some_and(|name| name.eq_ignore_ascii_case(domain))
+            });
+            if has_other || has_records(domain, other) {
+                names.push(domain.to_string());
+            }
+        }
+        if names.is_empty() {
+            return;
+        }
+
+        let withheld = |record: &DnsRecord| {
+            let covered = match record {
+                DnsRecord::RRSIG { type_covered, .. } => QueryType::from_num(*type_covered),
+                record => record.qtype(),
+            };
+            covered == filtered
+                && record.domain().is_some_and(|domain| {
+                    names.iter().any(|name| name.eq_ignore_ascii_case(domain))
+                })
+        };
+        // The owner of the withheld answers, and how long they could have been cached
+        let answered = packet
+            .answers
+            .iter()
+            .filter(|record| withheld(record))
+            .filter_map(|record| Some((record.domain()?.to_string(), record.ttl())))
+            .min_by_key(|(_, ttl)| *ttl);
+        packet.answers.retain(|record| !withheld(record));
+        packet.resources.retain(|record| !withheld(record));
+
+        // The empty answer is negative, and cached by clients for no longer than the records.
+        let nodata = qtype == filtered && !packet.answers.iter().any(|r| r.qtype() == filtered);
+        if let Some((owner, ttl)) = answered.filter(|_| nodata) {
+            packet
+                .authorities
+                .retain(|record| !matches!(record, DnsRecord::SOA { .. }));
+            packet.authorities.push(local_soa(&owner, ttl));
+        }
+    }
+}
+
+impl FromStr for AddressFilter {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<AddressFilter, String> {
+        match value {
+            "none" => Ok(AddressFilter::None),
+            "aaaa" => Ok(AddressFilter::Aaaa),
+            "a" => Ok(AddressFilter::A),
+            _ => Err(format!("expected none, aaaa or a, got \"{}\"", value)),
+        }
+    }
+}
+
+impl fmt::Display for AddressFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AddressFilter::None => write!(f, "none"),
+            AddressFilter::Aaaa => write!(f, "aaaa"),
+            AddressFilter::A => write!(f, "a"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resultcode::ResultCode;
+    use smallvec::smallvec;
+
+    fn a() -> DnsRecord {
+        DnsRecord::A {
+            domain: "example.com".to_string(),
+            addr: "192.0.2.1".parse().unwrap(),
+            ttl: 300,
+        }
+    }
+
+    fn aaaa() -> DnsRecord {
+        DnsRecord::AAAA {
+            domain: "example.com".to_string(),
+            addr: "2001:db8::1".parse().unwrap(),
+            ttl: 120,
+        }
+    }
+
+    fn rrsig(type_covered: QueryType) -> DnsRecord {
+        DnsRecord::RRSIG {
+            domain: "example.com".to_string(),
+            type_covered: type_covered.to_num(),
+            algorithm: 13,
+            labels: 2,
+            original_ttl: 300,
+            expiration: 0,
+            inception: 0,
+            key_tag: 1,
+            signer: "example.com".to_string(),
+            signature: vec![0; 64],
+            ttl: 300,
+        }
+    }
+
+    #[test]
+    fn withholds_filtered_records_only() {
+        let mut packet = DnsPacket::new();
+        packet.answers = smallvec![a(), aaaa()];
+
+        let mut filtered = packet.clone();
+        AddressFilter::Aaaa.apply(&mut filtered, QueryType::A, |_, _| false);
+        assert_eq!(filtered.answers[..], packet.answers[..1]);
+
+        AddressFilter::A.apply(&mut packet, QueryType::AAAA, |_, _| false);
+        assert_eq!(packet.answers[..], [aaaa()]);
+    }
+
+    #[test]
+    fn answers_nodata_with_a_soa_when_the_other_family_exists() {
+        let mut packet = DnsPacket::new();
+        packet.answers = smallvec![aaaa(), rrsig(QueryType::AAAA)];
+
+        AddressFilter::Aaaa.apply(&mut packet, QueryType::AAAA, |name, qtype| {
+            name == "example.com" && qtype == QueryType::A
+        });
+        assert!(packet.answers.is_empty());
+        assert_eq!(packet.header.rescode, ResultCode::NOERROR);
+        assert!(matches!(
+            packet.authorities[..],
+            [DnsRecord::SOA { ref domain, minimum: 120, .. }] if domain == "example.com"
+        ));
+    }
+
+    #[test]
+    fn keeps_addresses_of_names_without_the_other_family() {
+        let mut packet = DnsPacket::new();
+        packet.answers = smallvec![aaaa(), rrsig(QueryType::AAAA)];
+        let answers = packet.answers.clone();
+
+        AddressFilter::Aaaa.apply(&mut packet, QueryType::AAAA, |_, _| false);
+        assert_eq!(packet.answers, answers);
+        assert!(packet.authorities.is_empty());
+    }
+
+    #[test]
+    fn withholds_the_signatures_of_filtered_records() {
+        let mut packet = DnsPacket::new();
+        packet.answers = smallvec![a(), rrsig(QueryType::A)];
+        packet.resources = smallvec![aaaa(), rrsig(QueryType::AAAA)];
+
+        AddressFilter::Aaaa.apply(&mut packet, QueryType::A, |_, _| false);
+        assert_eq!(packet.answers[..], [a(), rrsig(QueryType::A)]);
+        assert!(packet.resources.is_empty());
+        assert!(packet.authorities.is_empty());
+    }
+}
@@ -1,51 +1,708 @@
-use log::info;
+use log::{error, info, log_enabled, trace, warn, Level};
 use rand::Rng;
-use std::net::{Ipv4Addr, UdpSocket};
+use std::cell::RefCell;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream, UdpSocket};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 
+#[cfg(feature = "chaos")]
+use crate::chaos;
 use crate::{
-    buffer::{Buffer, BufferError},
+    audit::{self, UpstreamQuery},
+    buffer::{Buffer, BufferError, MAX_SIZE},
+    cache::Cache,
+    conformance::{self, Verdict},
+    correlation,
+    dump::dump,
+    filter::AddressFilter,
+    holddown::HoldDown,
+    inflight::{LimitAction, Outstanding, Resolutions},
+    infra::{EdnsMode, InfraCache},
+    leases::Leases,
+    mode::{Maintenance, Modes},
+    opcode::{self, Dispatcher, Opcode, Route},
+    opt::{EdnsOption, OptRecord},
     packet::DnsPacket,
-    question::{DnsQuestion, QueryType},
+    policy::{PolicyAction, PolicyEngine},
+    privacy,
+    profiling::{self, Stage},
+    question::{DnsQuestion, QueryClass, QueryType},
+    record::DnsRecord,
     resultcode::ResultCode,
+    reverse, socket,
+    stats::{Resolution, Stats, RECURSIVE_BUCKET},
+    storm::StormGuard,
+    subnet::SubnetMap,
+    zone::{child_towards, is_subdomain, Authority},
 };
 
-/// IP of *a.root-servers.net*
-const A_ROOT_SERVERS_IP: Ipv4Addr = Ipv4Addr::new(198, 41, 0, 4);
-/// UDP socket port for lookups
-const LOOKUP_SOCKET_PORT: u16 = 42069;
+/// Maximum number of queries for minimized names in a resolution, after which servers are
+/// asked for the full name, so that names with many labels can't make resolution too slow
+const MAX_MINIMIZED_QUERIES: usize = 10;
 
-/// This function takes a UDP socket as input.
-/// It receives a DNS query from the socket, and sends a response back.
+/// The `Context` struct holds everything queries are answered from, shared by all the queries
+/// handled by the server: the locally configured data, the response policies, the configured
+/// search suffixes, the maximum size of UDP responses for each client, the record cache,
+/// the address records withheld from each client, the infrastructure cache and the statistics,
+/// whether the server runs in audit mode and whether it recurses for queries that don't ask
+/// for it.
+pub struct Context {
+    pub authority: RwLock<Authority>,
+    pub policies: PolicyEngine,
+    pub search: Vec<String>,
+    pub max_udp_size: SubnetMap<u16>,
+    pub address_filter: SubnetMap<AddressFilter>,
+    /// Replace CNAME chains with the records they lead to
+    pub flatten_cnames: bool,
+    pub cache: Arc<Cache>,
+    pub infra: Arc<InfraCache>,
+    pub stats: Arc<Stats>,
+    pub audit: bool,
+    /// Recurse even for queries with RD=0, like older versions did
+    pub always_recurse: bool,
+    /// Questions that failed to resolve recently, answered SERVFAIL without recursing
+    pub hold_down: HoldDown,
+    /// Resolve the reverse lookups of private addresses, instead of answering NXDOMAIN
+    pub forward_private_reverse: bool,
+    /// Devices the addresses of LAN clients are leased to
+    pub leases: Arc<Leases>,
+    /// Modes toggled at runtime, like maintenance
+    pub modes: Modes,
+    /// Names whose queries are answered even in maintenance mode
+    pub health_checks: Vec<String>,
+    /// Queries received over UDP and not answered yet
+    pub outstanding: Outstanding<ClientQuery>,
+    /// Resolutions in progress, limited per client and overall
+    pub resolutions: Resolutions,
+    /// Suffixes hit by random subdomain attacks
+    pub storms: StormGuard,
+    /// Code of the EDNS option clients send to have the origin of the answer described
+    pub debug_option: Option<u16>,
+    /// Routes the messages other than standard queries to the handlers of their opcode
+    pub dispatcher: Dispatcher,
+}
+
+/// Identifies a query from a client: a retry has the same source, ID and question
+pub type ClientQuery = (SocketAddr, u16, Option<DnsQuestion>);
+
+/// A query received over UDP, waiting to be answered
+pub struct UdpQuery {
+    /// The datagram holding the query
+    pub data: Vec<u8>,
+    /// Address of the client
+    pub src: SocketAddr,
+    /// Address the query was sent to, if known
+    pub dst: Option<Ipv4Addr>,
+    /// Correlation ID of the query, see `correlation`
+    pub correlation: u32,
+}
+
+thread_local! {
+    /// Buffer the datagrams received by the current thread are read into, reused for each one
+    static RECEIVE_BUFFER: RefCell<Vec<u8>> = RefCell::new(vec![0; MAX_SIZE]);
+}
+
+/// This function takes a UDP socket as input, and receives a DNS query from it.
+/// If an error occurs, it returns the error.
+pub fn receive_query(socket: &UdpSocket) -> Result<UdpQuery, BufferError> {
+    // Only the bytes received are copied out of the buffer, which is kept for the next query.
+    let (data, src, dst) = RECEIVE_BUFFER.with_borrow_mut(|buffer| {
+        socket::recv_from(socket, buffer).map(|(len, src, dst)| (buffer[..len].to_vec(), src, dst))
+    })?;
+
+    Ok(UdpQuery {
+        data,
+        src,
+        dst,
+        correlation: correlation::new_id(),
+    })
+}
+
+/// Parses a query received from a client. When it is malformed, an annotated dump of it is
+/// logged at trace level to help debugging.
+pub fn parse_query(data: &[u8], client: IpAddr) -> Result<DnsPacket, BufferError> {
+    profiling::time(Stage::Parse, || DnsPacket::parse(data)).inspect_err(|e| {
+        if log_enabled!(Level::Trace) {
+            trace!(
+                "Malformed query from {} ({}):\n{}",
+                privacy::client(client),
+                e,
+                dump(data)
+            );
+        }
+    })
+}
+
+/// Runs the handling of a query, containing any panic it causes so that a single query
+/// can't take down the thread answering it, or the whole server. The panic is logged along
+/// with the query, and `None` is returned.
+pub fn contain_panic<T>(query: &[u8], handle: impl FnOnce() -> T) -> Option<T> {
+    match panic::catch_unwind(AssertUnwindSafe(handle)) {
+        Ok(value) => Some(value),
+        Err(payload) => {
+            let cause = payload
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("unknown cause");
+            error!(
+                "Panic while handling a query ({}), the query was:\n{}",
+                cause,
+                dump(query)
+            );
+            None
+        }
+    }
+}
+
+/// This function takes a UDP socket, a query received from it and the context queries are
+/// answered from as input. It sends a response back, truncated when it exceeds what the client
+/// can receive or what is allowed to be sent to it.
 /// If an error occurs, it returns the error.
-pub fn handle_query(socket: &UdpSocket) -> Result<(), BufferError> {
-    let mut req_buffer = Buffer::new();
-    let (_, src) = socket.recv_from(&mut req_buffer.buf)?;
+pub fn handle_query(
+    socket: &UdpSocket,
+    query: UdpQuery,
+    context: &Context,
+) -> Result<(), BufferError> {
+    let request = parse_query(&query.data, query.src.ip())?;
+    let max_size = max_udp_size(&request, &query, context);
 
-    let mut request = DnsPacket::from_buffer(&mut req_buffer)?;
+    // A client retrying a query still being resolved gets the answer to the original one.
+    let key = (
+        query.src,
+        request.header.id,
+        request.questions.first().cloned(),
+    );
+    let Some(_started) = context.outstanding.start(key) else {
+        info!(
+            "Attaching retry from {} to the query in flight",
+            privacy::client(query.src.ip())
+        );
+        context.stats.record_retry();
+        return Ok(());
+    };
+
+    let Some(packet) = respond(request, query.src.ip(), context)? else {
+        return Ok(());
+    };
+
+    send_response(socket, &packet, max_size, &query)
+}
+
+/// This function takes a UDP socket, a query received from it and the context queries are
+/// answered from as input. When the answer is cached, and nothing else (a policy, local data,
+/// search suffixes or the audit mode) applies to the query, it answers the query right away
+/// and returns true. Otherwise it returns false, leaving the query to be answered by
+/// `handle_query`. This lets the receiving thread answer cache hits without queueing them
+/// behind the queries being resolved.
+pub fn try_fast_path(
+    socket: &UdpSocket,
+    query: &UdpQuery,
+    context: &Context,
+) -> Result<bool, BufferError> {
+    let start = Instant::now();
+
+    if context.audit || context.modes.maintenance() != Maintenance::Off {
+        return Ok(false);
+    }
+
+    let request = parse_query(&query.data, query.src.ip())?;
+    let edns = response_opt(&request, query.src.ip(), context);
+    if request.header.opcode != 0
+        || edns_version(&request).is_some_and(|version| version > 0)
+        || conformance::check(&request) != Verdict::Accept
+        || debug_option(&request, context).is_some()
+    {
+        return Ok(false);
+    }
+    let Some(asked) = request.questions.first() else {
+        return Ok(false);
+    };
+    let question = asked.normalized();
+
+    let single_label = !question.name.contains('.') && !context.search.is_empty();
+    if single_label
+        || context
+            .policies
+            .evaluate(&question.name, question.qtype)
+            .is_some()
+        || context
+            .authority
+            .read()
+            .unwrap()
+            .stats_bucket(&question.name)
+            != RECURSIVE_BUCKET
+    {
+        return Ok(false);
+    }
+
+    let Some(result) = cached_lookup(
+        &question.name,
+        question.qtype,
+        question.qclass,
+        &context.cache,
+    ) else {
+        return Ok(false);
+    };
+    if context
+        .policies
+        .evaluate_targets(&result.answers, question.qtype)
+        .is_some()
+    {
+        return Ok(false);
+    }
 
     let mut packet = DnsPacket::new();
     packet.header.id = request.header.id;
-    packet.header.recursion_desired = true;
+    packet.header.recursion_desired = request.header.recursion_desired;
+    packet.header.recursion_available = true;
+    packet.header.response = true;
+    packet.questions.push(asked.clone());
+    packet.answers = result.answers;
+    if context.flatten_cnames {
+        packet.flatten_cnames(&question.name, question.qtype);
+    }
+    add_target_addresses(&mut packet, context);
+    context
+        .address_filter
+        .get(query.src.ip())
+        .apply(&mut packet, question.qtype, |name, qtype| {
+            has_records(context, name, qtype)
+        });
+    if let Some(opt) = edns {
+        packet.resources.push(opt.to_record());
+    }
+
+    send_response(
+        socket,
+        &packet,
+        max_udp_size(&request, query, context),
+        query,
+    )?;
+    context.stats.record_opcode(Opcode::Query);
+    context.stats.record_name(&privacy::name(&question.name));
+    context.stats.record(
+        RECURSIVE_BUCKET,
+        Resolution::Cache,
+        packet.header.rescode,
+        start.elapsed(),
+    );
+
+    Ok(true)
+}
+
+/// Returns the maximum size of the response to a query: what the client can receive,
+/// within what is allowed to be sent to its subnet
+fn max_udp_size(request: &DnsPacket, query: &UdpQuery, context: &Context) -> usize {
+    request
+        .udp_payload_size()
+        .min(*context.max_udp_size.get(query.src.ip()) as usize)
+}
+
+/// Sends the response to a query received over UDP, truncating it if it exceeds `max_size`
+fn send_response(
+    socket: &UdpSocket,
+    packet: &DnsPacket,
+    max_size: usize,
+    query: &UdpQuery,
+) -> Result<(), BufferError> {
+    let mut data = profiling::time(Stage::Serialize, || packet.to_bytes())?;
+    if data.len() > max_size {
+        info!(
+            "Truncating response of {} bytes to {} (max {})",
+            data.len(),
+            privacy::client(query.src.ip()),
+            max_size
+        );
+        data = packet.truncated().to_bytes()?;
+    }
+
+    // Reply from the address the query was sent to.
+    profiling::time(Stage::Send, || {
+        socket::send_to(socket, &data, query.src, query.dst)
+    })?;
+
+    Ok(())
+}
+
+/// This function takes a DNS query, as received from any transport, the address of the client
+/// and the context queries are answered from as input. It builds the response, applying
+/// response policies first, then answering from local data when possible and resolving the
+/// query otherwise. It returns `None` when the query must not be answered at all.
+///
+/// Messages of other opcodes than standard queries go to the handlers registered for them on
+/// the dispatcher, when the client is allowed to send them.
+///
+/// In audit mode, the query is resolved as usual but never answered: the upstream queries it
+/// generated are logged instead, to debug the resolution and estimate the upstream load.
+pub fn respond(
+    request: DnsPacket,
+    client: IpAddr,
+    context: &Context,
+) -> Result<Option<DnsPacket>, BufferError> {
+    let opcode = Opcode::from_num(request.header.opcode);
+    context.stats.record_opcode(opcode);
+    match context.dispatcher.route(opcode, client) {
+        Route::Query => {}
+        Route::Handler(handler) => return Ok(handler.handle(&request, client)),
+        Route::Refused => {
+            info!(
+                "Refusing {} message from {}",
+                opcode,
+                privacy::client(client)
+            );
+            return Ok(Some(opcode::response_to(&request, ResultCode::REFUSED)));
+        }
+        Route::NotImplemented => {
+            return Ok(Some(opcode::response_to(&request, ResultCode::NOTIMP)));
+        }
+    }
+
+    if !context.audit {
+        return answer(request, client, context);
+    }
+
+    let question = request.questions.first().cloned();
+    let (result, queries) = audit::trace(|| answer(request, client, context));
+
+    if let Some(question) = question {
+        info!(
+            "Audit: {:?} {} generated {} upstream queries",
+            question.qtype,
+            privacy::name(&question.name),
+            queries.len()
+        );
+    }
+    for (i, query) in queries.iter().enumerate() {
+        info!(
+            "Audit: #{} {:?} {} to {} over {} ({:?})",
+            i + 1,
+            query.qtype,
+            privacy::name(&query.qname),
+            query.server,
+            if query.tcp { "TCP" } else { "UDP" },
+            query.edns
+        );
+    }
+    if let Ok(Some(response)) = result {
+        info!("Audit: would answer {:?}", response.header.rescode);
+    }
+
+    Ok(None)
+}
+
+/// This function takes a DNS query and the context queries are answered from as input,
+/// and builds the response to send back, if any.
+fn answer(
+    mut request: DnsPacket,
+    client: IpAddr,
+    context: &Context,
+) -> Result<Option<DnsPacket>, BufferError> {
+    let Context {
+        authority,
+        policies,
+        search,
+        cache,
+        infra,
+        stats,
+        always_recurse,
+        ..
+    } = context;
+
+    let start = Instant::now();
+    let mut bucket = RECURSIVE_BUCKET.to_string();
+    let mut resolution = Resolution::Rejected;
+    // The upstream queries are only traced for clients asking where the answer came from.
+    let debug = debug_option(&request, context);
+    let mut upstream = Vec::new();
+    // Queries with RD=0 (e.g. from other resolvers snooping the cache) are only
+    // answered from what the server already knows.
+    let recurse = request.header.recursion_desired || *always_recurse;
+
+    let mut packet = DnsPacket::new();
+    packet.header.id = request.header.id;
+    packet.header.recursion_desired = request.header.recursion_desired;
     packet.header.recursion_available = true;
     packet.header.response = true;
 
-    if let Some(question) = request.questions.pop() {
-        info!("Received query: {:?}", question);
+    // Clients that sent an OPT record get one back, and those speaking a later version of
+    // EDNS than 0 are told so, as RFC 6891 asks.
+    let edns = response_opt(&request, client, context);
+    if let Some(version) = edns_version(&request).filter(|version| *version > 0) {
+        info!(
+            "Query {} uses EDNS version {}, answering BADVERS",
+            request.header.id, version
+        );
+        packet.questions = request.questions;
+        packet.header.rescode = ResultCode::BADVERS;
+        packet.resources.extend(edns.map(|opt| opt.to_record()));
+        stats.record(&bucket, resolution, packet.header.rescode, start.elapsed());
+        return Ok(Some(packet));
+    }
+
+    // Oddball queries are refused or ignored, rather than answered as if they made sense.
+    let question = match conformance::check(&request) {
+        Verdict::Accept => request.questions.pop(),
+        Verdict::Ignore(reason) => {
+            info!("Ignoring query {}: {}", request.header.id, reason);
+            return Ok(None);
+        }
+        Verdict::FormErr(reason) => {
+            info!("Malformed query {}: {}", request.header.id, reason);
+            None
+        }
+    };
+
+    if let Some(asked) = question {
+        // Names are compared lowercased, but the question is echoed as asked.
+        let question = asked.normalized();
+        info!(
+            "Received query for {:?} {} from {}",
+            question.qtype,
+            privacy::name(&question.name),
+            context.leases.describe(client)
+        );
+        stats.record_name(&privacy::name(&question.name));
+        // In maintenance mode, only the health checks are answered as usual.
+        if let Some(rescode) = context.modes.maintenance().rescode() {
+            if !context.health_checks.contains(&question.name) {
+                packet.questions.push(asked);
+                packet.header.rescode = rescode;
+                stats.record(&bucket, Resolution::Rejected, rescode, start.elapsed());
+                return Ok(Some(packet));
+            }
+        }
+        // Zone transfers are only served over TCP.
+        if matches!(question.qtype, QueryType::AXFR | QueryType::IXFR) {
+            packet.questions.push(asked);
+            packet.header.rescode = ResultCode::REFUSED;
+            stats.record(
+                &bucket,
+                Resolution::Rejected,
+                packet.header.rescode,
+                start.elapsed(),
+            );
+            return Ok(Some(packet));
+        }
+        // Only the Internet class is resolved, answering queries in other classes with
+        // records of the Internet class would be wrong.
+        if question.qclass != QueryClass::IN {
+            info!(
+                "Refusing query for {} in class {:?}",
+                privacy::name(&question.name),
+                question.qclass
+            );
+            packet.questions.push(asked);
+            packet.header.rescode = ResultCode::REFUSED;
+            stats.record(
+                &bucket,
+                Resolution::Rejected,
+                packet.header.rescode,
+                start.elapsed(),
+            );
+            return Ok(Some(packet));
+        }
+
+        // The local data is only locked while it is looked up, as it can be updated
+        // while the query is being resolved.
+        let local = {
+            let authority = authority.read().unwrap();
+            bucket = authority.stats_bucket(&question.name).to_string();
+            authority.lookup(&question.name, question.qtype, question.qclass)
+        };
+
+        let result = match policies.evaluate(&question.name, question.qtype) {
+            Some(PolicyAction::Drop) => {
+                stats.record_blocked(&privacy::name(&question.name));
+                info!(
+                    "Dropping query for {} per policy",
+                    privacy::name(&question.name)
+                );
+                return Ok(None);
+            }
+            Some(PolicyAction::Passthru) | None => None,
+            Some(action) => {
+                info!(
+                    "Applying policy to {}: {:?}",
+                    privacy::name(&question.name),
+                    action
+                );
+                resolution = Resolution::Policy;
+                stats.record_blocked(&privacy::name(&question.name));
+                let ttl = policies.blocked_ttl.for_type(question.qtype);
+                Some(Ok(policy_response(action, &question.name, ttl)))
+            }
+        };
+
+        // Reverse lookups of the addresses leased by the DHCP server are answered with the
+        // hostname of the device, under the first search suffix.
+        let local = local.or_else(|| match question.qtype {
+            QueryType::PTR => {
+                let domain = search.first().map(String::as_str);
+                let record = context.leases.reverse_lookup(&question.name, domain)?;
+                let mut packet = DnsPacket::new();
+                packet.header.authoritative_answer = true;
+                packet.answers.push(record);
+                Some(packet)
+            }
+            _ => None,
+        });
+
+        // Reverse lookups of private addresses are answered locally unless a hosted zone
+        // has the name, as the public DNS can't answer them (RFC 6303).
+        let local = match local {
+            None if !context.forward_private_reverse => {
+                reverse::lookup(&question.name, question.qtype)
+            }
+            local => local,
+        };
 
-        if let Ok(result) = recursive_lookup(&question.name, question.qtype) {
-            packet.questions.push(question.clone());
+        let result = result.unwrap_or_else(|| match local {
+            Some(result) => {
+                resolution = Resolution::Local;
+                Ok(result)
+            }
+            None if recurse => {
+                // Looking up the cache first tells cache hits apart from actual recursions,
+                // except for names expanded with the search suffixes, which always recurse.
+                let expanded =
+                    !question.name.is_empty() && !question.name.contains('.') && !search.is_empty();
+                let cached = match expanded {
+                    true => None,
+                    false => cached_lookup(&question.name, question.qtype, question.qclass, cache),
+                };
+                match cached {
+                    Some(cached) => {
+                        resolution = Resolution::Cache;
+                        Ok(cached)
+                    }
+                    // Questions that just failed to resolve fail again without upstream
+                    // queries, until their hold-down is over.
+                    None if context
+                        .hold_down
+                        .is_held_down(&question.name, question.qtype) =>
+                    {
+                        resolution = Resolution::HeldDown;
+                        info!(
+                            "Query for {} {:?} held down after failing to resolve",
+                            privacy::name(&question.name),
+                            question.qtype
+                        );
+                        let mut packet = DnsPacket::new();
+                        packet.header.rescode = ResultCode::SERVFAIL;
+                        Ok(packet)
+                    }
+                    // Names under a suffix hit by a random subdomain attack are answered
+                    // locally, but for the few resolutions still let through.
+                    None if !context.storms.admit(&question.name) => {
+                        resolution = Resolution::Mitigated;
+                        let mut packet = DnsPacket::new();
+                        packet.header.rescode = ResultCode::NXDOMAIN;
+                        Ok(packet)
+                    }
+                    None => {
+                        // Resolutions are limited per client, as queries for random names
+                        // all miss the cache and would take all the upstream capacity.
+                        let Some(_resolving) = context.resolutions.start(client) else {
+                            resolution = Resolution::Limited;
+                            info!(
+                                "Too many resolutions in progress, limiting query for {} from {}",
+                                privacy::name(&question.name),
+                                privacy::client(client)
+                            );
+                            let mut packet = DnsPacket::new();
+                            packet.header.rescode = ResultCode::REFUSED;
+                            return Ok(packet);
+                        };
+                        resolution = Resolution::Iterative;
+                        let resolve =
+                            || search_lookup(&question.name, question.qtype, search, cache, infra);
+                        let result = match debug {
+                            Some(_) => {
+                                let (result, queries) = audit::trace(resolve);
+                                upstream = queries;
+                                result
+                            }
+                            None => resolve(),
+                        };
+                        if let Ok(result) = &result {
+                            context.storms.record(&question.name, result.header.rescode);
+                        }
+                        match &result {
+                            Ok(result) if result.header.rescode != ResultCode::SERVFAIL => context
+                                .hold_down
+                                .record_success(&question.name, question.qtype),
+                            _ => {
+                                let hold_down = context
+                                    .hold_down
+                                    .record_failure(&question.name, question.qtype);
+                                if !hold_down.is_zero() {
+                                    info!(
+                                        "Holding down {} {:?} for {:?}",
+                                        privacy::name(&question.name),
+                                        question.qtype,
+                                        hold_down
+                                    );
+                                }
+                            }
+                        }
+                        result
+                    }
+                }
+            }
+            None => {
+                resolution = Resolution::NonRecursive;
+                Ok(non_recursive_lookup(&question.name, question.qtype, cache))
+            }
+        });
+        if resolution == Resolution::Limited && context.resolutions.action == LimitAction::Drop {
+            stats.record(&bucket, resolution, ResultCode::REFUSED, start.elapsed());
+            return Ok(None);
+        }
+        info!(
+            "Query for {} answered via {}",
+            privacy::name(&question.name),
+            resolution
+        );
+
+        // The question is echoed as asked, whatever its type, even when resolution fails.
+        packet.questions.push(asked);
+        if let Ok(mut result) = result {
+            if resolution != Resolution::Policy
+                && !apply_target_policy(&mut result, question.qtype, policies)
+            {
+                stats.record_blocked(&privacy::name(&question.name));
+                return Ok(None);
+            }
+            if context.flatten_cnames {
+                result.flatten_cnames(&question.name, question.qtype);
+            }
+            add_target_addresses(&mut result, context);
+            context
+                .address_filter
+                .get(client)
+                .apply(&mut result, question.qtype, |name, qtype| {
+                    has_records(context, name, qtype)
+                });
             packet.header.rescode = result.header.rescode;
+            packet.header.authoritative_answer = result.header.authoritative_answer;
 
             for rec in result.answers {
-                info!("Answer: {:?}", rec);
+                info!("Answer: {}", privacy::record(&rec));
                 packet.answers.push(rec);
             }
             for rec in result.authorities {
-                info!("Authority: {:?}", rec);
+                info!("Authority: {}", privacy::record(&rec));
                 packet.authorities.push(rec);
             }
+            // The OPT record of the upstream server only applies to that hop.
             for rec in result.resources {
-                info!("Resource: {:?}", rec);
+                if let DnsRecord::OPT { .. } = rec {
+                    continue;
+                }
+                info!("Resource: {}", privacy::record(&rec));
                 packet.resources.push(rec);
             }
         } else {
@@ -55,29 +712,472 @@ pub fn handle_query(socket: &UdpSocket) -> Result<(), BufferError> {
         packet.header.rescode = ResultCode::FORMERR;
     }
 
-    let mut res_buffer = Buffer::new();
-    packet.write(&mut res_buffer)?;
+    stats.record(&bucket, resolution, packet.header.rescode, start.elapsed());
 
-    let len = res_buffer.pos();
-    let data = res_buffer.get_range(0, len)?;
+    if let Some(mut opt) = edns {
+        if let Some(code) = debug {
+            let origin = describe_origin(resolution, &upstream, &packet);
+            info!("Describing the origin of the answer: {}", origin);
+            opt = opt.with_option(EdnsOption::Unknown {
+                code,
+                data: origin.into_bytes(),
+            });
+        }
+        packet.resources.push(opt.to_record());
+    }
 
-    socket.send_to(data, src)?;
+    Ok(Some(packet))
+}
 
-    Ok(())
+/// Returns the OPT record of the response to a query that has one, advertising the largest
+/// UDP response the server sends to the client, see https://tools.ietf.org/html/rfc6891#section-6.1.1
+fn response_opt(request: &DnsPacket, client: IpAddr, context: &Context) -> Option<OptRecord> {
+    request
+        .opt()
+        .map(|_| OptRecord::new(*context.max_udp_size.get(client)))
+}
+
+/// Returns the version of EDNS a query uses, if it has an OPT record
+fn edns_version(request: &DnsPacket) -> Option<u8> {
+    request.opt().map(|opt| opt.version)
+}
+
+/// Returns the code of the debug option, if the server has one and the query carries it.
+/// Queries in audit mode are already traced, and never answered anyway.
+fn debug_option(request: &DnsPacket, context: &Context) -> Option<u16> {
+    let code = context.debug_option.filter(|_| !context.audit)?;
+    request.opt()?.option(code).map(|_| code)
+}
+
+/// Describes where an answer came from, for clients debugging it without access to the logs
+/// of the server: how it was answered, the upstream server that answered last and how many
+/// were queried, and the lowest TTL of the records along with where it comes from, e.g.
+/// `resolution=iterative; upstream=192.0.2.53 (4 queries); ttl=300 from upstream`
+fn describe_origin(
+    resolution: Resolution,
+    upstream: &[UpstreamQuery],
+    packet: &DnsPacket,
+) -> String {
+    let mut origin = format!("resolution={}", resolution);
+    if let Some(last) = upstream.last() {
+        origin.push_str(&format!(
+            "; upstream={} ({} queries)",
+            last.server,
+            upstream.len()
+        ));
+    }
+
+    let records = match packet.answers.is_empty() {
+        true => &packet.authorities,
+        false => &packet.answers,
+    };
+    if let Some(ttl) = records.iter().map(DnsRecord::ttl).min() {
+        origin.push_str(&format!("; ttl={}", ttl));
+        match resolution {
+            Resolution::Cache | Resolution::NonRecursive => origin.push_str(" remaining in cache"),
+            Resolution::Iterative => origin.push_str(" from upstream"),
+            Resolution::Local => origin.push_str(" from local data"),
+            Resolution::Policy => origin.push_str(" from policy"),
+            _ => {}
+        }
+    }
+
+    origin
+}
+
+/// This function takes a policy action, the query name and the TTL of blocked answers as
+/// input, and builds the response the action stands for. Negative answers carry an SOA
+/// record whose TTL (and minimum) is the blocked TTL, so that they are cached that long,
+/// and local data is cached at least that long.
+fn policy_response(action: PolicyAction, qname: &str, ttl: u32) -> DnsPacket {
+    let mut packet = DnsPacket::new();
+
+    match action {
+        PolicyAction::NxDomain => {
+            packet.header.rescode = ResultCode::NXDOMAIN;
+            packet.authorities.push(local_soa(qname, ttl));
+        }
+        PolicyAction::NoData => packet.authorities.push(local_soa(qname, ttl)),
+        PolicyAction::Refused => packet.header.rescode = ResultCode::REFUSED,
+        PolicyAction::LocalData(records) => {
+            packet.answers = records
+                .iter()
+                .map(|record| record.with_ttl(record.ttl().max(ttl)))
+                .collect();
+        }
+        PolicyAction::Passthru | PolicyAction::Drop => {}
+    }
+
+    packet
+}
+
+/// Applies the response policies to the targets of the aliases in an answer: the answer is
+/// cut after the first alias to a name a policy applies to, and completed the way the policy
+/// says, as if the target had been asked for. Returns false if the response must be dropped.
+fn apply_target_policy(
+    response: &mut DnsPacket,
+    qtype: QueryType,
+    policies: &PolicyEngine,
+) -> bool {
+    let Some((index, target, action)) = policies.evaluate_targets(&response.answers, qtype) else {
+        return true;
+    };
+    info!(
+        "Applying policy to {}, aliased in the answer: {:?}",
+        privacy::name(&target),
+        action
+    );
+    if action == PolicyAction::Drop {
+        return false;
+    }
+
+    let ttl = policies.blocked_ttl.for_type(qtype);
+    let policy = policy_response(action, &target, ttl);
+    response.header.rescode = policy.header.rescode;
+    response.answers.truncate(index + 1);
+    response.answers.extend(policy.answers);
+    response.authorities = policy.authorities;
+    response
+        .resources
+        .retain(|record| matches!(record, DnsRecord::OPT { .. }));
+    true
+}
+
+/// Builds the SOA record sent with the negative answers made up by the server, to blocked
+/// queries or those for filtered addresses
+pub(crate) fn local_soa(qname: &str, ttl: u32) -> DnsRecord {
+    DnsRecord::SOA {
+        domain: qname.to_string(),
+        mname: "localhost".to_string(),
+        rname: "nobody.invalid".to_string(),
+        serial: 1,
+        refresh: 3600,
+        retry: 600,
+        expire: 86400,
+        minimum: ttl,
+        ttl,
+    }
 }
 
-/// This function takes a domain name, a query type, and a server address as input.
+/// This function takes a domain name, a query type and a list of search suffixes as input.
+/// Single-label names (e.g. `nas`) are expanded with each suffix in turn (e.g. `nas.home.lan`)
+/// and the first expansion that yields an answer is returned, with a CNAME from the original
+/// name to the expanded one prepended so that clients can match the answer to their question.
+/// If no expansion yields an answer, or the name has more than one label, the name is looked
+/// up as-is.
+fn search_lookup(
+    qname: &str,
+    qtype: QueryType,
+    search: &[String],
+    cache: &Cache,
+    infra: &InfraCache,
+) -> Result<DnsPacket, BufferError> {
+    if qname.is_empty() || qname.contains('.') {
+        return recursive_lookup(qname, qtype, cache, infra);
+    }
+
+    for suffix in search {
+        let expanded = format!("{}.{}", qname, suffix.trim_matches('.'));
+        info!(
+            "expanding {} to {}",
+            privacy::name(qname),
+            privacy::name(&expanded)
+        );
+
+        let mut response = match recursive_lookup(&expanded, qtype, cache, infra) {
+            Ok(response) => response,
+            Err(e) => {
+                warn!("lookup of {} failed: {}", privacy::name(&expanded), e);
+                continue;
+            }
+        };
+        if response.answers.is_empty() || response.header.rescode != ResultCode::NOERROR {
+            continue;
+        }
+
+        let ttl = response
+            .answers
+            .iter()
+            .map(DnsRecord::ttl)
+            .min()
+            .unwrap_or(0);
+        response.answers.insert(
+            0,
+            DnsRecord::CNAME {
+                domain: qname.to_string(),
+                host: expanded,
+                ttl,
+            },
+        );
+
+        return Ok(response);
+    }
+
+    recursive_lookup(qname, qtype, cache, infra)
+}
+
+/// This function takes a domain name, a query type, a server address and the infrastructure
+/// cache as input. It sends the query to the server, unless the same query is already in
+/// flight to it (e.g. the glue lookups of a name server shared by concurrent recursions),
+/// in which case it waits for that query's response instead.
+fn deduplicated_lookup(
+    qname: &str,
+    qtype: QueryType,
+    server: (Ipv4Addr, u16),
+    infra: &InfraCache,
+) -> Result<DnsPacket, BufferError> {
+    let key = (qname.to_string(), qtype, server.0);
+    infra
+        .in_flight
+        .run(key, || {
+            lookup_with_fallback(qname, qtype, server, infra).map_err(Arc::new)
+        })
+        .map_err(|e| Arc::try_unwrap(e).unwrap_or_else(|e| shared_error(&e)))
+}
+
+/// Copies an error shared between threads, keeping the kind of I/O errors
+fn shared_error(e: &BufferError) -> BufferError {
+    match e {
+        BufferError::IoError(e) => BufferError::IoError(io::Error::new(e.kind(), e.to_string())),
+        BufferError::EndOfBuffer => BufferError::EndOfBuffer,
+        BufferError::JumpsLimitExceeded(jumps) => BufferError::JumpsLimitExceeded(*jumps),
+        BufferError::LabelTooLong => BufferError::LabelTooLong,
+        BufferError::StringTooLong => BufferError::StringTooLong,
+        BufferError::DataTooShort(len) => BufferError::DataTooShort(*len),
+        BufferError::TrailingData(len) => BufferError::TrailingData(*len),
+        BufferError::DataTooLong => BufferError::DataTooLong,
+    }
+}
+
+/// This function takes a domain name, a query type, a server address and the infrastructure
+/// cache as input. It sends the query with the EDNS mode known to work with the server, and
+/// when the server mishandles EDNS (by answering FORMERR/NOTIMP or not answering at all) it
+/// retries with a smaller advertised payload size and then without an OPT record.
+/// Truncated responses are retried over TCP. What worked is remembered in the
+/// infrastructure cache.
+fn lookup_with_fallback(
+    qname: &str,
+    qtype: QueryType,
+    server: (Ipv4Addr, u16),
+    infra: &InfraCache,
+) -> Result<DnsPacket, BufferError> {
+    let mut mode = infra.edns_mode(server.0);
+    let retry = infra.retry();
+    let mut attempt = 0;
+
+    loop {
+        let result = lookup(qname, qtype, server, mode, retry.timeout(attempt));
+        // Unanswered queries are sent again, before suspecting the server of dropping them
+        // because of their EDNS mode.
+        let unanswered = matches!(&result, Err(BufferError::IoError(e))
+            if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut));
+        if unanswered && attempt + 1 < retry.attempts {
+            attempt += 1;
+            info!(
+                "server {} didn't answer, retrying (attempt {} of {})",
+                server.0,
+                attempt + 1,
+                retry.attempts
+            );
+            continue;
+        }
+
+        let mishandled = match &result {
+            Ok(response) => matches!(
+                response.header.rescode,
+                ResultCode::FORMERR | ResultCode::NOTIMP
+            ),
+            Err(BufferError::IoError(e)) => {
+                matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+            }
+            Err(_) => false,
+        };
+
+        if mishandled {
+            if let Some(next) = mode.fallback() {
+                warn!(
+                    "server {} mishandled {:?}, retrying with {:?}",
+                    server.0, mode, next
+                );
+                mode = next;
+                attempt = 0;
+                continue;
+            }
+        } else if result.is_ok() {
+            infra.set_edns_mode(server.0, mode);
+        }
+
+        // Only servers that don't answer at all are down, not those sending bad responses.
+        let unreachable = matches!(&result, Err(BufferError::IoError(_)));
+        infra.record_reachability(server.0, !unreachable);
+
+        // Truncated responses are retried over TCP, unless the server is known not to
+        // support it, in which case the truncated response is the best there is.
+        if let Ok(response) = &result {
+            if response.header.truncated_message && infra.tcp_works(server.0) != Some(false) {
+                match lookup_tcp(qname, qtype, server, mode, retry.max_timeout()) {
+                    Ok(response) => {
+                        infra.set_tcp_works(server.0, true);
+                        return Ok(response);
+                    }
+                    Err(e) => {
+                        warn!("server {} failed over TCP: {}", server.0, e);
+                        infra.set_tcp_works(server.0, false);
+                    }
+                }
+            }
+        }
+
+        return result;
+    }
+}
+
+/// This function takes a domain name, a query type, a server address, an EDNS mode and the
+/// time to wait for the response as input.
 /// It creates a UDP socket, and sends a DNS query to the server.
-/// It then waits for a response from the server, and returns the response.
+/// It then waits for a response from the server, and returns the response. Datagrams that
+/// don't come from the server or don't answer the query (see `answers`) are dropped, as
+/// anyone can send them to the socket, and waiting goes on until the time is up.
 /// If an error occurs, it returns the error.
 fn lookup(
     qname: &str,
     qtype: QueryType,
     server: (Ipv4Addr, u16),
+    edns: EdnsMode,
+    timeout: Duration,
+) -> Result<DnsPacket, BufferError> {
+    // Socket into which the response is received. Letting the OS pick a random port
+    // allows concurrent lookups, and along with the random ID of the query and the checks
+    // of the datagrams received, makes responses harder to spoof.
+    let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+
+    audit::record(UpstreamQuery {
+        server: server.0,
+        qname: qname.to_string(),
+        qtype,
+        edns,
+        tcp: false,
+    });
+
+    let request = query(qname, qtype, edns);
+    log_upstream_query(&request, server, false);
+    socket.send_to(&request.to_bytes()?, server)?;
+
+    let deadline = Instant::now() + timeout;
+    let mut res_buffer = Buffer::with_size(edns.buffer_size());
+    profiling::time(Stage::Upstream, || loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(io::Error::new(ErrorKind::TimedOut, "no response from the server").into());
+        }
+        socket.set_read_timeout(Some(left))?;
+        let (len, src) = socket.recv_from(&mut res_buffer.buf)?;
+        #[cfg(feature = "chaos")]
+        chaos::inject(&mut res_buffer.buf[..len], timeout)?;
+
+        if src != SocketAddr::from(server) {
+            warn!("Dropping a datagram from {} instead of {}", src, server.0);
+            continue;
+        }
+        match DnsPacket::parse(&res_buffer.buf[..len]) {
+            Ok(response) if answers(&response, &request) => return Ok(response),
+            Ok(_) => warn!(
+                "Dropping a response of {} not answering query {}",
+                server.0, request.header.id
+            ),
+            Err(e) => warn!("Dropping a malformed response of {}: {}", server.0, e),
+        }
+    })
+}
+
+/// This function takes a domain name, a query type, a server address, an EDNS mode and the
+/// time to wait for each step of the exchange as input.
+/// It connects to the server over TCP, sends a DNS query prefixed with its length, and returns
+/// the response. If an error occurs, it returns the error.
+fn lookup_tcp(
+    qname: &str,
+    qtype: QueryType,
+    server: (Ipv4Addr, u16),
+    edns: EdnsMode,
+    timeout: Duration,
 ) -> Result<DnsPacket, BufferError> {
-    // Socket into which the response is received.
-    let socket = UdpSocket::bind(("0.0.0.0", LOOKUP_SOCKET_PORT))?;
+    let mut stream = TcpStream::connect_timeout(&server.into(), timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    audit::record(UpstreamQuery {
+        server: server.0,
+        qname: qname.to_string(),
+        qtype,
+        edns,
+        tcp: true,
+    });
+
+    let request = query(qname, qtype, edns);
+    log_upstream_query(&request, server, true);
+    let bytes = request.to_bytes()?;
+    stream.write_all(&(bytes.len() as u16).to_be_bytes())?;
+    stream.write_all(&bytes)?;
+
+    #[cfg_attr(not(feature = "chaos"), allow(unused_mut))]
+    let mut response = profiling::time(Stage::Upstream, || -> io::Result<Vec<u8>> {
+        let mut len = [0; 2];
+        stream.read_exact(&mut len)?;
+        let mut response = vec![0; u16::from_be_bytes(len) as usize];
+        stream.read_exact(&mut response)?;
+        Ok(response)
+    })?;
+    #[cfg(feature = "chaos")]
+    chaos::inject(&mut response, timeout)?;
+
+    let response = DnsPacket::parse(&response)?;
+    if !answers(&response, &request) {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("response of {} to another query", server.0),
+        )
+        .into());
+    }
+    Ok(response)
+}
+
+/// Whether a response answers a query: it must carry the ID of the query, and its question.
+/// Only error responses of servers rejecting the query outright may leave the question out.
+fn answers(response: &DnsPacket, request: &DnsPacket) -> bool {
+    let same_question = match (response.questions.first(), request.questions.first()) {
+        (Some(answered), Some(asked)) => {
+            response.questions.len() == 1
+                && answered.name.eq_ignore_ascii_case(&asked.name)
+                && answered.qtype == asked.qtype
+                && answered.qclass == asked.qclass
+        }
+        (None, _) => matches!(
+            response.header.rescode,
+            ResultCode::FORMERR | ResultCode::NOTIMP
+        ),
+        (Some(_), None) => false,
+    };
+    response.header.response && response.header.id == request.header.id && same_question
+}
 
+/// Logs the message ID of a query sent upstream, which ties the correlation ID of the client
+/// query it derives from (see `correlation`) to the messages seen on the wire
+fn log_upstream_query(request: &DnsPacket, server: (Ipv4Addr, u16), tcp: bool) {
+    if let Some(question) = request.questions.first() {
+        info!(
+            "Sending query {} for {:?} {} to {} over {}",
+            request.header.id,
+            question.qtype,
+            privacy::name(&question.name),
+            server.0,
+            if tcp { "TCP" } else { "UDP" }
+        );
+    }
+}
+
+/// This function takes a domain name, a query type and an EDNS mode as input, and builds
+/// the query to send upstream.
+fn query(qname: &str, qtype: QueryType, edns: EdnsMode) -> DnsPacket {
     let mut packet = DnsPacket::new();
 
     packet.header.id = rand::thread_rng().gen();
@@ -87,14 +1187,118 @@ fn lookup(
         .questions
         .push(DnsQuestion::new(qname.to_string(), qtype));
 
-    let mut req_buffer = Buffer::new();
-    packet.write(&mut req_buffer)?;
-    socket.send_to(&req_buffer.buf[0..req_buffer.pos], server)?;
+    if let EdnsMode::Enabled(packet_len) = edns {
+        packet
+            .resources
+            .push(OptRecord::new(packet_len).to_record());
+    }
 
-    let mut res_buffer = Buffer::new();
-    socket.recv_from(&mut res_buffer.buf)?;
+    packet
+}
+
+/// Adds the addresses of the hosts the answers point to, the targets of SRV records and the
+/// mail exchanges of MX records, to the additional section, from the hosted zones or the
+/// cache, sparing clients the lookups. Targets whose addresses aren't known locally are left
+/// to the clients.
+fn add_target_addresses(packet: &mut DnsPacket, context: &Context) {
+    let targets = packet.additional_targets();
+    if targets.is_empty() {
+        return;
+    }
 
-    DnsPacket::from_buffer(&mut res_buffer)
+    let authority = context.authority.read().unwrap();
+    for target in targets {
+        for qtype in [QueryType::A, QueryType::AAAA] {
+            if packet
+                .resources
+                .iter()
+                .any(|record| record.domain() == Some(&target) && record.qtype() == qtype)
+            {
+                continue;
+            }
+            let records = match authority.lookup(&target, qtype, QueryClass::IN) {
+                Some(local) => local.answers,
+                None => context
+                    .cache
+                    .lookup(&target, qtype, QueryClass::IN)
+                    .unwrap_or_default(),
+            };
+            packet
+                .resources
+                .extend(records.into_iter().filter(|record| record.qtype() == qtype));
+        }
+    }
+}
+
+/// Whether a name has records of a type in the hosted zones or the cache
+fn has_records(context: &Context, name: &str, qtype: QueryType) -> bool {
+    let local = context
+        .authority
+        .read()
+        .unwrap()
+        .lookup(name, qtype, QueryClass::IN)
+        .map(|local| local.answers);
+    local
+        .or_else(|| context.cache.lookup(name, qtype, QueryClass::IN))
+        .is_some_and(|records| records.iter().any(|record| record.qtype() == qtype))
+}
+
+/// This function takes a domain name, a query type and class, and the record cache as input.
+/// It builds a response from the cached records of that type and class, or from a cached
+/// CNAME for the name, and returns `None` when neither is cached.
+fn cached_lookup(
+    qname: &str,
+    qtype: QueryType,
+    qclass: QueryClass,
+    cache: &Cache,
+) -> Option<DnsPacket> {
+    let answers = profiling::time(Stage::CacheLookup, || {
+        cache
+            .lookup_answer(qname, qtype, qclass)
+            .or_else(|| cache.lookup_answer(qname, QueryType::CNAME, qclass))
+    })?;
+
+    let mut packet = DnsPacket::new();
+    packet.answers = answers;
+    cache.splice_cname_target(&mut packet, qname, qtype);
+
+    Some(packet)
+}
+
+/// This function takes a domain name, a query type and the record cache as input, and
+/// answers without recursing, for queries with RD=0. The answer comes from the cache when
+/// possible. Otherwise, it is a referral to the closest enclosing zone whose name servers
+/// are cached, or an empty response when none are.
+fn non_recursive_lookup(qname: &str, qtype: QueryType, cache: &Cache) -> DnsPacket {
+    if let Some(response) = cached_lookup(qname, qtype, QueryClass::IN, cache) {
+        return response;
+    }
+
+    let mut packet = DnsPacket::new();
+    let mut zone = qname;
+    loop {
+        if let Some(ns) = cache.lookup(zone, QueryType::NS, QueryClass::IN) {
+            for record in &ns {
+                if let DnsRecord::NS { host, .. } = record {
+                    packet.resources.extend(
+                        cache
+                            .lookup(host, QueryType::A, QueryClass::IN)
+                            .unwrap_or_default(),
+                    );
+                }
+            }
+            packet.authorities = ns;
+            break;
+        }
+
+        match zone.split_once('.') {
+            Some((_, parent)) => zone = parent,
+            None if !zone.is_empty() => zone = "",
+            None => break,
+        }
+    }
+
+    packet
 }
 
 /// This function takes a domain name and a query type as input.
@@ -102,57 +1306,419 @@ fn lookup(
 /// referrals until it finds the authoritative name server for the domain.
 /// It then looks up the domain name in the authoritative name server, and returns the
 /// result. If an error occurs, it returns the error.
-fn recursive_lookup(qname: &str, qtype: QueryType) -> Result<DnsPacket, BufferError> {
-    // For now we're always starting with *a.root-servers.net*.
-    let mut ns = A_ROOT_SERVERS_IP;
+pub fn recursive_lookup(
+    qname: &str,
+    qtype: QueryType,
+    cache: &Cache,
+    infra: &InfraCache,
+) -> Result<DnsPacket, BufferError> {
+    // Records still valid in the cache don't need to be looked up again.
+    if let Some(response) = cached_lookup(qname, qtype, QueryClass::IN, cache) {
+        info!("cache hit for {:?} {}", qtype, privacy::name(qname));
+        return Ok(response);
+    }
+
+    // For now we're always starting with the same root server, *a.root-servers.net*
+    // unless configured otherwise.
+    let mut ns = infra.root();
+    let mut bailiwick = String::new();
+    // With QNAME minimization, servers are asked for the name one label below the deepest
+    // name known to exist, rather than for the full name, until they delegate it or are
+    // asked for the full name (see RFC 9156).
+    let mut known = String::new();
+    let mut minimized = 0;
 
     // It might take an arbitrary number of steps, therefore it uses an unbounded loop.
     loop {
-        info!("attempting lookup of {:?} {} with ns {}", qtype, qname, ns);
+        let (name, name_qtype) = match privacy::is_strict() && minimized < MAX_MINIMIZED_QUERIES {
+            true => match child_towards(qname, &known) {
+                name if name == qname => (qname, qtype),
+                name => (name, QueryType::A),
+            },
+            false => (qname, qtype),
+        };
+
+        info!(
+            "attempting lookup of {:?} {} with ns {}",
+            name_qtype,
+            privacy::name(name),
+            ns
+        );
 
         // The next step is to send the query to the active server.
         let ns_copy = ns;
 
-        let server = (ns_copy, 53);
-        let response = lookup(qname, qtype, server)?;
+        let server = (ns_copy, infra.port());
+        let mut response = deduplicated_lookup(name, name_qtype, server, infra)?;
+
+        // Records the server has no authority over are never trusted.
+        response.scrub(name, &bailiwick);
+        cache.insert_response(&response);
 
         // If there are entries in the answer section, and no errors, it's done
-        if !response.answers.is_empty() && response.header.rescode == ResultCode::NOERROR {
+        if name == qname
+            && !response.answers.is_empty()
+            && response.header.rescode == ResultCode::NOERROR
+        {
+            // Aliases to names already cached are answered without resolving the target.
+            if cache.splice_cname_target(&mut response, qname, qtype) {
+                info!(
+                    "spliced cached target of the alias {} into the answer",
+                    privacy::name(qname)
+                );
+            }
             return Ok(response);
         }
 
         // `NXDOMAIN` is a possible reply, which is the authoritative name servers
-        // way of telling us that the name doesn't exist.
+        // way of telling us that the name doesn't exist. When it's about an ancestor of
+        // the name, the name doesn't exist either (see RFC 8020).
         if response.header.rescode == ResultCode::NXDOMAIN {
             return Ok(response);
         }
 
+        // A minimized name that isn't delegated elsewhere exists in the zone of the same
+        // server, which is then asked for the next label.
+        let delegated = response
+            .get_delegation(name)
+            .is_some_and(|zone| zone != bailiwick && is_subdomain(zone, &bailiwick));
+        if name != qname {
+            minimized += 1;
+            if !delegated {
+                known = name.to_string();
+                continue;
+            }
+        }
+
         // Otherwise, try to find a new nameserver based on NS and a corresponding A
         // record in the additional section. If this succeeds, switch name server
         // and retry the loop.
-        if let Some(new_ns) = response.get_resolved_ns(qname) {
+        if let Some(new_ns) = response.get_resolved_ns(name) {
             ns = new_ns;
+            bailiwick = delegation(&response, name);
+            known = bailiwick.clone();
 
             continue;
         }
 
         // If not, it must resolve the ip of a NS record. If no NS records exist,
         // it uses what the last server said.
-        let new_ns_name = match response.get_unresolved_ns(qname) {
+        let new_ns_name = match response.get_unresolved_ns(name) {
             Some(x) => x,
             None => return Ok(response),
         };
 
         // Starting a new lookup sequence in the midst of our current one.
         //  Hopefully, this will return the IP of an appropriate name server.
-        let recursive_response = recursive_lookup(new_ns_name, QueryType::A)?;
+        let recursive_response = recursive_lookup(new_ns_name, QueryType::A, cache, infra)?;
 
         // Finally, pick a random ip from the result, and restart the loop. If no such
         // record is available, it returns the last result received.
         if let Some(new_ns) = recursive_response.get_random_a() {
             ns = new_ns;
+            bailiwick = delegation(&response, name);
+            known = bailiwick.clone();
         } else {
             return Ok(response);
         }
     }
 }
+
+/// Returns the zone a referral delegates `qname` to, i.e. the bailiwick of the next server.
+fn delegation(response: &DnsPacket, qname: &str) -> String {
+    response
+        .get_delegation(qname)
+        .unwrap_or_default()
+        .to_string()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::policy::{BlockedTtl, ResponsePolicyZone};
+    use crate::simulation::{Conditions, Hierarchy};
+    use crate::zone::Zone;
+    use std::net::Ipv4Addr;
+
+    pub(crate) fn context() -> Context {
+        Context {
+            authority: RwLock::new(Authority::new(Vec::new(), Vec::new())),
+            policies: PolicyEngine::default(),
+            search: Vec::new(),
+            max_udp_size: SubnetMap::new(4096, Vec::new()),
+            address_filter: SubnetMap::new(AddressFilter::None, Vec::new()),
+            flatten_cnames: false,
+            cache: Arc::new(Cache::new()),
+            infra: Arc::new(InfraCache::new(1232)),
+            stats: Arc::new(Stats::new()),
+            audit: false,
+            always_recurse: false,
+            hold_down: HoldDown::new(Duration::ZERO),
+            forward_private_reverse: false,
+            leases: Arc::new(Leases::new(None)),
+            modes: Modes::new(Maintenance::Off, false),
+            health_checks: Vec::new(),
+            outstanding: Outstanding::new(),
+            resolutions: Resolutions::default(),
+            storms: StormGuard::new(0.0, 0),
+            debug_option: None,
+            dispatcher: Dispatcher::new(),
+        }
+    }
+
+    /// Sends a query to the fast path over UDP, and returns the response if it was answered
+    fn fast_path(context: &Context, qname: &str, qtype: QueryType) -> Option<DnsPacket> {
+        let server = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let client = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+
+        let mut request = DnsPacket::new();
+        request.header.id = 1234;
+        request.header.recursion_desired = true;
+        request
+            .questions
+            .push(DnsQuestion::new(qname.to_string(), qtype));
+        let query = UdpQuery {
+            data: request.to_bytes().unwrap(),
+            src: client.local_addr().unwrap(),
+            dst: None,
+            correlation: 0,
+        };
+        if !try_fast_path(&server, &query, context).unwrap() {
+            return None;
+        }
+
+        let mut response = [0; 4096];
+        let len = client.recv(&mut response).unwrap();
+        Some(DnsPacket::parse(&response[..len]).unwrap())
+    }
+
+    #[test]
+    fn answers_service_targets_from_cache_with_their_addresses() {
+        let context = context();
+        let mut cached = DnsPacket::new();
+        cached.answers.push(DnsRecord::SRV {
+            domain: "_sip._udp.example.com".to_string(),
+            priority: 10,
+            weight: 5,
+            port: 5060,
+            host: "sip.example.com".to_string(),
+            ttl: 300,
+        });
+        cached.resources.push(DnsRecord::A {
+            domain: "sip.example.com".to_string(),
+            addr: Ipv4Addr::new(192, 0, 2, 5),
+            ttl: 300,
+        });
+        context.cache.insert_response(&cached);
+
+        let response = fast_path(&context, "_sip._udp.example.com", QueryType::SRV).unwrap();
+        assert_eq!(response.answers.len(), 1);
+        assert_eq!(
+            response.resources[..],
+            [DnsRecord::A {
+                domain: "sip.example.com".to_string(),
+                addr: Ipv4Addr::new(192, 0, 2, 5),
+                ttl: 300,
+            }]
+        );
+    }
+
+    #[test]
+    fn answers_mail_exchanges_from_cache_with_their_addresses() {
+        let context = context();
+        let mut cached = DnsPacket::new();
+        cached.answers.push(DnsRecord::MX {
+            domain: "example.com".to_string(),
+            priority: 10,
+            host: "mx.example.com".to_string(),
+            ttl: 300,
+        });
+        cached.answers.push(DnsRecord::A {
+            domain: "mx.example.com".to_string(),
+            addr: Ipv4Addr::new(192, 0, 2, 25),
+            ttl: 300,
+        });
+        context.cache.insert_response(&cached);
+
+        let response = fast_path(&context, "example.com", QueryType::MX).unwrap();
+        assert_eq!(response.answers.len(), 1);
+        assert_eq!(
+            response.resources[..],
+            [DnsRecord::A {
+                domain: "mx.example.com".to_string(),
+                addr: Ipv4Addr::new(192, 0, 2, 25),
+                ttl: 300,
+            }]
+        );
+    }
+
+    #[test]
+    fn expands_single_label_names_with_the_search_suffixes() {
+        let hierarchy = Hierarchy::start(Conditions::default()).unwrap();
+        let infra = InfraCache::new(1232).with_root(hierarchy.root, hierarchy.port);
+        let cache = Cache::new();
+        let address = DnsRecord::A {
+            domain: "www.example.com".to_string(),
+            addr: Ipv4Addr::new(192, 0, 2, 1),
+            ttl: 300,
+        };
+
+        // The first suffix the name exists under answers, behind an alias from the name.
+        let search = ["example.org".to_string(), "example.com".to_string()];
+        let response = search_lookup("www", QueryType::A, &search, &cache, &infra).unwrap();
+        assert_eq!(response.header.rescode, ResultCode::NOERROR);
+        assert_eq!(
+            response.answers[..],
+            [
+                DnsRecord::CNAME {
+                    domain: "www".to_string(),
+                    host: "www.example.com".to_string(),
+                    ttl: 300,
+                },
+                address.clone(),
+            ]
+        );
+
+        // Names of more than one label are looked up as they are.
+        let response =
+            search_lookup("www.example.com", QueryType::A, &search, &cache, &infra).unwrap();
+        assert_eq!(response.answers[..], [address]);
+
+        // Names existing under none of the suffixes don't exist.
+        let search = ["example.org".to_string()];
+        let response = search_lookup("www", QueryType::A, &search, &cache, &infra).unwrap();
+        assert_eq!(response.header.rescode, ResultCode::NXDOMAIN);
+        assert!(response.answers.is_empty());
+    }
+
+    /// Answers every query with the address 192.0.2.1, each time after sending the client
+    /// forged responses giving another address: from another address, with another ID and
+    /// for another name
+    fn serve_behind_forgers(server: UdpSocket) {
+        let forger = UdpSocket::bind((Ipv4Addr::new(127, 0, 0, 6), 0)).unwrap();
+        let mut buffer = [0; 4096];
+        while let Ok((len, client)) = server.recv_from(&mut buffer) {
+            let request = DnsPacket::parse(&buffer[..len]).unwrap();
+            let response = |id: u16, name: &str, addr: Ipv4Addr| {
+                let mut response = DnsPacket::new();
+                response.header.id = id;
+                response.header.response = true;
+                response.header.authoritative_answer = true;
+                response
+                    .questions
+                    .push(DnsQuestion::new(name.to_string(), QueryType::A));
+                response.answers.push(DnsRecord::A {
+                    domain: name.to_string(),
+                    addr,
+                    ttl: 300,
+                });
+                response.to_bytes().unwrap()
+            };
+            let (id, name) = (request.header.id, request.questions[0].name.as_str());
+            let forged = Ipv4Addr::new(203, 0, 113, 66);
+
+            forger.send_to(&response(id, name, forged), client).unwrap();
+            let other_id = id.wrapping_add(1);
+            server
+                .send_to(&response(other_id, name, forged), client)
+                .unwrap();
+            let other_name = "other.example.com";
+            server
+                .send_to(&response(id, other_name, forged), client)
+                .unwrap();
+            let addr = Ipv4Addr::new(192, 0, 2, 1);
+            server.send_to(&response(id, name, addr), client).unwrap();
+        }
+    }
+
+    #[test]
+    fn drops_responses_not_answering_upstream_queries() {
+        let server = UdpSocket::bind((Ipv4Addr::new(127, 0, 0, 5), 0)).unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let port = server.local_addr().unwrap().port();
+        std::thread::spawn(move || serve_behind_forgers(server));
+
+        let infra = InfraCache::new(1232).with_root(Ipv4Addr::new(127, 0, 0, 5), port);
+        let cache = Cache::new();
+        let address = DnsRecord::A {
+            domain: "www.example.com".to_string(),
+            addr: Ipv4Addr::new(192, 0, 2, 1),
+            ttl: 300,
+        };
+
+        let response = recursive_lookup("www.example.com", QueryType::A, &cache, &infra).unwrap();
+        assert_eq!(response.answers[..], [address]);
+        let cached = cache
+            .lookup("www.example.com", QueryType::A, QueryClass::IN)
+            .unwrap();
+        assert!(matches!(
+            cached[..],
+            [DnsRecord::A { addr, .. }] if addr == Ipv4Addr::new(192, 0, 2, 1)
+        ));
+        assert!(cache
+            .lookup("other.example.com", QueryType::A, QueryClass::IN)
+            .is_none());
+    }
+
+    #[test]
+    fn applies_policies_to_alias_targets() {
+        let rule = |name: &str, host: &str| DnsRecord::CNAME {
+            domain: format!("{}.rpz.local", name),
+            host: host.to_string(),
+            ttl: 300,
+        };
+        let rpz = ResponsePolicyZone::from_zone(Zone {
+            origin: "rpz.local".to_string(),
+            records: vec![
+                rule("tracker.example.net", ""),
+                rule("ok.example.net", "rpz-passthru"),
+            ],
+        });
+        let policies = PolicyEngine::new(None, vec![rpz], BlockedTtl::default());
+        let alias = |domain: &str, host: &str| DnsRecord::CNAME {
+            domain: domain.to_string(),
+            host: host.to_string(),
+            ttl: 60,
+        };
+        let answer = |chain: &[DnsRecord]| {
+            let mut response = DnsPacket::new();
+            response.answers.extend(chain.iter().cloned());
+            response.answers.push(DnsRecord::A {
+                domain: "edge.cdn.example".to_string(),
+                addr: Ipv4Addr::new(192, 0, 2, 80),
+                ttl: 60,
+            });
+            response
+        };
+
+        // The chain is cut after the alias to the blocked name, which doesn't exist.
+        let chain = [
+            alias("www.example.com", "tracker.example.net"),
+            alias("tracker.example.net", "edge.cdn.example"),
+        ];
+        let mut response = answer(&chain);
+        assert!(apply_target_policy(&mut response, QueryType::A, &policies));
+        assert_eq!(response.header.rescode, ResultCode::NXDOMAIN);
+        assert_eq!(response.answers[..], chain[..1]);
+        assert!(matches!(
+            response.authorities[..],
+            [DnsRecord::SOA { ref domain, .. }] if domain == "tracker.example.net"
+        ));
+
+        // A passthru policy lets the rest of the chain through.
+        let chain = [
+            alias("www.example.com", "ok.example.net"),
+            alias("ok.example.net", "tracker.example.net"),
+            alias("tracker.example.net", "edge.cdn.example"),
+        ];
+        let mut response = answer(&chain);
+        assert!(apply_target_policy(&mut response, QueryType::A, &policies));
+        assert_eq!(response.header.rescode, ResultCode::NOERROR);
+        assert_eq!(response.answers.len(), 4);
+    }
+}
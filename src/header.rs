@@ -8,7 +8,7 @@ use crate::resultcode::ResultCode;
 /// - `authoritative_answer`: a 1-bit field that specifies that the responding name server is an authority for the domain name in question section
 /// - opcode: a 4-bit field that specifies kind of query in this message
 /// - response: a 1-bit field that specifies whether this message is a response to a query or a query
-/// - rescode: a 4-bit field that specifies the response code
+/// - rescode: a 4-bit field that specifies the response code, extended to 12 bits by the OPT record if any
 /// - `checking_disabled`: a 1-bit field that specifies whether checking of query and response is disabled or not
 /// - `authed_data`: a 1-bit field that specifies whether all data in the response is authenticated
 /// - z: a 1-bit field that must be zero in all queries and responses
@@ -39,6 +39,12 @@ pub struct DnsHeader {
     pub resource_entries: u16,
 }
 
+impl Default for DnsHeader {
+    fn default() -> Self {
+        DnsHeader::new()
+    }
+}
+
 impl DnsHeader {
     pub fn new() -> DnsHeader {
         DnsHeader {
@@ -82,7 +88,7 @@ impl DnsHeader {
         self.opcode = (a >> 3) & 0x0F;
         self.response = (a & (1 << 7)) > 0;
 
-        self.rescode = ResultCode::from_num(b & 0x0F);
+        self.rescode = ResultCode::from_num((b & 0x0F) as u16);
         self.checking_disabled = (b & (1 << 4)) > 0;
         self.authed_data = (b & (1 << 5)) > 0;
         self.z = (b & (1 << 6)) > 0;
@@ -113,7 +119,7 @@ impl DnsHeader {
         )?;
 
         buffer.write_u8(
-            (self.rescode as u8)
+            self.rescode.header_bits()
                 | (u8::from(self.checking_disabled) << 4)
                 | (u8::from(self.authed_data) << 5)
                 | (u8::from(self.z) << 6)
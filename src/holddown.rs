@@ -0,0 +1,146 @@
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::clock::{Clock, SystemClock};
+use crate::question::QueryType;
+
+/// Hold-down after the first failure, doubled by each following one
+const INITIAL_HOLD_DOWN: Duration = Duration::from_secs(1);
+
+/// Number of failing questions tracked before the expired ones are forgotten
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// Failures of a question since it was last resolved
+#[derive(Clone, Copy, Debug)]
+struct Failures {
+    count: u32,
+    until: Instant,
+}
+
+/// The `HoldDown` struct caches resolution failures (SERVFAIL) by name and type, as
+/// RFC 2308 section 7.1 allows: while a question is held down, it is answered SERVFAIL
+/// without being resolved again, so that clients retrying a broken name hundreds of times
+/// per second don't turn into as many upstream queries. The hold-down starts at one second
+/// and doubles with each consecutive failure, up to a maximum (at most 5 minutes per the
+/// RFC), and is lifted as soon as the question is resolved.
+pub struct HoldDown {
+    failures: Mutex<HashMap<(String, QueryType), Failures, RandomState>>,
+    max: Duration,
+    clock: Arc<dyn Clock>,
+}
+
+impl HoldDown {
+    /// Creates a hold-down of at most `max`, disabled if zero
+    pub fn new(max: Duration) -> HoldDown {
+        HoldDown::with_clock(max, Arc::new(SystemClock))
+    }
+
+    /// Creates a hold-down expiring according to the given clock
+    pub fn with_clock(max: Duration, clock: Arc<dyn Clock>) -> HoldDown {
+        HoldDown {
+            failures: Mutex::new(HashMap::with_hasher(RandomState::new())),
+            max,
+            clock,
+        }
+    }
+
+    /// Returns true if a question failed recently and shouldn't be resolved yet
+    pub fn is_held_down(&self, qname: &str, qtype: QueryType) -> bool {
+        let now = self.clock.now();
+        self.failures
+            .lock()
+            .unwrap()
+            .get(&(qname.to_string(), qtype))
+            .is_some_and(|failures| failures.until > now)
+    }
+
+    /// Records a failure to resolve a question, holding it down for twice as long as the
+    /// previous time. Returns the hold-down.
+    pub fn record_failure(&self, qname: &str, qtype: QueryType) -> Duration {
+        if self.max.is_zero() {
+            return Duration::ZERO;
+        }
+
+        let now = self.clock.now();
+        let mut failures = self.failures.lock().unwrap();
+        if failures.len() >= PRUNE_THRESHOLD {
+            // Past failures are forgotten once their hold-down is over for long enough that
+            // the next one would be back to the minimum.
+            failures.retain(|_, failures| failures.until + self.max > now);
+        }
+
+        let entry = failures
+            .entry((qname.to_string(), qtype))
+            .or_insert(Failures {
+                count: 0,
+                until: now,
+            });
+        if entry.until + self.max <= now {
+            entry.count = 0;
+        }
+        entry.count = entry.count.saturating_add(1);
+        let hold_down = INITIAL_HOLD_DOWN
+            .saturating_mul(1 << (entry.count - 1).min(16))
+            .min(self.max);
+        entry.until = now + hold_down;
+        hold_down
+    }
+
+    /// Lifts the hold-down of a question once it was resolved
+    pub fn record_success(&self, qname: &str, qtype: QueryType) {
+        let mut failures = self.failures.lock().unwrap();
+        if !failures.is_empty() {
+            failures.remove(&(qname.to_string(), qtype));
+        }
+    }
+
+    /// Number of questions that failed since they were last resolved
+    pub fn len(&self) -> usize {
+        self.failures.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+
+    #[test]
+    fn doubles_the_hold_down_until_resolved() {
+        let clock = Arc::new(ManualClock::new());
+        let hold_down = HoldDown::with_clock(Duration::from_secs(5), clock.clone());
+
+        assert!(!hold_down.is_held_down("broken.com", QueryType::A));
+        let expected = [1, 2, 4, 5, 5];
+        for secs in expected {
+            assert_eq!(
+                hold_down.record_failure("broken.com", QueryType::A),
+                Duration::from_secs(secs)
+            );
+            assert!(hold_down.is_held_down("broken.com", QueryType::A));
+            assert!(!hold_down.is_held_down("broken.com", QueryType::AAAA));
+            clock.advance(Duration::from_secs(secs));
+            assert!(!hold_down.is_held_down("broken.com", QueryType::A));
+        }
+
+        hold_down.record_success("broken.com", QueryType::A);
+        assert!(hold_down.is_empty());
+        assert_eq!(
+            hold_down.record_failure("broken.com", QueryType::A),
+            Duration::from_secs(1)
+        );
+    }
+
+    #[test]
+    fn is_disabled_by_a_zero_maximum() {
+        let hold_down = HoldDown::new(Duration::ZERO);
+        hold_down.record_failure("broken.com", QueryType::A);
+        assert!(!hold_down.is_held_down("broken.com", QueryType::A));
+    }
+}
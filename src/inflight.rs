@@ -0,0 +1,372 @@
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::{Arc, Condvar, Mutex};
+
+/// What became of a pending operation
+enum State<V> {
+    Running,
+    Done(V),
+    /// The thread running the operation panicked
+    Abandoned,
+}
+
+struct Pending<V> {
+    state: Mutex<State<V>>,
+    done: Condvar,
+}
+
+/// The `InFlight` struct deduplicates identical operations running at the same time: the
+/// first thread to start an operation runs it, and the threads starting it again before it
+/// completes wait for its result instead of running it too. Operations are only tracked
+/// while they run, nothing is remembered once they complete.
+pub struct InFlight<K, V> {
+    pending: Mutex<HashMap<K, Arc<Pending<V>>, RandomState>>,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> Default for InFlight<K, V> {
+    fn default() -> InFlight<K, V> {
+        InFlight::new()
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> InFlight<K, V> {
+    pub fn new() -> InFlight<K, V> {
+        InFlight {
+            pending: Mutex::new(HashMap::with_hasher(RandomState::new())),
+        }
+    }
+
+    /// Runs the operation identified by `key`, or waits for the result of the same
+    /// operation if another thread is already running it
+    pub fn run(&self, key: K, operation: impl FnOnce() -> V) -> V {
+        let (pending, leader) = {
+            let mut pending = self.pending.lock().unwrap();
+            match pending.get(&key) {
+                Some(existing) => (existing.clone(), false),
+                None => {
+                    let new = Arc::new(Pending {
+                        state: Mutex::new(State::Running),
+                        done: Condvar::new(),
+                    });
+                    pending.insert(key.clone(), new.clone());
+                    (new, true)
+                }
+            }
+        };
+
+        if leader {
+            let guard = Completion {
+                in_flight: self,
+                key,
+                pending: &pending,
+            };
+            let value = operation();
+            *pending.state.lock().unwrap() = State::Done(value.clone());
+            drop(guard);
+            return value;
+        }
+
+        let state = pending
+            .done
+            .wait_while(pending.state.lock().unwrap(), |state| {
+                matches!(state, State::Running)
+            })
+            .unwrap();
+        match &*state {
+            State::Done(value) => value.clone(),
+            // The operation is run again rather than failing all the waiting threads.
+            _ => {
+                drop(state);
+                operation()
+            }
+        }
+    }
+
+    /// Number of operations running
+    pub fn len(&self) -> usize {
+        self.pending.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Stops tracking an operation when it completes, and wakes up the threads waiting for it,
+/// even if the thread running it panicked
+struct Completion<'a, K: Hash + Eq, V> {
+    in_flight: &'a InFlight<K, V>,
+    key: K,
+    pending: &'a Pending<V>,
+}
+
+impl<K: Hash + Eq, V> Drop for Completion<'_, K, V> {
+    fn drop(&mut self) {
+        self.in_flight.pending.lock().unwrap().remove(&self.key);
+
+        let mut state = self
+            .pending
+            .state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if matches!(*state, State::Running) {
+            *state = State::Abandoned;
+        }
+        self.pending.done.notify_all();
+    }
+}
+
+/// The `Outstanding` struct tracks the requests being answered, so that a client sending a
+/// request again before getting an answer can be told apart from a new request: the answer
+/// to the first one answers the retry as well.
+pub struct Outstanding<K> {
+    keys: Mutex<HashSet<K, RandomState>>,
+}
+
+impl<K: Hash + Eq + Clone> Default for Outstanding<K> {
+    fn default() -> Outstanding<K> {
+        Outstanding::new()
+    }
+}
+
+impl<K: Hash + Eq + Clone> Outstanding<K> {
+    pub fn new() -> Outstanding<K> {
+        Outstanding {
+            keys: Mutex::new(HashSet::with_hasher(RandomState::new())),
+        }
+    }
+
+    /// Starts tracking the request identified by `key` until the returned guard is dropped,
+    /// or returns `None` if the same request is already being answered
+    pub fn start(&self, key: K) -> Option<Started<'_, K>> {
+        if !self.keys.lock().unwrap().insert(key.clone()) {
+            return None;
+        }
+
+        Some(Started {
+            outstanding: self,
+            key,
+        })
+    }
+
+    /// Number of requests being answered
+    pub fn len(&self) -> usize {
+        self.keys.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Stops tracking a request once it has been answered
+pub struct Started<'a, K: Hash + Eq> {
+    outstanding: &'a Outstanding<K>,
+    key: K,
+}
+
+impl<K: Hash + Eq> Drop for Started<'_, K> {
+    fn drop(&mut self) {
+        self.outstanding
+            .keys
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .remove(&self.key);
+    }
+}
+
+/// What to do with queries that would exceed the resolution limits
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LimitAction {
+    /// Answer them with REFUSED
+    #[default]
+    Refuse,
+    /// Don't answer them at all, so that spoofed sources don't get a response either
+    Drop,
+}
+
+impl FromStr for LimitAction {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<LimitAction, String> {
+        match value {
+            "refuse" => Ok(LimitAction::Refuse),
+            "drop" => Ok(LimitAction::Drop),
+            _ => Err(format!("expected refuse or drop, got \"{}\"", value)),
+        }
+    }
+}
+
+impl fmt::Display for LimitAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitAction::Refuse => write!(f, "refuse"),
+            LimitAction::Drop => write!(f, "drop"),
+        }
+    }
+}
+
+/// The `Resolutions` struct counts the resolutions in progress, for each client and overall,
+/// so that a single client can't take all the resolution capacity of the server, e.g. with a
+/// pseudo-random subdomain attack whose queries all miss the cache. Limits of zero are
+/// disabled.
+pub struct Resolutions {
+    per_client: Mutex<HashMap<IpAddr, usize, RandomState>>,
+    max_per_client: usize,
+    max_total: usize,
+    /// What to do with the queries beyond the limits
+    pub action: LimitAction,
+}
+
+impl Default for Resolutions {
+    fn default() -> Resolutions {
+        Resolutions::new(0, 0)
+    }
+}
+
+impl Resolutions {
+    pub fn new(max_per_client: usize, max_total: usize) -> Resolutions {
+        Resolutions {
+            per_client: Mutex::new(HashMap::with_hasher(RandomState::new())),
+            max_per_client,
+            max_total,
+            action: LimitAction::Refuse,
+        }
+    }
+
+    pub fn with_action(mut self, action: LimitAction) -> Resolutions {
+        self.action = action;
+        self
+    }
+
+    /// Counts a resolution for a client as in progress until the returned guard is dropped,
+    /// or returns `None` if it would exceed the limits
+    pub fn start(&self, client: IpAddr) -> Option<Resolving<'_>> {
+        let mut per_client = self.per_client.lock().unwrap();
+        let count = per_client.get(&client).copied().unwrap_or(0);
+        if (self.max_per_client > 0 && count >= self.max_per_client)
+            || (self.max_total > 0 && per_client.values().sum::<usize>() >= self.max_total)
+        {
+            return None;
+        }
+
+        *per_client.entry(client).or_insert(0) += 1;
+        Some(Resolving {
+            resolutions: self,
+            client,
+        })
+    }
+
+    /// Number of resolutions in progress
+    pub fn len(&self) -> usize {
+        self.per_client.lock().unwrap().values().sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// A resolution in progress, counted against the limits until dropped
+pub struct Resolving<'a> {
+    resolutions: &'a Resolutions,
+    client: IpAddr,
+}
+
+impl Drop for Resolving<'_> {
+    fn drop(&mut self) {
+        let mut per_client = self
+            .resolutions
+            .per_client
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(count) = per_client.get_mut(&self.client) {
+            *count -= 1;
+            if *count == 0 {
+                per_client.remove(&self.client);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Barrier;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn runs_concurrent_operations_once() {
+        let in_flight = InFlight::new();
+        let runs = AtomicUsize::new(0);
+        let barrier = Barrier::new(8);
+
+        thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    barrier.wait();
+                    let value = in_flight.run("key", || {
+                        runs.fetch_add(1, Ordering::SeqCst);
+                        thread::sleep(Duration::from_millis(200));
+                        42
+                    });
+                    assert_eq!(value, 42);
+                });
+            }
+        });
+
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert!(in_flight.is_empty());
+    }
+
+    #[test]
+    fn runs_sequential_operations_again() {
+        let in_flight = InFlight::new();
+        let runs = AtomicUsize::new(0);
+
+        for _ in 0..3 {
+            in_flight.run("key", || runs.fetch_add(1, Ordering::SeqCst));
+        }
+
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn tracks_requests_until_answered() {
+        let outstanding = Outstanding::new();
+
+        let started = outstanding.start(("client", 1));
+        assert!(started.is_some());
+        assert!(outstanding.start(("client", 1)).is_none());
+        assert!(outstanding.start(("client", 2)).is_some());
+
+        drop(started);
+        assert!(outstanding.start(("client", 1)).is_some());
+        assert!(outstanding.is_empty());
+    }
+
+    #[test]
+    fn limits_resolutions_per_client_and_overall() {
+        let resolutions = Resolutions::new(2, 3);
+        let (a, b): (IpAddr, IpAddr) = ("192.0.2.1".parse().unwrap(), "192.0.2.2".parse().unwrap());
+
+        let first = resolutions.start(a);
+        let _second = resolutions.start(a);
+        assert!(first.is_some());
+        assert!(resolutions.start(a).is_none());
+
+        let _third = resolutions.start(b);
+        assert!(resolutions.start(b).is_none());
+
+        drop(first);
+        let fourth = resolutions.start(b);
+        assert!(fourth.is_some());
+        assert_eq!(resolutions.len(), 3);
+    }
+}
@@ -0,0 +1,313 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::net::Ipv4Addr;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use log::warn;
+
+use crate::alert::{Alerter, Event};
+use crate::buffer::BufferError;
+use crate::inflight::InFlight;
+use crate::packet::DnsPacket;
+use crate::question::QueryType;
+use crate::retry::RetryPolicy;
+
+/// IP of *a.root-servers.net*
+pub const A_ROOT_SERVERS_IP: Ipv4Addr = Ipv4Addr::new(198, 41, 0, 4);
+/// Port upstream servers listen on
+pub const DNS_PORT: u16 = 53;
+
+/// Smallest UDP payload size every DNS implementation must handle, as defined in RFC 1035
+const MIN_PAYLOAD_SIZE: u16 = 512;
+/// Number of consecutive lookups a server must fail to be considered down
+const DOWN_AFTER_FAILURES: u32 = 3;
+
+/// A query sent to an upstream server: its name, type and the address of the server
+pub type QueryKey = (String, QueryType, Ipv4Addr);
+/// The response to a query sent to an upstream server, shared by all the threads that sent it
+pub type SharedResponse = Result<DnsPacket, Arc<BufferError>>;
+
+/// The EDNS behaviour used when talking to an upstream server:
+/// see https://tools.ietf.org/html/rfc6891#section-7
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EdnsMode {
+    /// Send an OPT record advertising the given UDP payload size
+    Enabled(u16),
+    /// Send plain RFC 1035 queries without an OPT record
+    Disabled,
+}
+
+impl EdnsMode {
+    /// Returns the mode to retry with when a server mishandles this one.
+    /// Servers are first retried with the smallest payload size, and then without EDNS at all.
+    pub fn fallback(self) -> Option<EdnsMode> {
+        match self {
+            EdnsMode::Enabled(size) if size > MIN_PAYLOAD_SIZE => {
+                Some(EdnsMode::Enabled(MIN_PAYLOAD_SIZE))
+            }
+            EdnsMode::Enabled(_) => Some(EdnsMode::Disabled),
+            EdnsMode::Disabled => None,
+        }
+    }
+
+    /// Size of the buffer needed to receive a response sent in this mode
+    pub fn buffer_size(self) -> usize {
+        match self {
+            EdnsMode::Enabled(size) => usize::from(size.max(MIN_PAYLOAD_SIZE)),
+            EdnsMode::Disabled => usize::from(MIN_PAYLOAD_SIZE),
+        }
+    }
+}
+
+/// What is known about an upstream server
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ServerInfo {
+    /// EDNS mode known to work with the server
+    pub edns: EdnsMode,
+    /// Whether the server answers over TCP, if it was ever tried
+    pub tcp: Option<bool>,
+}
+
+/// The `InfraCache` struct remembers what is known about the upstream servers
+/// contacted during resolution, so that the capabilities of a server (its working
+/// EDNS mode, and whether it can be reached over TCP) don't have to be discovered
+/// again on every query, nor after a restart when the cache is persisted to disk.
+/// It also tracks which servers are down, alerting when they go down or recover, and the
+/// queries in flight to each server, so that identical queries are only sent once.
+/// The cache can be shared between threads.
+pub struct InfraCache {
+    default_mode: EdnsMode,
+    servers: Mutex<HashMap<Ipv4Addr, ServerInfo>>,
+    /// Whether the cache changed since it was last saved
+    dirty: AtomicBool,
+    /// Number of consecutive failed lookups of each server, which isn't persisted
+    failures: Mutex<HashMap<Ipv4Addr, u32>>,
+    alerter: Alerter,
+    pub in_flight: InFlight<QueryKey, SharedResponse>,
+    /// Server resolution starts from, and port of all the upstream servers
+    root: Ipv4Addr,
+    port: u16,
+    /// How unanswered queries to the upstream servers are retried
+    retry: RetryPolicy,
+}
+
+impl InfraCache {
+    /// Creates an empty cache. An `edns_size` of 0 disables EDNS towards all servers.
+    pub fn new(edns_size: u16) -> InfraCache {
+        let default_mode = match edns_size {
+            0 => EdnsMode::Disabled,
+            size => EdnsMode::Enabled(size.max(MIN_PAYLOAD_SIZE)),
+        };
+
+        InfraCache {
+            default_mode,
+            servers: Mutex::new(HashMap::new()),
+            dirty: AtomicBool::new(false),
+            failures: Mutex::new(HashMap::new()),
+            alerter: Alerter::default(),
+            in_flight: InFlight::new(),
+            root: A_ROOT_SERVERS_IP,
+            port: DNS_PORT,
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    /// Sets where to notify operators when servers go down or recover
+    pub fn with_alerter(mut self, alerter: Alerter) -> InfraCache {
+        self.alerter = alerter;
+        self
+    }
+
+    /// Makes resolution start from another root server, with all the upstream servers
+    /// listening on the given port, e.g. to resolve from a simulated hierarchy
+    pub fn with_root(mut self, root: Ipv4Addr, port: u16) -> InfraCache {
+        self.root = root;
+        self.port = port;
+        self
+    }
+
+    /// Sets how unanswered queries to the upstream servers are retried
+    pub fn with_retry(mut self, retry: RetryPolicy) -> InfraCache {
+        self.retry = retry;
+        self
+    }
+
+    /// Returns the root server resolution starts from
+    pub fn root(&self) -> Ipv4Addr {
+        self.root
+    }
+
+    /// Returns the port upstream servers listen on
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn retry(&self) -> RetryPolicy {
+        self.retry
+    }
+
+    /// Returns what is known about a server, with the configured default EDNS mode
+    /// for servers never contacted
+    pub fn server_info(&self, server: Ipv4Addr) -> ServerInfo {
+        self.servers
+            .lock()
+            .unwrap()
+            .get(&server)
+            .copied()
+            .unwrap_or(ServerInfo {
+                edns: self.default_mode,
+                tcp: None,
+            })
+    }
+
+    /// Returns the EDNS mode known to work with a server, or the configured default
+    pub fn edns_mode(&self, server: Ipv4Addr) -> EdnsMode {
+        self.server_info(server).edns
+    }
+
+    /// Remembers the EDNS mode that worked with a server
+    pub fn set_edns_mode(&self, server: Ipv4Addr, mode: EdnsMode) {
+        self.update(server, |info| info.edns = mode);
+    }
+
+    /// Returns whether a server answers over TCP, if it was ever tried
+    pub fn tcp_works(&self, server: Ipv4Addr) -> Option<bool> {
+        self.server_info(server).tcp
+    }
+
+    /// Remembers whether a server answered over TCP
+    pub fn set_tcp_works(&self, server: Ipv4Addr, works: bool) {
+        self.update(server, |info| info.tcp = Some(works));
+    }
+
+    /// Returns true if the last lookups of a server all failed
+    pub fn is_down(&self, server: Ipv4Addr) -> bool {
+        self.failures
+            .lock()
+            .unwrap()
+            .get(&server)
+            .copied()
+            .unwrap_or(0)
+            >= DOWN_AFTER_FAILURES
+    }
+
+    /// Records whether a lookup of a server got a response, marking it down after
+    /// consecutive failures and up again on the next response
+    pub fn record_reachability(&self, server: Ipv4Addr, reachable: bool) {
+        let mut failures = self.failures.lock().unwrap();
+        let count = failures.entry(server).or_insert(0);
+        let was_down = *count >= DOWN_AFTER_FAILURES;
+
+        if reachable {
+            failures.remove(&server);
+            if was_down {
+                self.alerter.notify(Event::ServerUp(server));
+            }
+        } else {
+            *count += 1;
+            if *count == DOWN_AFTER_FAILURES {
+                self.alerter.notify(Event::ServerDown(server));
+            }
+        }
+    }
+
+    fn update(&self, server: Ipv4Addr, f: impl FnOnce(&mut ServerInfo)) {
+        let mut servers = self.servers.lock().unwrap();
+        let info = servers.entry(server).or_insert(ServerInfo {
+            edns: self.default_mode,
+            tcp: None,
+        });
+
+        let before = *info;
+        f(info);
+        if *info != before {
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Loads the servers saved to a file by `save`. A missing file is an empty cache, and
+    /// lines that can't be parsed (e.g. written by another version) are skipped.
+    pub fn load(&self, path: &Path) -> io::Result<()> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+
+        let mut servers = self.servers.lock().unwrap();
+        for line in contents.lines() {
+            match parse_server(line, self.default_mode) {
+                Some((server, info)) => {
+                    servers.insert(server, info);
+                }
+                None => warn!("Skipping invalid infrastructure cache entry: {}", line),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Saves the servers to a file, one per line, e.g. `192.0.2.1 edns=1232 tcp=yes`
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut contents = String::new();
+        for (server, info) in self.servers.lock().unwrap().iter() {
+            let edns = match info.edns {
+                EdnsMode::Enabled(size) => size.to_string(),
+                EdnsMode::Disabled => "off".to_string(),
+            };
+            contents.push_str(&format!("{} edns={}", server, edns));
+            match info.tcp {
+                Some(true) => contents.push_str(" tcp=yes"),
+                Some(false) => contents.push_str(" tcp=no"),
+                None => {}
+            }
+            contents.push('\n');
+        }
+
+        // The file is replaced at once, so that it is never read half written.
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, contents)?;
+        fs::rename(&tmp, path)
+    }
+
+    /// Saves the cache to a file every `interval` when it changed, forever. It never returns:
+    /// the server spawns a thread for it when a file to keep the cache in is configured.
+    pub fn persist(&self, path: &Path, interval: Duration) {
+        loop {
+            thread::sleep(interval);
+            if self.dirty.swap(false, Ordering::Relaxed) {
+                if let Err(e) = self.save(path) {
+                    warn!("Failed to save the infrastructure cache: {}", e);
+                    self.dirty.store(true, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+}
+
+/// Parses a line written by `InfraCache::save`
+fn parse_server(line: &str, default_mode: EdnsMode) -> Option<(Ipv4Addr, ServerInfo)> {
+    let mut fields = line.split_whitespace();
+    let server = fields.next()?.parse().ok()?;
+
+    let mut info = ServerInfo {
+        edns: default_mode,
+        tcp: None,
+    };
+    for field in fields {
+        match field.split_once('=')? {
+            ("edns", "off") => info.edns = EdnsMode::Disabled,
+            ("edns", size) => info.edns = EdnsMode::Enabled(size.parse().ok()?),
+            ("tcp", "yes") => info.tcp = Some(true),
+            ("tcp", "no") => info.tcp = Some(false),
+            _ => return None,
+        }
+    }
+
+    Some((server, info))
+}
@@ -0,0 +1,164 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::question::QueryType;
+use crate::record::DnsRecord;
+use crate::zone::Zone;
+
+/// Number of changes kept for each zone, older changes are forgotten
+const MAX_CHANGES: usize = 100;
+
+/// The changes between two versions of a zone: the records removed from the old version,
+/// and the records added to make the new one. SOA records are only kept as the
+/// versions themselves.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Change {
+    /// SOA record of the old version
+    pub from: DnsRecord,
+    /// SOA record of the new version
+    pub to: DnsRecord,
+    pub removed: Vec<DnsRecord>,
+    pub added: Vec<DnsRecord>,
+}
+
+impl Change {
+    /// Computes the changes between two versions of a zone, if both have an SOA record
+    pub fn between(old: &Zone, new: &Zone) -> Option<Change> {
+        let old_records: HashSet<&DnsRecord> = old.records.iter().collect();
+        let new_records: HashSet<&DnsRecord> = new.records.iter().collect();
+        let is_soa = |record: &&DnsRecord| record.qtype() == QueryType::SOA;
+
+        Some(Change {
+            from: old.soa()?.clone(),
+            to: new.soa()?.clone(),
+            removed: old
+                .records
+                .iter()
+                .filter(|record| !is_soa(record) && !new_records.contains(record))
+                .cloned()
+                .collect(),
+            added: new
+                .records
+                .iter()
+                .filter(|record| !is_soa(record) && !old_records.contains(record))
+                .cloned()
+                .collect(),
+        })
+    }
+}
+
+/// The `Journal` struct records the recent changes of each zone as they are replaced by newer
+/// versions, so that secondaries can be sent only what changed since the version they have
+/// (IXFR) rather than the whole zone.
+#[derive(Debug, Default)]
+pub struct Journal {
+    changes: HashMap<String, VecDeque<Change>>,
+}
+
+impl Journal {
+    pub fn new() -> Journal {
+        Journal::default()
+    }
+
+    /// Records the changes from the old version of a zone to the new one, unless they
+    /// have the same serial
+    pub fn record(&mut self, old: &Zone, new: &Zone) {
+        if old.serial() == new.serial() {
+            return;
+        }
+        let Some(change) = Change::between(old, new) else {
+            return;
+        };
+
+        let changes = self.changes.entry(new.origin.clone()).or_default();
+        // A serial going backwards (e.g. a zone rebuilt from scratch) breaks the history.
+        if changes.back().map(|last| serial(&last.to)) != Some(serial(&change.from)) {
+            changes.clear();
+        }
+        changes.push_back(change);
+        if changes.len() > MAX_CHANGES {
+            changes.pop_front();
+        }
+    }
+
+    /// Returns the changes bringing a zone from the given serial to its latest version,
+    /// or `None` if the journal doesn't go back that far
+    pub fn changes_since(&self, origin: &str, from: u32) -> Option<Vec<Change>> {
+        let changes = self.changes.get(origin)?;
+        let start = changes
+            .iter()
+            .position(|change| serial(&change.from) == Some(from))?;
+
+        Some(changes.iter().skip(start).cloned().collect())
+    }
+
+    /// Forgets the changes of a zone
+    pub fn remove(&mut self, origin: &str) {
+        self.changes.remove(origin);
+    }
+}
+
+/// Returns the serial of an SOA record
+pub fn serial(record: &DnsRecord) -> Option<u32> {
+    match record {
+        DnsRecord::SOA { serial, .. } => Some(*serial),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn version(serial: u32, hosts: &[u8]) -> Zone {
+        let mut records = vec![DnsRecord::SOA {
+            domain: "example.com".to_string(),
+            mname: "ns.example.com".to_string(),
+            rname: "admin.example.com".to_string(),
+            serial,
+            refresh: 3600,
+            retry: 600,
+            expire: 86400,
+            minimum: 300,
+            ttl: 3600,
+        }];
+        records.extend(hosts.iter().map(|host| DnsRecord::A {
+            domain: format!("host{}.example.com", host),
+            addr: Ipv4Addr::new(192, 0, 2, *host),
+            ttl: 300,
+        }));
+        Zone {
+            origin: "example.com".to_string(),
+            records,
+        }
+    }
+
+    #[test]
+    fn changes_bring_old_versions_up_to_date() {
+        let versions = [version(1, &[1, 2]), version(2, &[2, 3]), version(3, &[3])];
+        let mut journal = Journal::new();
+        journal.record(&versions[0], &versions[1]);
+        journal.record(&versions[1], &versions[2]);
+
+        let mut zone = versions[0].clone();
+        for change in journal.changes_since("example.com", 1).unwrap() {
+            zone.apply(&change);
+        }
+        assert_eq!(zone.serial(), Some(3));
+        assert_eq!(zone.records.len(), versions[2].records.len());
+        assert!(versions[2].records.iter().all(|r| zone.records.contains(r)));
+
+        assert_eq!(journal.changes_since("example.com", 2).unwrap().len(), 1);
+        assert!(journal.changes_since("example.com", 0).is_none());
+    }
+
+    #[test]
+    fn serial_going_backwards_clears_history() {
+        let mut journal = Journal::new();
+        journal.record(&version(1, &[1]), &version(2, &[2]));
+        journal.record(&version(5, &[1]), &version(6, &[3]));
+
+        assert!(journal.changes_since("example.com", 1).is_none());
+        assert!(journal.changes_since("example.com", 5).is_some());
+    }
+}
@@ -0,0 +1,293 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::{Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::{info, warn};
+
+use crate::privacy;
+use crate::record::DnsRecord;
+use crate::reverse;
+use crate::validate::validate_name;
+
+/// TTL of the answers to the reverse lookups of leased addresses, short as leases change
+const LEASE_TTL: u32 = 60;
+
+/// The device a DHCP server leased an address to
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Lease {
+    pub mac: String,
+    /// The hostname the device sent, if any
+    pub hostname: Option<String>,
+}
+
+/// The `Leases` struct maps the addresses of LAN clients to the devices they were leased to,
+/// as read from the leases file of a DHCP server (dnsmasq or ISC dhcpd), so that queries can
+/// be told apart by device rather than by address. The file is reloaded when it changes.
+#[derive(Debug, Default)]
+pub struct Leases {
+    path: Option<PathBuf>,
+    leases: RwLock<HashMap<IpAddr, Lease>>,
+    /// Modification time of the file when it was last loaded
+    modified: Mutex<Option<SystemTime>>,
+}
+
+impl Leases {
+    /// Creates an empty table, filled from the leases file, if any, by `reload`
+    pub fn new(path: Option<PathBuf>) -> Leases {
+        Leases {
+            path,
+            ..Leases::default()
+        }
+    }
+
+    /// Returns the device an address is leased to
+    pub fn get(&self, addr: IpAddr) -> Option<Lease> {
+        self.leases.read().unwrap().get(&addr).cloned()
+    }
+
+    /// Returns all the leases, ordered by address
+    pub fn all(&self) -> Vec<(IpAddr, Lease)> {
+        let mut leases: Vec<_> = self
+            .leases
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(addr, lease)| (*addr, lease.clone()))
+            .collect();
+        leases.sort_by_key(|(addr, _)| *addr);
+        leases
+    }
+
+    /// Formats a client address for the logs, along with the hostname of the device when
+    /// it is known and client addresses aren't anonymized
+    pub fn describe(&self, addr: IpAddr) -> String {
+        let client = privacy::client(addr);
+        if privacy::anonymizes_clients() {
+            return client;
+        }
+
+        match self.get(addr).and_then(|lease| lease.hostname) {
+            Some(hostname) => format!("{} ({})", client, hostname),
+            None => client,
+        }
+    }
+
+    /// Answers the reverse lookup of a leased address with the hostname of the device, under
+    /// `domain` if given, e.g. `laptop.home.lan`. Returns `None` if the name isn't the reverse
+    /// name of a leased address, or the device didn't send a valid hostname.
+    pub fn reverse_lookup(&self, qname: &str, domain: Option<&str>) -> Option<DnsRecord> {
+        let addr = reverse::parse_ptr_name(qname)?;
+        let hostname = self.get(addr)?.hostname?.to_lowercase();
+        let host = match domain {
+            Some(domain) => format!("{}.{}", hostname, domain),
+            None => hostname,
+        };
+        validate_name(&host).ok()?;
+
+        Some(DnsRecord::PTR {
+            domain: qname.to_string(),
+            host,
+            ttl: LEASE_TTL,
+        })
+    }
+
+    /// Reloads the leases file if it changed since it was last loaded
+    pub fn reload(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        let modified = fs::metadata(path)?.modified()?;
+        let mut last = self.modified.lock().unwrap();
+        if *last == Some(modified) {
+            return Ok(());
+        }
+
+        let leases = parse(&fs::read_to_string(path)?, SystemTime::now());
+        info!(
+            "Loaded {} DHCP leases from {}",
+            leases.len(),
+            path.display()
+        );
+        *self.leases.write().unwrap() = leases;
+        *last = Some(modified);
+
+        Ok(())
+    }
+
+    /// Reloads the leases file whenever it changes, forever
+    pub fn watch(&self, interval: Duration) {
+        loop {
+            thread::sleep(interval);
+            if let Err(e) = self.reload() {
+                warn!("Failed to reload the DHCP leases: {}", e);
+            }
+        }
+    }
+}
+
+/// Parses a leases file, in the format of ISC dhcpd if it has `lease` blocks, and of
+/// dnsmasq otherwise. Expired leases are left out.
+fn parse(contents: &str, now: SystemTime) -> HashMap<IpAddr, Lease> {
+    let is_isc = contents
+        .lines()
+        .any(|line| line.trim_start().starts_with("lease "));
+    match is_isc {
+        true => parse_isc(contents),
+        false => parse_dnsmasq(contents, now),
+    }
+}
+
+/// Parses the leases of dnsmasq, one per line:
+/// `<expiry> <mac> <address> <hostname or *> <client ID or *>`, where an expiry of 0
+/// means the lease never expires
+fn parse_dnsmasq(contents: &str, now: SystemTime) -> HashMap<IpAddr, Lease> {
+    let now = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+
+    let mut leases = HashMap::new();
+    for line in contents.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [expiry, mac, addr, hostname, ..] = fields[..] else {
+            continue;
+        };
+        let (Ok(expiry), Ok(addr)) = (expiry.parse::<u64>(), addr.parse()) else {
+            continue;
+        };
+        if expiry != 0 && expiry < now {
+            continue;
+        }
+
+        leases.insert(
+            addr,
+            Lease {
+                mac: mac.to_lowercase(),
+                hostname: (hostname != "*").then(|| hostname.to_string()),
+            },
+        );
+    }
+
+    leases
+}
+
+/// Parses the leases of ISC dhcpd, blocks of statements appended as leases change, so the
+/// last block of an address is the current one. Leases not in the active state are left out.
+fn parse_isc(contents: &str) -> HashMap<IpAddr, Lease> {
+    let mut leases = HashMap::new();
+    let mut current: Option<(IpAddr, Lease, bool)> = None;
+
+    for line in contents.lines() {
+        let line = line.trim().trim_end_matches(';');
+        if let Some(rest) = line.strip_prefix("lease ") {
+            current = rest.trim_end_matches('{').trim().parse().ok().map(|addr| {
+                let lease = Lease {
+                    mac: String::new(),
+                    hostname: None,
+                };
+                (addr, lease, true)
+            });
+            continue;
+        }
+
+        let Some((addr, lease, active)) = &mut current else {
+            continue;
+        };
+        if line == "}" {
+            match active {
+                true => leases.insert(*addr, lease.clone()),
+                false => leases.remove(addr),
+            };
+            current = None;
+        } else if let Some(state) = line.strip_prefix("binding state ") {
+            *active = state == "active";
+        } else if let Some(mac) = line.strip_prefix("hardware ethernet ") {
+            lease.mac = mac.to_lowercase();
+        } else if let Some(hostname) = line.strip_prefix("client-hostname ") {
+            lease.hostname = Some(hostname.trim_matches('"').to_string());
+        }
+    }
+
+    leases
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_dnsmasq_leases() {
+        let contents = "\
+1700000100 AA:BB:CC:DD:EE:01 192.168.1.20 laptop 01:aa:bb:cc:dd:ee:01
+1600000000 aa:bb:cc:dd:ee:02 192.168.1.21 expired *
+0 aa:bb:cc:dd:ee:03 192.168.1.22 * *
+";
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let leases = parse(contents, now);
+
+        assert_eq!(leases.len(), 2);
+        assert_eq!(
+            leases[&"192.168.1.20".parse().unwrap()],
+            Lease {
+                mac: "aa:bb:cc:dd:ee:01".to_string(),
+                hostname: Some("laptop".to_string()),
+            }
+        );
+        assert_eq!(leases[&"192.168.1.22".parse().unwrap()].hostname, None);
+    }
+
+    #[test]
+    fn answers_reverse_lookups_of_leases() {
+        let leases = Leases::default();
+        *leases.leases.write().unwrap() = parse(
+            "0 aa:bb:cc:dd:ee:01 192.168.1.20 Laptop *\n0 aa:bb:cc:dd:ee:02 192.168.1.21 bad_name! *\n",
+            SystemTime::now(),
+        );
+
+        assert_eq!(
+            leases.reverse_lookup("20.1.168.192.in-addr.arpa", Some("home.lan")),
+            Some(DnsRecord::PTR {
+                domain: "20.1.168.192.in-addr.arpa".to_string(),
+                host: "laptop.home.lan".to_string(),
+                ttl: LEASE_TTL,
+            })
+        );
+        assert_eq!(
+            leases.reverse_lookup("21.1.168.192.in-addr.arpa", None),
+            None
+        );
+        assert_eq!(
+            leases.reverse_lookup("22.1.168.192.in-addr.arpa", None),
+            None
+        );
+    }
+
+    #[test]
+    fn parses_isc_leases_keeping_the_last_block() {
+        let contents = r#"
+lease 192.168.1.30 {
+  binding state active;
+  hardware ethernet aa:bb:cc:dd:ee:04;
+  client-hostname "phone";
+}
+lease 192.168.1.31 {
+  binding state active;
+  hardware ethernet aa:bb:cc:dd:ee:05;
+}
+lease 192.168.1.31 {
+  binding state free;
+  hardware ethernet aa:bb:cc:dd:ee:05;
+}
+"#;
+        let leases = parse(contents, SystemTime::now());
+
+        assert_eq!(leases.len(), 1);
+        assert_eq!(
+            leases[&"192.168.1.30".parse().unwrap()].hostname.as_deref(),
+            Some("phone")
+        );
+    }
+}
@@ -0,0 +1,70 @@
+//! vòdo is a primitive DNS server written in Rust for fun.
+//!
+//! Besides the server binary, the crate exposes the building blocks it is made of,
+//! such as `packet::DnsPacket` for parsing and serializing DNS messages.
+
+#[cfg(feature = "admin")]
+pub mod acme;
+#[cfg(feature = "admin")]
+pub mod admin;
+pub mod alert;
+pub mod audit;
+pub mod buffer;
+pub mod cache;
+#[cfg(feature = "catalog")]
+pub mod catalog;
+#[cfg(feature = "chaos")]
+pub mod chaos;
+pub mod clock;
+pub mod config;
+pub mod conformance;
+pub mod correlation;
+#[cfg(feature = "tools")]
+pub mod decode;
+pub mod dump;
+pub mod filter;
+pub mod handler;
+pub mod header;
+pub mod holddown;
+pub mod inflight;
+pub mod infra;
+pub mod journal;
+pub mod leases;
+pub mod listener;
+pub mod memory;
+pub mod mode;
+pub mod notify;
+pub mod opcode;
+pub mod opt;
+pub mod packet;
+pub mod policy;
+pub mod privacy;
+pub mod profiling;
+#[cfg(feature = "tools")]
+pub mod query;
+pub mod question;
+pub mod queue;
+pub mod reader;
+pub mod record;
+pub mod resolver;
+pub mod resultcode;
+pub mod retry;
+pub mod reverse;
+pub mod schema;
+pub mod selftest;
+pub mod simulation;
+pub mod socket;
+pub mod stamp;
+pub mod stats;
+pub mod storm;
+pub mod subnet;
+pub mod svcb;
+#[cfg(feature = "tcp")]
+pub mod tcp;
+pub mod topn;
+pub mod transfer;
+pub mod udp;
+pub mod validate;
+pub mod verbosity;
+pub mod warmup;
+pub mod zone;
@@ -0,0 +1,327 @@
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, SocketAddrV4};
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::queue::OverflowPolicy;
+use crate::socket::BufferSizes;
+use crate::subnet::Subnet;
+
+/// Transports queries are received over
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transport {
+    Udp,
+    Tcp,
+}
+
+impl FromStr for Transport {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Transport, String> {
+        match value.to_ascii_lowercase().as_str() {
+            "udp" => Ok(Transport::Udp),
+            "tcp" => Ok(Transport::Tcp),
+            "dot" | "doh" | "doq" => Err(format!("transport {} is not supported", value)),
+            _ => Err(format!("expected udp or tcp, got \"{}\"", value)),
+        }
+    }
+}
+
+impl fmt::Display for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Transport::Udp => write!(f, "UDP"),
+            Transport::Tcp => write!(f, "TCP"),
+        }
+    }
+}
+
+/// Limits applied to client TCP connections, so that clients keeping connections open
+/// (or sending queries a byte at a time) can't exhaust the resources of the server.
+#[derive(Clone, Copy, Debug)]
+pub struct TcpLimits {
+    /// Maximum number of connections open at the same time
+    pub max_connections: usize,
+    /// Maximum number of queries answered on a connection before closing it
+    pub max_queries: usize,
+    /// Maximum number of queries of a connection answered at the same time
+    pub max_pipelined: usize,
+    /// Time a client has to send a complete query before the connection is closed
+    pub idle_timeout: Duration,
+}
+
+/// Limits of the UDP server: how many workers answer the queries, and how many queries can
+/// wait for them
+#[derive(Clone, Copy, Debug)]
+pub struct UdpLimits {
+    pub workers: usize,
+    /// Maximum number of queries waiting for a worker
+    pub queue_size: usize,
+    /// What to do with queries arriving when the queue is full
+    pub queue_policy: OverflowPolicy,
+}
+
+/// Limits of a listener, which depend on its transport
+#[derive(Clone, Copy, Debug)]
+pub enum Limits {
+    Udp(UdpLimits),
+    Tcp(TcpLimits),
+}
+
+/// The `Listener` struct holds the configuration of a socket queries are received on: its
+/// transport, address, kernel buffers, the clients allowed to use it and its limits. Each
+/// listener is served independently, and all of them feed the same handler.
+#[derive(Clone, Debug)]
+pub struct Listener {
+    pub address: SocketAddrV4,
+    pub buffers: BufferSizes,
+    /// Clients allowed to send queries, everyone if empty
+    pub allow: Vec<Subnet>,
+    pub limits: Limits,
+}
+
+impl Listener {
+    pub fn transport(&self) -> Transport {
+        match self.limits {
+            Limits::Udp(_) => Transport::Udp,
+            Limits::Tcp(_) => Transport::Tcp,
+        }
+    }
+
+    /// Whether a client is allowed to send queries to the listener
+    pub fn allows(&self, client: IpAddr) -> bool {
+        self.allow.is_empty() || self.allow.iter().any(|subnet| subnet.contains(client))
+    }
+
+    /// Overrides a setting of the listener
+    pub fn apply(&mut self, setting: &Setting) {
+        match (setting, &mut self.limits) {
+            (Setting::Allow(subnet), _) => self.allow.push(*subnet),
+            (Setting::RecvBuffer(size), _) => self.buffers.recv = *size,
+            (Setting::SendBuffer(size), _) => self.buffers.send = *size,
+            (Setting::Workers(workers), Limits::Udp(limits)) => limits.workers = *workers,
+            (Setting::QueueSize(size), Limits::Udp(limits)) => limits.queue_size = *size,
+            (Setting::QueuePolicy(policy), Limits::Udp(limits)) => limits.queue_policy = *policy,
+            (Setting::MaxConnections(max), Limits::Tcp(limits)) => limits.max_connections = *max,
+            (Setting::MaxQueries(max), Limits::Tcp(limits)) => limits.max_queries = *max,
+            (Setting::MaxPipelined(max), Limits::Tcp(limits)) => limits.max_pipelined = *max,
+            (Setting::IdleTimeout(timeout), Limits::Tcp(limits)) => limits.idle_timeout = *timeout,
+            // Settings of the other transport are rejected when parsed.
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for Listener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} on {} (", self.transport(), self.address)?;
+        match &self.limits {
+            Limits::Udp(limits) => write!(
+                f,
+                "{} workers, queue of {} ({})",
+                limits.workers, limits.queue_size, limits.queue_policy
+            )?,
+            Limits::Tcp(limits) => write!(
+                f,
+                "{} connections, {} queries each, {} at a time, {}s idle timeout",
+                limits.max_connections,
+                limits.max_queries,
+                limits.max_pipelined,
+                limits.idle_timeout.as_secs()
+            )?,
+        }
+        write!(
+            f,
+            ", buffers of {}/{} bytes",
+            self.buffers.recv, self.buffers.send
+        )?;
+        if !self.allow.is_empty() {
+            let allow: Vec<String> = self
+                .allow
+                .iter()
+                .map(|subnet| format!("{}/{}", subnet.addr, subnet.prefix))
+                .collect();
+            write!(f, ", allowing {}", allow.join(" "))?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// A setting of a listener block, overriding the global one
+#[derive(Clone, Debug)]
+pub enum Setting {
+    Allow(Subnet),
+    RecvBuffer(usize),
+    SendBuffer(usize),
+    Workers(usize),
+    QueueSize(usize),
+    QueuePolicy(OverflowPolicy),
+    MaxConnections(usize),
+    MaxQueries(usize),
+    MaxPipelined(usize),
+    IdleTimeout(Duration),
+}
+
+impl Setting {
+    /// Parses a `<KEY>=<VALUE>` setting of a listener with the given transport
+    fn parse(value: &str, transport: Transport) -> Result<Setting, String> {
+        let (key, setting) = value
+            .split_once('=')
+            .ok_or_else(|| format!("expected <KEY>=<VALUE>, got \"{}\"", value))?;
+        let number = || -> Result<usize, String> {
+            setting
+                .parse()
+                .map_err(|_| format!("invalid value \"{}\" for {}", setting, key))
+        };
+
+        let (setting, only) = match key {
+            "allow" => (
+                Setting::Allow(setting.parse().map_err(|e| format!("{}", e))?),
+                None,
+            ),
+            "rcvbuf" => (Setting::RecvBuffer(number()?), None),
+            "sndbuf" => (Setting::SendBuffer(number()?), None),
+            "workers" => (Setting::Workers(number()?), Some(Transport::Udp)),
+            "queue-size" => (Setting::QueueSize(number()?), Some(Transport::Udp)),
+            "queue-policy" => (Setting::QueuePolicy(setting.parse()?), Some(Transport::Udp)),
+            "max-connections" => (Setting::MaxConnections(number()?), Some(Transport::Tcp)),
+            "max-queries" => (Setting::MaxQueries(number()?), Some(Transport::Tcp)),
+            "max-pipelined" => (Setting::MaxPipelined(number()?), Some(Transport::Tcp)),
+            "idle-timeout" => (
+                Setting::IdleTimeout(Duration::from_secs(number()? as u64)),
+                Some(Transport::Tcp),
+            ),
+            _ => return Err(format!("unknown listener setting \"{}\"", key)),
+        };
+
+        match only {
+            Some(only) if only != transport => {
+                Err(format!("{} only applies to {} listeners", key, only))
+            }
+            _ => Ok(setting),
+        }
+    }
+}
+
+/// A listener block given on the command line, in the
+/// `<TRANSPORT>:<ADDRESS>[:<PORT>][,<KEY>=<VALUE>...]` form, e.g.
+/// `udp:192.168.1.1,allow=192.168.1.0/24,workers=8`. Settings not given are the global ones.
+#[derive(Clone, Debug)]
+pub struct ListenerSpec {
+    pub transport: Transport,
+    pub address: Ipv4Addr,
+    /// Port of the listener, the global one if not given
+    pub port: Option<u16>,
+    pub settings: Vec<Setting>,
+}
+
+impl ListenerSpec {
+    /// Builds the listener, overriding the global settings with those of the block
+    pub fn resolve(
+        &self,
+        port: u16,
+        buffers: BufferSizes,
+        udp: UdpLimits,
+        tcp: TcpLimits,
+    ) -> Listener {
+        let mut listener = Listener {
+            address: SocketAddrV4::new(self.address, self.port.unwrap_or(port)),
+            buffers,
+            allow: Vec::new(),
+            limits: match self.transport {
+                Transport::Udp => Limits::Udp(udp),
+                Transport::Tcp => Limits::Tcp(tcp),
+            },
+        };
+        for setting in &self.settings {
+            listener.apply(setting);
+        }
+        listener
+    }
+}
+
+impl FromStr for ListenerSpec {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<ListenerSpec, String> {
+        let mut parts = value.split(',');
+        let head = parts.next().unwrap_or_default();
+        let (transport, address) = head
+            .split_once(':')
+            .ok_or_else(|| format!("expected <TRANSPORT>:<ADDRESS>[:<PORT>], got \"{}\"", head))?;
+        let transport: Transport = transport.parse()?;
+
+        let (address, port) = match address.split_once(':') {
+            Some((address, port)) => (
+                address,
+                Some(
+                    port.parse()
+                        .map_err(|_| format!("invalid port \"{}\"", port))?,
+                ),
+            ),
+            None => (address, None),
+        };
+        let address = address
+            .parse()
+            .map_err(|_| format!("invalid IPv4 address \"{}\"", address))?;
+
+        let settings = parts
+            .map(|setting| Setting::parse(setting, transport))
+            .collect::<Result<_, _>>()?;
+
+        Ok(ListenerSpec {
+            transport,
+            address,
+            port,
+            settings,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_listener_blocks() {
+        let udp = UdpLimits {
+            workers: 4,
+            queue_size: 1024,
+            queue_policy: OverflowPolicy::DropOldest,
+        };
+        let tcp = TcpLimits {
+            max_connections: 64,
+            max_queries: 100,
+            max_pipelined: 16,
+            idle_timeout: Duration::from_secs(10),
+        };
+
+        let spec: ListenerSpec = "udp:192.168.1.1,allow=192.168.1.0/24,workers=8"
+            .parse()
+            .unwrap();
+        let listener = spec.resolve(5353, BufferSizes::default(), udp, tcp);
+        assert_eq!(listener.address.to_string(), "192.168.1.1:5353");
+        assert!(listener.allows("192.168.1.20".parse().unwrap()));
+        assert!(!listener.allows("10.0.0.1".parse().unwrap()));
+        match listener.limits {
+            Limits::Udp(limits) => {
+                assert_eq!(limits.workers, 8);
+                assert_eq!(limits.queue_size, 1024);
+            }
+            Limits::Tcp(_) => panic!("expected an UDP listener"),
+        }
+
+        let spec: ListenerSpec = "tcp:0.0.0.0:853,max-pipelined=4".parse().unwrap();
+        let listener = spec.resolve(5353, BufferSizes::default(), udp, tcp);
+        assert_eq!(listener.address.port(), 853);
+        assert!(listener.allows("10.0.0.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn rejects_settings_of_other_transports() {
+        assert!("tcp:0.0.0.0,workers=8".parse::<ListenerSpec>().is_err());
+        assert!("udp:0.0.0.0,max-queries=8".parse::<ListenerSpec>().is_err());
+        assert!("dot:0.0.0.0".parse::<ListenerSpec>().is_err());
+        assert!("udp:0.0.0.0,colour=blue".parse::<ListenerSpec>().is_err());
+    }
+}
@@ -1,48 +1,1227 @@
-mod buffer;
-mod handler;
-mod header;
-mod packet;
-mod question;
-mod record;
-mod resultcode;
-
-use clap::Parser;
-use handler::handle_query;
-use log::{info, warn};
-use simplelog::{ColorChoice, Config, LevelFilter, TermLogger, TerminalMode};
-use std::{error::Error, net::UdpSocket};
+use clap::{CommandFactory, Parser, Subcommand};
+use log::{error, info, warn};
+use simplelog::{ColorChoice, LevelFilter, TermLogger, TerminalMode};
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4, TcpListener, UdpSocket},
+    path::PathBuf,
+    process,
+    str::FromStr,
+    sync::{Arc, RwLock},
+    thread,
+    time::Duration,
+};
+#[cfg(feature = "tools")]
+use std::{fs, path::Path};
+#[cfg(feature = "admin")]
+use vodo::admin::Admin;
+use vodo::alert::{Alerter, Webhook};
+use vodo::cache::Cache;
+#[cfg(feature = "catalog")]
+use vodo::catalog::{Catalog, CatalogConsumer};
+#[cfg(feature = "chaos")]
+use vodo::chaos::{self, Chaos, Fault};
+use vodo::config::Config;
+use vodo::correlation::CorrelatedLogger;
+#[cfg(feature = "tools")]
+use vodo::decode::{diff, read_messages};
+#[cfg(feature = "tools")]
+use vodo::dump::dump;
+use vodo::filter::AddressFilter;
+use vodo::handler::Context;
+use vodo::holddown::HoldDown;
+use vodo::inflight::{LimitAction, Outstanding, Resolutions};
+use vodo::infra::InfraCache;
+use vodo::leases::Leases;
+use vodo::listener::{Limits, Listener, ListenerSpec, TcpLimits, Transport, UdpLimits};
+use vodo::memory;
+use vodo::mode::{Maintenance, Modes};
+use vodo::notify::Notifier;
+#[cfg(feature = "tools")]
+use vodo::packet::DnsPacket;
+use vodo::policy::{Allowlist, BlockedTtl, PolicyEngine, ResponsePolicyZone};
+use vodo::privacy::{self, Privacy, Profile};
+use vodo::profiling;
+#[cfg(feature = "tools")]
+use vodo::query::{format_response, Query};
+use vodo::question::QueryType;
+use vodo::queue::OverflowPolicy;
+use vodo::retry::RetryPolicy;
+use vodo::reverse;
+use vodo::schema;
+use vodo::selftest;
+use vodo::socket::{self, BufferSizes};
+use vodo::stats::Stats;
+use vodo::storm::StormGuard;
+use vodo::subnet::{Subnet, SubnetMap};
+#[cfg(feature = "tcp")]
+use vodo::tcp::TcpServer;
+use vodo::transfer::{TransferLimits, ZoneTransfers};
+use vodo::udp::UdpServer;
+use vodo::warmup::warm_up;
+use vodo::zone::{load_hosts, load_name_list, Authority, Zone, ZoneError};
+
+/// How often the infrastructure cache is saved to disk, when it changed
+const INFRA_CACHE_SAVE_INTERVAL: Duration = Duration::from_secs(60);
+/// Window over which the SERVFAIL rate is computed for alerts
+const SERVFAIL_RATE_INTERVAL: Duration = Duration::from_secs(60);
+/// How often the DHCP leases file is checked for changes
+const LEASES_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+/// How often the memory usage is measured, and checked against the cap
+const MEMORY_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// How often the serials of the zones are checked, to notify their secondaries of changes
+const NOTIFY_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+/// How often the serials of the transferred policy zones are checked, to transfer them again
+const RPZ_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
 
 #[derive(Parser, Debug)]
 #[command(author, version, about)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Port for the server to listen on
-    #[arg(short, long = "port", default_value_t = 5353)]
+    #[arg(short, long = "port", default_value_t = 5353, global = true)]
     port: u16,
+
+    /// Listener as <TRANSPORT>:<ADDRESS>[:<PORT>][,<KEY>=<VALUE>...] overriding the global settings, instead of UDP and TCP on all addresses (can be repeated)
+    #[arg(long = "listen", global = true)]
+    listen: Vec<ListenerSpec>,
+
+    /// Address clients reach the server at, when a NAT or container maps it to the port of the listeners, as <ADDRESS>[:<PORT>]
+    #[arg(long = "advertise", alias = "behind-nat", value_parser = parse_server_address, global = true)]
+    advertise: Option<SocketAddr>,
+
+    /// Search suffix used to expand single-label queries (can be repeated)
+    #[arg(short, long = "search", global = true)]
+    search: Vec<String>,
+
+    /// UDP payload size advertised to upstream servers via EDNS (0 disables EDNS)
+    #[arg(long = "edns-size", default_value_t = 1232, global = true)]
+    edns_size: u16,
+
+    /// Size in bytes of the kernel receive buffer of the listening sockets
+    #[arg(long = "so-rcvbuf", default_value_t = BufferSizes::default().recv, global = true)]
+    so_rcvbuf: usize,
+
+    /// Size in bytes of the kernel send buffer of the listening sockets
+    #[arg(long = "so-sndbuf", default_value_t = BufferSizes::default().send, global = true)]
+    so_sndbuf: usize,
+
+    /// Maximum size of UDP responses, beyond which they are truncated
+    #[arg(long = "max-udp-size", default_value_t = 1232, global = true)]
+    max_udp_size: u16,
+
+    /// Maximum size of UDP responses to clients in a subnet, given as <SUBNET>=<SIZE> (can be repeated)
+    #[arg(long = "subnet-max-udp-size", value_parser = parse_subnet_arg::<u16>, global = true)]
+    subnet_max_udp_size: Vec<(Subnet, u16)>,
+
+    /// Address records to withhold from answers: none, aaaa (for IPv4-only networks) or a
+    /// (for IPv6-only networks)
+    #[arg(long = "address-filter", default_value_t = AddressFilter::None, global = true)]
+    address_filter: AddressFilter,
+
+    /// Address records to withhold from answers to clients in a subnet, given as
+    /// <SUBNET>=<FILTER> (can be repeated)
+    #[arg(long = "subnet-address-filter", value_parser = parse_subnet_arg::<AddressFilter>, global = true)]
+    subnet_address_filter: Vec<(Subnet, AddressFilter)>,
+
+    /// Zone to host, given as <ORIGIN>=<PATH> to a master file, with the subnet as origin for reverse zones (can be repeated)
+    #[arg(short, long = "zone", value_parser = parse_zone_arg, global = true)]
+    zone: Vec<(String, PathBuf)>,
+
+    /// Catalog zone whose member zones to serve as a secondary, given as <ORIGIN>=<PRIMARY>
+    /// where the primary is an IP address with an optional port (can be repeated)
+    #[cfg(feature = "catalog")]
+    #[arg(long = "catalog", value_parser = parse_catalog_arg, global = true)]
+    catalog: Vec<Catalog>,
+
+    /// Response policy zone to apply, given as <ORIGIN>=<PATH> to a master file (can be repeated)
+    #[arg(long = "rpz", value_parser = parse_zone_arg, global = true)]
+    rpz: Vec<(String, PathBuf)>,
+
+    /// Response policy zone to transfer with AXFR, given as <ORIGIN>=<PRIMARY> (can be repeated)
+    #[arg(long = "rpz-primary", value_parser = parse_primary_arg, global = true)]
+    rpz_primary: Vec<(String, SocketAddr)>,
+
+    /// TTL in seconds of the answers to queries blocked by a policy
+    #[arg(long = "blocked-ttl", default_value_t = 300, global = true)]
+    blocked_ttl: u32,
+
+    /// TTL of the answers to blocked queries of a type, given as <TYPE>=<SECONDS> (can be repeated)
+    #[arg(long = "blocked-ttl-type", value_parser = parse_type_ttl_arg, global = true)]
+    blocked_ttl_type: Vec<(QueryType, u32)>,
+
+    /// List of the only domains to resolve, one per line, along with their subdomains;
+    /// queries for any other name are refused
+    #[arg(long = "allowlist", global = true)]
+    allowlist: Option<PathBuf>,
+
+    /// Hosts file whose entries override any other answer
+    #[arg(long = "hosts", global = true)]
+    hosts: Option<PathBuf>,
+
+    /// List of domains to resolve in the background on startup, one per line
+    #[arg(long = "warmup", global = true)]
+    warmup: Option<PathBuf>,
+
+    /// Number of threads answering UDP queries
+    #[arg(long = "workers", default_value_t = 4, global = true)]
+    workers: usize,
+
+    /// Maximum number of UDP queries waiting to be answered
+    #[arg(long = "queue-size", default_value_t = 1024, global = true)]
+    queue_size: usize,
+
+    /// What to do with UDP queries when the queue is full: drop-newest, drop-oldest or block
+    #[arg(long = "queue-policy", default_value_t = OverflowPolicy::DropOldest, global = true)]
+    queue_policy: OverflowPolicy,
+
+    /// Maximum number of TCP connections open at the same time
+    #[arg(long = "tcp-max-connections", default_value_t = 64, global = true)]
+    tcp_max_connections: usize,
+
+    /// Maximum number of queries answered on a TCP connection before closing it
+    #[arg(long = "tcp-max-queries", default_value_t = 100, global = true)]
+    tcp_max_queries: usize,
+
+    /// Maximum number of queries pipelined on a TCP connection answered at the same time
+    #[arg(long = "tcp-max-pipelined", default_value_t = 16, global = true)]
+    tcp_max_pipelined: usize,
+
+    /// Seconds a TCP client has to send a query before its connection is closed
+    #[arg(long = "tcp-idle-timeout", default_value_t = 10, global = true)]
+    tcp_idle_timeout: u64,
+
+    /// Recurse even for queries with RD=0, instead of only answering them from the cache
+    #[arg(long = "always-recurse", global = true)]
+    always_recurse: bool,
+
+    /// Answer with the records CNAME chains lead to, under the queried name, instead of the chains
+    #[arg(long = "flatten-cnames", global = true)]
+    flatten_cnames: bool,
+
+    /// Maximum seconds a question is answered SERVFAIL without recursing after failing to resolve (0 to disable)
+    #[arg(long = "servfail-hold-down", default_value_t = 30, value_parser = clap::value_parser!(u64).range(0..=300), global = true)]
+    servfail_hold_down: u64,
+
+    /// Milliseconds to wait for an upstream server to answer the first attempt of a query
+    #[arg(long = "retry-timeout", default_value_t = 1000, value_parser = clap::value_parser!(u64).range(1..=30_000), global = true)]
+    retry_timeout: u64,
+
+    /// Factor the wait for an upstream server grows by with each attempt, between 1 and 10
+    #[arg(long = "retry-multiplier", default_value_t = 2.0, value_parser = parse_multiplier_arg, global = true)]
+    retry_multiplier: f64,
+
+    /// Number of times a query is sent to an upstream server before giving up on it
+    #[arg(long = "retry-attempts", default_value_t = 2, value_parser = clap::value_parser!(u32).range(1..=10), global = true)]
+    retry_attempts: u32,
+
+    /// Ratio of each wait it's randomly shortened or lengthened by (e.g. 0.1)
+    #[arg(long = "retry-jitter", default_value_t = 0.1, value_parser = parse_ratio_arg, global = true)]
+    retry_jitter: f64,
+
+    /// Forward the reverse lookups of private addresses (e.g. 10.0.0.0/8) instead of answering NXDOMAIN
+    #[arg(long = "forward-private-reverse", global = true)]
+    forward_private_reverse: bool,
+
+    /// Maximum number of resolutions in progress for the same client (0 for unlimited)
+    #[arg(
+        long = "max-resolutions-per-client",
+        default_value_t = 0,
+        global = true
+    )]
+    max_resolutions_per_client: usize,
+
+    /// Maximum number of resolutions in progress overall (0 for unlimited)
+    #[arg(long = "max-resolutions", default_value_t = 0, global = true)]
+    max_resolutions: usize,
+
+    /// Ratio of NXDOMAIN answers under a domain above which it is considered under a random
+    /// subdomain attack, and most queries for it answered NXDOMAIN locally (0 to disable)
+    #[arg(long = "nxdomain-storm-ratio", default_value_t = 0.9, value_parser = parse_ratio_arg, global = true)]
+    nxdomain_storm_ratio: f64,
+
+    /// Minimum number of resolutions under a domain in 10 seconds to consider it under attack
+    #[arg(long = "nxdomain-storm-min", default_value_t = 100, global = true)]
+    nxdomain_storm_min: u32,
+
+    /// What to do with queries beyond the resolution limits: refuse, or drop to not answer them
+    #[arg(long = "resolution-limit-action", default_value_t = LimitAction::Refuse, global = true)]
+    resolution_limit_action: LimitAction,
+
+    /// Start in maintenance mode, answering queries with an error: off, servfail or refused
+    #[arg(long = "maintenance", default_value_t = Maintenance::Off, global = true)]
+    maintenance: Maintenance,
+
+    /// Start in read-only mode, with no updates to the zones and no new records in the cache
+    #[arg(long = "read-only", global = true)]
+    read_only: bool,
+
+    /// Name whose queries are answered even in maintenance mode, for health checks (can be repeated)
+    #[arg(long = "health-check", global = true)]
+    health_check: Vec<String>,
+
+    /// Maximum number of zone transfers at the same time
+    #[arg(long = "transfer-max-concurrent", default_value_t = 4, global = true)]
+    transfer_max_concurrent: usize,
+
+    /// Maximum number of zone transfers at the same time to the same client
+    #[arg(long = "transfer-max-per-client", default_value_t = 1, global = true)]
+    transfer_max_per_client: usize,
+
+    /// Maximum rate of each zone transfer in KiB per second (unlimited if not set)
+    #[arg(long = "transfer-rate", global = true)]
+    transfer_rate: Option<u64>,
+
+    /// Maximum size of a zone transfer in MiB, beyond which it is refused
+    #[arg(long = "transfer-max-size", default_value_t = 64, global = true)]
+    transfer_max_size: usize,
+
+    /// Clients allowed to transfer a hosted zone, given as <ORIGIN>=<SUBNET> (can be repeated,
+    /// zones without any can be transferred by everyone)
+    #[arg(long = "allow-transfer", value_parser = parse_allow_transfer_arg, global = true)]
+    allow_transfer: Vec<(String, Subnet)>,
+
+    /// Secondary to notify when a hosted zone changes, given as <ORIGIN>=<ADDRESS> where the
+    /// address is an IP address with an optional port (can be repeated)
+    #[arg(long = "also-notify", value_parser = parse_also_notify_arg, global = true)]
+    also_notify: Vec<(String, SocketAddr)>,
+
+    /// Primaries allowed to send NOTIFY messages, given as a subnet (can be repeated, NOTIFY
+    /// messages are refused if not given)
+    #[arg(long = "allow-notify", global = true)]
+    allow_notify: Vec<Subnet>,
+
+    /// Resolve queries without answering them, logging the upstream queries they generate
+    #[arg(long = "audit", global = true)]
+    audit: bool,
+
+    /// Time the stages of answering queries (parsing, cache lookups, upstream waits,
+    /// serializing and sending), exposed as histograms in the statistics
+    #[arg(long = "profile-stages", global = true)]
+    profile_stages: bool,
+
+    /// Fraction of the upstream responses to inject faults into (e.g. 0.1), to test how clients behave when resolution degrades
+    #[cfg(feature = "chaos")]
+    #[arg(long = "chaos-fraction", value_parser = parse_ratio_arg, global = true)]
+    chaos_fraction: Option<f64>,
+
+    /// Faults injected into upstream responses: delay, drop or corrupt (can be repeated, all of them if not given)
+    #[cfg(feature = "chaos")]
+    #[arg(long = "chaos-fault", global = true)]
+    chaos_fault: Vec<Fault>,
+
+    /// Milliseconds delayed upstream responses are held back
+    #[cfg(feature = "chaos")]
+    #[arg(long = "chaos-delay", default_value_t = 1000, global = true)]
+    chaos_delay: u64,
+
+    /// Code of an EDNS option (e.g. 65001) that clients add to their queries to have the origin of the answer described in it: how it was answered, by which upstream server and where its TTL comes from
+    #[arg(long = "debug-option", global = true)]
+    debug_option: Option<u16>,
+
+    /// Root server to start resolutions from instead of a.root-servers.net, with all the upstream servers listening on its port, e.g. a test hierarchy
+    #[arg(long = "root-server", global = true)]
+    root_server: Option<SocketAddrV4>,
+
+    /// File the capabilities of upstream servers are saved to and restored from across restarts
+    #[arg(long = "infra-cache", global = true)]
+    infra_cache: Option<PathBuf>,
+
+    /// Leases file of a DHCP server (dnsmasq or ISC dhcpd), to identify clients by device
+    #[arg(long = "dhcp-leases", global = true)]
+    dhcp_leases: Option<PathBuf>,
+
+    /// Truncate client addresses in the logs to their /24 (IPv4) or /56 (IPv6) subnet
+    #[arg(long = "anonymize-clients", global = true)]
+    anonymize_clients: bool,
+
+    /// Replace the names looked up by clients with a keyed hash in the logs
+    #[arg(long = "hash-names", global = true)]
+    hash_names: bool,
+
+    /// How much the queries sent upstream reveal: standard, or strict to minimize them
+    #[arg(long = "privacy", default_value_t = Profile::Standard, global = true)]
+    privacy: Profile,
+
+    /// Approximate memory usage in MiB above which the cache is evicted to make room
+    #[arg(long = "memory-limit", global = true)]
+    memory_limit: Option<usize>,
+
+    /// Command run by `sh -c` when an upstream server goes down or recovers, or the SERVFAIL
+    /// rate crosses the threshold, with the event in $VODO_EVENT and $VODO_DETAIL
+    #[arg(long = "alert-command", global = true)]
+    alert_command: Option<String>,
+
+    /// URL of a webhook the same events are posted to as JSON, e.g. http://127.0.0.1:8080/alerts
+    #[arg(long = "alert-webhook", global = true)]
+    alert_webhook: Option<Webhook>,
+
+    /// Ratio of queries answered with SERVFAIL (e.g. 0.2) above which to alert
+    #[arg(long = "alert-servfail-rate", global = true)]
+    alert_servfail_rate: Option<f64>,
+
+    /// Port on 127.0.0.1 for the admin API serving statistics and ACME challenges (disabled if not set)
+    #[cfg(feature = "admin")]
+    #[arg(long = "admin-port", global = true)]
+    admin_port: Option<u16>,
+
+    /// Print the effective configuration and exit
+    #[arg(long = "print-config", global = true)]
+    print_config: bool,
+}
+
+impl Args {
+    /// Resolves the arguments into the runtime configuration
+    fn resolve(&self) -> Config {
+        Config {
+            listeners: self.listeners(),
+            advertise: self.advertise,
+            #[cfg(feature = "admin")]
+            admin_port: self.admin_port,
+            search: self.search.clone(),
+            edns_size: self.edns_size,
+            max_udp_size: SubnetMap::new(self.max_udp_size, self.subnet_max_udp_size.clone()),
+            address_filter: SubnetMap::new(self.address_filter, self.subnet_address_filter.clone()),
+            flatten_cnames: self.flatten_cnames,
+            zones: self.zone.clone(),
+            #[cfg(feature = "catalog")]
+            catalogs: self.catalog.clone(),
+            hosts: self.hosts.clone(),
+            rpz: self.rpz.clone(),
+            rpz_primaries: self.rpz_primary.clone(),
+            allowlist: self.allowlist.clone(),
+            blocked_ttl: BlockedTtl {
+                default: self.blocked_ttl,
+                per_type: self.blocked_ttl_type.clone(),
+            },
+            warmup: self.warmup.clone(),
+            transfer_limits: TransferLimits {
+                max_transfers: self.transfer_max_concurrent,
+                max_transfers_per_client: self.transfer_max_per_client,
+                rate: self.transfer_rate.map(|kib| kib * 1024),
+                max_size: self.transfer_max_size * 1024 * 1024,
+            },
+            zone_transfers: self.zone_transfers(),
+            allow_notify: self.allow_notify.clone(),
+            always_recurse: self.always_recurse,
+            forward_private_reverse: self.forward_private_reverse,
+            root_server: self.root_server,
+            servfail_hold_down: Duration::from_secs(self.servfail_hold_down),
+            retry: RetryPolicy {
+                initial_timeout: Duration::from_millis(self.retry_timeout),
+                multiplier: self.retry_multiplier,
+                attempts: self.retry_attempts,
+                jitter: self.retry_jitter,
+            },
+            max_resolutions_per_client: self.max_resolutions_per_client,
+            max_resolutions: self.max_resolutions,
+            resolution_limit_action: self.resolution_limit_action,
+            nxdomain_storm_ratio: self.nxdomain_storm_ratio,
+            nxdomain_storm_min: self.nxdomain_storm_min,
+            maintenance: self.maintenance,
+            read_only: self.read_only,
+            health_checks: self
+                .health_check
+                .iter()
+                .map(|name| name.trim_end_matches('.').to_lowercase())
+                .collect(),
+            audit: self.audit,
+            profile_stages: self.profile_stages,
+            debug_option: self.debug_option,
+            #[cfg(feature = "chaos")]
+            chaos: self.chaos_fraction.map(|fraction| Chaos {
+                fraction,
+                faults: match self.chaos_fault.is_empty() {
+                    true => Fault::ALL.to_vec(),
+                    false => self.chaos_fault.clone(),
+                },
+                delay: Duration::from_millis(self.chaos_delay),
+            }),
+            infra_cache: self.infra_cache.clone(),
+            dhcp_leases: self.dhcp_leases.clone(),
+            anonymize_clients: self.anonymize_clients,
+            hash_names: self.hash_names,
+            privacy: self.privacy,
+            memory_limit: self.memory_limit.map(|mib| mib * 1024 * 1024),
+            alert_command: self.alert_command.clone(),
+            alert_webhook: self.alert_webhook.clone(),
+            alert_servfail_rate: self.alert_servfail_rate,
+        }
+    }
+
+    /// Groups the transfer settings by zone
+    fn zone_transfers(&self) -> BTreeMap<String, ZoneTransfers> {
+        let mut zone_transfers: BTreeMap<String, ZoneTransfers> = BTreeMap::new();
+        for (origin, subnet) in &self.allow_transfer {
+            zone_transfers
+                .entry(origin.clone())
+                .or_default()
+                .allow_transfer
+                .push(*subnet);
+        }
+        for (origin, target) in &self.also_notify {
+            zone_transfers
+                .entry(origin.clone())
+                .or_default()
+                .also_notify
+                .push(*target);
+        }
+        zone_transfers
+    }
+
+    /// Resolves the listener blocks with the global settings, defaulting to UDP and TCP on
+    /// all addresses
+    fn listeners(&self) -> Vec<Listener> {
+        let buffers = BufferSizes {
+            recv: self.so_rcvbuf,
+            send: self.so_sndbuf,
+        };
+        let udp = UdpLimits {
+            workers: self.workers,
+            queue_size: self.queue_size,
+            queue_policy: self.queue_policy,
+        };
+        let tcp = TcpLimits {
+            max_connections: self.tcp_max_connections,
+            max_queries: self.tcp_max_queries,
+            max_pipelined: self.tcp_max_pipelined,
+            idle_timeout: Duration::from_secs(self.tcp_idle_timeout),
+        };
+
+        let mut transports = vec![Transport::Udp];
+        if cfg!(feature = "tcp") {
+            transports.push(Transport::Tcp);
+        }
+        let defaults: Vec<ListenerSpec> = transports
+            .into_iter()
+            .map(|transport| ListenerSpec {
+                transport,
+                address: Ipv4Addr::UNSPECIFIED,
+                port: None,
+                settings: Vec::new(),
+            })
+            .collect();
+        let specs = match self.listen.is_empty() {
+            true => &defaults[..],
+            false => &self.listen[..],
+        };
+        specs
+            .iter()
+            .map(|spec| spec.resolve(self.port, buffers, udp, tcp))
+            .collect()
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Validate the configuration and the local data without starting the server
+    Check,
+    /// Print the decomposition of the DNS messages in a hex dump or a pcap capture
+    #[cfg(feature = "tools")]
+    Decode {
+        /// File holding the hex dump of messages, separated by blank lines, or a pcap capture
+        file: PathBuf,
+    },
+    /// Compare the first DNS messages of two hex dumps or pcap captures field by field
+    #[cfg(feature = "tools")]
+    Diff { a: PathBuf, b: PathBuf },
+    /// Query a DNS server, this one by default, and print the response like dig
+    #[cfg(feature = "tools")]
+    Query {
+        /// The name, the type (A by default), @<SERVER> to query another server, and +short
+        /// to print only the data of the answers
+        args: Vec<String>,
+        /// Query the PTR records of an address, in in-addr.arpa or ip6.arpa
+        #[arg(short = 'x', value_name = "ADDRESS")]
+        reverse: Option<IpAddr>,
+    },
+    /// Start the server, query it on each listener (and the advertised address) over UDP
+    /// and TCP, and exit with the outcome
+    Selftest,
+    /// Inspect the configuration options
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Print a JSON schema of the options, to validate configurations before deploying them
+    Schema,
+}
+
+/// Parses a zone argument in the <ORIGIN>=<PATH> form, where the origin of a reverse zone
+/// can be given as its subnet, e.g. 2001:db8::/48
+fn parse_zone_arg(value: &str) -> Result<(String, PathBuf), String> {
+    let (origin, path) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected <ORIGIN>=<PATH>, got \"{}\"", value))?;
+    let origin = match origin.parse::<Subnet>() {
+        Ok(subnet) => reverse::reverse_zone(&subnet).ok_or_else(|| {
+            format!(
+                "subnet {} doesn't end on a byte (IPv4) or nibble (IPv6) boundary",
+                origin
+            )
+        })?,
+        Err(_) => origin.to_string(),
+    };
+    Ok((origin, PathBuf::from(path)))
+}
+
+/// Parses a catalog argument in the <ORIGIN>=<PRIMARY> form
+#[cfg(feature = "catalog")]
+fn parse_catalog_arg(value: &str) -> Result<Catalog, String> {
+    let (origin, primary) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected <ORIGIN>=<PRIMARY>, got \"{}\"", value))?;
+
+    Ok(Catalog {
+        origin: origin.trim_end_matches('.').to_lowercase(),
+        primary: parse_server_address(primary)?,
+    })
+}
+
+/// Parses the origin of a zone and its primary, in the <ORIGIN>=<PRIMARY> form
+fn parse_primary_arg(value: &str) -> Result<(String, SocketAddr), String> {
+    let (origin, primary) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected <ORIGIN>=<PRIMARY>, got \"{}\"", value))?;
+
+    Ok((
+        origin.trim_end_matches('.').to_lowercase(),
+        parse_server_address(primary)?,
+    ))
+}
+
+/// Parses an allow-transfer argument in the <ORIGIN>=<SUBNET> form
+fn parse_allow_transfer_arg(value: &str) -> Result<(String, Subnet), String> {
+    let (origin, subnet) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected <ORIGIN>=<SUBNET>, got \"{}\"", value))?;
+    if subnet.starts_with("key:") {
+        return Err("TSIG keys are not supported, only subnets".to_string());
+    }
+
+    Ok((
+        origin.trim_end_matches('.').to_lowercase(),
+        subnet.parse().map_err(|e| format!("{}", e))?,
+    ))
+}
+
+/// Parses an also-notify argument in the <ORIGIN>=<ADDRESS> form
+fn parse_also_notify_arg(value: &str) -> Result<(String, SocketAddr), String> {
+    let (origin, target) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected <ORIGIN>=<ADDRESS>, got \"{}\"", value))?;
+
+    Ok((
+        origin.trim_end_matches('.').to_lowercase(),
+        parse_server_address(target)?,
+    ))
+}
+
+/// Parses the address of another server, an IP address with an optional port defaulting to 53
+fn parse_server_address(value: &str) -> Result<SocketAddr, String> {
+    match value.parse::<IpAddr>() {
+        Ok(ip) => Ok(SocketAddr::new(ip, 53)),
+        Err(_) => value
+            .parse()
+            .map_err(|_| format!("invalid server address \"{}\"", value)),
+    }
+}
+
+/// Parses a ratio between 0 and 1
+fn parse_ratio_arg(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(ratio) if (0.0..=1.0).contains(&ratio) => Ok(ratio),
+        _ => Err(format!(
+            "expected a ratio between 0 and 1, got \"{}\"",
+            value
+        )),
+    }
+}
+
+/// Parses the factor retry timeouts grow by, which must be between 1 and 10
+fn parse_multiplier_arg(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(multiplier) if (1.0..=10.0).contains(&multiplier) => Ok(multiplier),
+        _ => Err(format!(
+            "expected a multiplier between 1 and 10, got \"{}\"",
+            value
+        )),
+    }
+}
+
+/// Parses a per-type TTL in the <TYPE>=<SECONDS> form
+fn parse_type_ttl_arg(value: &str) -> Result<(QueryType, u32), String> {
+    let (qtype, ttl) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected <TYPE>=<SECONDS>, got \"{}\"", value))?;
+    let ttl = ttl
+        .parse()
+        .map_err(|_| format!("invalid TTL \"{}\"", ttl))?;
+
+    Ok((qtype.parse()?, ttl))
+}
+
+/// Parses a per-subnet setting in the <SUBNET>=<VALUE> form
+fn parse_subnet_arg<T: FromStr>(value: &str) -> Result<(Subnet, T), String> {
+    let (subnet, setting) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected <SUBNET>=<VALUE>, got \"{}\"", value))?;
+    let subnet = subnet.parse().map_err(|e| format!("{}", e))?;
+    let setting = setting
+        .parse()
+        .map_err(|_| format!("invalid value \"{}\"", setting))?;
+
+    Ok((subnet, setting))
+}
+
+/// Loads the hosts file and the zones given on the command line.
+fn load_authority(config: &Config) -> Result<Authority, ZoneError> {
+    let overrides = match &config.hosts {
+        Some(path) => load_hosts(path)?,
+        None => Vec::new(),
+    };
+
+    let zones = config
+        .zones
+        .iter()
+        .map(|(origin, path)| Zone::load(origin, path))
+        .collect::<Result<Vec<_>, _>>()?;
+    for zone in &zones {
+        info!(
+            "Loaded zone {} with {} records",
+            zone.origin,
+            zone.records.len()
+        );
+    }
+
+    Ok(Authority::new(overrides, zones))
+}
+
+/// Loads the allowlist and the response policy zones given on the command line,
+/// the latter in order of precedence.
+fn load_policies(config: &Config) -> Result<PolicyEngine, ZoneError> {
+    let allowlist = match &config.allowlist {
+        Some(path) => Some(Allowlist::load(path)?),
+        None => None,
+    };
+
+    let mut zones = config
+        .rpz
+        .iter()
+        .map(|(origin, path)| ResponsePolicyZone::load(origin, path))
+        .collect::<Result<Vec<_>, _>>()?;
+    zones.extend(
+        config
+            .rpz_primaries
+            .iter()
+            .map(|(origin, primary)| ResponsePolicyZone::transferred(origin, *primary)),
+    );
+
+    Ok(PolicyEngine::new(
+        allowlist,
+        zones,
+        config.blocked_ttl.clone(),
+    ))
+}
+
+/// Validates everything the server would load and bind on startup, and
+/// reports all the problems found. Returns true if there are none.
+fn check(config: &Config) -> bool {
+    let mut ok = true;
+
+    match load_authority(config) {
+        Ok(authority) => {
+            for zone in &authority.zones {
+                for problem in zone.check() {
+                    error!("Zone {}: {}", zone.origin, problem);
+                    ok = false;
+                }
+            }
+            info!("Loaded {} overrides", authority.overrides.len());
+        }
+        Err(e) => {
+            error!("Failed to load local data: {}", e);
+            ok = false;
+        }
+    }
+
+    if let Err(e) = load_policies(config) {
+        error!("Failed to load the policies: {}", e);
+        ok = false;
+    }
+
+    if let Some(path) = &config.warmup {
+        if let Err(e) = load_name_list(path) {
+            error!("Failed to load the warm-up list: {}", e);
+            ok = false;
+        }
+    }
+
+    if let Some(path) = &config.dhcp_leases {
+        if let Err(e) = Leases::new(Some(path.clone())).reload() {
+            error!("Failed to load the DHCP leases: {}", e);
+            ok = false;
+        }
+    }
+
+    for listener in &config.listeners {
+        if !supports(listener.transport()) {
+            error!(
+                "{} listener on {} isn't supported by this build",
+                listener.transport(),
+                listener.address
+            );
+            ok = false;
+            continue;
+        }
+        let bound = match listener.transport() {
+            Transport::Udp => UdpSocket::bind(listener.address).map(drop),
+            Transport::Tcp => TcpListener::bind(listener.address).map(drop),
+        };
+        if let Err(e) = bound {
+            error!(
+                "{} address {} cannot be bound: {}",
+                listener.transport(),
+                listener.address,
+                e
+            );
+            ok = false;
+        }
+    }
+
+    #[cfg(feature = "admin")]
+    if let Some(port) = config.admin_port {
+        if let Err(e) = TcpListener::bind(("127.0.0.1", port)) {
+            error!("Admin port {} cannot be bound: {}", port, e);
+            ok = false;
+        }
+    }
+
+    if config
+        .search
+        .iter()
+        .any(|suffix| suffix.trim_matches('.').is_empty())
+    {
+        error!("Search suffixes cannot be empty");
+        ok = false;
+    }
+
+    ok
+}
+
+/// Reads the DNS messages of a hex dump or a pcap capture, exiting if it is unreadable
+#[cfg(feature = "tools")]
+fn read_messages_or_exit(path: &Path) -> Vec<Vec<u8>> {
+    match fs::read(path)
+        .map_err(|e| e.to_string())
+        .and_then(|bytes| read_messages(&bytes).map_err(|e| e.to_string()))
+    {
+        Ok(messages) => messages,
+        Err(e) => {
+            error!("Failed to read {}: {}", path.display(), e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Parses the first DNS message of a hex dump or a pcap capture, exiting if there is none
+#[cfg(feature = "tools")]
+fn read_packet_or_exit(path: &Path) -> DnsPacket {
+    let Some(message) = read_messages_or_exit(path).into_iter().next() else {
+        error!("No DNS message in {}", path.display());
+        process::exit(1);
+    };
+    match DnsPacket::parse(&message) {
+        Ok(packet) => packet,
+        Err(e) => {
+            error!("Malformed DNS message in {}: {}", path.display(), e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Binds an UDP socket queries are received on, on startup and when it breaks
+fn bind_udp(address: SocketAddrV4, buffers: BufferSizes) -> io::Result<UdpSocket> {
+    let socket = UdpSocket::bind(address)?;
+    if let Err(e) = socket::enable_pktinfo(&socket) {
+        warn!("Replies might be sent from the wrong address: {}", e);
+    }
+    report_buffer_sizes("UDP", buffers, socket::set_buffer_sizes(&socket, buffers));
+
+    Ok(socket)
+}
+
+/// Binds a TCP listener queries are received on, on startup and when it breaks
+#[cfg(feature = "tcp")]
+fn bind_tcp(address: SocketAddrV4, buffers: BufferSizes) -> io::Result<TcpListener> {
+    let listener = TcpListener::bind(address)?;
+    // Accepted TCP connections inherit the buffer sizes of the listener.
+    report_buffer_sizes("TCP", buffers, socket::set_buffer_sizes(&listener, buffers));
+
+    Ok(listener)
+}
+
+/// Reports the buffer sizes the kernel actually uses for a listening socket, warning when
+/// they are smaller than asked for
+fn report_buffer_sizes(transport: &str, sizes: BufferSizes, effective: io::Result<BufferSizes>) {
+    match effective {
+        Ok(effective) => {
+            info!(
+                "{} socket buffers: {} bytes to receive, {} to send",
+                transport, effective.recv, effective.send
+            );
+            if effective.recv < sizes.recv || effective.send < sizes.send {
+                warn!(
+                    "{} socket buffers are smaller than asked for, raise net.core.rmem_max and net.core.wmem_max to allow more",
+                    transport
+                );
+            }
+        }
+        Err(e) => warn!("Failed to set the {} socket buffers: {}", transport, e),
+    }
 }
 
 /// Entry point of the server.
 fn main() -> Result<(), Box<dyn Error>> {
     // Initialize logging.
-    TermLogger::init(
+    // Lines logged while handling a client query are prefixed with its correlation ID.
+    let logger = TermLogger::new(
         LevelFilter::Trace,
-        Config::default(),
+        simplelog::Config::default(),
         TerminalMode::Stdout,
         ColorChoice::Auto,
-    )
-    .unwrap();
+    );
+    log::set_boxed_logger(Box::new(CorrelatedLogger::new(logger))).unwrap();
+    log::set_max_level(LevelFilter::Trace);
 
     // Parse command line arguments.
     let args = Args::parse();
+    let config = args.resolve();
+    privacy::init(
+        Privacy::new(config.anonymize_clients, config.hash_names).with_profile(config.privacy),
+    );
+    if config.profile_stages {
+        profiling::enable();
+    }
+    #[cfg(feature = "chaos")]
+    if let Some(chaos) = &config.chaos {
+        warn!("Injecting faults into upstream responses, never do this in production");
+        chaos::init(chaos.clone());
+    }
+
+    if args.print_config {
+        print!("{}", config);
+        return Ok(());
+    }
+
+    match &args.command {
+        Some(Command::Check) => {
+            if check(&config) {
+                info!("Configuration is valid");
+                return Ok(());
+            }
+            process::exit(1);
+        }
+        #[cfg(feature = "tools")]
+        Some(Command::Decode { file }) => {
+            for (i, message) in read_messages_or_exit(file).iter().enumerate() {
+                println!("; message {}, {} bytes", i + 1, message.len());
+                println!("{}", dump(message));
+            }
+            return Ok(());
+        }
+        #[cfg(feature = "tools")]
+        Some(Command::Diff { a, b }) => {
+            let differences = diff(&read_packet_or_exit(a), &read_packet_or_exit(b));
+            if differences.is_empty() {
+                println!("Messages are identical");
+                return Ok(());
+            }
+            for line in differences {
+                println!("{}", line);
+            }
+            process::exit(1);
+        }
+        #[cfg(feature = "tools")]
+        Some(Command::Query {
+            args: query_args,
+            reverse,
+        }) => {
+            let server = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), args.port);
+            let query = match Query::parse(query_args, *reverse, server) {
+                Ok(query) => query,
+                Err(e) => {
+                    error!("Invalid query: {}", e);
+                    process::exit(1);
+                }
+            };
+            match query.send() {
+                Ok(response) => print!("{}", format_response(&response, query.short)),
+                Err(e) => {
+                    error!("Query to {} failed: {}", query.server, e);
+                    process::exit(1);
+                }
+            }
+            return Ok(());
+        }
+        Some(Command::Config {
+            command: ConfigCommand::Schema,
+        }) => {
+            print!("{}", schema::json_schema(&Args::command()));
+            return Ok(());
+        }
+        Some(Command::Selftest) | None => {}
+    }
+
+    for (subsystem, summary) in config.report() {
+        info!("{}: {}", subsystem, summary);
+    }
+
+    // Load the locally hosted data, refusing to start if any of it is malformed.
+    let (authority, policies) = match load_authority(&config)
+        .and_then(|authority| Ok((authority, load_policies(&config)?)))
+    {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            error!("Failed to load local data: {}", e);
+            process::exit(1);
+        }
+    };
+
+    // Bind the sockets of the listeners, refusing to start if any of them can't be.
+    let mut udp_sockets = Vec::new();
+    #[cfg(feature = "tcp")]
+    let mut tcp_listeners = Vec::new();
+    for listener in &config.listeners {
+        match listener.limits {
+            Limits::Udp(limits) => udp_sockets.push((
+                listener.clone(),
+                limits,
+                bind_udp(listener.address, listener.buffers)?,
+            )),
+            #[cfg(feature = "tcp")]
+            Limits::Tcp(limits) => tcp_listeners.push((
+                listener.clone(),
+                limits,
+                bind_tcp(listener.address, listener.buffers)?,
+            )),
+            #[cfg(not(feature = "tcp"))]
+            Limits::Tcp(_) => {
+                error!(
+                    "TCP listener on {} isn't supported by this build",
+                    listener.address
+                );
+                process::exit(1);
+            }
+        }
+    }
+
+    // Remembers what works with each upstream server across queries.
+    let alerter = Alerter::new(config.alert_command.clone(), config.alert_webhook.clone());
+    let mut infra = InfraCache::new(config.edns_size)
+        .with_alerter(alerter.clone())
+        .with_retry(config.retry);
+    if let Some(root) = config.root_server {
+        infra = infra.with_root(*root.ip(), root.port());
+    }
+    let infra = Arc::new(infra);
+    if let Some(path) = config.infra_cache.clone() {
+        if let Err(e) = infra.load(&path) {
+            warn!("Failed to load the infrastructure cache: {}", e);
+        }
+        let infra = infra.clone();
+        thread::spawn(move || infra.persist(&path, INFRA_CACHE_SAVE_INTERVAL));
+    }
+    let cache = Arc::new(Cache::new());
+    cache.set_read_only(config.read_only);
+    let stats = Arc::new(Stats::new());
 
-    // Bind an UDP socket the specified port.
-    let socket = UdpSocket::bind(("0.0.0.0", args.port))?;
+    if let Some(threshold) = config.alert_servfail_rate {
+        if alerter.is_enabled() {
+            let stats = stats.clone();
+            thread::spawn(move || {
+                alerter.watch_servfail_rate(stats, threshold, SERVFAIL_RATE_INTERVAL)
+            });
+        } else {
+            warn!(
+                "Ignoring --alert-servfail-rate, as there is no --alert-command or --alert-webhook"
+            );
+        }
+    }
 
-    // Queries are handled sequentially, so an infinite loop for servicing requests is initiated.
-    info!("DNS server is listening on port {}...", args.port);
-    loop {
-        match handle_query(&socket) {
-            Ok(()) => {}
-            Err(e) => warn!("An error occurred: {}", e),
+    // Popular domains are resolved in the background, so that the first clients
+    // after a restart don't have to wait for a full recursion.
+    if let Some(path) = &config.warmup {
+        match load_name_list(path) {
+            Ok(names) => {
+                let (cache, infra) = (cache.clone(), infra.clone());
+                thread::spawn(move || warm_up(&names, &cache, &infra));
+            }
+            Err(e) => warn!("Skipping cache warm-up: {}", e),
         }
     }
+
+    // Client addresses are mapped to devices, as long as the DHCP server keeps them leased.
+    let leases = Arc::new(Leases::new(config.dhcp_leases.clone()));
+    if config.dhcp_leases.is_some() {
+        if let Err(e) = leases.reload() {
+            warn!("Failed to load the DHCP leases: {}", e);
+        }
+        let leases = leases.clone();
+        thread::spawn(move || leases.watch(LEASES_CHECK_INTERVAL));
+    }
+
+    let context = Arc::new(Context {
+        authority: RwLock::new(authority),
+        policies,
+        search: config.search.clone(),
+        max_udp_size: config.max_udp_size.clone(),
+        address_filter: config.address_filter.clone(),
+        flatten_cnames: config.flatten_cnames,
+        cache,
+        infra,
+        stats,
+        audit: config.audit,
+        always_recurse: config.always_recurse,
+        forward_private_reverse: config.forward_private_reverse,
+        hold_down: HoldDown::new(config.servfail_hold_down),
+        leases,
+        modes: Modes::new(config.maintenance, config.read_only),
+        health_checks: config.health_checks.clone(),
+        outstanding: Outstanding::new(),
+        resolutions: Resolutions::new(config.max_resolutions_per_client, config.max_resolutions)
+            .with_action(config.resolution_limit_action),
+        storms: StormGuard::new(config.nxdomain_storm_ratio, config.nxdomain_storm_min),
+        debug_option: config.debug_option,
+        dispatcher: config.dispatcher(),
+    });
+
+    #[cfg(feature = "admin")]
+    start_admin(&config, &context)?;
+
+    // The memory usage is always measured for the statistics, but only capped if asked to.
+    {
+        let context = context.clone();
+        let cap = config.memory_limit;
+        thread::spawn(move || memory::monitor(&context, cap, MEMORY_CHECK_INTERVAL));
+    }
+
+    #[cfg(feature = "catalog")]
+    start_catalogs(&config, &context);
+    start_notifier(&config, &context);
+    start_policy_transfers(&context);
+
+    // Each listener is served by its own threads, all of them feeding the same handler.
+    let mut servers = Vec::new();
+    for (listener, limits, socket) in udp_sockets {
+        let (address, buffers) = (listener.address, listener.buffers);
+        let server = UdpServer::new(context.clone(), limits).with_allow(listener.allow);
+        info!("DNS server is listening on UDP {}...", address);
+        servers.push(thread::spawn(move || {
+            server.serve(socket, || bind_udp(address, buffers))
+        }));
+    }
+    #[cfg(feature = "tcp")]
+    servers.extend(start_tcp(tcp_listeners, &config, &context));
+    if let Some(advertised) = config.advertise {
+        info!(
+            "Clients reach the server at {} through a port mapping",
+            advertised
+        );
+    }
+
+    if let Some(Command::Selftest) = args.command {
+        let passed = selftest::run(&config.listeners, config.advertise);
+        process::exit(if passed { 0 } else { 1 });
+    }
+    for server in servers {
+        let _ = server.join();
+    }
+
+    Ok(())
+}
+
+/// Whether this build can serve listeners of a transport
+fn supports(transport: Transport) -> bool {
+    transport == Transport::Udp || cfg!(feature = "tcp")
+}
+
+/// Starts the admin API, if it was given a port
+#[cfg(feature = "admin")]
+fn start_admin(config: &Config, context: &Arc<Context>) -> io::Result<()> {
+    if let Some(port) = config.admin_port {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        let admin = Admin::new(context.clone());
+        info!("Admin API is listening on 127.0.0.1:{}...", port);
+        thread::spawn(move || admin.serve(listener));
+    }
+    Ok(())
+}
+
+/// Starts consuming the catalogs: their member zones are transferred in the background, and
+/// served once they are.
+#[cfg(feature = "catalog")]
+fn start_catalogs(config: &Config, context: &Arc<Context>) {
+    if !config.catalogs.is_empty() {
+        let consumer = CatalogConsumer::new(config.catalogs.clone(), context.clone());
+        thread::spawn(move || consumer.run());
+    }
+}
+
+/// Keeps the response policy zones transferred from a primary up to date. They are
+/// transferred once before the server starts answering, so that their policies apply from
+/// the first query unless the primary can't be reached.
+fn start_policy_transfers(context: &Arc<Context>) {
+    if context
+        .policies
+        .zones
+        .iter()
+        .any(|rpz| rpz.primary.is_some())
+    {
+        context.policies.refresh();
+        let context = context.clone();
+        thread::spawn(move || context.policies.keep_refreshed(RPZ_REFRESH_INTERVAL));
+    }
+}
+
+/// Starts notifying the secondaries in the also-notify of the zones when they change
+fn start_notifier(config: &Config, context: &Arc<Context>) {
+    let targets: BTreeMap<String, Vec<SocketAddr>> = config
+        .zone_transfers
+        .iter()
+        .filter(|(_, transfers)| !transfers.also_notify.is_empty())
+        .map(|(origin, transfers)| (origin.clone(), transfers.also_notify.clone()))
+        .collect();
+    if !targets.is_empty() {
+        let notifier = Notifier::new(targets, context.clone());
+        thread::spawn(move || notifier.run(NOTIFY_CHECK_INTERVAL));
+    }
+}
+
+/// Starts serving the bound TCP listeners, returning the handles of their threads
+#[cfg(feature = "tcp")]
+fn start_tcp(
+    tcp_listeners: Vec<(Listener, TcpLimits, TcpListener)>,
+    config: &Config,
+    context: &Arc<Context>,
+) -> Vec<thread::JoinHandle<()>> {
+    tcp_listeners
+        .into_iter()
+        .map(|(listener, limits, tcp_listener)| {
+            let (address, buffers) = (listener.address, listener.buffers);
+            let server = TcpServer::new(context.clone(), limits, config.transfer_limits)
+                .with_allow(listener.allow)
+                .with_zone_transfers(config.zone_transfers.clone());
+            info!("DNS server is listening on TCP {}...", address);
+            thread::spawn(move || server.serve(tcp_listener, || bind_tcp(address, buffers)))
+        })
+        .collect()
 }
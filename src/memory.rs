@@ -0,0 +1,89 @@
+use std::fmt;
+use std::mem::size_of;
+use std::thread;
+use std::time::Duration;
+
+use log::warn;
+
+use crate::buffer::MAX_SIZE;
+use crate::handler::{Context, UdpQuery};
+
+/// Estimate of the memory used by a query waiting to be answered
+const PENDING_QUERY_SIZE: usize = size_of::<UdpQuery>() + 512;
+/// Estimate of the memory used by an open TCP connection: its thread stack and a message buffer
+const CONNECTION_SIZE: usize = 64 * 1024 + MAX_SIZE;
+/// Share of the cap the memory usage is brought back to when the cap is exceeded, so that
+/// the cache isn't evicted again right away
+const TARGET_RATIO: f64 = 0.8;
+
+/// Approximate memory used by the main components of the server, in bytes
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    pub cache: usize,
+    pub zones: usize,
+    /// Queries waiting to be answered, and upstream queries in flight
+    pub pending: usize,
+    /// Open TCP connections
+    pub connections: usize,
+}
+
+impl MemoryUsage {
+    /// Measures the memory used by everything queries are answered from
+    pub fn measure(context: &Context) -> MemoryUsage {
+        let pending = context.stats.queue_depth() + context.infra.in_flight.len();
+
+        MemoryUsage {
+            cache: context.cache.memory_usage(),
+            zones: context.authority.read().unwrap().memory_usage(),
+            pending: pending * PENDING_QUERY_SIZE,
+            connections: context.stats.tcp_connections() * CONNECTION_SIZE,
+        }
+    }
+
+    pub fn total(&self) -> usize {
+        self.cache + self.zones + self.pending + self.connections
+    }
+}
+
+impl fmt::Display for MemoryUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mib = |bytes: usize| bytes as f64 / (1024.0 * 1024.0);
+        write!(
+            f,
+            "{:.1} MiB (cache {:.1}, zones {:.1}, pending {:.1}, connections {:.1})",
+            mib(self.total()),
+            mib(self.cache),
+            mib(self.zones),
+            mib(self.pending),
+            mib(self.connections)
+        )
+    }
+}
+
+/// Measures the memory usage every `interval` forever, recording it in the statistics.
+/// When it exceeds `cap`, the cache is evicted down to what brings the usage back
+/// below the cap, before the process grows enough to be killed by the OS. The server always
+/// spawns a thread for it on startup, as the usage is reported even without a cap.
+pub fn monitor(context: &Context, cap: Option<usize>, interval: Duration) {
+    loop {
+        let usage = MemoryUsage::measure(context);
+        context.stats.set_memory_usage(usage);
+
+        if let Some(cap) = cap.filter(|cap| usage.total() > *cap) {
+            let target = (cap as f64 * TARGET_RATIO) as usize;
+            let max_cache = usage.cache.saturating_sub(usage.total() - target);
+            let evicted = context.cache.evict(max_cache);
+            warn!(
+                "Memory usage of {} exceeds the cap of {:.1} MiB, evicted {} cache entries",
+                usage,
+                cap as f64 / (1024.0 * 1024.0),
+                evicted
+            );
+            context
+                .stats
+                .set_memory_usage(MemoryUsage::measure(context));
+        }
+
+        thread::sleep(interval);
+    }
+}
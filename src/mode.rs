@@ -0,0 +1,110 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+use crate::resultcode::ResultCode;
+
+/// How queries are answered in maintenance mode
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Maintenance {
+    /// Queries are answered as usual
+    #[default]
+    Off,
+    /// Queries are answered with SERVFAIL, so that clients try their other servers
+    ServFail,
+    /// Queries are answered with REFUSED
+    Refused,
+}
+
+impl Maintenance {
+    /// Returns the result code queries are answered with, if they aren't answered as usual
+    pub fn rescode(self) -> Option<ResultCode> {
+        match self {
+            Maintenance::Off => None,
+            Maintenance::ServFail => Some(ResultCode::SERVFAIL),
+            Maintenance::Refused => Some(ResultCode::REFUSED),
+        }
+    }
+}
+
+impl FromStr for Maintenance {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Maintenance, String> {
+        match value {
+            "off" => Ok(Maintenance::Off),
+            "servfail" => Ok(Maintenance::ServFail),
+            "refused" => Ok(Maintenance::Refused),
+            _ => Err(format!(
+                "expected off, servfail or refused, got \"{}\"",
+                value
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Maintenance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Maintenance::Off => write!(f, "off"),
+            Maintenance::ServFail => write!(f, "servfail"),
+            Maintenance::Refused => write!(f, "refused"),
+        }
+    }
+}
+
+/// The `Modes` struct holds the modes that can be toggled while the server runs, during
+/// migrations or incidents:
+/// - maintenance mode answers every query with an error, except the health checks
+/// - read-only mode stops the changes to the server's data: hosted zones aren't updated
+///   through the admin API, and the cache stops storing new records
+#[derive(Debug, Default)]
+pub struct Modes {
+    maintenance: AtomicU8,
+    read_only: AtomicBool,
+}
+
+impl Modes {
+    pub fn new(maintenance: Maintenance, read_only: bool) -> Modes {
+        let modes = Modes::default();
+        modes.set_maintenance(maintenance);
+        modes.set_read_only(read_only);
+        modes
+    }
+
+    pub fn maintenance(&self) -> Maintenance {
+        match self.maintenance.load(Ordering::Relaxed) {
+            1 => Maintenance::ServFail,
+            2 => Maintenance::Refused,
+            _ => Maintenance::Off,
+        }
+    }
+
+    pub fn set_maintenance(&self, maintenance: Maintenance) {
+        let value = match maintenance {
+            Maintenance::Off => 0,
+            Maintenance::ServFail => 1,
+            Maintenance::Refused => 2,
+        };
+        self.maintenance.store(value, Ordering::Relaxed);
+    }
+
+    pub fn read_only(&self) -> bool {
+        self.read_only.load(Ordering::Relaxed)
+    }
+
+    pub fn set_read_only(&self, read_only: bool) {
+        self.read_only.store(read_only, Ordering::Relaxed);
+    }
+}
+
+impl fmt::Display for Modes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "maintenance {}, read-only {}",
+            self.maintenance(),
+            if self.read_only() { "on" } else { "off" }
+        )
+    }
+}
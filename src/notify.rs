@@ -0,0 +1,181 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, ErrorKind};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use log::{info, warn};
+use rand::Rng;
+
+use crate::buffer::{Buffer, BufferError, DEFAULT_SIZE};
+use crate::handler::Context;
+use crate::packet::DnsPacket;
+use crate::question::{DnsQuestion, QueryType};
+use crate::zone::Zone;
+
+/// Opcode of NOTIFY messages: see https://datatracker.ietf.org/doc/html/rfc1996
+pub const OPCODE_NOTIFY: u8 = 4;
+/// Time to wait for a secondary to acknowledge a NOTIFY before sending it again
+const NOTIFY_TIMEOUT: Duration = Duration::from_secs(2);
+/// Number of times a NOTIFY is sent to a secondary before giving up on it
+const NOTIFY_ATTEMPTS: usize = 3;
+
+/// Builds the NOTIFY message announcing the current version of a zone, with its SOA record
+/// so that secondaries can tell whether they already have it
+pub fn notify_message(zone: &Zone) -> DnsPacket {
+    let mut packet = DnsPacket::new();
+    packet.header.id = rand::thread_rng().gen();
+    packet.header.opcode = OPCODE_NOTIFY;
+    packet.header.authoritative_answer = true;
+    packet
+        .questions
+        .push(DnsQuestion::new(zone.origin.clone(), QueryType::SOA));
+    packet.answers.extend(zone.soa().cloned());
+
+    packet
+}
+
+/// Sends a NOTIFY for a zone to a secondary over UDP, sending it again until the secondary
+/// acknowledges it or `NOTIFY_ATTEMPTS` are made. If an error occurs, it returns the error.
+pub fn send_notify(target: SocketAddr, zone: &Zone) -> Result<(), BufferError> {
+    let socket = match target {
+        SocketAddr::V4(_) => UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?,
+        SocketAddr::V6(_) => UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0))?,
+    };
+    socket.set_read_timeout(Some(NOTIFY_TIMEOUT))?;
+
+    let request = notify_message(zone);
+    let message = request.to_bytes()?;
+    for _ in 0..NOTIFY_ATTEMPTS {
+        socket.send_to(&message, target)?;
+
+        let mut buffer = Buffer::with_size(DEFAULT_SIZE);
+        let (len, src) = match socket.recv_from(&mut buffer.buf) {
+            Ok(received) => received,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => continue,
+            Err(e) => return Err(e.into()),
+        };
+        // Anything else than the acknowledgement of the secondary is ignored, and the
+        // NOTIFY sent again.
+        let Ok(response) = DnsPacket::parse(&buffer.buf[..len]) else {
+            continue;
+        };
+        if src == target && response.header.response && response.header.id == request.header.id {
+            return Ok(());
+        }
+    }
+
+    Err(io::Error::new(ErrorKind::TimedOut, "NOTIFY not acknowledged").into())
+}
+
+/// The `Notifier` struct tells secondaries that a hosted zone changed (RFC 1996), so that
+/// they transfer it right away rather than at the next refresh of its SOA: the secondaries
+/// listed in the `also-notify` of a zone are sent a NOTIFY whenever its serial changes, e.g.
+/// when ACME challenges are published in it.
+pub struct Notifier {
+    /// Secondaries to notify, by origin of the zone
+    targets: BTreeMap<String, Vec<SocketAddr>>,
+    context: Arc<Context>,
+    /// Last serial seen of each zone
+    serials: HashMap<String, u32>,
+}
+
+impl Notifier {
+    pub fn new(targets: BTreeMap<String, Vec<SocketAddr>>, context: Arc<Context>) -> Notifier {
+        Notifier {
+            targets,
+            context,
+            serials: HashMap::new(),
+        }
+    }
+
+    /// Checks the serials of the zones forever, every `interval`. The server spawns a thread
+    /// for it on startup, when any zone has secondaries to notify.
+    pub fn run(mut self, interval: Duration) {
+        loop {
+            self.check();
+            thread::sleep(interval);
+        }
+    }
+
+    /// Notifies the secondaries of the zones whose serial changed since the last check
+    fn check(&mut self) {
+        for (origin, targets) in &self.targets {
+            let Some(zone) = self
+                .context
+                .authority
+                .read()
+                .unwrap()
+                .zones
+                .iter()
+                .find(|zone| zone.origin == *origin)
+                .cloned()
+            else {
+                continue;
+            };
+            let Some(serial) = zone.serial() else {
+                continue;
+            };
+
+            // Secondaries transfer the zones they don't have yet on their own on startup.
+            let previous = self.serials.insert(origin.clone(), serial);
+            if previous.is_none_or(|previous| previous == serial) {
+                continue;
+            }
+
+            for target in targets {
+                match send_notify(*target, &zone) {
+                    Ok(()) => info!(
+                        "Notified {} of serial {} of zone {}",
+                        target, serial, origin
+                    ),
+                    Err(e) => warn!("Failed to notify {} of zone {}: {}", target, origin, e),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record::DnsRecord;
+
+    #[test]
+    fn notifies_until_acknowledged() {
+        let zone = Zone {
+            origin: "home.lan".to_string(),
+            records: vec![DnsRecord::SOA {
+                domain: "home.lan".to_string(),
+                mname: "ns1.home.lan".to_string(),
+                rname: "admin.home.lan".to_string(),
+                serial: 7,
+                refresh: 3600,
+                retry: 600,
+                expire: 86400,
+                minimum: 300,
+                ttl: 3600,
+            }],
+        };
+
+        let secondary = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let target = secondary.local_addr().unwrap();
+        let acknowledged = thread::spawn(move || {
+            let mut buffer = [0; 512];
+            let (len, src) = secondary.recv_from(&mut buffer).unwrap();
+            let mut notify = DnsPacket::parse(&buffer[..len]).unwrap();
+            assert_eq!(notify.header.opcode, OPCODE_NOTIFY);
+            assert!(notify.header.authoritative_answer);
+            assert_eq!(notify.questions[0].qtype, QueryType::SOA);
+            assert_eq!(notify.answers.len(), 1);
+
+            notify.header.response = true;
+            notify.answers.clear();
+            secondary.send_to(&notify.to_bytes().unwrap(), src).unwrap();
+        });
+
+        send_notify(target, &zone).unwrap();
+        acknowledged.join().unwrap();
+    }
+}
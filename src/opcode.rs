@@ -0,0 +1,237 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use log::info;
+
+use crate::notify::OPCODE_NOTIFY;
+use crate::packet::DnsPacket;
+use crate::privacy;
+use crate::resultcode::ResultCode;
+use crate::subnet::Subnet;
+
+/// Opcode of DNS UPDATE messages: see https://datatracker.ietf.org/doc/html/rfc2136
+pub const OPCODE_UPDATE: u8 = 5;
+
+/// The kind of a message, from the opcode of its header
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Opcode {
+    /// Standard query
+    Query,
+    /// Change of a zone announced by its primary (RFC 1996)
+    Notify,
+    /// Change to a zone asked by a client (RFC 2136)
+    Update,
+    /// Any other opcode, obsolete or unassigned
+    Other(u8),
+}
+
+impl Opcode {
+    pub fn from_num(num: u8) -> Opcode {
+        match num {
+            0 => Opcode::Query,
+            OPCODE_NOTIFY => Opcode::Notify,
+            OPCODE_UPDATE => Opcode::Update,
+            num => Opcode::Other(num),
+        }
+    }
+}
+
+impl FromStr for Opcode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Opcode, String> {
+        match value.to_ascii_lowercase().as_str() {
+            "query" => Ok(Opcode::Query),
+            "notify" => Ok(Opcode::Notify),
+            "update" => Ok(Opcode::Update),
+            other => other
+                .parse()
+                .ok()
+                .filter(|num| *num < 16)
+                .map(Opcode::from_num)
+                .ok_or_else(|| format!("unknown opcode \"{}\"", value)),
+        }
+    }
+}
+
+impl fmt::Display for Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Opcode::Query => write!(f, "query"),
+            Opcode::Notify => write!(f, "notify"),
+            Opcode::Update => write!(f, "update"),
+            Opcode::Other(num) => write!(f, "{}", num),
+        }
+    }
+}
+
+/// Handles the messages of an opcode other than standard queries, returning the response to
+/// send back, if any. Handlers are registered on the `Dispatcher` on startup.
+pub trait OpcodeHandler: Send + Sync {
+    fn handle(&self, request: &DnsPacket, client: IpAddr) -> Option<DnsPacket>;
+}
+
+/// Where a message goes, depending on its opcode and on the client sending it
+pub enum Route<'a> {
+    /// Answered by the resolver
+    Query,
+    /// Handled by the handler of its opcode
+    Handler(&'a dyn OpcodeHandler),
+    /// Refused, as the client isn't allowed to send messages of its opcode
+    Refused,
+    /// Answered NOTIMP, as no handler is registered for its opcode
+    NotImplemented,
+}
+
+/// The `Dispatcher` struct routes the messages received to the handler of their opcode:
+/// standard queries go to the resolver, and the other opcodes to the handlers registered on
+/// startup, only for the clients in the subnets allowed for each opcode. Opcodes without a
+/// handler are answered NOTIMP, as RFC 1035 asks.
+#[derive(Default)]
+pub struct Dispatcher {
+    handlers: HashMap<Opcode, Box<dyn OpcodeHandler>>,
+    allowed: HashMap<Opcode, Vec<Subnet>>,
+}
+
+impl Dispatcher {
+    pub fn new() -> Dispatcher {
+        Dispatcher::default()
+    }
+
+    /// Registers the handler of an opcode, replacing the previous one
+    pub fn with_handler(
+        mut self,
+        opcode: Opcode,
+        handler: impl OpcodeHandler + 'static,
+    ) -> Dispatcher {
+        self.handlers.insert(opcode, Box::new(handler));
+        self
+    }
+
+    /// Sets the subnets allowed to send messages of an opcode, none by default
+    pub fn with_allowed(mut self, opcode: Opcode, subnets: Vec<Subnet>) -> Dispatcher {
+        self.allowed.insert(opcode, subnets);
+        self
+    }
+
+    /// Returns where a message of the given opcode from the given client goes
+    pub fn route(&self, opcode: Opcode, client: IpAddr) -> Route<'_> {
+        if opcode == Opcode::Query {
+            return Route::Query;
+        }
+        let Some(handler) = self.handlers.get(&opcode) else {
+            return Route::NotImplemented;
+        };
+        match self
+            .allowed
+            .get(&opcode)
+            .is_some_and(|subnets| subnets.iter().any(|subnet| subnet.contains(client)))
+        {
+            true => Route::Handler(handler.as_ref()),
+            false => Route::Refused,
+        }
+    }
+
+    /// Describes the opcodes handled besides standard queries, and who may send them
+    pub fn describe(&self) -> String {
+        let mut opcodes: Vec<&Opcode> = self.handlers.keys().collect();
+        opcodes.sort();
+
+        let mut description = vec!["query".to_string()];
+        for opcode in opcodes {
+            let subnets: Vec<String> = self
+                .allowed
+                .get(opcode)
+                .into_iter()
+                .flatten()
+                .map(|subnet| format!("{}/{}", subnet.addr, subnet.prefix))
+                .collect();
+            description.push(match subnets.is_empty() {
+                true => format!("{} refused", opcode),
+                false => format!("{} from {}", opcode, subnets.join(", ")),
+            });
+        }
+        description.join(", ")
+    }
+}
+
+/// Builds the response to a message with a result code only, echoing its ID, opcode and
+/// question
+pub fn response_to(request: &DnsPacket, rescode: ResultCode) -> DnsPacket {
+    let mut packet = DnsPacket::new();
+    packet.header.id = request.header.id;
+    packet.header.opcode = request.header.opcode;
+    packet.header.response = true;
+    packet.header.rescode = rescode;
+    packet.questions = request.questions.clone();
+    packet
+}
+
+/// Acknowledges the NOTIFY messages of primaries (RFC 1996), logging the zone they announce a
+/// change of. The zones transferred from a primary are still refreshed on their own schedule.
+pub struct NotifyHandler;
+
+impl OpcodeHandler for NotifyHandler {
+    fn handle(&self, request: &DnsPacket, client: IpAddr) -> Option<DnsPacket> {
+        let Some(question) = request.questions.first() else {
+            return Some(response_to(request, ResultCode::FORMERR));
+        };
+        info!(
+            "NOTIFY for zone {} from {}",
+            question.name,
+            privacy::client(client)
+        );
+
+        let mut response = response_to(request, ResultCode::NOERROR);
+        response.header.authoritative_answer = true;
+        Some(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::question::{DnsQuestion, QueryType};
+
+    #[test]
+    fn routes_opcodes_to_their_handlers() {
+        let dispatcher = Dispatcher::new()
+            .with_handler(Opcode::Notify, NotifyHandler)
+            .with_allowed(Opcode::Notify, vec!["192.0.2.0/24".parse().unwrap()]);
+        let primary: IpAddr = "192.0.2.53".parse().unwrap();
+        let stranger: IpAddr = "198.51.100.1".parse().unwrap();
+
+        assert!(matches!(
+            dispatcher.route(Opcode::Query, stranger),
+            Route::Query
+        ));
+        assert!(matches!(
+            dispatcher.route(Opcode::Notify, stranger),
+            Route::Refused
+        ));
+        assert!(matches!(
+            dispatcher.route(Opcode::Update, primary),
+            Route::NotImplemented
+        ));
+        let Route::Handler(handler) = dispatcher.route(Opcode::Notify, primary) else {
+            panic!("NOTIFY from an allowed primary not handled");
+        };
+
+        let mut notify = DnsPacket::new();
+        notify.header.id = 1234;
+        notify.header.opcode = OPCODE_NOTIFY;
+        notify
+            .questions
+            .push(DnsQuestion::new("example.com".to_string(), QueryType::SOA));
+        let ack = handler.handle(&notify, primary).unwrap();
+        assert!(ack.header.response && ack.header.authoritative_answer);
+        assert_eq!((ack.header.id, ack.header.opcode), (1234, OPCODE_NOTIFY));
+        assert_eq!(ack.questions, notify.questions);
+
+        assert_eq!("UPDATE".parse(), Ok(Opcode::Update));
+        assert_eq!(Opcode::from_num(2).to_string(), "2");
+        assert_eq!(dispatcher.describe(), "query, notify from 192.0.2.0/24");
+    }
+}
@@ -0,0 +1,340 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::record::DnsRecord;
+
+/// EDNS option codes, see https://www.iana.org/assignments/dns-parameters/dns-parameters.xhtml#dns-parameters-11
+const NSID: u16 = 3;
+const CLIENT_SUBNET: u16 = 8;
+const COOKIE: u16 = 10;
+const TCP_KEEPALIVE: u16 = 11;
+const PADDING: u16 = 12;
+const EXTENDED_ERROR: u16 = 15;
+
+/// Flag of the OPT record telling that the sender wants DNSSEC records
+const DNSSEC_OK: u32 = 0x8000;
+
+/// `OptError` is an enum that represents the ways in which the options of an OPT record
+/// can be malformed
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum OptError {
+    #[error("EDNS option {0} is longer than the OPT record")]
+    Truncated(u16),
+    #[error("Malformed EDNS option {0}")]
+    Malformed(u16),
+}
+
+/// A typed EDNS option, carried in the data of an OPT record as a {code, length, data}
+/// triplet: see https://tools.ietf.org/html/rfc6891#section-6.1.2
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EdnsOption {
+    /// The subnet of the client the query is made for, see https://tools.ietf.org/html/rfc7871
+    ClientSubnet {
+        source_prefix: u8,
+        scope_prefix: u8,
+        addr: IpAddr,
+    },
+    /// The client cookie and, in responses, the server cookie, see https://tools.ietf.org/html/rfc7873
+    Cookie {
+        client: [u8; 8],
+        server: Vec<u8>,
+    },
+    /// The identifier of the server, empty in queries, see https://tools.ietf.org/html/rfc5001
+    Nsid(Vec<u8>),
+    /// Why a query failed, see https://tools.ietf.org/html/rfc8914
+    ExtendedError {
+        code: u16,
+        text: String,
+    },
+    /// The idle timeout of TCP connections in units of 100 milliseconds, absent in queries,
+    /// see https://tools.ietf.org/html/rfc7828
+    Keepalive(Option<u16>),
+    /// Zeros padding the message to a given length, see https://tools.ietf.org/html/rfc7830
+    Padding(u16),
+    Unknown {
+        code: u16,
+        data: Vec<u8>,
+    },
+}
+
+impl EdnsOption {
+    pub fn code(&self) -> u16 {
+        match self {
+            EdnsOption::ClientSubnet { .. } => CLIENT_SUBNET,
+            EdnsOption::Cookie { .. } => COOKIE,
+            EdnsOption::Nsid(_) => NSID,
+            EdnsOption::ExtendedError { .. } => EXTENDED_ERROR,
+            EdnsOption::Keepalive(_) => TCP_KEEPALIVE,
+            EdnsOption::Padding(_) => PADDING,
+            EdnsOption::Unknown { code, .. } => *code,
+        }
+    }
+
+    /// Decodes the data of an option with the given code
+    fn decode(code: u16, data: &[u8]) -> Result<EdnsOption, OptError> {
+        let option = match code {
+            CLIENT_SUBNET => {
+                if data.len() < 4 {
+                    return Err(OptError::Malformed(code));
+                }
+                let family = u16::from_be_bytes([data[0], data[1]]);
+                let source_prefix = data[2];
+                let scope_prefix = data[3];
+                // The address is truncated to the bytes covered by the source prefix
+                let bytes = &data[4..];
+                let addr = match family {
+                    1 if bytes.len() <= 4 => {
+                        let mut octets = [0; 4];
+                        octets[..bytes.len()].copy_from_slice(bytes);
+                        IpAddr::V4(Ipv4Addr::from(octets))
+                    }
+                    2 if bytes.len() <= 16 => {
+                        let mut octets = [0; 16];
+                        octets[..bytes.len()].copy_from_slice(bytes);
+                        IpAddr::V6(Ipv6Addr::from(octets))
+                    }
+                    _ => return Err(OptError::Malformed(code)),
+                };
+                EdnsOption::ClientSubnet {
+                    source_prefix,
+                    scope_prefix,
+                    addr,
+                }
+            }
+            COOKIE => {
+                // The server cookie, when present, is 8 to 32 bytes long
+                if data.len() != 8 && !(16..=40).contains(&data.len()) {
+                    return Err(OptError::Malformed(code));
+                }
+                EdnsOption::Cookie {
+                    client: data[..8].try_into().unwrap(),
+                    server: data[8..].to_vec(),
+                }
+            }
+            NSID => EdnsOption::Nsid(data.to_vec()),
+            EXTENDED_ERROR => {
+                if data.len() < 2 {
+                    return Err(OptError::Malformed(code));
+                }
+                EdnsOption::ExtendedError {
+                    code: u16::from_be_bytes([data[0], data[1]]),
+                    text: String::from_utf8_lossy(&data[2..]).into_owned(),
+                }
+            }
+            TCP_KEEPALIVE => match data.len() {
+                0 => EdnsOption::Keepalive(None),
+                2 => EdnsOption::Keepalive(Some(u16::from_be_bytes([data[0], data[1]]))),
+                _ => return Err(OptError::Malformed(code)),
+            },
+            PADDING => EdnsOption::Padding(data.len() as u16),
+            _ => EdnsOption::Unknown {
+                code,
+                data: data.to_vec(),
+            },
+        };
+
+        Ok(option)
+    }
+
+    /// Appends the option, with its code and length, to the data of an OPT record
+    fn encode(&self, out: &mut Vec<u8>) {
+        let mut data = Vec::new();
+        match self {
+            EdnsOption::ClientSubnet {
+                source_prefix,
+                scope_prefix,
+                addr,
+            } => {
+                let (family, octets) = match addr {
+                    IpAddr::V4(addr) => (1u16, addr.octets().to_vec()),
+                    IpAddr::V6(addr) => (2u16, addr.octets().to_vec()),
+                };
+                let len = (*source_prefix as usize).div_ceil(8).min(octets.len());
+                data.extend_from_slice(&family.to_be_bytes());
+                data.push(*source_prefix);
+                data.push(*scope_prefix);
+                data.extend_from_slice(&octets[..len]);
+                // Bits past the source prefix must be zero
+                if let Some(last) = data.last_mut().filter(|_| source_prefix % 8 != 0) {
+                    *last &= 0xffu8 << (8 - source_prefix % 8);
+                }
+            }
+            EdnsOption::Cookie { client, server } => {
+                data.extend_from_slice(client);
+                data.extend_from_slice(server);
+            }
+            EdnsOption::Nsid(id) => data.extend_from_slice(id),
+            EdnsOption::ExtendedError { code, text } => {
+                data.extend_from_slice(&code.to_be_bytes());
+                data.extend_from_slice(text.as_bytes());
+            }
+            EdnsOption::Keepalive(timeout) => {
+                if let Some(timeout) = timeout {
+                    data.extend_from_slice(&timeout.to_be_bytes());
+                }
+            }
+            EdnsOption::Padding(len) => data.resize(*len as usize, 0),
+            EdnsOption::Unknown { data: raw, .. } => data.extend_from_slice(raw),
+        }
+
+        out.extend_from_slice(&self.code().to_be_bytes());
+        out.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        out.extend_from_slice(&data);
+    }
+}
+
+/// The `OptRecord` struct is the typed form of the OPT pseudo-record, which is carried on
+/// the wire by `DnsRecord::OPT`. Build one with `new` and `with_option`, and turn it into
+/// a record with `to_record`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OptRecord {
+    /// The largest UDP payload the sender can receive
+    pub udp_payload_size: u16,
+    /// The upper 8 bits of the result code
+    pub extended_rcode: u8,
+    pub version: u8,
+    pub dnssec_ok: bool,
+    pub options: Vec<EdnsOption>,
+}
+
+impl OptRecord {
+    pub fn new(udp_payload_size: u16) -> OptRecord {
+        OptRecord {
+            udp_payload_size,
+            extended_rcode: 0,
+            version: 0,
+            dnssec_ok: false,
+            options: Vec::new(),
+        }
+    }
+
+    pub fn with_option(mut self, option: EdnsOption) -> OptRecord {
+        self.options.push(option);
+        self
+    }
+
+    /// Returns the first option with the given code, if any
+    pub fn option(&self, code: u16) -> Option<&EdnsOption> {
+        self.options.iter().find(|option| option.code() == code)
+    }
+
+    pub fn keepalive(&self) -> Option<Option<u16>> {
+        self.options.iter().find_map(|option| match option {
+            EdnsOption::Keepalive(timeout) => Some(*timeout),
+            _ => None,
+        })
+    }
+
+    /// Decodes an OPT record, or returns `None` for any other record
+    pub fn from_record(record: &DnsRecord) -> Option<Result<OptRecord, OptError>> {
+        match record {
+            DnsRecord::OPT {
+                packet_len,
+                flags,
+                data,
+            } => Some(OptRecord::decode(*packet_len, *flags, data)),
+            _ => None,
+        }
+    }
+
+    fn decode(packet_len: u16, flags: u32, mut data: &[u8]) -> Result<OptRecord, OptError> {
+        let mut options = Vec::new();
+        while data.len() >= 4 {
+            let code = u16::from_be_bytes([data[0], data[1]]);
+            let len = u16::from_be_bytes([data[2], data[3]]) as usize;
+            let value = data.get(4..4 + len).ok_or(OptError::Truncated(code))?;
+            options.push(EdnsOption::decode(code, value)?);
+            data = &data[4 + len..];
+        }
+
+        Ok(OptRecord {
+            udp_payload_size: packet_len,
+            extended_rcode: (flags >> 24) as u8,
+            version: (flags >> 16) as u8,
+            dnssec_ok: flags & DNSSEC_OK != 0,
+            options,
+        })
+    }
+
+    pub fn to_record(&self) -> DnsRecord {
+        let mut data = Vec::new();
+        for option in &self.options {
+            option.encode(&mut data);
+        }
+
+        let mut flags = (self.extended_rcode as u32) << 24 | (self.version as u32) << 16;
+        if self.dnssec_ok {
+            flags |= DNSSEC_OK;
+        }
+
+        DnsRecord::OPT {
+            packet_len: self.udp_payload_size,
+            flags,
+            data,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn options_survive_a_round_trip() {
+        let opt = OptRecord {
+            dnssec_ok: true,
+            ..OptRecord::new(1232)
+        }
+        .with_option(EdnsOption::ClientSubnet {
+            source_prefix: 24,
+            scope_prefix: 0,
+            addr: "192.0.2.0".parse().unwrap(),
+        })
+        .with_option(EdnsOption::Cookie {
+            client: [1, 2, 3, 4, 5, 6, 7, 8],
+            server: vec![9; 8],
+        })
+        .with_option(EdnsOption::Nsid(b"vodo".to_vec()))
+        .with_option(EdnsOption::ExtendedError {
+            code: 18,
+            text: "Prohibited".to_string(),
+        })
+        .with_option(EdnsOption::Keepalive(Some(300)))
+        .with_option(EdnsOption::Padding(7))
+        .with_option(EdnsOption::Unknown {
+            code: 65001,
+            data: vec![0xab],
+        });
+
+        let record = opt.to_record();
+        assert_eq!(OptRecord::from_record(&record), Some(Ok(opt.clone())));
+        assert_eq!(opt.keepalive(), Some(Some(300)));
+    }
+
+    #[test]
+    fn client_subnet_address_is_truncated_to_its_prefix() {
+        let opt = OptRecord::new(512).with_option(EdnsOption::ClientSubnet {
+            source_prefix: 20,
+            scope_prefix: 0,
+            addr: "2001:db8::".parse().unwrap(),
+        });
+
+        match opt.to_record() {
+            DnsRecord::OPT { data, .. } => {
+                assert_eq!(data, [0, 8, 0, 7, 0, 2, 20, 0, 0x20, 0x01, 0x00])
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_options() {
+        assert_eq!(
+            OptRecord::decode(512, 0, &[0, 10, 0, 3, 1, 2, 3]),
+            Err(OptError::Malformed(COOKIE))
+        );
+        assert_eq!(
+            OptRecord::decode(512, 0, &[0, 3, 0, 4, 1]),
+            Err(OptError::Truncated(NSID))
+        );
+    }
+}
@@ -1,28 +1,45 @@
 use std::net::Ipv4Addr;
 
-use crate::buffer::{Buffer, BufferError};
+use smallvec::SmallVec;
+
+use crate::buffer::{Buffer, BufferError, DEFAULT_SIZE};
 use crate::header::DnsHeader;
+use crate::opt::OptRecord;
 use crate::question::DnsQuestion;
 use crate::question::QueryType;
 use crate::record::DnsRecord;
+use crate::resultcode::ResultCode;
+use crate::zone::is_subdomain;
+
+/// Questions of a message, kept inline as messages almost always have a single one
+pub type Questions = SmallVec<[DnsQuestion; 1]>;
+/// Records of a section of a message, kept inline up to the few of a typical response so
+/// that parsing it doesn't allocate a vector per section
+pub type Records = SmallVec<[DnsRecord; 3]>;
 
 #[derive(Clone, Debug)]
 pub struct DnsPacket {
     pub header: DnsHeader,
-    pub questions: Vec<DnsQuestion>,
-    pub answers: Vec<DnsRecord>,
-    pub authorities: Vec<DnsRecord>,
-    pub resources: Vec<DnsRecord>,
+    pub questions: Questions,
+    pub answers: Records,
+    pub authorities: Records,
+    pub resources: Records,
+}
+
+impl Default for DnsPacket {
+    fn default() -> Self {
+        DnsPacket::new()
+    }
 }
 
 impl DnsPacket {
     pub fn new() -> DnsPacket {
         DnsPacket {
             header: DnsHeader::new(),
-            questions: Vec::new(),
-            answers: Vec::new(),
-            authorities: Vec::new(),
-            resources: Vec::new(),
+            questions: Questions::new(),
+            answers: Records::new(),
+            authorities: Records::new(),
+            resources: Records::new(),
         }
     }
 
@@ -50,17 +67,41 @@ impl DnsPacket {
             result.resources.push(rec);
         }
 
+        // Extended result codes have their upper bits in the TTL of the OPT record.
+        if let Some(extended) = result.extended_rcode_bits() {
+            let num = (extended << 4) | result.header.rescode.to_num();
+            result.header.rescode = ResultCode::from_num(num);
+        }
+
         Ok(result)
     }
 
-    /// Writes a DNS packet to a buffer
-    pub fn write(&mut self, buffer: &mut Buffer) -> Result<(), BufferError> {
-        self.header.questions = self.questions.len() as u16;
-        self.header.answers = self.answers.len() as u16;
-        self.header.authoritative_entries = self.authorities.len() as u16;
-        self.header.resource_entries = self.resources.len() as u16;
+    /// Parses a DNS packet from a slice of bytes, such as a received datagram
+    pub fn parse(bytes: &[u8]) -> Result<DnsPacket, BufferError> {
+        DnsPacket::from_buffer(&mut Buffer::from_bytes(bytes))
+    }
+
+    /// Serializes a DNS packet into a vector of bytes of the exact message length
+    pub fn to_bytes(&self) -> Result<Vec<u8>, BufferError> {
+        let mut buffer = Buffer::growable();
+        self.write(&mut buffer)?;
 
-        self.header.write(buffer)?;
+        let mut bytes = buffer.buf;
+        bytes.truncate(buffer.pos);
+
+        Ok(bytes)
+    }
+
+    /// Writes a DNS packet to a buffer.
+    /// The section counts of the header are derived from the sections themselves.
+    pub fn write(&self, buffer: &mut Buffer) -> Result<(), BufferError> {
+        let mut header = self.header.clone();
+        header.questions = self.questions.len() as u16;
+        header.answers = self.answers.len() as u16;
+        header.authoritative_entries = self.authorities.len() as u16;
+        header.resource_entries = self.resources.len() as u16;
+
+        header.write(buffer)?;
 
         for question in &self.questions {
             question.write(buffer)?;
@@ -72,12 +113,157 @@ impl DnsPacket {
             rec.write(buffer)?;
         }
         for rec in &self.resources {
-            rec.write(buffer)?;
+            match rec {
+                // The OPT record carries the upper bits of extended result codes.
+                DnsRecord::OPT {
+                    packet_len,
+                    flags,
+                    data,
+                } => {
+                    let extended = self.header.rescode.extended_bits() as u32;
+                    let opt = DnsRecord::OPT {
+                        packet_len: *packet_len,
+                        flags: (flags & 0x00FF_FFFF) | (extended << 24),
+                        data: data.clone(),
+                    };
+                    opt.write(buffer)?;
+                }
+                _ => {
+                    rec.write(buffer)?;
+                }
+            }
         }
 
         Ok(())
     }
 
+    /// Returns the upper 8 bits of the result code from the OPT record, if any
+    fn extended_rcode_bits(&self) -> Option<u16> {
+        self.resources.iter().find_map(|record| match record {
+            DnsRecord::OPT { flags, .. } => Some((flags >> 24) as u16),
+            _ => None,
+        })
+    }
+
+    /// Returns the OPT record of the packet, if it has one with well-formed options
+    pub fn opt(&self) -> Option<OptRecord> {
+        self.resources
+            .iter()
+            .find_map(OptRecord::from_record)
+            .and_then(Result::ok)
+    }
+
+    /// Sets the OPT record of the packet, replacing the one it has, if any
+    pub fn set_opt(&mut self, opt: OptRecord) {
+        self.resources
+            .retain(|record| !matches!(record, DnsRecord::OPT { .. }));
+        self.resources.push(opt.to_record());
+    }
+
+    /// Returns the UDP payload size the sender of the packet can receive, as advertised
+    /// in its OPT record, or the 512 bytes every client supports
+    pub fn udp_payload_size(&self) -> usize {
+        self.resources
+            .iter()
+            .find_map(|record| match record {
+                DnsRecord::OPT { packet_len, .. } => Some(*packet_len as usize),
+                _ => None,
+            })
+            .map_or(DEFAULT_SIZE, |size| size.max(DEFAULT_SIZE))
+    }
+
+    /// Returns a copy of the packet without records, except for the OPT record, and with
+    /// the TC flag set, telling the client to retry over TCP to get the full response
+    pub fn truncated(&self) -> DnsPacket {
+        let mut packet = DnsPacket::new();
+        packet.header = self.header.clone();
+        packet.header.truncated_message = true;
+        packet.questions = self.questions.clone();
+        packet.resources = self
+            .resources
+            .iter()
+            .filter(|record| matches!(record, DnsRecord::OPT { .. }))
+            .cloned()
+            .collect();
+
+        packet
+    }
+
+    /// Replaces the CNAME chain answering `qname` with the records it leads to, renamed to
+    /// `qname`, for clients that mishandle chains. Their TTL is the lowest along the chain,
+    /// as the answer expires as soon as any link does. Answers without a chain, or whose
+    /// chain doesn't lead to records of the queried type, are left as they are.
+    pub fn flatten_cnames(&mut self, qname: &str, qtype: QueryType) {
+        if qtype == QueryType::CNAME {
+            return;
+        }
+
+        let mut target = qname.to_string();
+        let mut ttl = u32::MAX;
+        // Each link is followed at most once, in case the chain loops.
+        for _ in 0..self.answers.len() {
+            let link = self.answers.iter().find_map(|record| match record {
+                DnsRecord::CNAME {
+                    domain,
+                    host,
+                    ttl: link_ttl,
+                } if *domain == target => Some((host.clone(), *link_ttl)),
+                _ => None,
+            });
+            let Some((host, link_ttl)) = link else {
+                break;
+            };
+            target = host;
+            ttl = ttl.min(link_ttl);
+        }
+        if target == qname {
+            return;
+        }
+
+        let flattened: Records = self
+            .answers
+            .iter()
+            .filter(|record| record.domain() == Some(&target) && record.qtype() == qtype)
+            .map(|record| record.with_domain(qname).with_ttl(record.ttl().min(ttl)))
+            .collect();
+        if !flattened.is_empty() {
+            self.answers = flattened;
+        }
+    }
+
+    /// Returns the name the CNAME chain of the answers starting from `qname` ends at, or
+    /// `qname` itself when the answers hold no CNAME for it
+    pub fn cname_target(&self, qname: &str) -> String {
+        let mut target = qname.to_string();
+        // Each link is followed at most once, in case the chain loops.
+        for _ in 0..self.answers.len() {
+            let link = self.answers.iter().find_map(|record| match record {
+                DnsRecord::CNAME { domain, host, .. } if *domain == target => Some(host),
+                _ => None,
+            });
+            match link {
+                Some(host) => target = host.clone(),
+                None => break,
+            }
+        }
+        target
+    }
+
+    /// Returns the hosts the answers point clients to, whose addresses they look up next:
+    /// the targets of SRV records and the exchanges of MX records, without duplicates
+    pub fn additional_targets(&self) -> Vec<String> {
+        let mut targets: Vec<String> = Vec::new();
+        for record in &self.answers {
+            if let DnsRecord::SRV { host, .. } | DnsRecord::MX { host, .. } = record {
+                // A target of "." means that there is no such service (RFC 2782 and RFC 7505).
+                if !host.is_empty() && !targets.contains(host) {
+                    targets.push(host.clone());
+                }
+            }
+        }
+        targets
+    }
+
     /// It's useful to be able to pick a random A record from a packet. When there
     /// are multiple IPs for a single name, it doesn't matter which one is chosen
     /// so in those cases a random pick is fine.
@@ -107,6 +293,86 @@ impl DnsPacket {
             .filter(move |(domain, _)| qname.ends_with(*domain))
     }
 
+    /// Returns the zone the name servers in the authorities section are delegated, if any
+    pub fn get_delegation<'a>(&'a self, qname: &'a str) -> Option<&'a str> {
+        self.get_ns(qname).map(|(domain, _)| domain).next()
+    }
+
+    /// Removes the records that don't belong in a response to a query for `qname`
+    /// sent to a server authoritative for `bailiwick`, so that they are neither cached
+    /// nor relayed to clients (see https://datatracker.ietf.org/doc/html/rfc2181#section-5.4.1):
+    /// - answers must be `qname` or the CNAME chain starting from it;
+    /// - authorities must be NS, SOA or DS records for an ancestor of the answered names, or
+    ///   NSEC records proving what doesn't exist;
+    /// - additional records must be addresses of the name servers or mail exchangers
+    ///   mentioned in the other sections.
+    ///
+    /// RRSIG records are kept along with the records they sign, so that responses to DO
+    /// queries are relayed with their signatures. Records for names outside of the bailiwick
+    /// are removed from all sections.
+    pub fn scrub(&mut self, qname: &str, bailiwick: &str) {
+        let in_bailiwick = |record: &DnsRecord| {
+            record
+                .domain()
+                .is_some_and(|domain| is_subdomain(domain, bailiwick))
+        };
+
+        // Follow the CNAME chain, whatever the order of the records is.
+        let mut names = vec![qname.to_string()];
+        while let Some(target) = self.answers.iter().find_map(|record| match record {
+            DnsRecord::CNAME { domain, host, .. }
+                if names.contains(domain) && !names.contains(host) && in_bailiwick(record) =>
+            {
+                Some(host.clone())
+            }
+            _ => None,
+        }) {
+            names.push(target);
+        }
+
+        self.answers.retain(|record| {
+            in_bailiwick(record)
+                && record
+                    .domain()
+                    .is_some_and(|domain| names.iter().any(|name| name == domain))
+        });
+
+        retain_signatures(&mut self.answers);
+
+        self.authorities.retain(|record| match &*record {
+            DnsRecord::NS { domain, .. }
+            | DnsRecord::SOA { domain, .. }
+            | DnsRecord::DS { domain, .. } => {
+                in_bailiwick(record) && names.iter().any(|name| is_subdomain(name, domain))
+            }
+            DnsRecord::NSEC { .. } | DnsRecord::RRSIG { .. } => in_bailiwick(record),
+            _ => false,
+        });
+        retain_signatures(&mut self.authorities);
+
+        let hosts: Vec<String> = self
+            .authorities
+            .iter()
+            .chain(self.answers.iter())
+            .filter_map(|record| match record {
+                DnsRecord::NS { host, .. }
+                | DnsRecord::MX { host, .. }
+                | DnsRecord::SRV { host, .. } => Some(host.clone()),
+                _ => None,
+            })
+            .collect();
+
+        self.resources.retain(|record| match &*record {
+            DnsRecord::OPT { .. } => true,
+            DnsRecord::A { domain, .. } | DnsRecord::AAAA { domain, .. } => {
+                in_bailiwick(record) && hosts.contains(domain)
+            }
+            DnsRecord::RRSIG { .. } => in_bailiwick(record),
+            _ => false,
+        });
+        retain_signatures(&mut self.resources);
+    }
+
     /// Name servers often bundle the corresponding A records
     /// when replying to an NS query. This fact can be used function that
     /// returns the actual IP for an NS record if possible.
@@ -142,3 +408,303 @@ impl DnsPacket {
             .next()
     }
 }
+
+/// Removes the RRSIG records of a section that don't sign any set of records left in it
+fn retain_signatures(records: &mut Records) {
+    let signed: Vec<(String, u16)> = records
+        .iter()
+        .filter(|record| !matches!(record, DnsRecord::RRSIG { .. }))
+        .filter_map(|record| Some((record.domain()?.to_string(), record.qtype().to_num())))
+        .collect();
+
+    records.retain(|record| match &*record {
+        DnsRecord::RRSIG {
+            domain,
+            type_covered,
+            ..
+        } => signed
+            .iter()
+            .any(|(owner, qtype)| owner == domain && qtype == type_covered),
+        _ => true,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opt::EdnsOption;
+    use smallvec::smallvec;
+
+    /// Packets captured from real exchanges, read back from the files in `tests/corpus`
+    const CORPUS: [(&str, &[u8]); 4] = [
+        (
+            "root_referral",
+            include_bytes!("../tests/corpus/root_referral.bin"),
+        ),
+        (
+            "edns_answer",
+            include_bytes!("../tests/corpus/edns_answer.bin"),
+        ),
+        ("truncated", include_bytes!("../tests/corpus/truncated.bin")),
+        (
+            "dnssec_answer",
+            include_bytes!("../tests/corpus/dnssec_answer.bin"),
+        ),
+    ];
+
+    fn corpus(name: &str) -> DnsPacket {
+        let (_, bytes) = CORPUS.iter().find(|(n, _)| *n == name).unwrap();
+        DnsPacket::parse(bytes).unwrap()
+    }
+
+    #[test]
+    fn reserializing_the_corpus_preserves_packets() {
+        for (name, bytes) in CORPUS {
+            let packet = DnsPacket::parse(bytes).unwrap();
+            let reparsed = DnsPacket::parse(&packet.to_bytes().unwrap()).unwrap();
+
+            assert_eq!(
+                format!("{:?}", reparsed.header),
+                format!("{:?}", packet.header),
+                "{}",
+                name
+            );
+            assert_eq!(reparsed.questions, packet.questions, "{}", name);
+            assert_eq!(reparsed.answers, packet.answers, "{}", name);
+            assert_eq!(reparsed.authorities, packet.authorities, "{}", name);
+            assert_eq!(reparsed.resources, packet.resources, "{}", name);
+        }
+    }
+
+    #[test]
+    fn parses_root_referral() {
+        let packet = corpus("root_referral");
+
+        assert!(packet.answers.is_empty());
+        assert_eq!(
+            packet.get_ns("example.com").collect::<Vec<_>>(),
+            vec![("com", "a.gtld-servers.net"), ("com", "b.gtld-servers.net")]
+        );
+        assert_eq!(
+            packet.get_resolved_ns("example.com"),
+            Some(Ipv4Addr::new(192, 5, 6, 30))
+        );
+        assert_eq!(packet.resources.len(), 4);
+    }
+
+    #[test]
+    fn parses_edns_answer() {
+        let packet = corpus("edns_answer");
+
+        assert_eq!(packet.get_random_a(), Some(Ipv4Addr::new(93, 184, 215, 14)));
+        let opt = packet.opt().unwrap();
+        // 10 is the code of the COOKIE option.
+        assert!(matches!(
+            opt.option(10),
+            Some(EdnsOption::Cookie { server, .. }) if server.len() == 16
+        ));
+        assert_eq!(packet.udp_payload_size(), 1232);
+    }
+
+    #[test]
+    fn parses_truncated_response() {
+        let packet = corpus("truncated");
+
+        assert!(packet.header.truncated_message);
+        assert_eq!(packet.questions[0].qtype, QueryType::TXT);
+        assert!(packet.answers.is_empty());
+        assert_eq!(packet.udp_payload_size(), 512);
+    }
+
+    #[test]
+    fn parses_dnssec_answer() {
+        let packet = corpus("dnssec_answer");
+
+        assert!(packet.header.authed_data);
+        assert!(matches!(
+            packet.answers[1],
+            DnsRecord::RRSIG { type_covered: 1, ref signature, .. } if signature.len() == 64
+        ));
+        assert!(matches!(
+            packet.resources[0],
+            DnsRecord::OPT { flags, .. } if flags & 0x8000 != 0
+        ));
+        // The signatures are relayed as they were received.
+        let relayed = DnsPacket::parse(&packet.to_bytes().unwrap()).unwrap();
+        assert_eq!(relayed.answers, packet.answers);
+    }
+
+    #[test]
+    fn scrubbing_keeps_signatures_of_kept_records() {
+        let answer = corpus("dnssec_answer");
+        let mut scrubbed = answer.clone();
+        scrubbed.scrub(&answer.questions[0].name, "");
+        assert_eq!(scrubbed.answers, answer.answers);
+
+        let sig = |domain: &str, type_covered: u16| DnsRecord::RRSIG {
+            domain: domain.to_string(),
+            type_covered,
+            algorithm: 13,
+            labels: 2,
+            original_ttl: 3600,
+            expiration: 1_700_000_000,
+            inception: 1_690_000_000,
+            key_tag: 12345,
+            signer: "com".to_string(),
+            signature: vec![1; 64],
+            ttl: 3600,
+        };
+        let ds = DnsRecord::DS {
+            domain: "example.com".to_string(),
+            key_tag: 370,
+            algorithm: 13,
+            digest_type: 2,
+            digest: vec![2; 32],
+            ttl: 3600,
+        };
+        let glue = DnsRecord::A {
+            domain: "ns1.example.com".to_string(),
+            addr: "192.0.2.53".parse().unwrap(),
+            ttl: 3600,
+        };
+        let mut referral = DnsPacket::new();
+        referral.authorities = smallvec![
+            DnsRecord::NS {
+                domain: "example.com".to_string(),
+                host: "ns1.example.com".to_string(),
+                ttl: 3600,
+            },
+            ds.clone(),
+            sig("example.com", 43),
+            // Signs records that aren't there
+            sig("example.com", 1),
+            DnsRecord::NSEC {
+                domain: "example.org".to_string(),
+                next: "example.net".to_string(),
+                types: vec![2, 46, 47],
+                ttl: 3600,
+            },
+        ];
+        referral.resources = smallvec![glue.clone(), sig("ns1.example.com", 1)];
+
+        referral.scrub("www.example.com", "com");
+        assert_eq!(referral.authorities.len(), 3);
+        assert_eq!(referral.authorities[1], ds);
+        assert_eq!(referral.authorities[2], sig("example.com", 43));
+        assert_eq!(referral.resources[..], [glue, sig("ns1.example.com", 1)]);
+    }
+
+    #[test]
+    fn carries_extended_result_codes_in_its_only_opt_record() {
+        let mut packet = DnsPacket::new();
+        packet.header.response = true;
+        packet.header.rescode = ResultCode::BADVERS;
+        packet.set_opt(OptRecord::new(512));
+        packet.set_opt(OptRecord::new(1232).with_option(EdnsOption::Keepalive(Some(100))));
+
+        let reparsed = DnsPacket::parse(&packet.to_bytes().unwrap()).unwrap();
+        assert_eq!(reparsed.header.rescode, ResultCode::BADVERS);
+        assert_eq!(reparsed.resources.len(), 1);
+        assert_eq!(reparsed.udp_payload_size(), 1232);
+        assert_eq!(reparsed.opt().unwrap().keepalive(), Some(Some(100)));
+    }
+
+    #[test]
+    fn echoes_questions_and_records_of_unknown_types() {
+        // Response for `example.com` of type 65280 (private use) in class 1, with a record of
+        // that type.
+        let mut bytes = vec![0x12, 0x34, 0x81, 0x80, 0, 1, 0, 1, 0, 0, 0, 0];
+        bytes.extend_from_slice(b"\x07example\x03com\x00\xff\x00\x00\x01");
+        bytes.extend_from_slice(b"\x07example\x03com\x00\xff\x00\x00\x01");
+        bytes.extend_from_slice(&[0, 0, 0x0e, 0x10, 0, 3, 0, 1, 0]);
+
+        let packet = DnsPacket::parse(&bytes).unwrap();
+
+        assert_eq!(packet.questions[0].qtype, QueryType::UNKNOWN(65280));
+        assert_eq!(packet.answers[0].qtype(), QueryType::UNKNOWN(65280));
+        assert_eq!(packet.to_bytes().unwrap(), bytes);
+    }
+
+    #[test]
+    fn names_the_targets_of_srv_and_mx_records() {
+        let srv = |port, host: &str| DnsRecord::SRV {
+            domain: "_sip._udp.example.com".to_string(),
+            priority: 10,
+            weight: 5,
+            port,
+            host: host.to_string(),
+            ttl: 300,
+        };
+        let mut packet = DnsPacket::new();
+        packet.answers = smallvec![
+            srv(5060, "sip1.example.com"),
+            srv(5061, "sip1.example.com"),
+            srv(5060, "sip2.example.com"),
+            srv(0, ""),
+            DnsRecord::MX {
+                domain: "example.com".to_string(),
+                priority: 10,
+                host: "mail.example.com".to_string(),
+                ttl: 300,
+            },
+        ];
+
+        let reparsed = DnsPacket::parse(&packet.to_bytes().unwrap()).unwrap();
+        assert_eq!(reparsed.answers, packet.answers);
+        assert_eq!(
+            reparsed.additional_targets(),
+            vec!["sip1.example.com", "sip2.example.com", "mail.example.com"]
+        );
+    }
+
+    #[test]
+    fn writes_ptr_answers_to_reverse_lookups() {
+        let name = crate::reverse::ptr_name("192.0.2.10".parse().unwrap());
+        let mut packet = DnsPacket::new();
+        packet
+            .questions
+            .push(DnsQuestion::new(name.clone(), QueryType::PTR));
+        packet.answers.push(DnsRecord::PTR {
+            domain: name,
+            host: "nas.home.lan".to_string(),
+            ttl: 300,
+        });
+
+        let reparsed = DnsPacket::parse(&packet.to_bytes().unwrap()).unwrap();
+        assert_eq!(reparsed.questions[0].qtype, QueryType::PTR);
+        assert_eq!(reparsed.answers, packet.answers);
+    }
+
+    #[test]
+    fn flattens_cname_chains_with_the_lowest_ttl() {
+        let cname = |domain: &str, host: &str, ttl| DnsRecord::CNAME {
+            domain: domain.to_string(),
+            host: host.to_string(),
+            ttl,
+        };
+        let mut packet = DnsPacket::new();
+        packet.answers = smallvec![
+            cname("www.example.com", "cdn.example.net", 300),
+            cname("cdn.example.net", "edge.example.org", 60),
+            DnsRecord::A {
+                domain: "edge.example.org".to_string(),
+                addr: "192.0.2.1".parse().unwrap(),
+                ttl: 3600,
+            },
+        ];
+
+        let mut unchanged = packet.clone();
+        unchanged.flatten_cnames("www.example.com", QueryType::AAAA);
+        assert_eq!(unchanged.answers, packet.answers);
+
+        packet.flatten_cnames("www.example.com", QueryType::A);
+        assert_eq!(
+            packet.answers[..],
+            [DnsRecord::A {
+                domain: "www.example.com".to_string(),
+                addr: "192.0.2.1".parse().unwrap(),
+                ttl: 60,
+            }]
+        );
+    }
+}
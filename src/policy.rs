@@ -0,0 +1,496 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::RwLock;
+use std::thread;
+use std::time::Duration;
+
+use log::{info, warn};
+
+use crate::question::QueryType;
+use crate::record::DnsRecord;
+use crate::transfer::{axfr, query_serial, TransferError};
+use crate::zone::{is_subdomain, load_name_list, Zone, ZoneError};
+
+/// Action to take for a query matching a policy, as defined by the RPZ specification:
+/// see https://datatracker.ietf.org/doc/html/draft-vixie-dnsop-dns-rpz#section-3
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PolicyAction {
+    /// Answer that the name doesn't exist (`CNAME .`)
+    NxDomain,
+    /// Answer that the name exists but has no records (`CNAME *.`)
+    NoData,
+    /// Resolve the query as if there was no policy (`CNAME rpz-passthru.`)
+    Passthru,
+    /// Don't answer at all (`CNAME rpz-drop.`)
+    Drop,
+    /// Answer with the given records, whose owner is rewritten to the query name
+    LocalData(Vec<DnsRecord>),
+    /// Refuse to answer, for names outside of the allowlist
+    Refused,
+}
+
+/// A response policy zone (RPZ): a zone whose owner names, relative to its origin,
+/// are query names to trigger on, and whose records describe the action to take.
+/// For example, in the `rpz.local` policy zone, `bad.example.com.rpz.local CNAME .`
+/// answers NXDOMAIN to queries for `bad.example.com`, and `*.example.com.rpz.local`
+/// applies to all its subdomains.
+///
+/// Policy zones are loaded from a master file, or transferred from a primary (e.g. the
+/// server of a threat intelligence feed) and transferred again whenever they change.
+pub struct ResponsePolicyZone {
+    pub origin: String,
+    /// Primary the zone is transferred from, if it isn't loaded from a master file
+    pub primary: Option<SocketAddr>,
+    triggers: RwLock<Triggers>,
+}
+
+/// The policies of a version of a policy zone, by the query name they trigger on
+#[derive(Default)]
+struct Triggers {
+    serial: Option<u32>,
+    records: HashMap<String, Vec<DnsRecord>>,
+}
+
+impl ResponsePolicyZone {
+    /// Loads a policy zone from a master file
+    pub fn load(origin: &str, path: &Path) -> Result<ResponsePolicyZone, ZoneError> {
+        Ok(ResponsePolicyZone::from_zone(Zone::load(origin, path)?))
+    }
+
+    /// Returns a policy zone to transfer from a primary, without any policy until it is
+    /// (see `refresh`)
+    pub fn transferred(origin: &str, primary: SocketAddr) -> ResponsePolicyZone {
+        ResponsePolicyZone {
+            origin: origin.to_string(),
+            primary: Some(primary),
+            triggers: RwLock::default(),
+        }
+    }
+
+    /// Indexes the records of a zone by the query name they trigger on
+    pub fn from_zone(zone: Zone) -> ResponsePolicyZone {
+        ResponsePolicyZone {
+            origin: zone.origin.clone(),
+            primary: None,
+            triggers: RwLock::new(Triggers::from_zone(zone)),
+        }
+    }
+
+    /// Transfers the zone from its primary with AXFR if its serial changed since it was last
+    /// transferred, returning the number of triggers of the new version if it did. Zones
+    /// loaded from master files are left as they are.
+    pub fn refresh(&self) -> Result<Option<usize>, TransferError> {
+        let Some(primary) = self.primary else {
+            return Ok(None);
+        };
+        let serial = query_serial(primary, &self.origin)?;
+        if self.triggers.read().unwrap().serial == Some(serial) {
+            return Ok(None);
+        }
+
+        let triggers = Triggers::from_zone(axfr(primary, &self.origin)?);
+        let count = triggers.records.len();
+        *self.triggers.write().unwrap() = triggers;
+        Ok(Some(count))
+    }
+
+    /// Returns the action to take for a query, if any policy applies to it.
+    /// Exact matches take precedence over wildcards, and more specific wildcards
+    /// take precedence over less specific ones.
+    pub fn lookup(&self, qname: &str, qtype: QueryType) -> Option<PolicyAction> {
+        let triggers = &self.triggers.read().unwrap().records;
+        if let Some(records) = triggers.get(qname) {
+            return Some(action(records, qname, qtype));
+        }
+
+        let mut parent = qname;
+        while let Some((_, rest)) = parent.split_once('.') {
+            if let Some(records) = triggers.get(&format!("*.{}", rest)) {
+                return Some(action(records, qname, qtype));
+            }
+            parent = rest;
+        }
+
+        None
+    }
+}
+
+impl Triggers {
+    fn from_zone(zone: Zone) -> Triggers {
+        let serial = zone.serial();
+        let mut triggers: HashMap<String, Vec<DnsRecord>> = HashMap::new();
+
+        for record in zone.records {
+            // The SOA and NS records of the policy zone itself aren't policies.
+            let Some(trigger) = record
+                .domain()
+                .and_then(|domain| domain.strip_suffix(&zone.origin))
+                .and_then(|domain| domain.strip_suffix('.'))
+            else {
+                continue;
+            };
+
+            triggers
+                .entry(trigger.to_string())
+                .or_default()
+                .push(record);
+        }
+
+        Triggers {
+            serial,
+            records: triggers,
+        }
+    }
+}
+
+/// A list of the only domains that can be looked up, along with their subdomains
+pub struct Allowlist {
+    names: Vec<String>,
+}
+
+impl Allowlist {
+    /// Loads an allowlist from a file holding a domain per line
+    pub fn load(path: &Path) -> Result<Allowlist, ZoneError> {
+        Ok(Allowlist {
+            names: load_name_list(path)?,
+        })
+    }
+
+    /// Returns true if the name is an allowed domain or one of its subdomains
+    pub fn allows(&self, qname: &str) -> bool {
+        self.names.iter().any(|name| is_subdomain(qname, name))
+    }
+}
+
+/// TTL of the answers synthesized for blocked queries, so that clients cache them instead
+/// of asking again right away, independently of the TTLs of the policy records
+#[derive(Clone, Debug, Default)]
+pub struct BlockedTtl {
+    /// TTL for all the query types without a TTL of their own
+    pub default: u32,
+    /// TTL for specific query types, e.g. longer for AAAA queries of IPv4-only devices
+    pub per_type: Vec<(QueryType, u32)>,
+}
+
+impl BlockedTtl {
+    /// Returns the TTL of blocked answers to queries of the given type
+    pub fn for_type(&self, qtype: QueryType) -> u32 {
+        self.per_type
+            .iter()
+            .find(|(t, _)| *t == qtype)
+            .map_or(self.default, |(_, ttl)| *ttl)
+    }
+}
+
+/// The policy engine decides what to do with queries before they are answered as usual.
+/// When there is an allowlist (lockdown mode), queries for names outside of it are refused.
+/// The response policy zones then apply, in order.
+#[derive(Default)]
+pub struct PolicyEngine {
+    pub allowlist: Option<Allowlist>,
+    pub zones: Vec<ResponsePolicyZone>,
+    pub blocked_ttl: BlockedTtl,
+}
+
+impl PolicyEngine {
+    pub fn new(
+        allowlist: Option<Allowlist>,
+        zones: Vec<ResponsePolicyZone>,
+        blocked_ttl: BlockedTtl,
+    ) -> PolicyEngine {
+        PolicyEngine {
+            allowlist,
+            zones,
+            blocked_ttl,
+        }
+    }
+
+    /// Returns the action to take for a query, if any policy applies to it
+    pub fn evaluate(&self, qname: &str, qtype: QueryType) -> Option<PolicyAction> {
+        if let Some(allowlist) = &self.allowlist {
+            if !allowlist.allows(qname) {
+                return Some(PolicyAction::Refused);
+            }
+        }
+
+        self.zones.iter().find_map(|rpz| rpz.lookup(qname, qtype))
+    }
+
+    /// Returns the position of the first alias in an answer whose target a policy applies
+    /// to, if any, along with the target and the action to take for it. The response policy
+    /// zones apply to every name of a CNAME chain, not only to the query name, but a passthru
+    /// policy ends the evaluation. The allowlist only restricts query names.
+    pub fn evaluate_targets(
+        &self,
+        answers: &[DnsRecord],
+        qtype: QueryType,
+    ) -> Option<(usize, String, PolicyAction)> {
+        answers
+            .iter()
+            .enumerate()
+            .find_map(|(index, record)| match record {
+                DnsRecord::CNAME { host, .. } => self
+                    .zones
+                    .iter()
+                    .find_map(|rpz| rpz.lookup(host, qtype))
+                    .map(|action| (index, host.clone(), action)),
+                _ => None,
+            })
+            .filter(|(_, _, action)| *action != PolicyAction::Passthru)
+    }
+
+    /// Transfers the policy zones that have a primary again, those that changed since their
+    /// last transfer. A zone that can't be transferred keeps its previous version.
+    pub fn refresh(&self) {
+        for rpz in &self.zones {
+            match rpz.refresh() {
+                Ok(Some(count)) => info!(
+                    "Transferred policy zone {} with {} triggers",
+                    rpz.origin, count
+                ),
+                Ok(None) => {}
+                Err(e) => warn!("Failed to transfer policy zone {}: {}", rpz.origin, e),
+            }
+        }
+    }
+
+    /// Refreshes the transferred policy zones every `interval`, forever. The server spawns
+    /// a thread for it once they are first transferred, if any policy zone has a primary.
+    pub fn keep_refreshed(&self, interval: Duration) {
+        loop {
+            thread::sleep(interval);
+            self.refresh();
+        }
+    }
+}
+
+/// Derives the action to take from the records of a trigger
+fn action(records: &[DnsRecord], qname: &str, qtype: QueryType) -> PolicyAction {
+    if let [DnsRecord::CNAME { host, .. }] = records {
+        match host.as_str() {
+            "" => return PolicyAction::NxDomain,
+            "*" => return PolicyAction::NoData,
+            "rpz-passthru" => return PolicyAction::Passthru,
+            "rpz-drop" => return PolicyAction::Drop,
+            _ => {}
+        }
+    }
+
+    let matching: Vec<_> = records
+        .iter()
+        .filter(|record| record.qtype() == qtype)
+        .collect();
+    let selected = if matching.is_empty() {
+        records
+            .iter()
+            .filter(|record| record.qtype() == QueryType::CNAME)
+            .collect()
+    } else {
+        matching
+    };
+
+    PolicyAction::LocalData(
+        selected
+            .into_iter()
+            .map(|record| record.with_domain(qname))
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::DnsPacket;
+    use crate::transfer::axfr_messages;
+    use std::fs;
+    use std::io::{Read, Write};
+    use std::net::{Ipv4Addr, TcpListener};
+
+    /// Loads a policy zone from the contents of a master file
+    fn policy_zone(test: &str, contents: &str) -> ResponsePolicyZone {
+        let path = std::env::temp_dir().join(format!("vodo-rpz-{}-{}", test, std::process::id()));
+        fs::write(&path, contents).unwrap();
+        let rpz = ResponsePolicyZone::load("rpz.local", &path).unwrap();
+        fs::remove_file(&path).unwrap();
+        rpz
+    }
+
+    #[test]
+    fn matches_exact_triggers_before_wildcards() {
+        let rpz = policy_zone(
+            "triggers",
+            "\
+$TTL 300
+@                       SOA   localhost. nobody.invalid. 1 3600 600 86400 300
+@                       NS    localhost.
+bad.example.com         CNAME .
+*.example.com           CNAME *.
+ok.example.com          CNAME rpz-passthru.
+*.slow.example.com      CNAME rpz-drop.
+",
+        );
+
+        assert_eq!(
+            rpz.lookup("bad.example.com", QueryType::A),
+            Some(PolicyAction::NxDomain)
+        );
+        assert_eq!(
+            rpz.lookup("ads.example.com", QueryType::A),
+            Some(PolicyAction::NoData)
+        );
+        assert_eq!(
+            rpz.lookup("ok.example.com", QueryType::A),
+            Some(PolicyAction::Passthru)
+        );
+        // The most specific wildcard applies, at any depth below it.
+        assert_eq!(
+            rpz.lookup("a.b.slow.example.com", QueryType::A),
+            Some(PolicyAction::Drop)
+        );
+        // Wildcards don't match the name they are under, nor names merely ending like it.
+        assert_eq!(rpz.lookup("example.com", QueryType::A), None);
+        assert_eq!(rpz.lookup("badexample.com", QueryType::A), None);
+        // The records of the policy zone itself aren't triggers.
+        assert_eq!(rpz.lookup("rpz.local", QueryType::SOA), None);
+    }
+
+    #[test]
+    fn answers_local_data_under_the_query_name() {
+        let rpz = policy_zone(
+            "local-data",
+            "\
+$TTL 300
+@                       SOA   localhost. nobody.invalid. 1 3600 600 86400 300
+nas.example.com         A     192.168.1.10
+nas.example.com         TXT   \"blocked\"
+*.tracker.example.com   CNAME walled.garden.example.
+",
+        );
+
+        assert_eq!(
+            rpz.lookup("nas.example.com", QueryType::A),
+            Some(PolicyAction::LocalData(vec![DnsRecord::A {
+                domain: "nas.example.com".to_string(),
+                addr: Ipv4Addr::new(192, 168, 1, 10),
+                ttl: 300,
+            }]))
+        );
+        // Types without local data get none, rather than the data of other types.
+        assert_eq!(
+            rpz.lookup("nas.example.com", QueryType::MX),
+            Some(PolicyAction::LocalData(Vec::new()))
+        );
+        // Aliases answer queries of any type, owned by the query name.
+        assert_eq!(
+            rpz.lookup("x.tracker.example.com", QueryType::AAAA),
+            Some(PolicyAction::LocalData(vec![DnsRecord::CNAME {
+                domain: "x.tracker.example.com".to_string(),
+                host: "walled.garden.example".to_string(),
+                ttl: 300,
+            }]))
+        );
+    }
+
+    /// Serves a zone to the given number of connections, each asking for its SOA record or
+    /// transferring it
+    fn serve_zone(listener: TcpListener, zone: Zone, connections: usize) {
+        for stream in listener.incoming().take(connections) {
+            let mut stream = stream.unwrap();
+            let mut len = [0; 2];
+            stream.read_exact(&mut len).unwrap();
+            let mut message = vec![0; u16::from_be_bytes(len) as usize];
+            stream.read_exact(&mut message).unwrap();
+            let request = DnsPacket::parse(&message).unwrap();
+
+            let messages = match request.questions[0].qtype {
+                QueryType::AXFR => axfr_messages(&zone, &request).unwrap(),
+                _ => {
+                    let mut response = DnsPacket::new();
+                    response.header.id = request.header.id;
+                    response.header.response = true;
+                    response.answers.push(zone.soa().unwrap().clone());
+                    vec![response.to_bytes().unwrap()]
+                }
+            };
+            for message in messages {
+                stream
+                    .write_all(&(message.len() as u16).to_be_bytes())
+                    .unwrap();
+                stream.write_all(&message).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn transfers_policy_zones_when_they_change() {
+        let zone = Zone {
+            origin: "rpz.local".to_string(),
+            records: vec![
+                DnsRecord::SOA {
+                    domain: "rpz.local".to_string(),
+                    mname: "localhost".to_string(),
+                    rname: "nobody.invalid".to_string(),
+                    serial: 7,
+                    refresh: 3600,
+                    retry: 600,
+                    expire: 86400,
+                    minimum: 300,
+                    ttl: 300,
+                },
+                DnsRecord::CNAME {
+                    domain: "bad.example.com.rpz.local".to_string(),
+                    host: String::new(),
+                    ttl: 300,
+                },
+            ],
+        };
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let primary = listener.local_addr().unwrap();
+        // Its serial, the zone, and its serial again.
+        thread::spawn(move || serve_zone(listener, zone, 3));
+
+        let rpz = ResponsePolicyZone::transferred("rpz.local", primary);
+        assert_eq!(rpz.lookup("bad.example.com", QueryType::A), None);
+        assert_eq!(rpz.refresh().unwrap(), Some(1));
+        assert_eq!(
+            rpz.lookup("bad.example.com", QueryType::A),
+            Some(PolicyAction::NxDomain)
+        );
+        // The zone isn't transferred again while its serial stays the same.
+        assert_eq!(rpz.refresh().unwrap(), None);
+    }
+
+    #[test]
+    fn refuses_names_outside_the_allowlist() {
+        let path = std::env::temp_dir().join(format!("vodo-allowlist-{}", std::process::id()));
+        fs::write(&path, "example.com\n").unwrap();
+        let allowlist = Allowlist::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert!(allowlist.allows("example.com"));
+        assert!(allowlist.allows("a.example.com"));
+        assert!(!allowlist.allows("badexample.com"));
+        assert!(!allowlist.allows("com"));
+
+        let rpz = ResponsePolicyZone::from_zone(Zone {
+            origin: "rpz.local".to_string(),
+            records: vec![DnsRecord::CNAME {
+                domain: "ads.example.com.rpz.local".to_string(),
+                host: String::new(),
+                ttl: 300,
+            }],
+        });
+        let policies = PolicyEngine::new(Some(allowlist), vec![rpz], BlockedTtl::default());
+        assert_eq!(policies.evaluate("www.example.com", QueryType::A), None);
+        assert_eq!(
+            policies.evaluate("badexample.com", QueryType::A),
+            Some(PolicyAction::Refused)
+        );
+        // The policy zones still apply to the names allowed.
+        assert_eq!(
+            policies.evaluate("ads.example.com", QueryType::A),
+            Some(PolicyAction::NxDomain)
+        );
+    }
+}
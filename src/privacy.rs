@@ -0,0 +1,145 @@
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::BuildHasher;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use crate::record::DnsRecord;
+
+/// Prefix length client IPv4 addresses are truncated to
+const IPV4_PREFIX: u32 = 24;
+/// Prefix length client IPv6 addresses are truncated to
+const IPV6_PREFIX: u32 = 56;
+
+/// How much of the clients and their queries the logs reveal, set once on startup
+static PRIVACY: OnceLock<Privacy> = OnceLock::new();
+
+/// Privacy options applied to the logs, so that the server can be operated under
+/// logging policies that forbid keeping personal data such as client addresses
+/// and the names they look up.
+#[derive(Debug, Default)]
+pub struct Privacy {
+    /// Truncate client addresses to their /24 (IPv4) or /56 (IPv6) subnet
+    pub anonymize_clients: bool,
+    /// Replace query names with a keyed hash, so that queries for the same name can still be
+    /// correlated within a run, but the name can't be recovered
+    pub hash_names: bool,
+    /// How much the queries sent upstream reveal
+    pub profile: Profile,
+    hasher: RandomState,
+}
+
+impl Privacy {
+    pub fn new(anonymize_clients: bool, hash_names: bool) -> Privacy {
+        Privacy {
+            anonymize_clients,
+            hash_names,
+            profile: Profile::Standard,
+            hasher: RandomState::new(),
+        }
+    }
+
+    pub fn with_profile(mut self, profile: Profile) -> Privacy {
+        self.profile = profile;
+        self
+    }
+}
+
+/// How much the queries sent upstream reveal. They never carry the EDNS options of the
+/// client queries they derive from (client subnet, cookies...), whatever the profile.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Profile {
+    /// Ask every server for the full name, in queries of any length
+    #[default]
+    Standard,
+    /// Only tell each server the part of the name it needs to know (QNAME minimization,
+    /// see RFC 9156). Queries aren't padded: upstream servers are reached over plain UDP and
+    /// TCP, where padding hides nothing (see RFC 7830 section 6).
+    Strict,
+}
+
+impl FromStr for Profile {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Profile, String> {
+        match value {
+            "standard" => Ok(Profile::Standard),
+            "strict" => Ok(Profile::Strict),
+            _ => Err(format!("expected standard or strict, got \"{}\"", value)),
+        }
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Profile::Standard => write!(f, "standard"),
+            Profile::Strict => write!(f, "strict"),
+        }
+    }
+}
+
+/// Sets the privacy options for the rest of the run. Only the first call has an effect.
+pub fn init(privacy: Privacy) {
+    let _ = PRIVACY.set(privacy);
+}
+
+fn privacy() -> &'static Privacy {
+    PRIVACY.get_or_init(Privacy::default)
+}
+
+/// Whether client addresses are anonymized in the logs, in which case nothing else
+/// identifying clients must be logged either
+pub fn anonymizes_clients() -> bool {
+    privacy().anonymize_clients
+}
+
+/// Whether the names of upstream queries are minimized
+pub fn is_strict() -> bool {
+    privacy().profile == Profile::Strict
+}
+
+/// Formats a client address for the logs
+pub fn client(addr: IpAddr) -> String {
+    if !privacy().anonymize_clients {
+        return addr.to_string();
+    }
+
+    match addr {
+        IpAddr::V4(addr) => {
+            let mask = u32::MAX << (32 - IPV4_PREFIX);
+            let network = std::net::Ipv4Addr::from(u32::from(addr) & mask);
+            format!("{}/{}", network, IPV4_PREFIX)
+        }
+        IpAddr::V6(addr) => {
+            let mask = u128::MAX << (128 - IPV6_PREFIX);
+            let network = std::net::Ipv6Addr::from(u128::from(addr) & mask);
+            format!("{}/{}", network, IPV6_PREFIX)
+        }
+    }
+}
+
+/// Formats a domain name for the logs
+pub fn name(name: &str) -> String {
+    let privacy = privacy();
+    if !privacy.hash_names {
+        return name.to_string();
+    }
+
+    format!("#{:016x}", privacy.hasher.hash_one(name))
+}
+
+/// Formats a record for the logs. When names are hashed, only the type and the hashed
+/// owner of the record are shown, as its data can reveal the name too.
+pub fn record(record: &DnsRecord) -> String {
+    if !privacy().hash_names {
+        return format!("{:?}", record);
+    }
+
+    format!(
+        "{:?} {}",
+        record.qtype(),
+        name(record.domain().unwrap_or_default())
+    )
+}
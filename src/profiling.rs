@@ -0,0 +1,189 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Upper bounds of the buckets of the stage histograms, in microseconds
+pub const BUCKET_BOUNDS: [u64; 10] = [
+    10, 50, 100, 500, 1_000, 5_000, 10_000, 50_000, 100_000, 1_000_000,
+];
+
+/// Whether the stages are timed, set once on startup
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Time spent in each stage, indexed like `Stage::ALL`
+static HISTOGRAMS: [Histogram; Stage::ALL.len()] = [const { Histogram::new() }; Stage::ALL.len()];
+
+/// Stages of the handling of a query that are timed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    /// Parsing the query received from the client
+    Parse,
+    /// Looking the records up in the cache
+    CacheLookup,
+    /// Waiting for the response of an upstream server
+    Upstream,
+    /// Serializing the response
+    Serialize,
+    /// Sending the response to the client
+    Send,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 5] = [
+        Stage::Parse,
+        Stage::CacheLookup,
+        Stage::Upstream,
+        Stage::Serialize,
+        Stage::Send,
+    ];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Stage::Parse => "parse",
+            Stage::CacheLookup => "cache-lookup",
+            Stage::Upstream => "upstream",
+            Stage::Serialize => "serialize",
+            Stage::Send => "send",
+        };
+        f.write_str(name)
+    }
+}
+
+/// The `Histogram` struct counts durations in the buckets of `BUCKET_BOUNDS`, plus one for
+/// the longer ones, without locking so that timing the hot path doesn't slow it down.
+pub struct Histogram {
+    buckets: [AtomicU64; BUCKET_BOUNDS.len() + 1],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    pub const fn new() -> Histogram {
+        Histogram {
+            buckets: [const { AtomicU64::new(0) }; BUCKET_BOUNDS.len() + 1],
+            count: AtomicU64::new(0),
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+
+    pub fn record(&self, duration: Duration) {
+        let micros = u64::try_from(duration.as_micros()).unwrap_or(u64::MAX);
+        let bucket = BUCKET_BOUNDS
+            .iter()
+            .position(|&bound| micros <= bound)
+            .unwrap_or(BUCKET_BOUNDS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(micros, Ordering::Relaxed);
+    }
+
+    /// Returns a copy of the counts
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            buckets: self
+                .buckets
+                .iter()
+                .map(|bucket| bucket.load(Ordering::Relaxed))
+                .collect(),
+            count: self.count.load(Ordering::Relaxed),
+            sum: Duration::from_micros(self.sum_micros.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Histogram {
+        Histogram::new()
+    }
+}
+
+/// Counts of a histogram at some point in time
+#[derive(Clone, Debug, Default)]
+pub struct Snapshot {
+    /// Number of durations in each bucket of `BUCKET_BOUNDS`, then above the last one
+    pub buckets: Vec<u64>,
+    pub count: u64,
+    pub sum: Duration,
+}
+
+impl Snapshot {
+    /// Number of durations up to each bound of `BUCKET_BOUNDS`, as Prometheus expects them
+    pub fn cumulative(&self) -> Vec<u64> {
+        self.buckets
+            .iter()
+            .take(BUCKET_BOUNDS.len())
+            .scan(0, |total, count| {
+                *total += count;
+                Some(*total)
+            })
+            .collect()
+    }
+
+    pub fn average(&self) -> Duration {
+        match u32::try_from(self.count) {
+            Ok(0) => Duration::ZERO,
+            Ok(count) => self.sum / count,
+            Err(_) => self.sum.div_f64(self.count as f64),
+        }
+    }
+}
+
+/// Starts timing the stages of the queries, see `time`
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Runs a stage of the handling of a query, recording how long it took in the histogram of
+/// the stage when profiling is enabled. Otherwise, the stage isn't timed at all, so that the
+/// hooks cost nothing unless asked for.
+pub fn time<T>(stage: Stage, run: impl FnOnce() -> T) -> T {
+    if !is_enabled() {
+        return run();
+    }
+
+    let start = Instant::now();
+    let value = run();
+    HISTOGRAMS[stage.index()].record(start.elapsed());
+    value
+}
+
+/// Returns the time spent in each stage so far
+pub fn stages() -> Vec<(Stage, Snapshot)> {
+    Stage::ALL
+        .iter()
+        .map(|&stage| (stage, HISTOGRAMS[stage.index()].snapshot()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_durations_in_buckets() {
+        let histogram = Histogram::new();
+        histogram.record(Duration::from_micros(5));
+        histogram.record(Duration::from_micros(300));
+        histogram.record(Duration::from_millis(2));
+        histogram.record(Duration::from_secs(3));
+
+        let snapshot = histogram.snapshot();
+        assert_eq!(snapshot.count, 4);
+        assert_eq!(snapshot.buckets[0], 1);
+        assert_eq!(snapshot.buckets[3], 1);
+        assert_eq!(snapshot.buckets[5], 1);
+        assert_eq!(snapshot.buckets[BUCKET_BOUNDS.len()], 1);
+        assert_eq!(snapshot.cumulative(), vec![1, 1, 1, 2, 2, 3, 3, 3, 3, 3]);
+        assert_eq!(snapshot.average(), Duration::from_nanos(750_576_250));
+    }
+}
@@ -0,0 +1,443 @@
+use std::fmt::Write as _;
+use std::io::{Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket};
+use std::time::Duration;
+
+use rand::Rng;
+
+use crate::buffer::{BufferError, MAX_SIZE};
+use crate::opt::OptRecord;
+use crate::packet::DnsPacket;
+use crate::question::{DnsQuestion, QueryType};
+use crate::record::DnsRecord;
+use crate::reverse;
+
+/// Time to wait for the server to answer
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+/// Size of the UDP responses the queries accept
+const UDP_PAYLOAD_SIZE: u16 = 1232;
+
+/// `QueryError` is an enum that represents the ways a query can go unanswered
+#[derive(thiserror::Error, Debug)]
+pub enum QueryError {
+    #[error("No valid response: {0}")]
+    BufferError(#[from] BufferError),
+    #[error("I/O error: {0}")]
+    IoError(#[from] std::io::Error),
+}
+
+/// The `Query` struct is a query of the `query` subcommand, a small client in the spirit
+/// of dig to check what a server answers without installing anything else
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Query {
+    pub name: String,
+    pub qtype: QueryType,
+    /// Server the query is sent to
+    pub server: SocketAddr,
+    /// Whether only the data of the answers is printed, one record per line
+    pub short: bool,
+}
+
+impl Query {
+    /// Builds a query from the arguments of the subcommand, given in any order as in dig:
+    /// the name, the type (A by default), `@<SERVER>` to query another server than `server`
+    /// and `+short`. With `reverse`, the PTR records of the address are queried instead of
+    /// a name.
+    pub fn parse(
+        args: &[String],
+        reverse: Option<IpAddr>,
+        server: SocketAddr,
+    ) -> Result<Query, String> {
+        let mut query = Query {
+            name: String::new(),
+            qtype: QueryType::A,
+            server,
+            short: false,
+        };
+        let mut name = None;
+        let mut qtype = None;
+
+        for arg in args {
+            if let Some(server) = arg.strip_prefix('@') {
+                query.server = match server.parse::<IpAddr>() {
+                    Ok(ip) => SocketAddr::new(ip, 53),
+                    Err(_) => server
+                        .parse()
+                        .map_err(|_| format!("invalid server address \"{}\"", server))?,
+                };
+            } else if let Some(option) = arg.strip_prefix('+') {
+                match option {
+                    "short" => query.short = true,
+                    _ => return Err(format!("unknown option \"{}\"", arg)),
+                }
+            } else if qtype.is_none() && !arg.contains('.') && arg.parse::<QueryType>().is_ok() {
+                qtype = arg.parse().ok();
+            } else if name.is_none() {
+                name = Some(arg.trim_end_matches('.').to_lowercase());
+            } else {
+                return Err(format!("unexpected argument \"{}\"", arg));
+            }
+        }
+
+        match (name, reverse) {
+            (Some(_), Some(_)) => return Err("expected a name or -x, not both".to_string()),
+            (Some(name), None) => query.name = name,
+            (None, Some(addr)) => {
+                query.name = reverse::ptr_name(addr);
+                query.qtype = QueryType::PTR;
+            }
+            (None, None) => return Err("expected a name to query".to_string()),
+        }
+        if let Some(qtype) = qtype {
+            query.qtype = qtype;
+        }
+
+        Ok(query)
+    }
+
+    /// Sends the query over UDP, asking for recursion, and again over TCP if the response
+    /// is truncated
+    pub fn send(&self) -> Result<DnsPacket, QueryError> {
+        let mut request = DnsPacket::new();
+        request.header.id = rand::thread_rng().gen();
+        request.header.recursion_desired = true;
+        request
+            .questions
+            .push(DnsQuestion::new(self.name.clone(), self.qtype));
+        request
+            .resources
+            .push(OptRecord::new(UDP_PAYLOAD_SIZE).to_record());
+
+        let response = self.exchange_udp(&request)?;
+        match response.header.truncated_message {
+            true => self.exchange_tcp(&request),
+            false => Ok(response),
+        }
+    }
+
+    fn exchange_udp(&self, request: &DnsPacket) -> Result<DnsPacket, QueryError> {
+        let local: IpAddr = match self.server {
+            SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+            SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+        };
+        let socket = UdpSocket::bind((local, 0))?;
+        socket.set_read_timeout(Some(QUERY_TIMEOUT))?;
+        socket.connect(self.server)?;
+        socket.send(&request.to_bytes()?)?;
+
+        let mut response = vec![0; MAX_SIZE];
+        loop {
+            let len = socket.recv(&mut response)?;
+            let packet = DnsPacket::parse(&response[..len])?;
+            // Late responses to other queries from the same port are skipped.
+            if packet.header.id == request.header.id {
+                return Ok(packet);
+            }
+        }
+    }
+
+    fn exchange_tcp(&self, request: &DnsPacket) -> Result<DnsPacket, QueryError> {
+        let mut stream = TcpStream::connect_timeout(&self.server, QUERY_TIMEOUT)?;
+        stream.set_read_timeout(Some(QUERY_TIMEOUT))?;
+        stream.set_write_timeout(Some(QUERY_TIMEOUT))?;
+
+        let request = request.to_bytes()?;
+        stream.write_all(&(request.len() as u16).to_be_bytes())?;
+        stream.write_all(&request)?;
+
+        let mut len = [0; 2];
+        stream.read_exact(&mut len)?;
+        let mut response = vec![0; u16::from_be_bytes(len) as usize];
+        stream.read_exact(&mut response)?;
+        Ok(DnsPacket::parse(&response)?)
+    }
+}
+
+/// Formats a response the way dig does: the header and every section, or only the data of
+/// the answers with `short`
+pub fn format_response(response: &DnsPacket, short: bool) -> String {
+    let mut output = String::new();
+    if short {
+        for record in &response.answers {
+            let _ = writeln!(output, "{}", rdata(record));
+        }
+        return output;
+    }
+
+    let header = &response.header;
+    let flags = [
+        ("qr", header.response),
+        ("aa", header.authoritative_answer),
+        ("tc", header.truncated_message),
+        ("rd", header.recursion_desired),
+        ("ra", header.recursion_available),
+        ("ad", header.authed_data),
+        ("cd", header.checking_disabled),
+    ];
+    let flags: Vec<&str> = flags
+        .iter()
+        .filter(|(_, set)| *set)
+        .map(|(flag, _)| *flag)
+        .collect();
+    let _ = writeln!(
+        output,
+        ";; ->>HEADER<<- opcode: {}, status: {:?}, id: {}",
+        header.opcode, header.rescode, header.id
+    );
+    let _ = writeln!(
+        output,
+        ";; flags: {}; QUERY: {}, ANSWER: {}, AUTHORITY: {}, ADDITIONAL: {}",
+        flags.join(" "),
+        response.questions.len(),
+        response.answers.len(),
+        response.authorities.len(),
+        response.resources.len()
+    );
+
+    let _ = writeln!(output, "\n;; QUESTION SECTION:");
+    for question in &response.questions {
+        let _ = writeln!(
+            output,
+            ";{}.\tIN\t{}",
+            question.name,
+            type_name(question.qtype)
+        );
+    }
+    let sections = [
+        ("ANSWER", &response.answers),
+        ("AUTHORITY", &response.authorities),
+        ("ADDITIONAL", &response.resources),
+    ];
+    for (section, records) in sections {
+        let records: Vec<&DnsRecord> = records
+            .iter()
+            .filter(|record| record.qtype() != QueryType::OPT)
+            .collect();
+        if records.is_empty() {
+            continue;
+        }
+        let _ = writeln!(output, "\n;; {} SECTION:", section);
+        for record in records {
+            let _ = writeln!(
+                output,
+                "{}.\t{}\tIN\t{}\t{}",
+                record.domain().unwrap_or_default(),
+                record.ttl(),
+                type_name(record.qtype()),
+                rdata(record)
+            );
+        }
+    }
+    output
+}
+
+/// Returns the mnemonic of a type, or its `TYPE<N>` form for unknown ones (RFC 3597)
+fn type_name(qtype: QueryType) -> String {
+    match qtype {
+        QueryType::UNKNOWN(num) => format!("TYPE{}", num),
+        qtype => format!("{:?}", qtype),
+    }
+}
+
+/// Returns the data of a record in the presentation format of zone files, or in the generic
+/// format of RFC 3597 for the types without one
+pub fn rdata(record: &DnsRecord) -> String {
+    let hex = |data: &[u8]| -> String { data.iter().map(|byte| format!("{:02x}", byte)).collect() };
+    let generic = |data: &[u8]| format!("\\# {} {}", data.len(), hex(data));
+
+    match record {
+        DnsRecord::A { addr, .. } => addr.to_string(),
+        DnsRecord::AAAA { addr, .. } => addr.to_string(),
+        DnsRecord::NS { host, .. }
+        | DnsRecord::CNAME { host, .. }
+        | DnsRecord::PTR { host, .. } => {
+            format!("{}.", host)
+        }
+        DnsRecord::MX { priority, host, .. } => format!("{} {}.", priority, host),
+        DnsRecord::SRV {
+            priority,
+            weight,
+            port,
+            host,
+            ..
+        } => format!("{} {} {} {}.", priority, weight, port, host),
+        DnsRecord::SVCB {
+            priority,
+            target,
+            params,
+            ..
+        }
+        | DnsRecord::HTTPS {
+            priority,
+            target,
+            params,
+            ..
+        } => params
+            .iter()
+            .fold(format!("{} {}.", priority, target), |rdata, param| {
+                format!("{} {}", rdata, param)
+            }),
+        DnsRecord::SOA {
+            mname,
+            rname,
+            serial,
+            refresh,
+            retry,
+            expire,
+            minimum,
+            ..
+        } => format!(
+            "{}. {}. {} {} {} {} {}",
+            mname, rname, serial, refresh, retry, expire, minimum
+        ),
+        DnsRecord::TXT { data, .. } => data
+            .iter()
+            .map(|string| format!("{:?}", string))
+            .collect::<Vec<_>>()
+            .join(" "),
+        DnsRecord::HINFO { cpu, os, .. } => format!("{:?} {:?}", cpu, os),
+        DnsRecord::SSHFP {
+            algorithm,
+            fp_type,
+            fingerprint,
+            ..
+        } => format!("{} {} {}", algorithm, fp_type, hex(fingerprint)),
+        DnsRecord::DS {
+            key_tag,
+            algorithm,
+            digest_type,
+            digest,
+            ..
+        } => format!("{} {} {} {}", key_tag, algorithm, digest_type, hex(digest)),
+        DnsRecord::RRSIG {
+            type_covered,
+            algorithm,
+            labels,
+            original_ttl,
+            expiration,
+            inception,
+            key_tag,
+            signer,
+            signature,
+            ..
+        } => format!(
+            "{} {} {} {} {} {} {} {}. {}",
+            type_name(QueryType::from_num(*type_covered)),
+            algorithm,
+            labels,
+            original_ttl,
+            expiration,
+            inception,
+            key_tag,
+            signer,
+            hex(signature)
+        ),
+        DnsRecord::NSEC { next, types, .. } => {
+            types.iter().fold(format!("{}.", next), |rdata, qtype| {
+                format!("{} {}", rdata, type_name(QueryType::from_num(*qtype)))
+            })
+        }
+        DnsRecord::DNSKEY {
+            flags,
+            protocol,
+            algorithm,
+            public_key,
+            ..
+        } => format!("{} {} {} {}", flags, protocol, algorithm, hex(public_key)),
+        DnsRecord::TLSA {
+            usage,
+            selector,
+            matching_type,
+            data,
+            ..
+        } => format!("{} {} {} {}", usage, selector, matching_type, hex(data)),
+        DnsRecord::CERT {
+            cert_type,
+            key_tag,
+            algorithm,
+            certificate,
+            ..
+        } => format!(
+            "{} {} {} {}",
+            cert_type,
+            key_tag,
+            algorithm,
+            hex(certificate)
+        ),
+        DnsRecord::URI {
+            priority,
+            weight,
+            target,
+            ..
+        } => format!("{} {} {:?}", priority, weight, target),
+        DnsRecord::CAA {
+            flags, tag, value, ..
+        } => format!("{} {} {:?}", flags, tag, String::from_utf8_lossy(value)),
+        DnsRecord::LOC {
+            version,
+            size,
+            horiz_pre,
+            vert_pre,
+            latitude,
+            longitude,
+            altitude,
+            ..
+        } => {
+            let mut data = vec![*version, *size, *horiz_pre, *vert_pre];
+            data.extend(latitude.to_be_bytes());
+            data.extend(longitude.to_be_bytes());
+            data.extend(altitude.to_be_bytes());
+            generic(&data)
+        }
+        DnsRecord::NULL { data, .. }
+        | DnsRecord::UNKNOWN { data, .. }
+        | DnsRecord::OPT { data, .. } => generic(data),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_dig_style_arguments() {
+        let server: SocketAddr = "127.0.0.1:5353".parse().unwrap();
+        let args =
+            |args: &[&str]| -> Vec<String> { args.iter().map(|arg| arg.to_string()).collect() };
+
+        let query = Query::parse(
+            &args(&["Example.com.", "mx", "@9.9.9.9", "+short"]),
+            None,
+            server,
+        )
+        .unwrap();
+        assert_eq!(query.name, "example.com");
+        assert_eq!(query.qtype, QueryType::MX);
+        assert_eq!(query.server, "9.9.9.9:53".parse().unwrap());
+        assert!(query.short);
+
+        let query = Query::parse(&args(&[]), Some("2001:db8::1".parse().unwrap()), server).unwrap();
+        assert_eq!(
+            query.name,
+            "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa"
+        );
+        assert_eq!(query.qtype, QueryType::PTR);
+        assert_eq!(query.server, server);
+
+        assert!(Query::parse(&args(&["a.test", "+trace"]), None, server).is_err());
+        assert!(Query::parse(
+            &args(&["a.test"]),
+            Some("10.0.0.1".parse().unwrap()),
+            server
+        )
+        .is_err());
+
+        let mut response = DnsPacket::new();
+        response.answers.push(DnsRecord::PTR {
+            domain: query.name.clone(),
+            host: "host.lan".to_string(),
+            ttl: 60,
+        });
+        assert_eq!(format_response(&response, true), "host.lan.\n");
+    }
+}
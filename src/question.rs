@@ -1,16 +1,48 @@
 use crate::buffer::{Buffer, BufferError};
+use std::str::FromStr;
 
-/// 1, 2, 5, 15, 28 are IDs of the query types as defined in RFC 1035:
+/// 1, 2, 5, 6, 10, 12, 13, 15, 16, 28, 29, 33, 37, 41, 43, 44, 46, 47, 48, 52, 64, 65, 251, 252, 256, 257 are IDs of the query types as defined in RFC 1035:
 /// see https://tools.ietf.org/html/rfc1035#section-3.2.2
+/// RFC 1876: see https://tools.ietf.org/html/rfc1876
+/// RFC 2782: see https://tools.ietf.org/html/rfc2782
+/// RFC 4398: see https://tools.ietf.org/html/rfc4398
+/// RFC 4034: see https://tools.ietf.org/html/rfc4034
+/// RFC 4255: see https://tools.ietf.org/html/rfc4255
+/// RFC 6698: see https://tools.ietf.org/html/rfc6698
+/// RFC 1995: see https://tools.ietf.org/html/rfc1995
+/// RFC 7553: see https://tools.ietf.org/html/rfc7553
+/// RFC 8659: see https://tools.ietf.org/html/rfc8659
+/// and RFC 9460: see https://tools.ietf.org/html/rfc9460
 #[allow(clippy::upper_case_acronyms)]
 #[derive(PartialEq, Eq, Debug, Clone, Hash, Copy)]
 pub enum QueryType {
     UNKNOWN(u16),
-    A,     // 1
-    NS,    // 2
-    CNAME, // 5
-    MX,    // 15
-    AAAA,  // 28
+    A,      // 1
+    NS,     // 2
+    CNAME,  // 5
+    SOA,    // 6
+    NULL,   // 10
+    PTR,    // 12
+    HINFO,  // 13
+    MX,     // 15
+    TXT,    // 16
+    AAAA,   // 28
+    LOC,    // 29
+    SRV,    // 33
+    CERT,   // 37
+    OPT,    // 41
+    DS,     // 43
+    SSHFP,  // 44
+    RRSIG,  // 46
+    NSEC,   // 47
+    DNSKEY, // 48
+    TLSA,   // 52
+    SVCB,   // 64
+    HTTPS,  // 65
+    IXFR,   // 251
+    AXFR,   // 252
+    URI,    // 256
+    CAA,    // 257
 }
 
 impl QueryType {
@@ -20,8 +52,29 @@ impl QueryType {
             QueryType::A => 1,
             QueryType::NS => 2,
             QueryType::CNAME => 5,
+            QueryType::SOA => 6,
+            QueryType::NULL => 10,
+            QueryType::PTR => 12,
+            QueryType::HINFO => 13,
             QueryType::MX => 15,
+            QueryType::TXT => 16,
             QueryType::AAAA => 28,
+            QueryType::LOC => 29,
+            QueryType::SRV => 33,
+            QueryType::CERT => 37,
+            QueryType::OPT => 41,
+            QueryType::DS => 43,
+            QueryType::SSHFP => 44,
+            QueryType::RRSIG => 46,
+            QueryType::NSEC => 47,
+            QueryType::DNSKEY => 48,
+            QueryType::TLSA => 52,
+            QueryType::SVCB => 64,
+            QueryType::HTTPS => 65,
+            QueryType::IXFR => 251,
+            QueryType::AXFR => 252,
+            QueryType::URI => 256,
+            QueryType::CAA => 257,
         }
     }
 
@@ -30,30 +83,147 @@ impl QueryType {
             1 => QueryType::A,
             2 => QueryType::NS,
             5 => QueryType::CNAME,
+            6 => QueryType::SOA,
+            10 => QueryType::NULL,
+            12 => QueryType::PTR,
+            13 => QueryType::HINFO,
             15 => QueryType::MX,
+            16 => QueryType::TXT,
             28 => QueryType::AAAA,
+            29 => QueryType::LOC,
+            33 => QueryType::SRV,
+            37 => QueryType::CERT,
+            41 => QueryType::OPT,
+            43 => QueryType::DS,
+            44 => QueryType::SSHFP,
+            46 => QueryType::RRSIG,
+            47 => QueryType::NSEC,
+            48 => QueryType::DNSKEY,
+            52 => QueryType::TLSA,
+            64 => QueryType::SVCB,
+            65 => QueryType::HTTPS,
+            251 => QueryType::IXFR,
+            252 => QueryType::AXFR,
+            256 => QueryType::URI,
+            257 => QueryType::CAA,
             _ => QueryType::UNKNOWN(num),
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+impl FromStr for QueryType {
+    type Err = String;
+
+    /// Parses a type from its mnemonic (e.g. `AAAA`), or from its number in the
+    /// `TYPE<N>` form of RFC 3597 (e.g. `TYPE28`)
+    fn from_str(value: &str) -> Result<QueryType, String> {
+        let qtype = match value.to_uppercase().as_str() {
+            "A" => QueryType::A,
+            "NS" => QueryType::NS,
+            "CNAME" => QueryType::CNAME,
+            "SOA" => QueryType::SOA,
+            "NULL" => QueryType::NULL,
+            "PTR" => QueryType::PTR,
+            "HINFO" => QueryType::HINFO,
+            "MX" => QueryType::MX,
+            "TXT" => QueryType::TXT,
+            "AAAA" => QueryType::AAAA,
+            "LOC" => QueryType::LOC,
+            "SRV" => QueryType::SRV,
+            "CERT" => QueryType::CERT,
+            "OPT" => QueryType::OPT,
+            "DS" => QueryType::DS,
+            "SSHFP" => QueryType::SSHFP,
+            "RRSIG" => QueryType::RRSIG,
+            "NSEC" => QueryType::NSEC,
+            "DNSKEY" => QueryType::DNSKEY,
+            "TLSA" => QueryType::TLSA,
+            "SVCB" => QueryType::SVCB,
+            "HTTPS" => QueryType::HTTPS,
+            "IXFR" => QueryType::IXFR,
+            "AXFR" => QueryType::AXFR,
+            "URI" => QueryType::URI,
+            "CAA" => QueryType::CAA,
+            other => other
+                .strip_prefix("TYPE")
+                .and_then(|num| num.parse().ok())
+                .map(QueryType::from_num)
+                .ok_or_else(|| format!("unknown query type \"{}\"", value))?,
+        };
+
+        Ok(qtype)
+    }
+}
+
+/// 1, 3, 4, 254 and 255 are IDs of the query classes as defined in RFC 1035 and RFC 2136:
+/// see https://tools.ietf.org/html/rfc1035#section-3.2.4
+/// In practice, everything is in the Internet class (IN), the others are only used for
+/// experiments and server metadata (e.g. `version.bind` in the Chaos class).
+#[allow(clippy::upper_case_acronyms)]
+#[derive(PartialEq, Eq, Debug, Clone, Hash, Copy)]
+pub enum QueryClass {
+    UNKNOWN(u16),
+    IN,   // 1
+    CH,   // 3
+    HS,   // 4
+    NONE, // 254
+    ANY,  // 255
+}
+
+impl QueryClass {
+    pub fn to_num(self) -> u16 {
+        match self {
+            QueryClass::UNKNOWN(x) => x,
+            QueryClass::IN => 1,
+            QueryClass::CH => 3,
+            QueryClass::HS => 4,
+            QueryClass::NONE => 254,
+            QueryClass::ANY => 255,
+        }
+    }
+
+    pub fn from_num(num: u16) -> QueryClass {
+        match num {
+            1 => QueryClass::IN,
+            3 => QueryClass::CH,
+            4 => QueryClass::HS,
+            254 => QueryClass::NONE,
+            255 => QueryClass::ANY,
+            _ => QueryClass::UNKNOWN(num),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct DnsQuestion {
+    /// The name as asked, in its original case
     pub name: String,
     pub qtype: QueryType,
+    pub qclass: QueryClass,
 }
 
 impl DnsQuestion {
+    /// Builds a question in the Internet class
     pub fn new(name: String, qtype: QueryType) -> DnsQuestion {
-        DnsQuestion { name, qtype }
+        DnsQuestion {
+            name,
+            qtype,
+            qclass: QueryClass::IN,
+        }
+    }
+
+    /// Returns the question with its name lowercased, to be compared with other names
+    pub fn normalized(&self) -> DnsQuestion {
+        DnsQuestion {
+            name: self.name.to_ascii_lowercase(),
+            ..self.clone()
+        }
     }
 
     pub fn read(&mut self, buffer: &mut Buffer) -> Result<(), BufferError> {
         buffer.read_qname(&mut self.name)?;
         self.qtype = QueryType::from_num(buffer.read_u16()?);
-        // DNS question class, in practice always equal to 1:
-        // see https://tools.ietf.org/html/rfc1035#section-3.2.4
-        let _ = buffer.read_u16()?;
+        self.qclass = QueryClass::from_num(buffer.read_u16()?);
 
         Ok(())
     }
@@ -63,7 +233,7 @@ impl DnsQuestion {
 
         let typenum = self.qtype.to_num();
         buffer.write_u16(typenum)?;
-        buffer.write_u16(1)?;
+        buffer.write_u16(self.qclass.to_num())?;
 
         Ok(())
     }
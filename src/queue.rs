@@ -0,0 +1,170 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Condvar, Mutex};
+
+/// What to do with a new item when the queue is full
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Drop the new item, keeping those that waited the longest
+    DropNewest,
+    /// Drop the item that waited the longest, whose client has likely given up already
+    DropOldest,
+    /// Wait for room in the queue, deferring the reception of new items
+    Block,
+}
+
+impl FromStr for OverflowPolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<OverflowPolicy, String> {
+        match value {
+            "drop-newest" => Ok(OverflowPolicy::DropNewest),
+            "drop-oldest" => Ok(OverflowPolicy::DropOldest),
+            "block" => Ok(OverflowPolicy::Block),
+            _ => Err(format!(
+                "expected drop-newest, drop-oldest or block, got \"{}\"",
+                value
+            )),
+        }
+    }
+}
+
+impl fmt::Display for OverflowPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OverflowPolicy::DropNewest => write!(f, "drop-newest"),
+            OverflowPolicy::DropOldest => write!(f, "drop-oldest"),
+            OverflowPolicy::Block => write!(f, "block"),
+        }
+    }
+}
+
+/// A bounded first-in first-out queue, shared between the threads pushing items
+/// and the threads popping them
+pub struct Queue<T> {
+    items: Mutex<VecDeque<T>>,
+    not_empty: Condvar,
+    not_full: Condvar,
+    capacity: usize,
+    policy: OverflowPolicy,
+}
+
+impl<T> Queue<T> {
+    pub fn new(capacity: usize, policy: OverflowPolicy) -> Queue<T> {
+        Queue {
+            items: Mutex::new(VecDeque::with_capacity(capacity)),
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+            capacity: capacity.max(1),
+            policy,
+        }
+    }
+
+    /// Adds an item at the end of the queue, applying the overflow policy when it is full.
+    /// Returns false if an item was dropped to do so.
+    pub fn push(&self, item: T) -> bool {
+        let mut items = self.items.lock().unwrap();
+        let mut dropped = false;
+
+        if items.len() >= self.capacity {
+            match self.policy {
+                OverflowPolicy::DropNewest => return false,
+                OverflowPolicy::DropOldest => {
+                    items.pop_front();
+                    dropped = true;
+                }
+                OverflowPolicy::Block => {
+                    items = self
+                        .not_full
+                        .wait_while(items, |items| items.len() >= self.capacity)
+                        .unwrap();
+                }
+            }
+        }
+
+        items.push_back(item);
+        self.not_empty.notify_one();
+
+        !dropped
+    }
+
+    /// Removes the item at the front of the queue, waiting for one if it is empty
+    pub fn pop(&self) -> T {
+        let mut items = self
+            .not_empty
+            .wait_while(self.items.lock().unwrap(), |items| items.is_empty())
+            .unwrap();
+
+        let item = items.pop_front().unwrap();
+        self.not_full.notify_one();
+
+        item
+    }
+
+    /// Number of items waiting in the queue
+    pub fn len(&self) -> usize {
+        self.items.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::Stats;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    fn full(policy: OverflowPolicy) -> Queue<u32> {
+        let queue = Queue::new(2, policy);
+        assert!(queue.push(1) && queue.push(2));
+        queue
+    }
+
+    #[test]
+    fn applies_overflow_policies_when_full() {
+        let queue = full(OverflowPolicy::DropNewest);
+        assert!(!queue.push(3));
+        assert_eq!((queue.pop(), queue.pop()), (1, 2));
+        assert!(queue.is_empty());
+
+        let queue = full(OverflowPolicy::DropOldest);
+        assert!(!queue.push(3));
+        assert_eq!(queue.len(), 2);
+        assert_eq!((queue.pop(), queue.pop()), (2, 3));
+
+        // A blocked push goes through once an item is popped.
+        let queue = Arc::new(full(OverflowPolicy::Block));
+        let pusher = {
+            let queue = queue.clone();
+            thread::spawn(move || queue.push(3))
+        };
+        thread::sleep(Duration::from_millis(50));
+        assert!(!pusher.is_finished());
+        assert_eq!(queue.pop(), 1);
+        assert!(pusher.join().unwrap());
+        assert_eq!((queue.pop(), queue.pop()), (2, 3));
+    }
+
+    #[test]
+    fn reports_depth_and_drops() {
+        let (queue, stats) = (full(OverflowPolicy::DropNewest), Stats::new());
+        // As the receiving loop of the UDP server does for each query
+        for item in 3..6 {
+            if !queue.push(item) {
+                stats.record_queue_drop();
+            }
+            stats.set_queue_depth(queue.len());
+        }
+
+        assert_eq!((stats.queue_depth(), stats.queue_drops()), (2, 3));
+        let metrics = stats.to_prometheus();
+        assert!(metrics.contains("vodo_queue_depth 2\n"));
+        assert!(metrics.contains("vodo_queue_drops_total 3\n"));
+    }
+}
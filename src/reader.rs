@@ -0,0 +1,238 @@
+use crate::buffer::{Buffer, BufferError};
+use crate::header::DnsHeader;
+use crate::question::{DnsQuestion, QueryType};
+use crate::record::DnsRecord;
+
+/// The section of a message an entry is in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Section {
+    Question,
+    Answer,
+    Authority,
+    Additional,
+}
+
+/// An entry of a message, as yielded by a `PacketReader`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Entry {
+    Question(DnsQuestion),
+    Record(Section, DnsRecord),
+}
+
+/// The `PacketReader` struct reads the entries of a DNS message one at a time, for code that
+/// only looks at part of a message (e.g. its question) and would rather not decode the rest
+/// of it into a `DnsPacket`. Entries can also be skipped without decoding their data.
+///
+/// ```
+/// # use vodo::reader::{Entry, PacketReader};
+/// # let bytes = vodo::packet::DnsPacket::new().to_bytes().unwrap();
+/// let mut reader = PacketReader::new(&bytes).unwrap();
+/// for entry in reader.by_ref() {
+///     if let Entry::Record(section, record) = entry.unwrap() {
+///         println!("{:?}: {:?}", section, record);
+///     }
+/// }
+/// ```
+pub struct PacketReader {
+    buffer: Buffer,
+    header: DnsHeader,
+    /// Section of the next entry, `None` once all the entries are read
+    section: Option<Section>,
+    /// Entries left in the section of the next entry
+    left: u16,
+}
+
+impl PacketReader {
+    /// Reads the header of a message, leaving its entries to be read
+    pub fn new(bytes: &[u8]) -> Result<PacketReader, BufferError> {
+        let mut buffer = Buffer::from_bytes(bytes);
+        let mut header = DnsHeader::new();
+        header.read(&mut buffer)?;
+
+        let mut reader = PacketReader {
+            buffer,
+            left: header.questions,
+            header,
+            section: Some(Section::Question),
+        };
+        reader.skip_empty_sections();
+        Ok(reader)
+    }
+
+    pub fn header(&self) -> &DnsHeader {
+        &self.header
+    }
+
+    /// Returns the section of the next entry, if any is left
+    pub fn section(&self) -> Option<Section> {
+        self.section
+    }
+
+    /// Reads the first question of the message, when nothing has been read yet, without
+    /// reading any record
+    pub fn question(&mut self) -> Result<Option<DnsQuestion>, BufferError> {
+        if self.section != Some(Section::Question) || self.left != self.header.questions {
+            return Ok(None);
+        }
+        match self.read_entry()? {
+            Entry::Question(question) => Ok(Some(question)),
+            Entry::Record(..) => Ok(None),
+        }
+    }
+
+    /// Skips the next entry, only reading where it ends. Records are skipped without decoding
+    /// their name or their data.
+    pub fn skip_entry(&mut self) -> Result<(), BufferError> {
+        let Some(section) = self.section else {
+            return Ok(());
+        };
+        self.skip_name()?;
+        // Type and class, followed by the TTL and the data of records
+        self.buffer.read_u32()?;
+        if section != Section::Question {
+            self.buffer.read_u32()?;
+            let len = self.buffer.read_u16()? as usize;
+            self.buffer.get_range(self.buffer.pos(), len)?;
+            self.buffer.step(len)?;
+        }
+        self.advance();
+        Ok(())
+    }
+
+    /// Skips the entries left in the section of the next entry
+    pub fn skip_section(&mut self) -> Result<(), BufferError> {
+        let section = self.section;
+        while self.section.is_some() && self.section == section {
+            self.skip_entry()?;
+        }
+        Ok(())
+    }
+
+    fn read_entry(&mut self) -> Result<Entry, BufferError> {
+        let entry = match self.section {
+            Some(Section::Question) => {
+                let mut question = DnsQuestion::new(String::new(), QueryType::UNKNOWN(0));
+                question.read(&mut self.buffer)?;
+                Entry::Question(question)
+            }
+            Some(section) => Entry::Record(section, DnsRecord::read(&mut self.buffer)?),
+            None => return Err(BufferError::EndOfBuffer),
+        };
+        self.advance();
+        Ok(entry)
+    }
+
+    /// Steps past a name, compressed or not, without decoding it
+    fn skip_name(&mut self) -> Result<(), BufferError> {
+        loop {
+            let len = self.buffer.read()?;
+            if len & 0xC0 == 0xC0 {
+                self.buffer.read()?;
+                return Ok(());
+            }
+            if len == 0 {
+                return Ok(());
+            }
+            self.buffer.get_range(self.buffer.pos(), len as usize)?;
+            self.buffer.step(len as usize)?;
+        }
+    }
+
+    /// Moves on to the next entry, counting it off its section
+    fn advance(&mut self) {
+        self.left = self.left.saturating_sub(1);
+        self.skip_empty_sections();
+    }
+
+    /// Moves on to the next section holding entries, if the current one has none left
+    fn skip_empty_sections(&mut self) {
+        while self.left == 0 {
+            let (section, count) = match self.section {
+                Some(Section::Question) => (Section::Answer, self.header.answers),
+                Some(Section::Answer) => (Section::Authority, self.header.authoritative_entries),
+                Some(Section::Authority) => (Section::Additional, self.header.resource_entries),
+                Some(Section::Additional) | None => {
+                    self.section = None;
+                    return;
+                }
+            };
+            self.section = Some(section);
+            self.left = count;
+        }
+    }
+}
+
+/// Yields the entries left, in order. Reading stops at the first malformed entry.
+impl Iterator for PacketReader {
+    type Item = Result<Entry, BufferError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.section?;
+        let entry = self.read_entry();
+        if entry.is_err() {
+            self.section = None;
+        }
+        Some(entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::DnsPacket;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn reads_entries_lazily() {
+        let mut packet = DnsPacket::new();
+        packet.header.id = 4242;
+        packet.questions.push(DnsQuestion::new(
+            "www.example.com".to_string(),
+            QueryType::A,
+        ));
+        packet.answers.push(DnsRecord::CNAME {
+            domain: "www.example.com".to_string(),
+            host: "example.com".to_string(),
+            ttl: 300,
+        });
+        packet.answers.push(DnsRecord::A {
+            domain: "example.com".to_string(),
+            addr: Ipv4Addr::new(192, 0, 2, 1),
+            ttl: 300,
+        });
+        packet.resources.push(DnsRecord::A {
+            domain: "ns1.example.com".to_string(),
+            addr: Ipv4Addr::new(192, 0, 2, 53),
+            ttl: 300,
+        });
+        let bytes = packet.to_bytes().unwrap();
+
+        let mut reader = PacketReader::new(&bytes).unwrap();
+        assert_eq!(reader.header().id, 4242);
+        assert_eq!(
+            reader.question().unwrap(),
+            Some(packet.questions[0].clone())
+        );
+        assert_eq!(reader.question().unwrap(), None);
+        reader.skip_entry().unwrap();
+        assert_eq!(reader.section(), Some(Section::Answer));
+        let rest: Vec<Entry> = reader.map(Result::unwrap).collect();
+        assert_eq!(
+            rest,
+            [
+                Entry::Record(Section::Answer, packet.answers[1].clone()),
+                Entry::Record(Section::Additional, packet.resources[0].clone()),
+            ]
+        );
+
+        let mut reader = PacketReader::new(&bytes).unwrap();
+        reader.skip_section().unwrap();
+        reader.skip_section().unwrap();
+        assert_eq!(reader.section(), Some(Section::Additional));
+
+        // A message cut short yields an error, then nothing.
+        let mut reader = PacketReader::new(&bytes[..bytes.len() - 2]).unwrap();
+        assert!(reader.by_ref().any(|entry| entry.is_err()));
+        assert!(reader.next().is_none());
+    }
+}
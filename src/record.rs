@@ -1,17 +1,28 @@
 use crate::buffer::{Buffer, BufferError};
 use crate::question::QueryType;
-use log::info;
+use crate::svcb::{self, SvcParam};
+use std::cmp::Ordering;
 use std::net::{Ipv4Addr, Ipv6Addr};
 
-/// 0, 1, 2, 5, 15, 28 are IDs of the query types as defined in RFC 1035:
+/// 0, 1, 2, 5, 6, 10, 13, 15, 16, 28, 29, 33, 37, 41, 43, 44, 46, 47, 48, 52, 64, 65, 256, 257 are IDs of the query types as defined in RFC 1035:
 /// see https://tools.ietf.org/html/rfc1035#section-3.2.2
+/// RFC 1876: see https://tools.ietf.org/html/rfc1876
+/// RFC 2782: see https://tools.ietf.org/html/rfc2782
+/// RFC 4398: see https://tools.ietf.org/html/rfc4398
+/// RFC 4034: see https://tools.ietf.org/html/rfc4034
+/// RFC 4255: see https://tools.ietf.org/html/rfc4255
+/// RFC 6698: see https://tools.ietf.org/html/rfc6698
+/// RFC 7553: see https://tools.ietf.org/html/rfc7553
+/// RFC 8659: see https://tools.ietf.org/html/rfc8659
+/// and RFC 9460: see https://tools.ietf.org/html/rfc9460
 #[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum DnsRecord {
     UNKNOWN {
         domain: String,
         qtype: u16,
-        data_len: u16,
+        /// The record data, kept as is so that the record can be passed along
+        data: Vec<u8>,
         ttl: u32,
     }, // 0
     A {
@@ -29,49 +40,192 @@ pub enum DnsRecord {
         host: String,
         ttl: u32,
     }, // 5
+    SOA {
+        domain: String,
+        mname: String,
+        rname: String,
+        serial: u32,
+        refresh: u32,
+        retry: u32,
+        expire: u32,
+        minimum: u32,
+        ttl: u32,
+    }, // 6
+    NULL {
+        domain: String,
+        data: Vec<u8>,
+        ttl: u32,
+    }, // 10
+    PTR {
+        domain: String,
+        host: String,
+        ttl: u32,
+    }, // 12
+    HINFO {
+        domain: String,
+        cpu: String,
+        os: String,
+        ttl: u32,
+    }, // 13
     MX {
         domain: String,
         priority: u16,
         host: String,
         ttl: u32,
     }, // 15
+    TXT {
+        domain: String,
+        /// The character strings of the record, each up to 255 bytes long
+        data: Vec<String>,
+        ttl: u32,
+    }, // 16
     AAAA {
         domain: String,
         addr: Ipv6Addr,
         ttl: u32,
     }, // 28
+    LOC {
+        domain: String,
+        version: u8,
+        size: u8,
+        horiz_pre: u8,
+        vert_pre: u8,
+        latitude: u32,
+        longitude: u32,
+        altitude: u32,
+        ttl: u32,
+    }, // 29
+    SRV {
+        domain: String,
+        priority: u16,
+        weight: u16,
+        port: u16,
+        host: String,
+        ttl: u32,
+    }, // 33
+    CERT {
+        domain: String,
+        cert_type: u16,
+        key_tag: u16,
+        algorithm: u8,
+        certificate: Vec<u8>,
+        ttl: u32,
+    }, // 37
+    OPT {
+        packet_len: u16,
+        flags: u32,
+        data: Vec<u8>,
+    }, // 41
+    DS {
+        domain: String,
+        key_tag: u16,
+        algorithm: u8,
+        digest_type: u8,
+        /// Digest of the DNSKEY record of the child zone
+        digest: Vec<u8>,
+        ttl: u32,
+    }, // 43
+    SSHFP {
+        domain: String,
+        algorithm: u8,
+        fp_type: u8,
+        fingerprint: Vec<u8>,
+        ttl: u32,
+    }, // 44
+    RRSIG {
+        domain: String,
+        /// Type of the records signed
+        type_covered: u16,
+        algorithm: u8,
+        /// Number of labels of the owner name, without a wildcard
+        labels: u8,
+        original_ttl: u32,
+        /// Validity period of the signature, in seconds since the epoch
+        expiration: u32,
+        inception: u32,
+        key_tag: u16,
+        /// The zone whose key made the signature
+        signer: String,
+        signature: Vec<u8>,
+        ttl: u32,
+    }, // 46
+    NSEC {
+        domain: String,
+        /// The next owner name in the canonical order of the zone
+        next: String,
+        /// Types of the records of the owner name
+        types: Vec<u16>,
+        ttl: u32,
+    }, // 47
+    DNSKEY {
+        domain: String,
+        /// Bit 7 marks zone keys, bit 15 secure entry points
+        flags: u16,
+        /// Always 3
+        protocol: u8,
+        algorithm: u8,
+        public_key: Vec<u8>,
+        ttl: u32,
+    }, // 48
+    TLSA {
+        domain: String,
+        usage: u8,
+        selector: u8,
+        matching_type: u8,
+        data: Vec<u8>,
+        ttl: u32,
+    }, // 52
+    SVCB {
+        domain: String,
+        /// 0 for the alias form, the order of preference of the endpoint otherwise
+        priority: u16,
+        /// The endpoint, the owner itself if the root name
+        target: String,
+        params: Vec<SvcParam>,
+        ttl: u32,
+    }, // 64
+    HTTPS {
+        domain: String,
+        priority: u16,
+        target: String,
+        params: Vec<SvcParam>,
+        ttl: u32,
+    }, // 65
+    URI {
+        domain: String,
+        priority: u16,
+        weight: u16,
+        target: String,
+        ttl: u32,
+    }, // 256
+    CAA {
+        domain: String,
+        /// Bit 7 is the issuer critical flag, the others are reserved
+        flags: u8,
+        /// The property, e.g. `issue` or `iodef`
+        tag: String,
+        /// The value of the property, kept as bytes as it isn't required to be text
+        value: Vec<u8>,
+        ttl: u32,
+    }, // 257
 }
 
 impl DnsRecord {
     /// Reads a DNS record from a buffer
-    #[allow(clippy::identity_op, clippy::redundant_field_names)]
+    #[allow(clippy::redundant_field_names)]
     pub fn read(buffer: &mut Buffer) -> Result<DnsRecord, BufferError> {
         let mut domain = String::new();
-        buffer.read_qname(&mut domain)?;
+        buffer.read_normalized_qname(&mut domain)?;
 
         let qtype_num = buffer.read_u16()?;
         let qtype = QueryType::from_num(qtype_num);
-        let _ = buffer.read_u16()?;
+        let class = buffer.read_u16()?;
         let ttl = buffer.read_u32()?;
         let data_len = buffer.read_u16()?;
 
-        // The purpose of the upcoming bitwise-operations is to extract
-        // the most significant byte of the 32-bit integer raw_addr (in case of IPv4).
-        // By shifting the bits to the right by 24 positions, the most significant byte is moved to
-        // the least significant position, and all other bits are set to 0.
-        // The bitwise AND operation with 0xFF then clears all bits except for the least significant byte,
-        // which contains the value of the most significant byte of raw_addr.
-        // Finally, the resulting value is cast to an unsigned 8-bit integer,
-        // which can be used to represent a single byte of data.
         match qtype {
             QueryType::A => {
-                let raw_addr = buffer.read_u32()?;
-                let addr = Ipv4Addr::new(
-                    ((raw_addr >> 24) & 0xFF) as u8,
-                    ((raw_addr >> 16) & 0xFF) as u8,
-                    ((raw_addr >> 8) & 0xFF) as u8,
-                    ((raw_addr >> 0) & 0xFF) as u8,
-                );
+                let addr = Ipv4Addr::from(buffer.read_array::<4>()?);
 
                 Ok(DnsRecord::A {
                     domain: domain,
@@ -80,20 +234,7 @@ impl DnsRecord {
                 })
             }
             QueryType::AAAA => {
-                let raw_addr1 = buffer.read_u32()?;
-                let raw_addr2 = buffer.read_u32()?;
-                let raw_addr3 = buffer.read_u32()?;
-                let raw_addr4 = buffer.read_u32()?;
-                let addr = Ipv6Addr::new(
-                    ((raw_addr1 >> 16) & 0xFFFF) as u16,
-                    ((raw_addr1 >> 0) & 0xFFFF) as u16,
-                    ((raw_addr2 >> 16) & 0xFFFF) as u16,
-                    ((raw_addr2 >> 0) & 0xFFFF) as u16,
-                    ((raw_addr3 >> 16) & 0xFFFF) as u16,
-                    ((raw_addr3 >> 0) & 0xFFFF) as u16,
-                    ((raw_addr4 >> 16) & 0xFFFF) as u16,
-                    ((raw_addr4 >> 0) & 0xFFFF) as u16,
-                );
+                let addr = Ipv6Addr::from(buffer.read_array::<16>()?);
 
                 Ok(DnsRecord::AAAA {
                     domain,
@@ -103,7 +244,7 @@ impl DnsRecord {
             }
             QueryType::NS => {
                 let mut ns = String::new();
-                buffer.read_qname(&mut ns)?;
+                buffer.read_normalized_qname(&mut ns)?;
 
                 Ok(DnsRecord::NS {
                     domain: domain,
@@ -113,7 +254,7 @@ impl DnsRecord {
             }
             QueryType::CNAME => {
                 let mut cname = String::new();
-                buffer.read_qname(&mut cname)?;
+                buffer.read_normalized_qname(&mut cname)?;
 
                 Ok(DnsRecord::CNAME {
                     domain: domain,
@@ -121,10 +262,34 @@ impl DnsRecord {
                     ttl: ttl,
                 })
             }
+            QueryType::PTR => {
+                let mut host = String::new();
+                buffer.read_normalized_qname(&mut host)?;
+
+                Ok(DnsRecord::PTR { domain, host, ttl })
+            }
+            QueryType::SOA => {
+                let mut mname = String::new();
+                buffer.read_normalized_qname(&mut mname)?;
+                let mut rname = String::new();
+                buffer.read_normalized_qname(&mut rname)?;
+
+                Ok(DnsRecord::SOA {
+                    domain,
+                    mname,
+                    rname,
+                    serial: buffer.read_u32()?,
+                    refresh: buffer.read_u32()?,
+                    retry: buffer.read_u32()?,
+                    expire: buffer.read_u32()?,
+                    minimum: buffer.read_u32()?,
+                    ttl,
+                })
+            }
             QueryType::MX => {
                 let priority = buffer.read_u16()?;
                 let mut mx = String::new();
-                buffer.read_qname(&mut mx)?;
+                buffer.read_normalized_qname(&mut mx)?;
 
                 Ok(DnsRecord::MX {
                     domain: domain,
@@ -133,19 +298,596 @@ impl DnsRecord {
                     ttl: ttl,
                 })
             }
-            QueryType::UNKNOWN(_) => {
-                buffer.step(data_len as usize)?;
+            QueryType::TXT => {
+                // The character strings fill the record data.
+                let end = buffer.pos() + data_len as usize;
+                let mut data = Vec::new();
+                while buffer.pos() < end {
+                    data.push(buffer.read_character_string()?);
+                }
+
+                Ok(DnsRecord::TXT { domain, data, ttl })
+            }
+            QueryType::NULL => {
+                let data = buffer.read_bytes(data_len as usize)?;
+
+                Ok(DnsRecord::NULL { domain, data, ttl })
+            }
+            QueryType::HINFO => {
+                // The lengths of the character strings could run past the record data.
+                let end = buffer.pos() + data_len as usize;
+                let cpu = buffer.read_character_string()?;
+                let os = buffer.read_character_string()?;
+                check_end(buffer, end, data_len)?;
+
+                Ok(DnsRecord::HINFO {
+                    domain,
+                    cpu,
+                    os,
+                    ttl,
+                })
+            }
+            QueryType::LOC => {
+                let end = buffer.pos() + data_len as usize;
+                let version = buffer.read()?;
+                // Only version 0 is defined, the data of other versions is kept as is:
+                // see https://tools.ietf.org/html/rfc1876#section-2
+                if version != 0 {
+                    let mut data = vec![version];
+                    data.extend(buffer.read_bytes(rest_len(data_len, 1)?)?);
+
+                    return Ok(DnsRecord::UNKNOWN {
+                        domain,
+                        qtype: qtype_num,
+                        data,
+                        ttl,
+                    });
+                }
+                let size = buffer.read()?;
+                let horiz_pre = buffer.read()?;
+                let vert_pre = buffer.read()?;
+                let latitude = buffer.read_u32()?;
+                let longitude = buffer.read_u32()?;
+                let altitude = buffer.read_u32()?;
+                check_end(buffer, end, data_len)?;
+
+                Ok(DnsRecord::LOC {
+                    domain,
+                    version,
+                    size,
+                    horiz_pre,
+                    vert_pre,
+                    latitude,
+                    longitude,
+                    altitude,
+                    ttl,
+                })
+            }
+            QueryType::SRV => {
+                let priority = buffer.read_u16()?;
+                let weight = buffer.read_u16()?;
+                let port = buffer.read_u16()?;
+                let mut host = String::new();
+                buffer.read_normalized_qname(&mut host)?;
+
+                Ok(DnsRecord::SRV {
+                    domain,
+                    priority,
+                    weight,
+                    port,
+                    host,
+                    ttl,
+                })
+            }
+            QueryType::CERT => {
+                let cert_type = buffer.read_u16()?;
+                let key_tag = buffer.read_u16()?;
+                let algorithm = buffer.read()?;
+                // The certificate fills the rest of the record data.
+                let certificate = buffer.read_bytes(rest_len(data_len, 5)?)?;
+
+                Ok(DnsRecord::CERT {
+                    domain,
+                    cert_type,
+                    key_tag,
+                    algorithm,
+                    certificate,
+                    ttl,
+                })
+            }
+            QueryType::OPT => {
+                // The OPT pseudo-record reuses the class field for the UDP payload size
+                // and the TTL field for the extended rcode, version and flags:
+                // see https://tools.ietf.org/html/rfc6891#section-6.1.2
+                let data = buffer.read_bytes(data_len as usize)?;
+
+                Ok(DnsRecord::OPT {
+                    packet_len: class,
+                    flags: ttl,
+                    data,
+                })
+            }
+            QueryType::DS => {
+                let key_tag = buffer.read_u16()?;
+                let algorithm = buffer.read()?;
+                let digest_type = buffer.read()?;
+                // The digest fills the rest of the record data.
+                let digest = buffer.read_bytes(rest_len(data_len, 4)?)?;
+
+                Ok(DnsRecord::DS {
+                    domain,
+                    key_tag,
+                    algorithm,
+                    digest_type,
+                    digest,
+                    ttl,
+                })
+            }
+            QueryType::RRSIG => {
+                let start = buffer.pos();
+                let type_covered = buffer.read_u16()?;
+                let algorithm = buffer.read()?;
+                let labels = buffer.read()?;
+                let original_ttl = buffer.read_u32()?;
+                let expiration = buffer.read_u32()?;
+                let inception = buffer.read_u32()?;
+                let key_tag = buffer.read_u16()?;
+                let mut signer = String::new();
+                buffer.read_normalized_qname(&mut signer)?;
+                // The signature fills the rest of the record data.
+                let len = rest_len(data_len, buffer.pos() - start)?;
+                let signature = buffer.read_bytes(len)?;
+
+                Ok(DnsRecord::RRSIG {
+                    domain,
+                    type_covered,
+                    algorithm,
+                    labels,
+                    original_ttl,
+                    expiration,
+                    inception,
+                    key_tag,
+                    signer,
+                    signature,
+                    ttl,
+                })
+            }
+            QueryType::NSEC => {
+                let start = buffer.pos();
+                let mut next = String::new();
+                buffer.read_normalized_qname(&mut next)?;
+                let len = rest_len(data_len, buffer.pos() - start)?;
+                let types = read_type_bitmaps(&buffer.read_bytes(len)?);
+
+                Ok(DnsRecord::NSEC {
+                    domain,
+                    next,
+                    types,
+                    ttl,
+                })
+            }
+            QueryType::DNSKEY => {
+                let flags = buffer.read_u16()?;
+                let protocol = buffer.read()?;
+                let algorithm = buffer.read()?;
+                // The key fills the rest of the record data.
+                let public_key = buffer.read_bytes(rest_len(data_len, 4)?)?;
+
+                Ok(DnsRecord::DNSKEY {
+                    domain,
+                    flags,
+                    protocol,
+                    algorithm,
+                    public_key,
+                    ttl,
+                })
+            }
+            QueryType::SSHFP => {
+                let algorithm = buffer.read()?;
+                let fp_type = buffer.read()?;
+                // The fingerprint fills the rest of the record data.
+                let fingerprint = buffer.read_bytes(rest_len(data_len, 2)?)?;
+
+                Ok(DnsRecord::SSHFP {
+                    domain,
+                    algorithm,
+                    fp_type,
+                    fingerprint,
+                    ttl,
+                })
+            }
+            QueryType::TLSA => {
+                let usage = buffer.read()?;
+                let selector = buffer.read()?;
+                let matching_type = buffer.read()?;
+                // The certificate association data fills the rest of the record data.
+                let data = buffer.read_bytes(rest_len(data_len, 3)?)?;
+
+                Ok(DnsRecord::TLSA {
+                    domain,
+                    usage,
+                    selector,
+                    matching_type,
+                    data,
+                    ttl,
+                })
+            }
+            QueryType::URI => {
+                let priority = buffer.read_u16()?;
+                let weight = buffer.read_u16()?;
+                // Unlike other strings, the target isn't prefixed by its length,
+                // but fills the rest of the record data.
+                let target = buffer.read_bytes(rest_len(data_len, 4)?)?;
+
+                Ok(DnsRecord::URI {
+                    domain,
+                    priority,
+                    weight,
+                    target: String::from_utf8_lossy(&target).into_owned(),
+                    ttl,
+                })
+            }
+            QueryType::CAA => {
+                let flags = buffer.read()?;
+                let tag_len = buffer.read()?;
+                let tag = buffer.read_bytes(tag_len as usize)?;
+                // The value fills the rest of the record data.
+                let value = buffer.read_bytes(rest_len(data_len, 2 + tag_len as usize)?)?;
+
+                Ok(DnsRecord::CAA {
+                    domain,
+                    flags,
+                    tag: String::from_utf8_lossy(&tag).into_owned(),
+                    value,
+                    ttl,
+                })
+            }
+            QueryType::SVCB | QueryType::HTTPS => {
+                let start = buffer.pos();
+                let priority = buffer.read_u16()?;
+                let mut target = String::new();
+                buffer.read_normalized_qname(&mut target)?;
+                // The parameters fill the rest of the record data.
+                let len = rest_len(data_len, buffer.pos() - start)?;
+                let params = svcb::read_params(buffer, len)?;
+
+                Ok(match qtype {
+                    QueryType::SVCB => DnsRecord::SVCB {
+                        domain,
+                        priority,
+                        target,
+                        params,
+                        ttl,
+                    },
+                    _ => DnsRecord::HTTPS {
+                        domain,
+                        priority,
+                        target,
+                        params,
+                        ttl,
+                    },
+                })
+            }
+            // IXFR and AXFR are only ever questions, never the type of a record.
+            QueryType::UNKNOWN(_) | QueryType::IXFR | QueryType::AXFR => {
+                let data = buffer.read_bytes(data_len as usize)?;
 
                 Ok(DnsRecord::UNKNOWN {
                     domain: domain,
                     qtype: qtype_num,
-                    data_len: data_len,
+                    data: data,
                     ttl: ttl,
                 })
             }
         }
     }
 
+    /// Returns the owner name of the record, if it has one
+    pub fn domain(&self) -> Option<&str> {
+        match self {
+            DnsRecord::UNKNOWN { domain, .. }
+            | DnsRecord::A { domain, .. }
+            | DnsRecord::NS { domain, .. }
+            | DnsRecord::CNAME { domain, .. }
+            | DnsRecord::SOA { domain, .. }
+            | DnsRecord::NULL { domain, .. }
+            | DnsRecord::PTR { domain, .. }
+            | DnsRecord::HINFO { domain, .. }
+            | DnsRecord::MX { domain, .. }
+            | DnsRecord::TXT { domain, .. }
+            | DnsRecord::AAAA { domain, .. }
+            | DnsRecord::LOC { domain, .. }
+            | DnsRecord::SRV { domain, .. }
+            | DnsRecord::CERT { domain, .. }
+            | DnsRecord::DS { domain, .. }
+            | DnsRecord::SSHFP { domain, .. }
+            | DnsRecord::RRSIG { domain, .. }
+            | DnsRecord::NSEC { domain, .. }
+            | DnsRecord::DNSKEY { domain, .. }
+            | DnsRecord::TLSA { domain, .. }
+            | DnsRecord::SVCB { domain, .. }
+            | DnsRecord::HTTPS { domain, .. }
+            | DnsRecord::URI { domain, .. }
+            | DnsRecord::CAA { domain, .. } => Some(domain),
+            DnsRecord::OPT { .. } => None,
+        }
+    }
+
+    /// Returns the type of the record
+    pub fn qtype(&self) -> QueryType {
+        match *self {
+            // Records of known types can be kept as is too, e.g. LOC records of other versions.
+            DnsRecord::UNKNOWN { qtype, .. } => QueryType::from_num(qtype),
+            DnsRecord::A { .. } => QueryType::A,
+            DnsRecord::NS { .. } => QueryType::NS,
+            DnsRecord::CNAME { .. } => QueryType::CNAME,
+            DnsRecord::SOA { .. } => QueryType::SOA,
+            DnsRecord::NULL { .. } => QueryType::NULL,
+            DnsRecord::PTR { .. } => QueryType::PTR,
+            DnsRecord::HINFO { .. } => QueryType::HINFO,
+            DnsRecord::MX { .. } => QueryType::MX,
+            DnsRecord::TXT { .. } => QueryType::TXT,
+            DnsRecord::AAAA { .. } => QueryType::AAAA,
+            DnsRecord::LOC { .. } => QueryType::LOC,
+            DnsRecord::SRV { .. } => QueryType::SRV,
+            DnsRecord::CERT { .. } => QueryType::CERT,
+            DnsRecord::OPT { .. } => QueryType::OPT,
+            DnsRecord::DS { .. } => QueryType::DS,
+            DnsRecord::SSHFP { .. } => QueryType::SSHFP,
+            DnsRecord::RRSIG { .. } => QueryType::RRSIG,
+            DnsRecord::NSEC { .. } => QueryType::NSEC,
+            DnsRecord::DNSKEY { .. } => QueryType::DNSKEY,
+            DnsRecord::TLSA { .. } => QueryType::TLSA,
+            DnsRecord::SVCB { .. } => QueryType::SVCB,
+            DnsRecord::HTTPS { .. } => QueryType::HTTPS,
+            DnsRecord::URI { .. } => QueryType::URI,
+            DnsRecord::CAA { .. } => QueryType::CAA,
+        }
+    }
+
+    /// Returns the time to live of the record, regardless of its type
+    pub fn ttl(&self) -> u32 {
+        match *self {
+            DnsRecord::UNKNOWN { ttl, .. }
+            | DnsRecord::A { ttl, .. }
+            | DnsRecord::NS { ttl, .. }
+            | DnsRecord::CNAME { ttl, .. }
+            | DnsRecord::SOA { ttl, .. }
+            | DnsRecord::NULL { ttl, .. }
+            | DnsRecord::PTR { ttl, .. }
+            | DnsRecord::HINFO { ttl, .. }
+            | DnsRecord::MX { ttl, .. }
+            | DnsRecord::TXT { ttl, .. }
+            | DnsRecord::AAAA { ttl, .. }
+            | DnsRecord::LOC { ttl, .. }
+            | DnsRecord::SRV { ttl, .. }
+            | DnsRecord::CERT { ttl, .. }
+            | DnsRecord::DS { ttl, .. }
+            | DnsRecord::SSHFP { ttl, .. }
+            | DnsRecord::RRSIG { ttl, .. }
+            | DnsRecord::NSEC { ttl, .. }
+            | DnsRecord::DNSKEY { ttl, .. }
+            | DnsRecord::TLSA { ttl, .. }
+            | DnsRecord::SVCB { ttl, .. }
+            | DnsRecord::HTTPS { ttl, .. }
+            | DnsRecord::URI { ttl, .. }
+            | DnsRecord::CAA { ttl, .. } => ttl,
+            DnsRecord::OPT { .. } => 0,
+        }
+    }
+
+    /// Returns an estimate of the memory used by the record, including the data it owns
+    pub fn approximate_size(&self) -> usize {
+        let owned = match self {
+            DnsRecord::NS { domain, host, .. }
+            | DnsRecord::CNAME { domain, host, .. }
+            | DnsRecord::PTR { domain, host, .. }
+            | DnsRecord::MX { domain, host, .. }
+            | DnsRecord::SRV { domain, host, .. } => domain.len() + host.len(),
+            DnsRecord::SOA {
+                domain,
+                mname,
+                rname,
+                ..
+            } => domain.len() + mname.len() + rname.len(),
+            DnsRecord::HINFO {
+                domain, cpu, os, ..
+            } => domain.len() + cpu.len() + os.len(),
+            DnsRecord::UNKNOWN { domain, data, .. }
+            | DnsRecord::NULL { domain, data, .. }
+            | DnsRecord::TLSA { domain, data, .. } => domain.len() + data.len(),
+            DnsRecord::CERT {
+                domain,
+                certificate,
+                ..
+            } => domain.len() + certificate.len(),
+            DnsRecord::SSHFP {
+                domain,
+                fingerprint,
+                ..
+            } => domain.len() + fingerprint.len(),
+            DnsRecord::DS { domain, digest, .. } => domain.len() + digest.len(),
+            DnsRecord::RRSIG {
+                domain,
+                signer,
+                signature,
+                ..
+            } => domain.len() + signer.len() + signature.len(),
+            DnsRecord::NSEC {
+                domain,
+                next,
+                types,
+                ..
+            } => domain.len() + next.len() + size_of_val(types.as_slice()),
+            DnsRecord::DNSKEY {
+                domain, public_key, ..
+            } => domain.len() + public_key.len(),
+            DnsRecord::URI { domain, target, .. } => domain.len() + target.len(),
+            DnsRecord::SVCB {
+                domain,
+                target,
+                params,
+                ..
+            }
+            | DnsRecord::HTTPS {
+                domain,
+                target,
+                params,
+                ..
+            } => {
+                domain.len()
+                    + target.len()
+                    + params.iter().map(SvcParam::approximate_size).sum::<usize>()
+            }
+            DnsRecord::CAA {
+                domain, tag, value, ..
+            } => domain.len() + tag.len() + value.len(),
+            DnsRecord::TXT { domain, data, .. } => {
+                domain.len() + data.iter().map(String::len).sum::<usize>()
+            }
+            DnsRecord::OPT { data, .. } => data.len(),
+            DnsRecord::A { domain, .. }
+            | DnsRecord::AAAA { domain, .. }
+            | DnsRecord::LOC { domain, .. } => domain.len(),
+        };
+
+        std::mem::size_of::<DnsRecord>() + owned
+    }
+
+    /// Returns a copy of the record with a different time to live
+    pub fn with_ttl(&self, new_ttl: u32) -> DnsRecord {
+        let mut record = self.clone();
+        match &mut record {
+            DnsRecord::UNKNOWN { ttl, .. }
+            | DnsRecord::A { ttl, .. }
+            | DnsRecord::NS { ttl, .. }
+            | DnsRecord::CNAME { ttl, .. }
+            | DnsRecord::SOA { ttl, .. }
+            | DnsRecord::NULL { ttl, .. }
+            | DnsRecord::PTR { ttl, .. }
+            | DnsRecord::HINFO { ttl, .. }
+            | DnsRecord::MX { ttl, .. }
+            | DnsRecord::TXT { ttl, .. }
+            | DnsRecord::AAAA { ttl, .. }
+            | DnsRecord::LOC { ttl, .. }
+            | DnsRecord::SRV { ttl, .. }
+            | DnsRecord::CERT { ttl, .. }
+            | DnsRecord::DS { ttl, .. }
+            | DnsRecord::SSHFP { ttl, .. }
+            | DnsRecord::RRSIG { ttl, .. }
+            | DnsRecord::NSEC { ttl, .. }
+            | DnsRecord::DNSKEY { ttl, .. }
+            | DnsRecord::TLSA { ttl, .. }
+            | DnsRecord::SVCB { ttl, .. }
+            | DnsRecord::HTTPS { ttl, .. }
+            | DnsRecord::URI { ttl, .. }
+            | DnsRecord::CAA { ttl, .. } => *ttl = new_ttl,
+            DnsRecord::OPT { .. } => {}
+        }
+
+        record
+    }
+
+    /// Returns a copy of the record with a different owner name
+    pub fn with_domain(&self, new_domain: &str) -> DnsRecord {
+        let mut record = self.clone();
+        match &mut record {
+            DnsRecord::UNKNOWN { domain, .. }
+            | DnsRecord::A { domain, .. }
+            | DnsRecord::NS { domain, .. }
+            | DnsRecord::CNAME { domain, .. }
+            | DnsRecord::SOA { domain, .. }
+            | DnsRecord::NULL { domain, .. }
+            | DnsRecord::PTR { domain, .. }
+            | DnsRecord::HINFO { domain, .. }
+            | DnsRecord::MX { domain, .. }
+            | DnsRecord::TXT { domain, .. }
+            | DnsRecord::AAAA { domain, .. }
+            | DnsRecord::LOC { domain, .. }
+            | DnsRecord::SRV { domain, .. }
+            | DnsRecord::CERT { domain, .. }
+            | DnsRecord::DS { domain, .. }
+            | DnsRecord::SSHFP { domain, .. }
+            | DnsRecord::RRSIG { domain, .. }
+            | DnsRecord::NSEC { domain, .. }
+            | DnsRecord::DNSKEY { domain, .. }
+            | DnsRecord::TLSA { domain, .. }
+            | DnsRecord::SVCB { domain, .. }
+            | DnsRecord::HTTPS { domain, .. }
+            | DnsRecord::URI { domain, .. }
+            | DnsRecord::CAA { domain, .. } => *domain = new_domain.to_string(),
+            DnsRecord::OPT { .. } => {}
+        }
+
+        record
+    }
+
+    /// Returns a copy of the record in the canonical form of RFC 4034 (section 6.2): its owner
+    /// name and the names in its data lowercased
+    pub fn to_canonical(&self) -> DnsRecord {
+        let mut record = match self.domain() {
+            Some(domain) => self.with_domain(&domain.to_ascii_lowercase()),
+            None => self.clone(),
+        };
+        match &mut record {
+            DnsRecord::NS { host, .. }
+            | DnsRecord::CNAME { host, .. }
+            | DnsRecord::PTR { host, .. }
+            | DnsRecord::MX { host, .. }
+            | DnsRecord::SRV { host, .. }
+            | DnsRecord::SVCB { target: host, .. }
+            | DnsRecord::HTTPS { target: host, .. }
+            | DnsRecord::RRSIG { signer: host, .. }
+            | DnsRecord::NSEC { next: host, .. } => host.make_ascii_lowercase(),
+            DnsRecord::SOA { mname, rname, .. } => {
+                mname.make_ascii_lowercase();
+                rname.make_ascii_lowercase();
+            }
+            _ => {}
+        }
+
+        record
+    }
+
+    /// Returns the wire format of the record in canonical form (RFC 4034 section 6.2), as
+    /// signed in RRSIG records: names lowercased and never compressed
+    pub fn canonical_bytes(&self) -> Result<Vec<u8>, BufferError> {
+        let mut buffer = Buffer::growable();
+        let len = self.to_canonical().write(&mut buffer)?;
+
+        let mut bytes = buffer.buf;
+        bytes.truncate(len);
+
+        Ok(bytes)
+    }
+
+    /// Returns the data of the record in canonical form, without the owner name, type, class,
+    /// TTL and length that precede it on the wire
+    fn canonical_data(&self) -> Result<Vec<u8>, BufferError> {
+        let mut owner = Buffer::new();
+        match self.domain() {
+            Some(domain) => owner.write_qname(domain)?,
+            None => owner.write_u8(0)?,
+        }
+
+        let mut bytes = self.canonical_bytes()?;
+        Ok(bytes.split_off((owner.pos() + 10).min(bytes.len())))
+    }
+
+    /// Compares records in the canonical order of RFC 4034: by owner name (section 6.1), then
+    /// by type, then by data in canonical form, as unsigned bytes (section 6.3). Records of an
+    /// RRset sorted this way are in the order they are signed in, and records equal but for
+    /// the case of their names or their TTL compare equal, which deduplicates them.
+    pub fn canonical_cmp(&self, other: &DnsRecord) -> Ordering {
+        canonical_name_cmp(self.domain().unwrap_or(""), other.domain().unwrap_or(""))
+            .then_with(|| self.qtype().to_num().cmp(&other.qtype().to_num()))
+            .then_with(|| {
+                // Records that can't be written, e.g. with a label too long, sort first.
+                let data = self.canonical_data().unwrap_or_default();
+                data.cmp(&other.canonical_data().unwrap_or_default())
+            })
+    }
+
     pub fn write(&self, buffer: &mut Buffer) -> Result<usize, BufferError> {
         let start_pos = buffer.pos();
 
@@ -161,11 +903,7 @@ impl DnsRecord {
                 buffer.write_u32(ttl)?;
                 buffer.write_u16(4)?;
 
-                let octets = addr.octets();
-                buffer.write_u8(octets[0])?;
-                buffer.write_u8(octets[1])?;
-                buffer.write_u8(octets[2])?;
-                buffer.write_u8(octets[3])?;
+                buffer.write_bytes(&addr.octets())?;
             }
             DnsRecord::NS {
                 ref domain,
@@ -203,6 +941,54 @@ impl DnsRecord {
                 let size = buffer.pos() - (pos + 2);
                 buffer.set_u16(pos, size as u16)?;
             }
+            DnsRecord::PTR {
+                ref domain,
+                ref host,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::PTR.to_num())?;
+                buffer.write_u16(1)?;
+                buffer.write_u32(ttl)?;
+
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+
+                buffer.write_qname(host)?;
+
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
+            DnsRecord::SOA {
+                ref domain,
+                ref mname,
+                ref rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::SOA.to_num())?;
+                buffer.write_u16(1)?;
+                buffer.write_u32(ttl)?;
+
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+
+                buffer.write_qname(mname)?;
+                buffer.write_qname(rname)?;
+                buffer.write_u32(serial)?;
+                buffer.write_u32(refresh)?;
+                buffer.write_u32(retry)?;
+                buffer.write_u32(expire)?;
+                buffer.write_u32(minimum)?;
+
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
             DnsRecord::MX {
                 ref domain,
                 priority,
@@ -223,6 +1009,26 @@ impl DnsRecord {
                 let size = buffer.pos() - (pos + 2);
                 buffer.set_u16(pos, size as u16)?;
             }
+            DnsRecord::TXT {
+                ref domain,
+                ref data,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::TXT.to_num())?;
+                buffer.write_u16(1)?;
+                buffer.write_u32(ttl)?;
+
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+
+                for string in data {
+                    buffer.write_character_string(string)?;
+                }
+
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
             DnsRecord::AAAA {
                 ref domain,
                 ref addr,
@@ -234,15 +1040,716 @@ impl DnsRecord {
                 buffer.write_u32(ttl)?;
                 buffer.write_u16(16)?;
 
-                for octet in &addr.segments() {
-                    buffer.write_u16(*octet)?;
-                }
+                buffer.write_bytes(&addr.octets())?;
             }
-            DnsRecord::UNKNOWN { .. } => {
-                info!("Skipping record: {:?}", self);
+            DnsRecord::NULL {
+                ref domain,
+                ref data,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::NULL.to_num())?;
+                buffer.write_u16(1)?;
+                buffer.write_u32(ttl)?;
+                buffer.write_u16(data_len(0, data.len())?)?;
+
+                buffer.write_bytes(data)?;
+            }
+            DnsRecord::HINFO {
+                ref domain,
+                ref cpu,
+                ref os,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::HINFO.to_num())?;
+                buffer.write_u16(1)?;
+                buffer.write_u32(ttl)?;
+
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+
+                buffer.write_character_string(cpu)?;
+                buffer.write_character_string(os)?;
+
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
+            DnsRecord::LOC {
+                ref domain,
+                version,
+                size,
+                horiz_pre,
+                vert_pre,
+                latitude,
+                longitude,
+                altitude,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::LOC.to_num())?;
+                buffer.write_u16(1)?;
+                buffer.write_u32(ttl)?;
+                buffer.write_u16(16)?;
+
+                buffer.write_u8(version)?;
+                buffer.write_u8(size)?;
+                buffer.write_u8(horiz_pre)?;
+                buffer.write_u8(vert_pre)?;
+                buffer.write_u32(latitude)?;
+                buffer.write_u32(longitude)?;
+                buffer.write_u32(altitude)?;
+            }
+            DnsRecord::SRV {
+                ref domain,
+                priority,
+                weight,
+                port,
+                ref host,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::SRV.to_num())?;
+                buffer.write_u16(1)?;
+                buffer.write_u32(ttl)?;
+
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+
+                buffer.write_u16(priority)?;
+                buffer.write_u16(weight)?;
+                buffer.write_u16(port)?;
+                buffer.write_qname(host)?;
+
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
+            DnsRecord::CERT {
+                ref domain,
+                cert_type,
+                key_tag,
+                algorithm,
+                ref certificate,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::CERT.to_num())?;
+                buffer.write_u16(1)?;
+                buffer.write_u32(ttl)?;
+                buffer.write_u16(data_len(5, certificate.len())?)?;
+
+                buffer.write_u16(cert_type)?;
+                buffer.write_u16(key_tag)?;
+                buffer.write_u8(algorithm)?;
+                buffer.write_bytes(certificate)?;
+            }
+            DnsRecord::OPT {
+                packet_len,
+                flags,
+                ref data,
+            } => {
+                // The owner name of the OPT pseudo-record is always the root domain
+                buffer.write_u8(0)?;
+                buffer.write_u16(QueryType::OPT.to_num())?;
+                buffer.write_u16(packet_len)?;
+                buffer.write_u32(flags)?;
+                buffer.write_u16(data_len(0, data.len())?)?;
+
+                buffer.write_bytes(data)?;
+            }
+            DnsRecord::DS {
+                ref domain,
+                key_tag,
+                algorithm,
+                digest_type,
+                ref digest,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::DS.to_num())?;
+                buffer.write_u16(1)?;
+                buffer.write_u32(ttl)?;
+                buffer.write_u16(data_len(4, digest.len())?)?;
+
+                buffer.write_u16(key_tag)?;
+                buffer.write_u8(algorithm)?;
+                buffer.write_u8(digest_type)?;
+                buffer.write_bytes(digest)?;
+            }
+            DnsRecord::RRSIG {
+                ref domain,
+                type_covered,
+                algorithm,
+                labels,
+                original_ttl,
+                expiration,
+                inception,
+                key_tag,
+                ref signer,
+                ref signature,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::RRSIG.to_num())?;
+                buffer.write_u16(1)?;
+                buffer.write_u32(ttl)?;
+
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+
+                buffer.write_u16(type_covered)?;
+                buffer.write_u8(algorithm)?;
+                buffer.write_u8(labels)?;
+                buffer.write_u32(original_ttl)?;
+                buffer.write_u32(expiration)?;
+                buffer.write_u32(inception)?;
+                buffer.write_u16(key_tag)?;
+                buffer.write_qname(signer)?;
+                buffer.write_bytes(signature)?;
+
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
+            DnsRecord::NSEC {
+                ref domain,
+                ref next,
+                ref types,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::NSEC.to_num())?;
+                buffer.write_u16(1)?;
+                buffer.write_u32(ttl)?;
+
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+
+                buffer.write_qname(next)?;
+                buffer.write_bytes(&type_bitmaps(types))?;
+
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
+            DnsRecord::DNSKEY {
+                ref domain,
+                flags,
+                protocol,
+                algorithm,
+                ref public_key,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::DNSKEY.to_num())?;
+                buffer.write_u16(1)?;
+                buffer.write_u32(ttl)?;
+                buffer.write_u16(data_len(4, public_key.len())?)?;
+
+                buffer.write_u16(flags)?;
+                buffer.write_u8(protocol)?;
+                buffer.write_u8(algorithm)?;
+                buffer.write_bytes(public_key)?;
+            }
+            DnsRecord::SSHFP {
+                ref domain,
+                algorithm,
+                fp_type,
+                ref fingerprint,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::SSHFP.to_num())?;
+                buffer.write_u16(1)?;
+                buffer.write_u32(ttl)?;
+                buffer.write_u16(data_len(2, fingerprint.len())?)?;
+
+                buffer.write_u8(algorithm)?;
+                buffer.write_u8(fp_type)?;
+                buffer.write_bytes(fingerprint)?;
+            }
+            DnsRecord::TLSA {
+                ref domain,
+                usage,
+                selector,
+                matching_type,
+                ref data,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::TLSA.to_num())?;
+                buffer.write_u16(1)?;
+                buffer.write_u32(ttl)?;
+                buffer.write_u16(data_len(3, data.len())?)?;
+
+                buffer.write_u8(usage)?;
+                buffer.write_u8(selector)?;
+                buffer.write_u8(matching_type)?;
+                buffer.write_bytes(data)?;
+            }
+            DnsRecord::SVCB {
+                ref domain,
+                priority,
+                ref target,
+                ref params,
+                ttl,
+            }
+            | DnsRecord::HTTPS {
+                ref domain,
+                priority,
+                ref target,
+                ref params,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(self.qtype().to_num())?;
+                buffer.write_u16(1)?;
+                buffer.write_u32(ttl)?;
+
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+
+                buffer.write_u16(priority)?;
+                buffer.write_qname(target)?;
+                svcb::write_params(buffer, params)?;
+
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
+            DnsRecord::URI {
+                ref domain,
+                priority,
+                weight,
+                ref target,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::URI.to_num())?;
+                buffer.write_u16(1)?;
+                buffer.write_u32(ttl)?;
+                buffer.write_u16(data_len(4, target.len())?)?;
+
+                buffer.write_u16(priority)?;
+                buffer.write_u16(weight)?;
+                buffer.write_bytes(target.as_bytes())?;
+            }
+            DnsRecord::CAA {
+                ref domain,
+                flags,
+                ref tag,
+                ref value,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::CAA.to_num())?;
+                buffer.write_u16(1)?;
+                buffer.write_u32(ttl)?;
+                buffer.write_u16(data_len(2, tag.len() + value.len())?)?;
+
+                buffer.write_u8(flags)?;
+                buffer.write_character_string(tag)?;
+                buffer.write_bytes(value)?;
+            }
+            // Records of types without a variant are written back with their data as is:
+            // see https://tools.ietf.org/html/rfc3597
+            DnsRecord::UNKNOWN {
+                ref domain,
+                qtype,
+                ref data,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(qtype)?;
+                buffer.write_u16(1)?;
+                buffer.write_u32(ttl)?;
+                buffer.write_u16(data_len(0, data.len())?)?;
+
+                buffer.write_bytes(data)?;
             }
         }
 
         Ok(buffer.pos() - start_pos)
     }
 }
+
+/// Compares names in the canonical order of RFC 4034 (section 6.1), ignoring case: label by
+/// label from the rightmost one, so that a name sorts right after its parent and before its
+/// parent's next sibling
+pub fn canonical_name_cmp(a: &str, b: &str) -> Ordering {
+    let labels = |name: &str| -> Vec<Vec<u8>> {
+        let name = name.trim_end_matches('.');
+        if name.is_empty() {
+            return Vec::new();
+        }
+        name.rsplit('.')
+            .map(|label| label.to_ascii_lowercase().into_bytes())
+            .collect()
+    };
+
+    labels(a).cmp(&labels(b))
+}
+
+/// Returns the length of the data following the `fixed` bytes of fields at the start of a
+/// record's data, failing when the data is too short to hold them
+fn rest_len(data_len: u16, fixed: usize) -> Result<usize, BufferError> {
+    (data_len as usize)
+        .checked_sub(fixed)
+        .ok_or(BufferError::DataTooShort(data_len))
+}
+
+/// Fails when the fields read from record data didn't end where the data does, either running
+/// into the next record or leaving some of the data unread
+fn check_end(buffer: &Buffer, end: usize, data_len: u16) -> Result<(), BufferError> {
+    match buffer.pos().cmp(&end) {
+        Ordering::Equal => Ok(()),
+        Ordering::Greater => Err(BufferError::DataTooShort(data_len)),
+        Ordering::Less => Err(BufferError::TrailingData(data_len)),
+    }
+}
+
+/// Returns the length of record data made of `fixed` bytes of fields followed by `variable`
+/// bytes, failing when it doesn't fit in the two bytes the length is written on
+fn data_len(fixed: usize, variable: usize) -> Result<u16, BufferError> {
+    u16::try_from(fixed + variable).map_err(|_| BufferError::DataTooLong)
+}
+
+/// Reads the types listed in the bitmaps of an NSEC record: a window for each block of 256
+/// types, with a bit set for each type of the block, see https://tools.ietf.org/html/rfc4034#section-4.1.2
+fn read_type_bitmaps(mut data: &[u8]) -> Vec<u16> {
+    let mut types = Vec::new();
+    while let [window, len, rest @ ..] = data {
+        let bitmap = &rest[..(*len as usize).min(rest.len())];
+        for (i, byte) in bitmap.iter().enumerate() {
+            for bit in 0..8 {
+                if byte & (0x80 >> bit) != 0 {
+                    types.push((*window as u16) << 8 | (i * 8 + bit) as u16);
+                }
+            }
+        }
+        data = &rest[bitmap.len()..];
+    }
+
+    types
+}
+
+/// Writes types as the bitmaps of an NSEC record, the reverse of `read_type_bitmaps`
+fn type_bitmaps(types: &[u16]) -> Vec<u8> {
+    let mut types = types.to_vec();
+    types.sort_unstable();
+    types.dedup();
+
+    let mut data = Vec::new();
+    for window in types.chunk_by(|a, b| a >> 8 == b >> 8) {
+        let last = (window[window.len() - 1] & 0xFF) as usize;
+        let mut bitmap = vec![0; last / 8 + 1];
+        for qtype in window {
+            let low = (qtype & 0xFF) as usize;
+            bitmap[low / 8] |= 0x80 >> (low % 8);
+        }
+        data.push((window[0] >> 8) as u8);
+        data.push(bitmap.len() as u8);
+        data.extend(bitmap);
+    }
+
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orders_names_canonically() {
+        // The example of RFC 4034 section 6.1, shuffled
+        let mut names = vec![
+            "\\200.z.example",
+            "z.example",
+            "a.example",
+            "*.z.example",
+            "Z.a.example",
+            "example",
+            "zABC.a.EXAMPLE",
+            "yljkjljk.a.example",
+        ];
+        names.sort_by(|a, b| canonical_name_cmp(a, b));
+        assert_eq!(
+            names,
+            vec![
+                "example",
+                "a.example",
+                "yljkjljk.a.example",
+                "Z.a.example",
+                "zABC.a.EXAMPLE",
+                "z.example",
+                "*.z.example",
+                "\\200.z.example",
+            ]
+        );
+        assert_eq!(
+            canonical_name_cmp("Example.COM", "example.com."),
+            Ordering::Equal
+        );
+    }
+
+    #[test]
+    fn orders_records_by_canonical_data() {
+        let ns = |host: &str, ttl| DnsRecord::NS {
+            domain: "Example.com".to_string(),
+            host: host.to_string(),
+            ttl,
+        };
+
+        let mut records = [ns("ns2.example.com", 300), ns("NS1.example.com", 60)];
+        records.sort_by(DnsRecord::canonical_cmp);
+        assert_eq!(records[0], ns("NS1.example.com", 60));
+        assert_eq!(
+            ns("NS1.Example.com", 300).canonical_cmp(&ns("ns1.example.com", 60)),
+            Ordering::Equal
+        );
+
+        let a = DnsRecord::A {
+            domain: "example.com".to_string(),
+            addr: Ipv4Addr::new(192, 0, 2, 1),
+            ttl: 60,
+        };
+        assert_eq!(a.canonical_cmp(&records[0]), Ordering::Less);
+
+        let bytes = records[0].canonical_bytes().unwrap();
+        assert!(bytes.starts_with(b"\x07example\x03com\x00\x00\x02\x00\x01"));
+        assert!(bytes.ends_with(b"\x03ns1\x07example\x03com\x00"));
+    }
+
+    #[test]
+    fn reads_back_written_soa_records() {
+        let soa = DnsRecord::SOA {
+            domain: "example.com".to_string(),
+            mname: "ns1.example.com".to_string(),
+            rname: "hostmaster.example.com".to_string(),
+            serial: 2024010101,
+            refresh: 7200,
+            retry: 3600,
+            expire: 1209600,
+            minimum: 300,
+            ttl: 3600,
+        };
+
+        let mut buffer = Buffer::new();
+        soa.write(&mut buffer).unwrap();
+        let mut written = Buffer::from_bytes(&buffer.buf[..buffer.pos()]);
+        assert_eq!(DnsRecord::read(&mut written).unwrap(), soa);
+        assert_eq!(written.pos(), buffer.pos());
+    }
+
+    #[test]
+    fn reads_back_written_caa_records() {
+        let caa = |tag: &str, value: &[u8]| DnsRecord::CAA {
+            domain: "example.com".to_string(),
+            flags: 128,
+            tag: tag.to_string(),
+            value: value.to_vec(),
+            ttl: 3600,
+        };
+
+        for record in [caa("issue", b"letsencrypt.org"), caa("x", b"\xff\x00")] {
+            let mut buffer = Buffer::new();
+            record.write(&mut buffer).unwrap();
+            let mut written = Buffer::from_bytes(&buffer.buf[..buffer.pos()]);
+            assert_eq!(DnsRecord::read(&mut written).unwrap(), record);
+            assert_eq!(written.pos(), buffer.pos());
+        }
+    }
+
+    #[test]
+    fn reads_back_written_https_records() {
+        let https = DnsRecord::HTTPS {
+            domain: "example.com".to_string(),
+            priority: 1,
+            target: String::new(),
+            params: vec![
+                SvcParam::Alpn(vec!["h2".to_string(), "h3".to_string()]),
+                SvcParam::Port(8443),
+                SvcParam::Ipv4Hint(vec!["192.0.2.1".parse().unwrap()]),
+                SvcParam::Unknown {
+                    key: 5,
+                    value: vec![0, 1, 2],
+                },
+                SvcParam::Ipv6Hint(vec!["2001:db8::1".parse().unwrap()]),
+            ],
+            ttl: 300,
+        };
+        let svcb = DnsRecord::SVCB {
+            domain: "_dns.example.com".to_string(),
+            priority: 0,
+            target: "dns.example.com".to_string(),
+            params: Vec::new(),
+            ttl: 300,
+        };
+
+        for record in [https, svcb] {
+            let mut buffer = Buffer::new();
+            record.write(&mut buffer).unwrap();
+            let mut written = Buffer::from_bytes(&buffer.buf[..buffer.pos()]);
+            assert_eq!(DnsRecord::read(&mut written).unwrap(), record);
+            assert_eq!(written.pos(), buffer.pos());
+        }
+
+        // A port that isn't two bytes long is kept as is.
+        let mut buffer = Buffer::new();
+        for byte in [0, 3, 0, 1, 80] {
+            buffer.write_u8(byte).unwrap();
+        }
+        let mut written = Buffer::from_bytes(&buffer.buf[..buffer.pos()]);
+        assert_eq!(
+            svcb::read_params(&mut written, 5).unwrap(),
+            vec![SvcParam::Unknown {
+                key: 3,
+                value: vec![80]
+            }]
+        );
+    }
+
+    #[test]
+    fn reads_back_written_dnssec_records() {
+        let nsec = DnsRecord::NSEC {
+            domain: "alpha.example.com".to_string(),
+            next: "host.example.com".to_string(),
+            types: vec![1, 15, 46, 47, 257],
+            ttl: 3600,
+        };
+        let records = [
+            DnsRecord::DS {
+                domain: "example.com".to_string(),
+                key_tag: 60485,
+                algorithm: 13,
+                digest_type: 2,
+                digest: vec![0xab; 32],
+                ttl: 86400,
+            },
+            DnsRecord::RRSIG {
+                domain: "example.com".to_string(),
+                type_covered: 1,
+                algorithm: 13,
+                labels: 2,
+                original_ttl: 300,
+                expiration: 1793491200,
+                inception: 1792067405,
+                key_tag: 60485,
+                signer: "example.com".to_string(),
+                signature: vec![0xcd; 64],
+                ttl: 300,
+            },
+            DnsRecord::DNSKEY {
+                domain: "example.com".to_string(),
+                flags: 257,
+                protocol: 3,
+                algorithm: 13,
+                public_key: vec![0xef; 64],
+                ttl: 3600,
+            },
+            nsec.clone(),
+        ];
+
+        for record in records {
+            let mut buffer = Buffer::new();
+            record.write(&mut buffer).unwrap();
+            let mut written = Buffer::from_bytes(&buffer.buf[..buffer.pos()]);
+            assert_eq!(DnsRecord::read(&mut written).unwrap(), record);
+            assert_eq!(written.pos(), buffer.pos());
+        }
+
+        // A, MX, RRSIG and NSEC in the first window, CAA in the second.
+        assert_eq!(
+            type_bitmaps(&[257, 47, 1, 46, 15, 1]),
+            [0, 6, 0x40, 0x01, 0, 0, 0, 0x03, 1, 1, 0x40]
+        );
+    }
+
+    #[test]
+    fn rejects_record_data_too_short_for_its_fields() {
+        for qtype in [
+            QueryType::HINFO,
+            QueryType::LOC,
+            QueryType::CERT,
+            QueryType::SSHFP,
+            QueryType::TLSA,
+            QueryType::URI,
+        ] {
+            // A single byte of data, followed by bytes the fields mustn't be read from
+            let mut bytes = vec![0];
+            bytes.extend(qtype.to_num().to_be_bytes());
+            bytes.extend([0, 1, 0, 0, 0x0e, 0x10, 0, 1]);
+            bytes.extend([0; 16]);
+
+            let mut buffer = Buffer::from_bytes(&bytes);
+            assert!(matches!(
+                DnsRecord::read(&mut buffer),
+                Err(BufferError::DataTooShort(1))
+            ));
+        }
+    }
+
+    #[test]
+    fn rejects_record_data_longer_than_its_fields() {
+        // An HINFO record with a byte left after its two character strings
+        let mut bytes = vec![0];
+        bytes.extend(QueryType::HINFO.to_num().to_be_bytes());
+        bytes.extend([0, 1, 0, 0, 0x0e, 0x10, 0, 7]);
+        bytes.extend(b"\x02x1\x02y2\x00");
+
+        let mut buffer = Buffer::from_bytes(&bytes);
+        assert!(matches!(
+            DnsRecord::read(&mut buffer),
+            Err(BufferError::TrailingData(7))
+        ));
+    }
+
+    #[test]
+    fn keeps_loc_records_of_other_versions_as_is() {
+        let mut bytes = vec![0];
+        bytes.extend(QueryType::LOC.to_num().to_be_bytes());
+        bytes.extend([0, 1, 0, 0, 0x0e, 0x10, 0, 5]);
+        bytes.extend([1, 2, 3, 4, 5]);
+
+        let mut buffer = Buffer::from_bytes(&bytes);
+        let record = DnsRecord::read(&mut buffer).unwrap();
+        assert_eq!(
+            record,
+            DnsRecord::UNKNOWN {
+                domain: String::new(),
+                qtype: QueryType::LOC.to_num(),
+                data: vec![1, 2, 3, 4, 5],
+                ttl: 3600,
+            }
+        );
+        assert_eq!(record.qtype(), QueryType::LOC);
+
+        let mut written = Buffer::new();
+        record.write(&mut written).unwrap();
+        assert_eq!(written.buf[..written.pos()], bytes[..]);
+    }
+
+    #[test]
+    fn rejects_record_data_too_long_for_its_length() {
+        let tlsa = DnsRecord::TLSA {
+            domain: "_443._tcp.example.com".to_string(),
+            usage: 3,
+            selector: 1,
+            matching_type: 1,
+            data: vec![0; 65533],
+            ttl: 3600,
+        };
+        let uri = DnsRecord::URI {
+            domain: "_http._tcp.example.com".to_string(),
+            priority: 10,
+            weight: 1,
+            target: "x".repeat(65532),
+            ttl: 3600,
+        };
+
+        for record in [tlsa, uri] {
+            let mut buffer = Buffer::growable();
+            assert!(matches!(
+                record.write(&mut buffer),
+                Err(BufferError::DataTooLong)
+            ));
+        }
+    }
+}
@@ -0,0 +1,233 @@
+use std::net::{IpAddr, Ipv4Addr};
+
+use crate::buffer::BufferError;
+use crate::cache::Cache;
+use crate::handler::recursive_lookup;
+use crate::infra::InfraCache;
+use crate::question::QueryType;
+use crate::record::DnsRecord;
+use crate::resultcode::ResultCode;
+
+/// UDP payload size advertised to upstream servers, the default of the server
+const EDNS_SIZE: u16 = 1232;
+/// Maximum number of aliases followed from the name looked up
+const MAX_CNAMES: usize = 8;
+
+/// `ResolveError` is an enum that represents the ways a lookup can fail
+#[derive(thiserror::Error, Debug)]
+pub enum ResolveError {
+    #[error("No valid response: {0}")]
+    BufferError(#[from] BufferError),
+    #[error("Name \"{0}\" does not exist")]
+    NxDomain(String),
+    #[error("Lookup of \"{0}\" failed with {1:?}")]
+    Failed(String, ResultCode),
+    #[error("Aliases of \"{0}\" go on for more than {MAX_CNAMES} names")]
+    TooManyCnames(String),
+}
+
+/// The records found for a name, with the time they can be cached for
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Lookup<T> {
+    /// The name the records belong to, at the end of the aliases of the name looked up
+    pub name: String,
+    pub records: Vec<T>,
+    /// Lowest TTL of the records and of the aliases leading to them, in seconds
+    pub ttl: u32,
+}
+
+/// A mail exchange of a domain
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MxRecord {
+    /// Exchanges with a lower preference are tried first
+    pub priority: u16,
+    pub exchange: String,
+}
+
+/// The `Resolver` struct resolves names for applications embedding the crate, the way the
+/// server does (iteratively from the root servers, caching what it learns), and returns
+/// typed records rather than DNS messages.
+///
+/// ```no_run
+/// let resolver = vodo::resolver::Resolver::new();
+/// let lookup = resolver.lookup_ip("example.com").unwrap();
+/// println!("{:?}, valid for {}s", lookup.records, lookup.ttl);
+/// ```
+pub struct Resolver {
+    cache: Cache,
+    infra: InfraCache,
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Resolver::new()
+    }
+}
+
+impl Resolver {
+    pub fn new() -> Resolver {
+        Resolver {
+            cache: Cache::new(),
+            infra: InfraCache::new(EDNS_SIZE),
+        }
+    }
+
+    /// Starts resolutions from the given root server rather than a.root-servers.net, with
+    /// all the upstream servers listening on the given port, e.g. a test hierarchy
+    pub fn with_root(mut self, root: Ipv4Addr, port: u16) -> Resolver {
+        self.infra = self.infra.with_root(root, port);
+        self
+    }
+
+    /// Looks up the IPv4 and IPv6 addresses of a name
+    pub fn lookup_ip(&self, name: &str) -> Result<Lookup<IpAddr>, ResolveError> {
+        let v4 = self.lookup(name, QueryType::A, |record| match record {
+            DnsRecord::A { addr, .. } => Some(IpAddr::V4(*addr)),
+            _ => None,
+        })?;
+        let v6 = self.lookup(name, QueryType::AAAA, |record| match record {
+            DnsRecord::AAAA { addr, .. } => Some(IpAddr::V6(*addr)),
+            _ => None,
+        })?;
+
+        let ttl = match (v4.records.is_empty(), v6.records.is_empty()) {
+            (false, true) => v4.ttl,
+            (true, false) => v6.ttl,
+            _ => v4.ttl.min(v6.ttl),
+        };
+        Ok(Lookup {
+            name: v4.name,
+            records: v4.records.into_iter().chain(v6.records).collect(),
+            ttl,
+        })
+    }
+
+    /// Looks up the mail exchanges of a domain, in the order of their preference
+    pub fn lookup_mx(&self, name: &str) -> Result<Lookup<MxRecord>, ResolveError> {
+        let mut lookup = self.lookup(name, QueryType::MX, |record| match record {
+            DnsRecord::MX { priority, host, .. } => Some(MxRecord {
+                priority: *priority,
+                exchange: host.clone(),
+            }),
+            _ => None,
+        })?;
+        lookup.records.sort_by_key(|mx| mx.priority);
+
+        Ok(lookup)
+    }
+
+    /// Looks up the text records of a name, each with its strings joined
+    pub fn lookup_txt(&self, name: &str) -> Result<Lookup<String>, ResolveError> {
+        self.lookup(name, QueryType::TXT, |record| match record {
+            DnsRecord::TXT { data, .. } => Some(data.concat()),
+            _ => None,
+        })
+    }
+
+    /// Resolves a name, following its aliases, and extracts the records of the given type
+    /// owned by the name they lead to. Names without such records return none, while names
+    /// that don't exist return an error.
+    fn lookup<T>(
+        &self,
+        name: &str,
+        qtype: QueryType,
+        extract: impl Fn(&DnsRecord) -> Option<T>,
+    ) -> Result<Lookup<T>, ResolveError> {
+        let mut target = name.trim_end_matches('.').to_ascii_lowercase();
+        let mut ttl = u32::MAX;
+
+        for _ in 0..=MAX_CNAMES {
+            let response = recursive_lookup(&target, qtype, &self.cache, &self.infra)?;
+            match response.header.rescode {
+                ResultCode::NOERROR => {}
+                ResultCode::NXDOMAIN => return Err(ResolveError::NxDomain(target)),
+                rescode => return Err(ResolveError::Failed(target, rescode)),
+            }
+
+            let end = response.cname_target(&target);
+            let mut records = Vec::new();
+            for record in &response.answers {
+                let owner = record.domain().unwrap_or_default();
+                if matches!(record, DnsRecord::CNAME { .. }) || owner == end {
+                    ttl = ttl.min(record.ttl());
+                }
+                if owner == end {
+                    records.extend(extract(record));
+                }
+            }
+
+            // Aliases whose target was left out of the answer are resolved in turn.
+            if records.is_empty() && end != target {
+                target = end;
+                continue;
+            }
+            return Ok(Lookup {
+                name: end,
+                ttl: if records.is_empty() { 0 } else { ttl },
+                records,
+            });
+        }
+
+        Err(ResolveError::TooManyCnames(name.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::DnsPacket;
+    use crate::simulation::{Conditions, Hierarchy};
+    use smallvec::smallvec;
+
+    #[test]
+    fn returns_typed_records_of_names_and_their_aliases() {
+        let hierarchy = Hierarchy::start(Conditions::default()).unwrap();
+        let resolver = Resolver::new().with_root(hierarchy.root, hierarchy.port);
+
+        let lookup = resolver.lookup_ip("www.example.com.").unwrap();
+        assert_eq!(lookup.records, vec![IpAddr::from([192, 0, 2, 1])]);
+        assert_eq!((lookup.name.as_str(), lookup.ttl), ("www.example.com", 300));
+        assert!(matches!(
+            resolver.lookup_ip("example.org"),
+            Err(ResolveError::NxDomain(name)) if name == "example.org"
+        ));
+
+        // Records already cached are answered without asking the hierarchy.
+        let mut response = DnsPacket::new();
+        response.answers = smallvec![
+            DnsRecord::CNAME {
+                domain: "mail.example.com".to_string(),
+                host: "example.com".to_string(),
+                ttl: 60,
+            },
+            DnsRecord::MX {
+                domain: "example.com".to_string(),
+                priority: 20,
+                host: "mx2.example.com".to_string(),
+                ttl: 300,
+            },
+            DnsRecord::MX {
+                domain: "example.com".to_string(),
+                priority: 10,
+                host: "mx1.example.com".to_string(),
+                ttl: 300,
+            },
+        ];
+        resolver.cache.insert_response(&response);
+
+        let lookup = resolver.lookup_mx("mail.example.com").unwrap();
+        let exchanges: Vec<&str> = lookup
+            .records
+            .iter()
+            .map(|mx| mx.exchange.as_str())
+            .collect();
+        assert_eq!(exchanges, ["mx1.example.com", "mx2.example.com"]);
+        assert_eq!(lookup.name, "example.com");
+        assert!((55..=60).contains(&lookup.ttl));
+        assert!(resolver
+            .lookup_txt("www.example.com")
+            .unwrap()
+            .records
+            .is_empty());
+    }
+}
@@ -1,26 +1,107 @@
-/// Result codes as defined in RFC 1035:
+/// Result codes as defined in RFC 1035 and later RFCs:
 /// see https://tools.ietf.org/html/rfc1035#section-4.1.1
+/// and https://www.iana.org/assignments/dns-parameters/dns-parameters.xhtml#dns-parameters-6
+///
+/// Codes up to 15 fit in the 4 bits of the header. Extended codes, from 16 onwards, can only
+/// be carried in messages with an OPT record, whose TTL holds their upper 8 bits:
+/// see https://tools.ietf.org/html/rfc6891#section-6.1.3
+///
+/// - NOERROR (0), FORMERR (1), SERVFAIL (2), NXDOMAIN (3), NOTIMP (4), REFUSED (5):
+///   [RFC 1035](https://tools.ietf.org/html/rfc1035)
+/// - YXDOMAIN (6), YXRRSET (7), NXRRSET (8), NOTAUTH (9), NOTZONE (10):
+///   [RFC 2136](https://tools.ietf.org/html/rfc2136)
+/// - DSOTYPENI (11): [RFC 8490](https://tools.ietf.org/html/rfc8490)
+/// - BADVERS (16): [RFC 6891](https://tools.ietf.org/html/rfc6891), also BADSIG in TSIG records
+/// - BADKEY (17), BADTIME (18): [RFC 8945](https://tools.ietf.org/html/rfc8945)
+/// - BADMODE (19), BADNAME (20), BADALG (21): [RFC 2930](https://tools.ietf.org/html/rfc2930)
+/// - BADTRUNC (22): [RFC 8945](https://tools.ietf.org/html/rfc8945)
+/// - BADCOOKIE (23): [RFC 7873](https://tools.ietf.org/html/rfc7873)
 #[allow(clippy::upper_case_acronyms)]
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum ResultCode {
-    NOERROR = 0,
-    FORMERR = 1,
-    SERVFAIL = 2,
-    NXDOMAIN = 3,
-    NOTIMP = 4,
-    REFUSED = 5,
+    UNKNOWN(u16),
+    NOERROR,
+    FORMERR,
+    SERVFAIL,
+    NXDOMAIN,
+    NOTIMP,
+    REFUSED,
+    YXDOMAIN,
+    YXRRSET,
+    NXRRSET,
+    NOTAUTH,
+    NOTZONE,
+    DSOTYPENI,
+    BADVERS,
+    BADKEY,
+    BADTIME,
+    BADMODE,
+    BADNAME,
+    BADALG,
+    BADTRUNC,
+    BADCOOKIE,
 }
 
 impl ResultCode {
-    #[allow(clippy::wildcard_in_or_patterns)]
-    pub fn from_num(num: u8) -> ResultCode {
+    pub fn to_num(&self) -> u16 {
+        match *self {
+            ResultCode::UNKNOWN(x) => x,
+            ResultCode::NOERROR => 0,
+            ResultCode::FORMERR => 1,
+            ResultCode::SERVFAIL => 2,
+            ResultCode::NXDOMAIN => 3,
+            ResultCode::NOTIMP => 4,
+            ResultCode::REFUSED => 5,
+            ResultCode::YXDOMAIN => 6,
+            ResultCode::YXRRSET => 7,
+            ResultCode::NXRRSET => 8,
+            ResultCode::NOTAUTH => 9,
+            ResultCode::NOTZONE => 10,
+            ResultCode::DSOTYPENI => 11,
+            ResultCode::BADVERS => 16,
+            ResultCode::BADKEY => 17,
+            ResultCode::BADTIME => 18,
+            ResultCode::BADMODE => 19,
+            ResultCode::BADNAME => 20,
+            ResultCode::BADALG => 21,
+            ResultCode::BADTRUNC => 22,
+            ResultCode::BADCOOKIE => 23,
+        }
+    }
+
+    pub fn from_num(num: u16) -> ResultCode {
         match num {
+            0 => ResultCode::NOERROR,
             1 => ResultCode::FORMERR,
             2 => ResultCode::SERVFAIL,
             3 => ResultCode::NXDOMAIN,
             4 => ResultCode::NOTIMP,
             5 => ResultCode::REFUSED,
-            _ => ResultCode::NOERROR,
+            6 => ResultCode::YXDOMAIN,
+            7 => ResultCode::YXRRSET,
+            8 => ResultCode::NXRRSET,
+            9 => ResultCode::NOTAUTH,
+            10 => ResultCode::NOTZONE,
+            11 => ResultCode::DSOTYPENI,
+            16 => ResultCode::BADVERS,
+            17 => ResultCode::BADKEY,
+            18 => ResultCode::BADTIME,
+            19 => ResultCode::BADMODE,
+            20 => ResultCode::BADNAME,
+            21 => ResultCode::BADALG,
+            22 => ResultCode::BADTRUNC,
+            23 => ResultCode::BADCOOKIE,
+            _ => ResultCode::UNKNOWN(num),
         }
     }
+
+    /// Returns the lower 4 bits of the code, which go in the header
+    pub fn header_bits(&self) -> u8 {
+        (self.to_num() & 0x0F) as u8
+    }
+
+    /// Returns the upper 8 bits of the code, which go in the OPT record
+    pub fn extended_bits(&self) -> u8 {
+        (self.to_num() >> 4) as u8
+    }
 }
@@ -0,0 +1,121 @@
+use std::fmt;
+use std::time::Duration;
+
+use rand::Rng;
+
+/// Longest time to wait for an attempt, however many came before it
+const MAX_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The `RetryPolicy` struct tells how queries to upstream servers are retried when they go
+/// unanswered: each attempt waits longer than the previous one, by `multiplier`, and each
+/// wait is randomized by up to `jitter` of it, so that the retries of many resolutions
+/// don't all hit a struggling server at the same time.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RetryPolicy {
+    /// Time to wait for the response to the first attempt
+    pub initial_timeout: Duration,
+    /// Factor the timeout grows by with each attempt
+    pub multiplier: f64,
+    /// Number of times a query is sent before giving up on the server (or on its EDNS mode)
+    pub attempts: u32,
+    /// Ratio of the timeout it's randomly shortened or lengthened by, between 0 and 1
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    /// Two attempts, waiting 1 and then 2 seconds: as long as the single 3 seconds attempt of
+    /// older versions, but recovering from a lost packet without falling back on EDNS
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            initial_timeout: Duration::from_secs(1),
+            multiplier: 2.0,
+            attempts: 2,
+            jitter: 0.1,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Returns the time to wait for the response to an attempt, counted from 0, jittered
+    pub fn timeout(&self, attempt: u32) -> Duration {
+        let jitter = match self.jitter {
+            jitter if jitter > 0.0 => rand::thread_rng().gen_range(-jitter..=jitter),
+            _ => 0.0,
+        };
+        // Sockets reject a timeout of zero.
+        self.base_timeout(attempt)
+            .mul_f64(1.0 + jitter)
+            .max(Duration::from_millis(1))
+    }
+
+    /// Returns the time to wait for an attempt before jitter, up to `MAX_TIMEOUT`
+    fn base_timeout(&self, attempt: u32) -> Duration {
+        let factor = self.multiplier.max(1.0).powi(attempt as i32);
+        Duration::try_from_secs_f64(self.initial_timeout.as_secs_f64() * factor)
+            .unwrap_or(MAX_TIMEOUT)
+            .min(MAX_TIMEOUT)
+    }
+
+    /// Returns the longest time to wait, that of the last attempt, used for TCP exchanges
+    /// which aren't retried
+    pub fn max_timeout(&self) -> Duration {
+        self.base_timeout(self.attempts.saturating_sub(1))
+    }
+}
+
+impl fmt::Display for RetryPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} attempts, timeout of {}ms growing {}x, {}% jitter",
+            self.attempts,
+            self.initial_timeout.as_millis(),
+            self.multiplier,
+            (self.jitter * 100.0).round()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grows_and_jitters_timeouts() {
+        let policy = RetryPolicy {
+            initial_timeout: Duration::from_millis(500),
+            multiplier: 2.0,
+            attempts: 3,
+            jitter: 0.2,
+        };
+        assert_eq!(policy.max_timeout(), Duration::from_secs(2));
+
+        for _ in 0..100 {
+            let timeout = policy.timeout(1);
+            assert!(
+                timeout >= Duration::from_millis(800) && timeout <= Duration::from_millis(1200)
+            );
+        }
+
+        let steady = RetryPolicy {
+            jitter: 0.0,
+            ..policy
+        };
+        assert_eq!(steady.timeout(0), Duration::from_millis(500));
+        assert_eq!(steady.timeout(2), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn caps_timeouts_growing_out_of_range() {
+        for multiplier in [1000.0, f64::INFINITY] {
+            let policy = RetryPolicy {
+                initial_timeout: Duration::from_secs(1),
+                multiplier,
+                attempts: 10,
+                jitter: 0.1,
+            };
+            assert_eq!(policy.max_timeout(), MAX_TIMEOUT);
+            assert!(policy.timeout(9) <= MAX_TIMEOUT.mul_f64(1.1));
+        }
+    }
+}
@@ -0,0 +1,253 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::packet::DnsPacket;
+use crate::question::QueryType;
+use crate::record::DnsRecord;
+use crate::resultcode::ResultCode;
+use crate::subnet::Subnet;
+use crate::zone::is_subdomain;
+
+/// TTL of the records of the private reverse zones, and of the negative answers from them
+const TTL: u32 = 10800;
+
+/// Reverse zones of the private and special-use address ranges (RFC 6303 and RFC 7793),
+/// which the public DNS can't answer for
+const FIXED_ZONES: &[&str] = &[
+    // RFC 1918 networks
+    "10.in-addr.arpa",
+    "168.192.in-addr.arpa",
+    // "This" network, loopback and link-local addresses
+    "0.in-addr.arpa",
+    "127.in-addr.arpa",
+    "254.169.in-addr.arpa",
+    // Documentation networks (TEST-NET-1, 2 and 3)
+    "2.0.192.in-addr.arpa",
+    "100.51.198.in-addr.arpa",
+    "113.0.203.in-addr.arpa",
+    // Broadcast address
+    "255.255.255.255.in-addr.arpa",
+    // IPv6 unspecified and loopback addresses
+    "0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.ip6.arpa",
+    "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.ip6.arpa",
+    // IPv6 unique local, link-local and documentation addresses
+    "d.f.ip6.arpa",
+    "8.e.f.ip6.arpa",
+    "9.e.f.ip6.arpa",
+    "a.e.f.ip6.arpa",
+    "b.e.f.ip6.arpa",
+    "8.b.d.0.1.0.0.2.ip6.arpa",
+];
+
+/// Returns the private reverse zone containing a name, if any: the fixed zones, the 16 zones
+/// of 172.16.0.0/12 and the 64 zones of the shared address space 100.64.0.0/10
+pub fn find_zone(qname: &str) -> Option<String> {
+    if let Some(zone) = FIXED_ZONES.iter().find(|zone| is_subdomain(qname, zone)) {
+        return Some(zone.to_string());
+    }
+
+    let (first, second) = (16..32)
+        .map(|second| (172, second))
+        .chain((64..128).map(|second| (100, second)))
+        .find(|(first, second)| {
+            is_subdomain(qname, &format!("{}.{}.in-addr.arpa", second, first))
+        })?;
+    Some(format!("{}.{}.in-addr.arpa", second, first))
+}
+
+/// This function takes a domain name and a query type as input, and answers the query
+/// locally if the name belongs to a private reverse zone, so that lookups of private
+/// addresses don't leak to the root servers (RFC 6303). The zones are empty: their apex
+/// only has the SOA and NS records, and the names below it don't exist.
+pub fn lookup(qname: &str, qtype: QueryType) -> Option<DnsPacket> {
+    let zone = find_zone(qname)?;
+
+    let mut packet = DnsPacket::new();
+    packet.header.authoritative_answer = true;
+
+    let soa = DnsRecord::SOA {
+        domain: zone.clone(),
+        mname: zone.clone(),
+        rname: "nobody.invalid".to_string(),
+        serial: 1,
+        refresh: 3600,
+        retry: 1200,
+        expire: 604800,
+        minimum: TTL,
+        ttl: TTL,
+    };
+    if qname != zone {
+        packet.header.rescode = ResultCode::NXDOMAIN;
+        packet.authorities.push(soa);
+        return Some(packet);
+    }
+
+    match qtype {
+        QueryType::SOA => packet.answers.push(soa),
+        QueryType::NS => packet.answers.push(DnsRecord::NS {
+            domain: zone.clone(),
+            host: zone,
+            ttl: TTL,
+        }),
+        _ => packet.authorities.push(soa),
+    }
+
+    Some(packet)
+}
+
+/// Returns the name the reverse lookups of an address are made for: its bytes in reverse
+/// order under `in-addr.arpa` for IPv4, and its nibbles under `ip6.arpa` for IPv6
+pub fn ptr_name(addr: IpAddr) -> String {
+    match addr {
+        IpAddr::V4(addr) => in_addr_arpa_name(addr, 4),
+        IpAddr::V6(addr) => ip6_arpa_name(addr, 32),
+    }
+}
+
+/// Returns the reverse zone of a subnet, e.g. `1.168.192.in-addr.arpa` for `192.168.1.0/24`
+/// or `8.b.d.0.1.0.0.2.ip6.arpa` for `2001:db8::/32`. Returns `None` if the prefix doesn't
+/// end on a byte (IPv4) or nibble (IPv6) boundary, as the reverse tree only has a label
+/// for each of them.
+pub fn reverse_zone(subnet: &Subnet) -> Option<String> {
+    match subnet.addr {
+        IpAddr::V4(addr) if subnet.prefix.is_multiple_of(8) => {
+            Some(in_addr_arpa_name(addr, usize::from(subnet.prefix / 8)))
+        }
+        IpAddr::V6(addr) if subnet.prefix.is_multiple_of(4) => {
+            Some(ip6_arpa_name(addr, usize::from(subnet.prefix / 4)))
+        }
+        _ => None,
+    }
+}
+
+/// Returns the nibbles of an IPv6 address, the most significant first
+pub fn nibbles(addr: Ipv6Addr) -> [u8; 32] {
+    let mut nibbles = [0; 32];
+    for (pair, byte) in nibbles.chunks_mut(2).zip(addr.octets()) {
+        pair[0] = byte >> 4;
+        pair[1] = byte & 0xf;
+    }
+    nibbles
+}
+
+/// Returns the `ip6.arpa` name of the first `count` nibbles of an address
+fn ip6_arpa_name(addr: Ipv6Addr, count: usize) -> String {
+    let mut name = String::with_capacity(2 * count + 8);
+    for nibble in nibbles(addr)[..count].iter().rev() {
+        name.push_str(&format!("{:x}.", nibble));
+    }
+    name.push_str("ip6.arpa");
+    name
+}
+
+/// Returns the `in-addr.arpa` name of the first `count` bytes of an address
+fn in_addr_arpa_name(addr: Ipv4Addr, count: usize) -> String {
+    let mut name = String::with_capacity(4 * count + 12);
+    for byte in addr.octets()[..count].iter().rev() {
+        name.push_str(&format!("{}.", byte));
+    }
+    name.push_str("in-addr.arpa");
+    name
+}
+
+/// Returns the address a reverse lookup is made for, if the name is the full reverse name of
+/// an address (see `ptr_name`)
+pub fn parse_ptr_name(qname: &str) -> Option<IpAddr> {
+    if let Some(labels) = qname.strip_suffix(".in-addr.arpa") {
+        let mut octets: Vec<u8> = labels
+            .split('.')
+            .map(|label| label.parse().ok())
+            .collect::<Option<_>>()?;
+        octets.reverse();
+        let octets: [u8; 4] = octets.try_into().ok()?;
+        return Some(IpAddr::V4(Ipv4Addr::from(octets)));
+    }
+
+    let labels = qname.strip_suffix(".ip6.arpa")?;
+    let mut nibbles: Vec<u8> = labels
+        .split('.')
+        .map(|label| match label.len() {
+            1 => u8::from_str_radix(label, 16).ok(),
+            _ => None,
+        })
+        .collect::<Option<_>>()?;
+    if nibbles.len() != 32 {
+        return None;
+    }
+    nibbles.reverse();
+    let mut octets = [0; 16];
+    for (octet, pair) in octets.iter_mut().zip(nibbles.chunks(2)) {
+        *octet = pair[0] << 4 | pair[1];
+    }
+    Some(IpAddr::V6(Ipv6Addr::from(octets)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_private_reverse_zones() {
+        assert_eq!(
+            find_zone("1.1.168.192.in-addr.arpa").as_deref(),
+            Some("168.192.in-addr.arpa")
+        );
+        assert_eq!(
+            find_zone("5.0.20.172.in-addr.arpa").as_deref(),
+            Some("20.172.in-addr.arpa")
+        );
+        assert_eq!(find_zone("5.0.32.172.in-addr.arpa"), None);
+        assert_eq!(
+            find_zone("1.0.0.100.100.in-addr.arpa").as_deref(),
+            Some("100.100.in-addr.arpa")
+        );
+        assert_eq!(find_zone("8.8.8.8.in-addr.arpa"), None);
+        assert_eq!(find_zone("110.in-addr.arpa"), None);
+    }
+
+    #[test]
+    fn maps_addresses_to_reverse_names() {
+        for addr in ["192.168.1.10", "2001:db8::1", "fe80::1:2"] {
+            let addr: IpAddr = addr.parse().unwrap();
+            assert_eq!(parse_ptr_name(&ptr_name(addr)), Some(addr));
+        }
+        assert_eq!(
+            ptr_name("192.168.1.10".parse().unwrap()),
+            "10.1.168.192.in-addr.arpa"
+        );
+        assert!(ptr_name("2001:db8::1".parse().unwrap()).starts_with("1.0.0.0."));
+        assert!(ptr_name("2001:db8::1".parse().unwrap()).ends_with(".8.b.d.0.1.0.0.2.ip6.arpa"));
+        assert_eq!(parse_ptr_name("1.168.192.in-addr.arpa"), None);
+        assert_eq!(parse_ptr_name("x.1.168.192.in-addr.arpa"), None);
+    }
+
+    #[test]
+    fn names_the_reverse_zones_of_subnets() {
+        let zone = |subnet: &str| reverse_zone(&subnet.parse().unwrap());
+        assert_eq!(
+            zone("192.168.1.0/24").as_deref(),
+            Some("1.168.192.in-addr.arpa")
+        );
+        assert_eq!(zone("10.0.0.0/8").as_deref(), Some("10.in-addr.arpa"));
+        assert_eq!(
+            zone("2001:db8::/32").as_deref(),
+            Some("8.b.d.0.1.0.0.2.ip6.arpa")
+        );
+        assert_eq!(zone("fd00::/8").as_deref(), Some("d.f.ip6.arpa"));
+        assert_eq!(zone("::/0").as_deref(), Some("ip6.arpa"));
+        assert_eq!(zone("192.168.1.0/23"), None);
+        assert_eq!(zone("2001:db8::/33"), None);
+    }
+
+    #[test]
+    fn answers_nxdomain_below_the_apex() {
+        let packet = lookup("4.3.2.10.in-addr.arpa", QueryType::PTR).unwrap();
+        assert_eq!(packet.header.rescode, ResultCode::NXDOMAIN);
+        assert!(packet.header.authoritative_answer);
+        assert_eq!(packet.authorities.len(), 1);
+        assert_eq!(packet.authorities[0].domain(), Some("10.in-addr.arpa"));
+
+        let apex = lookup("10.in-addr.arpa", QueryType::SOA).unwrap();
+        assert_eq!(apex.header.rescode, ResultCode::NOERROR);
+        assert_eq!(apex.answers.len(), 1);
+    }
+}
@@ -0,0 +1,143 @@
+use std::any::TypeId;
+use std::fmt::Write;
+
+use clap::{Arg, ArgAction, Command};
+
+/// Version of JSON Schema the schemas are written in
+const DIALECT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+/// Returns a JSON schema of the options of a command, one property per long option named
+/// after it, so that configurations can be validated (and completed) by editors and
+/// deployment tooling. Unknown properties are rejected, like unknown options on the
+/// command line.
+pub fn json_schema(command: &Command) -> String {
+    let properties: Vec<String> = command
+        .get_arguments()
+        .filter(|arg| !matches!(arg.get_action(), ArgAction::Help | ArgAction::Version))
+        .filter_map(|arg| Some(format!("\"{}\": {}", arg.get_long()?, property(arg))))
+        .collect();
+
+    let mut schema = String::new();
+    let _ = writeln!(schema, "{{");
+    let _ = writeln!(schema, "  \"$schema\": \"{}\",", DIALECT);
+    let _ = writeln!(schema, "  \"title\": \"{}\",", escape(command.get_name()));
+    let _ = writeln!(schema, "  \"type\": \"object\",");
+    let _ = writeln!(schema, "  \"additionalProperties\": false,");
+    let _ = writeln!(schema, "  \"properties\": {{");
+    let _ = writeln!(schema, "    {}", properties.join(",\n    "));
+    let _ = writeln!(schema, "  }}");
+    let _ = writeln!(schema, "}}");
+    schema
+}
+
+/// Returns the schema of the values of an option
+fn property(arg: &Arg) -> String {
+    let (kind, bounds) = value_type(arg);
+    let mut fields = Vec::new();
+
+    match arg.get_action() {
+        ArgAction::SetTrue | ArgAction::SetFalse => {
+            fields.push("\"type\": \"boolean\"".to_string())
+        }
+        ArgAction::Append => fields.push(format!(
+            "\"type\": \"array\", \"items\": {{\"type\": \"{}\"{}}}",
+            kind, bounds
+        )),
+        _ => {
+            fields.push(format!("\"type\": \"{}\"{}", kind, bounds));
+            if let Some(default) = arg.get_default_values().first() {
+                let default = default.to_string_lossy();
+                match kind {
+                    "string" => fields.push(format!("\"default\": \"{}\"", escape(&default))),
+                    _ => fields.push(format!("\"default\": {}", default)),
+                }
+            }
+        }
+    }
+    if let Some(help) = arg.get_help() {
+        fields.push(format!(
+            "\"description\": \"{}\"",
+            escape(&help.to_string())
+        ));
+    }
+
+    format!("{{{}}}", fields.join(", "))
+}
+
+/// Returns the JSON type of the values of an option and the bounds of numbers, from the type
+/// they are parsed into. Anything else than numbers is given as a string.
+fn value_type(arg: &Arg) -> (&'static str, String) {
+    let parsed = arg.get_value_parser().type_id();
+    let integers: [(TypeId, u64); 5] = [
+        (TypeId::of::<u8>(), u8::MAX as u64),
+        (TypeId::of::<u16>(), u16::MAX as u64),
+        (TypeId::of::<u32>(), u32::MAX as u64),
+        (TypeId::of::<u64>(), u64::MAX),
+        (TypeId::of::<usize>(), usize::MAX as u64),
+    ];
+
+    if let Some((_, max)) = integers.iter().find(|(id, _)| parsed == *id) {
+        ("integer", format!(", \"minimum\": 0, \"maximum\": {}", max))
+    } else if parsed == TypeId::of::<f64>() {
+        ("number", String::new())
+    } else {
+        ("string", String::new())
+    }
+}
+
+/// Escapes a string for a JSON string literal
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c if c.is_control() => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::value_parser;
+
+    #[test]
+    fn describes_options_by_type() {
+        let command = Command::new("vodo")
+            .arg(
+                Arg::new("port")
+                    .long("port")
+                    .help("Port to listen on")
+                    .value_parser(value_parser!(u16))
+                    .default_value("5353"),
+            )
+            .arg(
+                Arg::new("zone")
+                    .long("zone")
+                    .help("Zone as \"<ORIGIN>=<PATH>\"")
+                    .action(ArgAction::Append),
+            )
+            .arg(
+                Arg::new("verbose")
+                    .long("verbose")
+                    .action(ArgAction::SetTrue),
+            );
+
+        let schema = json_schema(&command);
+        assert!(schema.contains("\"additionalProperties\": false"));
+        assert!(schema.contains(
+            "\"port\": {\"type\": \"integer\", \"minimum\": 0, \"maximum\": 65535, \"default\": 5353, \"description\": \"Port to listen on\"}"
+        ));
+        assert!(schema.contains(
+            "\"zone\": {\"type\": \"array\", \"items\": {\"type\": \"string\"}, \"description\": \"Zone as \\\"<ORIGIN>=<PATH>\\\"\"}"
+        ));
+        assert!(schema.contains("\"verbose\": {\"type\": \"boolean\"}"));
+        assert!(!schema.contains("\"help\""));
+    }
+}
@@ -0,0 +1,178 @@
+use std::io::{Read, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream, UdpSocket};
+use std::time::{Duration, Instant};
+
+use log::{error, info};
+use rand::Rng;
+
+use crate::buffer::{BufferError, MAX_SIZE};
+use crate::listener::{Listener, Transport};
+use crate::packet::DnsPacket;
+use crate::question::{DnsQuestion, QueryType};
+use crate::record::DnsRecord;
+use crate::resultcode::ResultCode;
+
+/// Name the self-tests query: the apex of a private reverse zone, which the server answers
+/// with its SOA record without asking any upstream server (RFC 6303)
+pub const PROBE_NAME: &str = "10.in-addr.arpa";
+/// Time to wait for the server to answer a probe
+const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// `SelftestError` is an enum that represents the ways a probe of a listener can fail
+#[derive(thiserror::Error, Debug)]
+pub enum SelftestError {
+    #[error("No valid response: {0}")]
+    BufferError(#[from] BufferError),
+    #[error("I/O error: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("Unexpected response: {0}")]
+    Unexpected(String),
+}
+
+/// Returns the address to probe a listener at: the loopback address for listeners on all
+/// addresses, the address of the listener otherwise
+pub fn probe_target(listener: &Listener) -> SocketAddr {
+    let ip = match listener.address.ip() {
+        ip if ip.is_unspecified() => Ipv4Addr::LOCALHOST,
+        ip => *ip,
+    };
+    SocketAddr::new(IpAddr::V4(ip), listener.address.port())
+}
+
+/// Sends a probe to a server over a transport, and checks the response. Returns the time the
+/// server took to answer.
+pub fn probe(transport: Transport, target: SocketAddr) -> Result<Duration, SelftestError> {
+    let request = probe_query();
+    let start = Instant::now();
+    let response = match transport {
+        Transport::Udp => exchange_udp(&request, target)?,
+        Transport::Tcp => exchange_tcp(&request, target)?,
+    };
+    let elapsed = start.elapsed();
+
+    check_response(&request, &response)?;
+    Ok(elapsed)
+}
+
+/// Probes each listener over its transport, then the advertised address if there is one,
+/// logging the outcome of each probe. Returns true if they all passed.
+pub fn run(listeners: &[Listener], advertised: Option<SocketAddr>) -> bool {
+    let mut probes: Vec<(Transport, SocketAddr)> = listeners
+        .iter()
+        .map(|listener| (listener.transport(), probe_target(listener)))
+        .collect();
+    // The port mapping in front of the server is checked for each transport it serves.
+    if let Some(advertised) = advertised {
+        for transport in [Transport::Udp, Transport::Tcp] {
+            if listeners
+                .iter()
+                .any(|listener| listener.transport() == transport)
+            {
+                probes.push((transport, advertised));
+            }
+        }
+    }
+
+    let mut passed = 0;
+    for (transport, target) in &probes {
+        match probe(*transport, *target) {
+            Ok(elapsed) => {
+                info!(
+                    "Self-test over {} to {} passed in {:?}",
+                    transport, target, elapsed
+                );
+                passed += 1;
+            }
+            Err(e) => error!("Self-test over {} to {} failed: {}", transport, target, e),
+        }
+    }
+    info!("{} of {} self-tests passed", passed, probes.len());
+
+    passed == probes.len()
+}
+
+/// Builds the query sent by the probes
+fn probe_query() -> DnsPacket {
+    let mut packet = DnsPacket::new();
+    packet.header.id = rand::thread_rng().gen();
+    packet.header.recursion_desired = true;
+    packet
+        .questions
+        .push(DnsQuestion::new(PROBE_NAME.to_string(), QueryType::SOA));
+    packet
+}
+
+fn exchange_udp(request: &DnsPacket, target: SocketAddr) -> Result<DnsPacket, SelftestError> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.set_read_timeout(Some(PROBE_TIMEOUT))?;
+    socket.connect(target)?;
+    socket.send(&request.to_bytes()?)?;
+
+    let mut response = vec![0; MAX_SIZE];
+    let len = socket.recv(&mut response)?;
+    Ok(DnsPacket::parse(&response[..len])?)
+}
+
+fn exchange_tcp(request: &DnsPacket, target: SocketAddr) -> Result<DnsPacket, SelftestError> {
+    let mut stream = TcpStream::connect_timeout(&target, PROBE_TIMEOUT)?;
+    stream.set_read_timeout(Some(PROBE_TIMEOUT))?;
+    stream.set_write_timeout(Some(PROBE_TIMEOUT))?;
+
+    let request = request.to_bytes()?;
+    stream.write_all(&(request.len() as u16).to_be_bytes())?;
+    stream.write_all(&request)?;
+
+    let mut len = [0; 2];
+    stream.read_exact(&mut len)?;
+    let mut response = vec![0; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut response)?;
+    Ok(DnsPacket::parse(&response)?)
+}
+
+/// Checks that a response answers the probe: same ID and question, and the SOA record of
+/// the probed zone
+fn check_response(request: &DnsPacket, response: &DnsPacket) -> Result<(), SelftestError> {
+    let unexpected = |reason: String| Err(SelftestError::Unexpected(reason));
+
+    if !response.header.response || response.header.id != request.header.id {
+        return unexpected(format!("not a response to query {}", request.header.id));
+    }
+    if response.questions != request.questions {
+        return unexpected("question not echoed".to_string());
+    }
+    if response.header.rescode != ResultCode::NOERROR {
+        return unexpected(format!("answered {:?}", response.header.rescode));
+    }
+    if !response
+        .answers
+        .iter()
+        .any(|record| matches!(record, DnsRecord::SOA { domain, .. } if domain == PROBE_NAME))
+    {
+        return unexpected(format!("no SOA record for {}", PROBE_NAME));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reverse;
+
+    #[test]
+    fn accepts_the_local_answer_only() {
+        let request = probe_query();
+        let mut response = reverse::lookup(PROBE_NAME, QueryType::SOA).unwrap();
+        response.header.id = request.header.id;
+        response.header.response = true;
+        response.questions = request.questions.clone();
+        assert!(check_response(&request, &response).is_ok());
+
+        response.header.rescode = ResultCode::SERVFAIL;
+        assert!(check_response(&request, &response).is_err());
+
+        response.header.rescode = ResultCode::NOERROR;
+        response.header.id = request.header.id.wrapping_add(1);
+        assert!(check_response(&request, &response).is_err());
+    }
+}
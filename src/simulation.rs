@@ -0,0 +1,195 @@
+use std::io;
+use std::net::{Ipv4Addr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use rand::Rng;
+
+use crate::buffer::MAX_SIZE;
+use crate::packet::DnsPacket;
+use crate::question::QueryType;
+use crate::record::DnsRecord;
+use crate::resultcode::ResultCode;
+use crate::zone::is_subdomain;
+
+/// TTL of the records served by the simulated servers
+const TTL: u32 = 300;
+
+/// Network conditions applied to every query sent to a simulated server
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Conditions {
+    /// Time before a server answers
+    pub latency: Duration,
+    /// Ratio of queries (between 0 and 1) a server drops without answering
+    pub loss: f64,
+}
+
+/// A zone served by a simulated server: it delegates some of its subdomains to other
+/// servers, and may answer every other name under it with the same address
+#[derive(Clone, Debug)]
+pub struct SimulatedZone {
+    pub origin: String,
+    /// Subdomains delegated to another server: the delegated zone, and the name and
+    /// address of its server
+    pub delegations: Vec<(String, String, Ipv4Addr)>,
+    /// Address of the A records of all the names in the zone, if it answers them
+    pub answer: Option<Ipv4Addr>,
+}
+
+impl SimulatedZone {
+    /// Builds the response of the server to a query
+    fn respond(&self, request: &DnsPacket) -> DnsPacket {
+        let mut response = DnsPacket::new();
+        response.header.id = request.header.id;
+        response.header.response = true;
+
+        let Some(question) = request.questions.first() else {
+            response.header.rescode = ResultCode::FORMERR;
+            return response;
+        };
+        response.questions.push(question.clone());
+
+        let delegation = self
+            .delegations
+            .iter()
+            .find(|(zone, _, _)| is_subdomain(&question.name, zone));
+        if let Some((zone, host, addr)) = delegation {
+            response.authorities.push(DnsRecord::NS {
+                domain: zone.clone(),
+                host: host.clone(),
+                ttl: TTL,
+            });
+            response.resources.push(DnsRecord::A {
+                domain: host.clone(),
+                addr: *addr,
+                ttl: TTL,
+            });
+            return response;
+        }
+
+        response.header.authoritative_answer = true;
+        match self.answer {
+            Some(addr) if is_subdomain(&question.name, &self.origin) => {
+                if question.qtype == QueryType::A {
+                    response.answers.push(DnsRecord::A {
+                        domain: question.name.clone(),
+                        addr,
+                        ttl: TTL,
+                    });
+                }
+            }
+            _ => response.header.rescode = ResultCode::NXDOMAIN,
+        }
+
+        response
+    }
+}
+
+/// The `Hierarchy` struct runs simulated authoritative servers on loopback addresses, all
+/// listening on the same port: a root server delegating `com` to a TLD server, itself
+/// delegating `example.com` to a server answering every name under it. Resolving from it
+/// (see `InfraCache::with_root`) exercises the whole recursion under controlled network
+/// conditions, to benchmark resolution without depending on the real DNS.
+/// The servers stop when the hierarchy is dropped.
+pub struct Hierarchy {
+    pub root: Ipv4Addr,
+    pub port: u16,
+    running: Arc<AtomicBool>,
+}
+
+impl Hierarchy {
+    /// Starts the servers, answering under the given network conditions
+    pub fn start(conditions: Conditions) -> io::Result<Hierarchy> {
+        let root = Ipv4Addr::new(127, 0, 0, 2);
+        let tld = Ipv4Addr::new(127, 0, 0, 3);
+        let auth = Ipv4Addr::new(127, 0, 0, 4);
+
+        let zones = [
+            (
+                root,
+                SimulatedZone {
+                    origin: String::new(),
+                    delegations: vec![("com".to_string(), "ns.tld.com".to_string(), tld)],
+                    answer: None,
+                },
+            ),
+            (
+                tld,
+                SimulatedZone {
+                    origin: "com".to_string(),
+                    delegations: vec![(
+                        "example.com".to_string(),
+                        "ns.example.com".to_string(),
+                        auth,
+                    )],
+                    answer: None,
+                },
+            ),
+            (
+                auth,
+                SimulatedZone {
+                    origin: "example.com".to_string(),
+                    delegations: Vec::new(),
+                    answer: Some(Ipv4Addr::new(192, 0, 2, 1)),
+                },
+            ),
+        ];
+
+        // The port is picked by the OS for the first server, and shared by the others.
+        let mut port = 0;
+        let mut sockets = Vec::new();
+        for (addr, zone) in zones {
+            let socket = UdpSocket::bind((addr, port))?;
+            socket.set_read_timeout(Some(Duration::from_millis(100)))?;
+            port = socket.local_addr()?.port();
+            sockets.push((socket, zone));
+        }
+
+        let running = Arc::new(AtomicBool::new(true));
+        for (socket, zone) in sockets {
+            let running = running.clone();
+            thread::spawn(move || serve(socket, zone, conditions, &running));
+        }
+
+        Ok(Hierarchy {
+            root,
+            port,
+            running,
+        })
+    }
+}
+
+impl Drop for Hierarchy {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Answers queries until the hierarchy stops, each after the simulated latency
+fn serve(socket: UdpSocket, zone: SimulatedZone, conditions: Conditions, running: &AtomicBool) {
+    let socket = Arc::new(socket);
+    let zone = Arc::new(zone);
+    let mut buf = vec![0; MAX_SIZE];
+
+    while running.load(Ordering::Relaxed) {
+        let Ok((len, src)) = socket.recv_from(&mut buf) else {
+            continue;
+        };
+        if rand::thread_rng().gen_bool(conditions.loss.clamp(0.0, 1.0)) {
+            continue;
+        }
+        let Ok(request) = DnsPacket::parse(&buf[..len]) else {
+            continue;
+        };
+
+        let (socket, zone) = (socket.clone(), zone.clone());
+        thread::spawn(move || {
+            thread::sleep(conditions.latency);
+            if let Ok(response) = zone.respond(&request).to_bytes() {
+                let _ = socket.send_to(&response, src);
+            }
+        });
+    }
+}
@@ -0,0 +1,408 @@
+//! When the server is bound to the unspecified address of a host with several addresses,
+//! the kernel picks the source address of each reply on its own, which might not be the
+//! address the query was sent to. Clients drop such replies, so on Linux the destination
+//! address of each query is learned via `IP_PKTINFO` and used as the source of its reply.
+
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::thread;
+use std::time::Duration;
+
+use log::{info, warn};
+
+/// Number of transient errors in a row before the receiving loops back off anyway
+const TRANSIENT_ERRORS_BEFORE_BACKOFF: u32 = 100;
+/// First delay of the backoff, doubled with each error until it reaches the maximum
+const MIN_BACKOFF: Duration = Duration::from_millis(10);
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+/// Sizes of the kernel buffers of a socket, in bytes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BufferSizes {
+    pub recv: usize,
+    pub send: usize,
+}
+
+impl Default for BufferSizes {
+    /// Larger than the usual OS defaults (around 200 KiB), so that bursts of queries aren't
+    /// dropped by the kernel while the workers are busy
+    fn default() -> BufferSizes {
+        BufferSizes {
+            recv: 4 * 1024 * 1024,
+            send: 1024 * 1024,
+        }
+    }
+}
+
+/// What an error of a listening socket means for the loop receiving from it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// The socket still works, e.g. an ICMP port unreachable reported by a previous send,
+    /// or an interrupted call: receiving again right away is fine
+    Transient,
+    /// The process or the system ran out of descriptors or memory: receiving again only
+    /// makes sense after a while
+    Exhausted,
+    /// The socket itself is broken and must be bound again
+    Fatal,
+}
+
+impl ErrorClass {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorClass::Transient => "transient",
+            ErrorClass::Exhausted => "exhausted",
+            ErrorClass::Fatal => "fatal",
+        }
+    }
+}
+
+/// Tells what an error returned when receiving from a listening socket means
+pub fn classify(error: &io::Error) -> ErrorClass {
+    match error.kind() {
+        io::ErrorKind::ConnectionRefused
+        | io::ErrorKind::ConnectionReset
+        | io::ErrorKind::ConnectionAborted
+        | io::ErrorKind::Interrupted
+        | io::ErrorKind::WouldBlock
+        | io::ErrorKind::TimedOut
+        | io::ErrorKind::HostUnreachable
+        | io::ErrorKind::NetworkUnreachable => return ErrorClass::Transient,
+        io::ErrorKind::OutOfMemory => return ErrorClass::Exhausted,
+        _ => {}
+    }
+
+    // Linux also reports network errors pending on the new connection when accepting one.
+    #[cfg(unix)]
+    match error.raw_os_error() {
+        Some(libc::EPROTO | libc::ENETDOWN | libc::EHOSTDOWN) => return ErrorClass::Transient,
+        Some(libc::EMFILE | libc::ENFILE | libc::ENOBUFS | libc::ENOMEM) => {
+            return ErrorClass::Exhausted
+        }
+        _ => {}
+    }
+
+    ErrorClass::Fatal
+}
+
+/// The `Backoff` struct spaces out the attempts of a receiving loop hitting errors in a row,
+/// so that it can't spin on a persistent error. Transient errors are retried right away,
+/// unless there are too many of them in a row; the others wait for a delay doubling with
+/// each error, from 10 ms up to 5 seconds.
+#[derive(Debug, Default)]
+pub struct Backoff {
+    /// Number of errors since the last success
+    errors: u32,
+    /// Number of errors that were waited for since the last success
+    delayed: u32,
+}
+
+impl Backoff {
+    pub fn new() -> Backoff {
+        Backoff::default()
+    }
+
+    /// Records a success, resetting the delay
+    pub fn reset(&mut self) {
+        *self = Backoff::default();
+    }
+
+    /// Records an error, and returns how long to wait before trying again
+    pub fn delay(&mut self, class: ErrorClass) -> Duration {
+        self.errors = self.errors.saturating_add(1);
+        if class == ErrorClass::Transient && self.errors <= TRANSIENT_ERRORS_BEFORE_BACKOFF {
+            return Duration::ZERO;
+        }
+
+        let delay = MIN_BACKOFF.saturating_mul(1 << self.delayed.min(16));
+        self.delayed += 1;
+        delay.min(MAX_BACKOFF)
+    }
+}
+
+/// Binds a broken listening socket again, retrying with the backoff until it works
+pub fn rebind<T>(transport: &str, bind: impl Fn() -> io::Result<T>, backoff: &mut Backoff) -> T {
+    loop {
+        match bind() {
+            Ok(socket) => {
+                info!("{} socket bound again", transport);
+                return socket;
+            }
+            Err(e) => {
+                let delay = backoff.delay(ErrorClass::Fatal);
+                warn!(
+                    "Failed to bind the {} socket again, retrying in {:?}: {}",
+                    transport, delay, e
+                );
+                thread::sleep(delay);
+            }
+        }
+    }
+}
+
+/// Asks the kernel for the given buffer sizes, and returns the sizes it actually uses: it
+/// caps them (see `net.core.rmem_max` and `net.core.wmem_max` on Linux), and Linux doubles
+/// them to account for its bookkeeping.
+#[cfg(unix)]
+pub fn set_buffer_sizes(
+    socket: &impl std::os::fd::AsRawFd,
+    sizes: BufferSizes,
+) -> io::Result<BufferSizes> {
+    let fd = socket.as_raw_fd();
+    set_option(fd, libc::SO_RCVBUF, sizes.recv)?;
+    set_option(fd, libc::SO_SNDBUF, sizes.send)?;
+
+    Ok(BufferSizes {
+        recv: get_option(fd, libc::SO_RCVBUF)?,
+        send: get_option(fd, libc::SO_SNDBUF)?,
+    })
+}
+
+/// Sets a socket option whose value is an integer
+#[cfg(unix)]
+fn set_option(fd: libc::c_int, option: libc::c_int, value: usize) -> io::Result<()> {
+    let value = value.min(libc::c_int::MAX as usize) as libc::c_int;
+    // SAFETY: the option value points to a valid `c_int` of the given size.
+    let res = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            option,
+            (&value as *const libc::c_int).cast(),
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Reads a socket option whose value is an integer
+#[cfg(unix)]
+fn get_option(fd: libc::c_int, option: libc::c_int) -> io::Result<usize> {
+    let mut value: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    // SAFETY: the option value points to a valid `c_int`, whose size is given in `len`.
+    let res = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            option,
+            (&mut value as *mut libc::c_int).cast(),
+            &mut len,
+        )
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(value.max(0) as usize)
+}
+
+/// Asks the kernel to report the destination address of each received datagram
+#[cfg(target_os = "linux")]
+pub fn enable_pktinfo(socket: &UdpSocket) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let on: libc::c_int = 1;
+    // SAFETY: the option value points to a valid `c_int` of the given size.
+    let res = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_IP,
+            libc::IP_PKTINFO,
+            (&on as *const libc::c_int).cast(),
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Receives a datagram, returning its length, its source and the local address it was sent to
+#[cfg(target_os = "linux")]
+pub fn recv_from(
+    socket: &UdpSocket,
+    buf: &mut [u8],
+) -> io::Result<(usize, SocketAddr, Option<Ipv4Addr>)> {
+    use std::mem::{size_of, size_of_val, zeroed};
+    use std::net::SocketAddrV4;
+    use std::os::fd::AsRawFd;
+
+    // SAFETY: all the pointers handed to `recvmsg` point to live buffers of the given sizes,
+    // and control messages are only read within the bounds reported by the kernel.
+    unsafe {
+        let mut addr: libc::sockaddr_in = zeroed();
+        let mut iov = libc::iovec {
+            iov_base: buf.as_mut_ptr().cast(),
+            iov_len: buf.len(),
+        };
+        // Control messages must be aligned like `cmsghdr`, hence the u64 array.
+        let mut control = [0u64; 8];
+
+        let mut msg: libc::msghdr = zeroed();
+        msg.msg_name = (&mut addr as *mut libc::sockaddr_in).cast();
+        msg.msg_namelen = size_of::<libc::sockaddr_in>() as libc::socklen_t;
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr().cast();
+        msg.msg_controllen = size_of_val(&control) as _;
+
+        let len = libc::recvmsg(socket.as_raw_fd(), &mut msg, 0);
+        if len < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut dst = None;
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::IPPROTO_IP && (*cmsg).cmsg_type == libc::IP_PKTINFO {
+                let info: libc::in_pktinfo = std::ptr::read_unaligned(libc::CMSG_DATA(cmsg).cast());
+                dst = Some(Ipv4Addr::from(u32::from_be(info.ipi_addr.s_addr)));
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+
+        let src = SocketAddrV4::new(
+            Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr)),
+            u16::from_be(addr.sin_port),
+        );
+
+        Ok((len as usize, SocketAddr::V4(src), dst))
+    }
+}
+
+/// Sends a datagram, using `src` as its source address when given
+#[cfg(target_os = "linux")]
+pub fn send_to(
+    socket: &UdpSocket,
+    buf: &[u8],
+    dst: SocketAddr,
+    src: Option<Ipv4Addr>,
+) -> io::Result<usize> {
+    use std::mem::{size_of, size_of_val, zeroed};
+    use std::os::fd::AsRawFd;
+
+    let (SocketAddr::V4(dst), Some(src)) = (dst, src) else {
+        return socket.send_to(buf, dst);
+    };
+
+    // SAFETY: all the pointers handed to `sendmsg` point to live buffers of the given sizes,
+    // and the control buffer is big enough for the single `in_pktinfo` message written to it.
+    unsafe {
+        let mut addr: libc::sockaddr_in = zeroed();
+        addr.sin_family = libc::AF_INET as libc::sa_family_t;
+        addr.sin_port = dst.port().to_be();
+        addr.sin_addr.s_addr = u32::from(*dst.ip()).to_be();
+
+        let mut iov = libc::iovec {
+            iov_base: buf.as_ptr() as *mut libc::c_void,
+            iov_len: buf.len(),
+        };
+        let mut control = [0u64; 8];
+        let info_len = size_of::<libc::in_pktinfo>() as u32;
+
+        let mut msg: libc::msghdr = zeroed();
+        msg.msg_name = (&mut addr as *mut libc::sockaddr_in).cast();
+        msg.msg_namelen = size_of::<libc::sockaddr_in>() as libc::socklen_t;
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr().cast();
+        msg.msg_controllen = (libc::CMSG_SPACE(info_len) as usize).min(size_of_val(&control)) as _;
+
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::IPPROTO_IP;
+        (*cmsg).cmsg_type = libc::IP_PKTINFO;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(info_len) as _;
+
+        let mut info: libc::in_pktinfo = zeroed();
+        info.ipi_spec_dst.s_addr = u32::from(src).to_be();
+        std::ptr::write_unaligned(libc::CMSG_DATA(cmsg).cast(), info);
+
+        let len = libc::sendmsg(socket.as_raw_fd(), &msg, 0);
+        if len < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(len as usize)
+    }
+}
+
+/// Buffer sizes can't be set on this platform, the OS defaults apply.
+#[cfg(not(unix))]
+pub fn set_buffer_sizes<T>(_socket: &T, _sizes: BufferSizes) -> io::Result<BufferSizes> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// `IP_PKTINFO` is Linux specific, elsewhere the kernel picks the source address.
+#[cfg(not(target_os = "linux"))]
+pub fn enable_pktinfo(_socket: &UdpSocket) -> io::Result<()> {
+    Ok(())
+}
+
+/// Receives a datagram, the local address it was sent to is unknown on this platform.
+#[cfg(not(target_os = "linux"))]
+pub fn recv_from(
+    socket: &UdpSocket,
+    buf: &mut [u8],
+) -> io::Result<(usize, SocketAddr, Option<Ipv4Addr>)> {
+    let (len, src) = socket.recv_from(buf)?;
+
+    Ok((len, src, None))
+}
+
+/// Sends a datagram, letting the kernel pick its source address on this platform.
+#[cfg(not(target_os = "linux"))]
+pub fn send_to(
+    socket: &UdpSocket,
+    buf: &[u8],
+    dst: SocketAddr,
+    _src: Option<Ipv4Addr>,
+) -> io::Result<usize> {
+    socket.send_to(buf, dst)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_errors() {
+        let refused = io::Error::from(io::ErrorKind::ConnectionRefused);
+        assert_eq!(classify(&refused), ErrorClass::Transient);
+        let bad_fd = io::Error::from_raw_os_error(libc::EBADF);
+        assert_eq!(classify(&bad_fd), ErrorClass::Fatal);
+        let too_many_files = io::Error::from_raw_os_error(libc::EMFILE);
+        assert_eq!(classify(&too_many_files), ErrorClass::Exhausted);
+    }
+
+    #[test]
+    fn backs_off_exponentially() {
+        let mut backoff = Backoff::new();
+        assert_eq!(backoff.delay(ErrorClass::Transient), Duration::ZERO);
+
+        let delays: Vec<_> = (0..3).map(|_| backoff.delay(ErrorClass::Fatal)).collect();
+        assert_eq!(delays, [MIN_BACKOFF, MIN_BACKOFF * 2, MIN_BACKOFF * 4]);
+        for _ in 0..20 {
+            backoff.delay(ErrorClass::Exhausted);
+        }
+        assert_eq!(backoff.delay(ErrorClass::Exhausted), MAX_BACKOFF);
+
+        backoff.reset();
+        assert_eq!(backoff.delay(ErrorClass::Fatal), MIN_BACKOFF);
+    }
+
+    #[test]
+    fn backs_off_on_persistent_transient_errors() {
+        let mut backoff = Backoff::new();
+        for _ in 0..TRANSIENT_ERRORS_BEFORE_BACKOFF {
+            assert_eq!(backoff.delay(ErrorClass::Transient), Duration::ZERO);
+        }
+        assert_eq!(backoff.delay(ErrorClass::Transient), MIN_BACKOFF);
+    }
+}
@@ -0,0 +1,283 @@
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+/// Port of plain DNS servers when a stamp doesn't give one
+const DNS_PORT: u16 = 53;
+/// Port of DNS over TLS servers when a stamp doesn't give one
+const DOT_PORT: u16 = 853;
+/// Port of DNS over HTTPS servers when a stamp doesn't give one
+const DOH_PORT: u16 = 443;
+
+/// `StampError` is an enum that represents the ways in which a DNS stamp can be malformed
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum StampError {
+    #[error("DNS stamps start with sdns://")]
+    MissingScheme,
+    #[error("Invalid base64 in DNS stamp")]
+    InvalidBase64,
+    #[error("DNS stamp ends before its {0}")]
+    Truncated(&'static str),
+    #[error("Invalid address \"{0}\" in DNS stamp")]
+    InvalidAddress(String),
+    #[error("Invalid {0} in DNS stamp")]
+    InvalidString(&'static str),
+    #[error("Unsupported protocol {0:#04x} in DNS stamp")]
+    UnsupportedProtocol(u8),
+}
+
+/// Properties a server announces in its stamp
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Properties {
+    /// The server validates DNSSEC
+    pub dnssec: bool,
+    /// The server doesn't keep logs of the queries
+    pub no_logs: bool,
+    /// The server doesn't block any domain
+    pub no_filter: bool,
+}
+
+impl Properties {
+    fn from_bits(bits: u64) -> Properties {
+        Properties {
+            dnssec: bits & 1 != 0,
+            no_logs: bits & 2 != 0,
+            no_filter: bits & 4 != 0,
+        }
+    }
+}
+
+/// A DNS stamp (`sdns://...`), which packs everything needed to reach an upstream server into
+/// a single string: see https://dnscrypt.info/stamps-specifications
+///
+/// Only the transports that make sense for a forwarder are supported: plain DNS, DNS over TLS
+/// and DNS over HTTPS. Addresses are optional for the encrypted transports, whose hostname
+/// can be resolved instead.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Stamp {
+    Plain {
+        props: Properties,
+        addr: SocketAddr,
+    },
+    Tls {
+        props: Properties,
+        addr: Option<SocketAddr>,
+        /// SHA-256 digests of certificates of the chain, one of which must match
+        hashes: Vec<Vec<u8>>,
+        hostname: String,
+    },
+    Https {
+        props: Properties,
+        addr: Option<SocketAddr>,
+        /// SHA-256 digests of certificates of the chain, one of which must match
+        hashes: Vec<Vec<u8>>,
+        hostname: String,
+        path: String,
+    },
+}
+
+impl FromStr for Stamp {
+    type Err = StampError;
+
+    fn from_str(value: &str) -> Result<Stamp, StampError> {
+        let encoded = value
+            .strip_prefix("sdns://")
+            .ok_or(StampError::MissingScheme)?;
+        let bytes = decode_base64url(encoded).ok_or(StampError::InvalidBase64)?;
+        let mut reader = Reader { bytes: &bytes };
+
+        let protocol = reader.byte("protocol")?;
+        match protocol {
+            0x00 => Ok(Stamp::Plain {
+                props: reader.props()?,
+                addr: parse_addr(&reader.string("address")?, DNS_PORT)?
+                    .ok_or(StampError::Truncated("address"))?,
+            }),
+            0x02 => Ok(Stamp::Https {
+                props: reader.props()?,
+                addr: parse_addr(&reader.string("address")?, DOH_PORT)?,
+                hashes: reader.hashes()?,
+                hostname: reader.string("hostname")?,
+                path: reader.string("path")?,
+            }),
+            0x03 => Ok(Stamp::Tls {
+                props: reader.props()?,
+                addr: parse_addr(&reader.string("address")?, DOT_PORT)?,
+                hashes: reader.hashes()?,
+                hostname: reader.string("hostname")?,
+            }),
+            other => Err(StampError::UnsupportedProtocol(other)),
+        }
+    }
+}
+
+/// Reads the fields of a decoded stamp in order
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn take(&mut self, len: usize, field: &'static str) -> Result<&[u8], StampError> {
+        if self.bytes.len() < len {
+            return Err(StampError::Truncated(field));
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn byte(&mut self, field: &'static str) -> Result<u8, StampError> {
+        Ok(self.take(1, field)?[0])
+    }
+
+    /// Reads the properties, a little-endian 64 bits integer
+    fn props(&mut self) -> Result<Properties, StampError> {
+        let bits = self.take(8, "properties")?;
+        Ok(Properties::from_bits(u64::from_le_bytes(
+            bits.try_into().unwrap(),
+        )))
+    }
+
+    /// Reads a string prefixed with its length
+    fn string(&mut self, field: &'static str) -> Result<String, StampError> {
+        let len = self.byte(field)? as usize;
+        let bytes = self.take(len, field)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| StampError::InvalidString(field))
+    }
+
+    /// Reads a set of hashes, each prefixed with its length whose high bit is set when
+    /// more hashes follow. Empty hashes are padding, and are skipped.
+    fn hashes(&mut self) -> Result<Vec<Vec<u8>>, StampError> {
+        let mut hashes = Vec::new();
+        loop {
+            let prefix = self.byte("hashes")?;
+            let hash = self.take((prefix & 0x7f) as usize, "hashes")?;
+            if !hash.is_empty() {
+                hashes.push(hash.to_vec());
+            }
+            if prefix & 0x80 == 0 {
+                return Ok(hashes);
+            }
+        }
+    }
+}
+
+/// Parses the address of a stamp, an IP address with an optional port (IPv6 addresses in
+/// brackets). Returns `None` for an empty address.
+fn parse_addr(value: &str, default_port: u16) -> Result<Option<SocketAddr>, StampError> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+    if let Ok(addr) = value.parse::<SocketAddr>() {
+        return Ok(Some(addr));
+    }
+
+    let ip = value.trim_start_matches('[').trim_end_matches(']');
+    ip.parse::<IpAddr>()
+        .map(|ip| Some(SocketAddr::new(ip, default_port)))
+        .map_err(|_| StampError::InvalidAddress(value.to_string()))
+}
+
+/// Decodes URL-safe base64 without padding, as used by stamps
+fn decode_base64url(encoded: &str) -> Option<Vec<u8>> {
+    let value = |c: u8| match c {
+        b'A'..=b'Z' => Some(c - b'A'),
+        b'a'..=b'z' => Some(c - b'a' + 26),
+        b'0'..=b'9' => Some(c - b'0' + 52),
+        b'-' => Some(62),
+        b'_' => Some(63),
+        _ => None,
+    };
+
+    let mut bytes = Vec::with_capacity(encoded.len() * 3 / 4);
+    let mut bits = 0u32;
+    let mut count = 0;
+    for c in encoded.trim_end_matches('=').bytes() {
+        bits = (bits << 6) | u32::from(value(c)?);
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            bytes.push((bits >> count) as u8);
+        }
+    }
+
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_doh_stamp() {
+        let stamp: Stamp =
+            "sdns://AgcAAAAAAAAABzEuMC4wLjEAEmRucy5jbG91ZGZsYXJlLmNvbQovZG5zLXF1ZXJ5"
+                .parse()
+                .unwrap();
+
+        assert_eq!(
+            stamp,
+            Stamp::Https {
+                props: Properties {
+                    dnssec: true,
+                    no_logs: true,
+                    no_filter: true,
+                },
+                addr: Some("1.0.0.1:443".parse().unwrap()),
+                hashes: Vec::new(),
+                hostname: "dns.cloudflare.com".to_string(),
+                path: "/dns-query".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn parses_dot_stamp_with_several_hashes() {
+        let stamp: Stamp = "sdns://AwAAAAAAAAAABzkuOS45LjmBqgG7DWRucy5xdWFkOS5uZXQ"
+            .parse()
+            .unwrap();
+
+        assert_eq!(
+            stamp,
+            Stamp::Tls {
+                props: Properties::default(),
+                addr: Some("9.9.9.9:853".parse().unwrap()),
+                hashes: vec![vec![0xaa], vec![0xbb]],
+                hostname: "dns.quad9.net".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn parses_plain_stamp_with_ipv6_address() {
+        let stamp: Stamp = "sdns://AAEAAAAAAAAAElsyMDAxOmRiODo6MV06NTM1Mw"
+            .parse()
+            .unwrap();
+
+        assert_eq!(
+            stamp,
+            Stamp::Plain {
+                props: Properties {
+                    dnssec: true,
+                    ..Properties::default()
+                },
+                addr: "[2001:db8::1]:5353".parse().unwrap(),
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_stamps() {
+        assert_eq!(
+            "https://example.com".parse::<Stamp>(),
+            Err(StampError::MissingScheme)
+        );
+        assert_eq!(
+            "sdns://AQ!".parse::<Stamp>(),
+            Err(StampError::InvalidBase64)
+        );
+        assert_eq!(
+            "sdns://AAEAAAAAAAAA".parse::<Stamp>(),
+            Err(StampError::Truncated("address"))
+        );
+    }
+}
@@ -0,0 +1,544 @@
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::memory::MemoryUsage;
+use crate::opcode::Opcode;
+use crate::profiling::{self, BUCKET_BOUNDS};
+use crate::resultcode::ResultCode;
+use crate::socket::ErrorClass;
+use crate::topn::TopN;
+
+/// Bucket of the queries answered by recursion from the root servers
+pub const RECURSIVE_BUCKET: &str = ".";
+
+/// How a query was answered
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Resolution {
+    /// From the cache, without sending any upstream query
+    Cache,
+    /// From a hosted zone or the hosts file
+    Local,
+    /// By a response policy or the allowlist
+    Policy,
+    /// By recursion from the root servers
+    Iterative,
+    /// From what the server already knew, for a query with RD=0
+    NonRecursive,
+    /// With SERVFAIL, for a question that failed to resolve moments ago
+    HeldDown,
+    /// Refused or dropped, as the client had too many resolutions in progress
+    Limited,
+    /// With NXDOMAIN, for a name under a suffix hit by a random subdomain attack
+    Mitigated,
+    /// With an error, without looking the name up (e.g. malformed or unsupported queries)
+    Rejected,
+}
+
+impl fmt::Display for Resolution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Resolution::Cache => "cache",
+            Resolution::Local => "local",
+            Resolution::Policy => "policy",
+            Resolution::Iterative => "iterative",
+            Resolution::NonRecursive => "non-recursive",
+            Resolution::HeldDown => "held-down",
+            Resolution::Limited => "limited",
+            Resolution::Mitigated => "mitigated",
+            Resolution::Rejected => "rejected",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Number of names shown in the top queried and blocked names of the table
+const TABLE_TOP_NAMES: usize = 10;
+
+/// Name, description and accessor of a metric derived from the counters
+type Metric = (&'static str, &'static str, fn(&Counters) -> String);
+
+/// Counters kept for all the queries, and for each bucket of queries
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Counters {
+    pub queries: u64,
+    pub nxdomain: u64,
+    pub servfail: u64,
+    pub latency: Duration,
+}
+
+impl Counters {
+    fn record(&mut self, rescode: ResultCode, latency: Duration) {
+        self.queries += 1;
+        self.latency += latency;
+        match rescode {
+            ResultCode::NXDOMAIN => self.nxdomain += 1,
+            ResultCode::SERVFAIL => self.servfail += 1,
+            _ => {}
+        }
+    }
+
+    /// Ratio of queries answered with NXDOMAIN
+    pub fn nxdomain_rate(&self) -> f64 {
+        if self.queries == 0 {
+            return 0.0;
+        }
+        self.nxdomain as f64 / self.queries as f64
+    }
+
+    /// Average time taken to answer a query
+    pub fn average_latency(&self) -> Duration {
+        match u32::try_from(self.queries) {
+            Ok(0) => Duration::ZERO,
+            Ok(queries) => self.latency / queries,
+            Err(_) => self.latency.div_f64(self.queries as f64),
+        }
+    }
+}
+
+/// The `Stats` struct collects query statistics, both globally and bucketed
+/// by the hosted zone that answered the query or by the way it was resolved.
+/// They are also counted by resolution path (cache, local data, recursion...).
+#[derive(Default)]
+pub struct Stats {
+    global: Mutex<Counters>,
+    buckets: Mutex<BTreeMap<String, Counters>>,
+    resolutions: Mutex<BTreeMap<Resolution, Counters>>,
+    /// Number of queries waiting to be answered
+    queue_depth: AtomicUsize,
+    /// Number of queries dropped because too many were waiting
+    queue_drops: AtomicU64,
+    /// Number of client retries answered by the resolution of the original query
+    retries: AtomicU64,
+    /// Number of open TCP connections
+    tcp_connections: AtomicUsize,
+    /// Last measured memory usage
+    memory: Mutex<MemoryUsage>,
+    /// Number of errors of the listening sockets, by class
+    socket_errors: Mutex<BTreeMap<&'static str, u64>>,
+    /// Number of times a broken listening socket was bound again
+    socket_rebinds: AtomicU64,
+    /// Number of messages received, by opcode
+    opcodes: Mutex<BTreeMap<Opcode, u64>>,
+    /// Most queried names, approximately counted
+    queried: Mutex<TopN>,
+    /// Most blocked names by response policies, approximately counted
+    blocked: Mutex<TopN>,
+}
+
+impl Stats {
+    pub fn new() -> Stats {
+        Stats::default()
+    }
+
+    /// Records the outcome of a query in the global counters, in its bucket and in its
+    /// resolution path
+    pub fn record(
+        &self,
+        bucket: &str,
+        resolution: Resolution,
+        rescode: ResultCode,
+        latency: Duration,
+    ) {
+        self.global.lock().unwrap().record(rescode, latency);
+        self.resolutions
+            .lock()
+            .unwrap()
+            .entry(resolution)
+            .or_default()
+            .record(rescode, latency);
+        self.buckets
+            .lock()
+            .unwrap()
+            .entry(bucket.to_string())
+            .or_default()
+            .record(rescode, latency);
+    }
+
+    /// Records a query for a name, as formatted for the logs
+    pub fn record_name(&self, name: &str) {
+        self.queried.lock().unwrap().record(name);
+    }
+
+    /// Records a query for a name, as formatted for the logs, blocked by a response policy
+    pub fn record_blocked(&self, name: &str) {
+        self.blocked.lock().unwrap().record(name);
+    }
+
+    /// Returns the `n` most queried names with their approximate counts
+    pub fn top_queried(&self, n: usize) -> Vec<(String, u64)> {
+        self.queried.lock().unwrap().top(n)
+    }
+
+    /// Returns the `n` most blocked names with their approximate counts
+    pub fn top_blocked(&self, n: usize) -> Vec<(String, u64)> {
+        self.blocked.lock().unwrap().top(n)
+    }
+
+    /// Records the number of queries waiting to be answered
+    pub fn set_queue_depth(&self, depth: usize) {
+        self.queue_depth.store(depth, Ordering::Relaxed);
+    }
+
+    /// Records a query dropped because too many were waiting
+    pub fn record_queue_drop(&self) {
+        self.queue_drops.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the number of queries waiting to be answered
+    pub fn queue_depth(&self) -> usize {
+        self.queue_depth.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of queries dropped because too many were waiting
+    pub fn queue_drops(&self) -> u64 {
+        self.queue_drops.load(Ordering::Relaxed)
+    }
+
+    /// Records a client retry attached to the resolution of the original query
+    pub fn record_retry(&self) {
+        self.retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the number of client retries attached to the resolution of the original query
+    pub fn retries(&self) -> u64 {
+        self.retries.load(Ordering::Relaxed)
+    }
+
+    /// Records an error of a listening socket
+    pub fn record_socket_error(&self, class: ErrorClass) {
+        *self
+            .socket_errors
+            .lock()
+            .unwrap()
+            .entry(class.as_str())
+            .or_default() += 1;
+    }
+
+    /// Returns the number of errors of the listening sockets, by class
+    pub fn socket_errors(&self) -> BTreeMap<&'static str, u64> {
+        self.socket_errors.lock().unwrap().clone()
+    }
+
+    /// Records a broken listening socket bound again
+    pub fn record_socket_rebind(&self) {
+        self.socket_rebinds.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the number of times a broken listening socket was bound again
+    pub fn socket_rebinds(&self) -> u64 {
+        self.socket_rebinds.load(Ordering::Relaxed)
+    }
+
+    /// Records a message received, by its opcode
+    pub fn record_opcode(&self, opcode: Opcode) {
+        *self.opcodes.lock().unwrap().entry(opcode).or_default() += 1;
+    }
+
+    /// Returns the number of messages received, by opcode
+    pub fn opcodes(&self) -> BTreeMap<Opcode, u64> {
+        self.opcodes.lock().unwrap().clone()
+    }
+
+    /// Records the number of open TCP connections
+    pub fn set_tcp_connections(&self, connections: usize) {
+        self.tcp_connections.store(connections, Ordering::Relaxed);
+    }
+
+    /// Returns the number of open TCP connections
+    pub fn tcp_connections(&self) -> usize {
+        self.tcp_connections.load(Ordering::Relaxed)
+    }
+
+    /// Records the memory usage of the server
+    pub fn set_memory_usage(&self, usage: MemoryUsage) {
+        *self.memory.lock().unwrap() = usage;
+    }
+
+    /// Returns the last measured memory usage of the server
+    pub fn memory_usage(&self) -> MemoryUsage {
+        *self.memory.lock().unwrap()
+    }
+
+    /// Returns a copy of the global counters
+    pub fn global(&self) -> Counters {
+        *self.global.lock().unwrap()
+    }
+
+    /// Returns a copy of the counters of each bucket
+    pub fn buckets(&self) -> BTreeMap<String, Counters> {
+        self.buckets.lock().unwrap().clone()
+    }
+
+    /// Returns a copy of the counters of each resolution path
+    pub fn resolutions(&self) -> BTreeMap<Resolution, Counters> {
+        self.resolutions.lock().unwrap().clone()
+    }
+
+    /// Renders the statistics in the Prometheus text exposition format:
+    /// see https://prometheus.io/docs/instrumenting/exposition_formats/
+    pub fn to_prometheus(&self) -> String {
+        let global = self.global();
+        let buckets = self.buckets();
+        let resolutions = self.resolutions();
+        let mut out = String::new();
+
+        let metrics: [Metric; 4] = [
+            ("queries_total", "Queries received", |c| {
+                c.queries.to_string()
+            }),
+            ("nxdomain_total", "Queries answered with NXDOMAIN", |c| {
+                c.nxdomain.to_string()
+            }),
+            ("servfail_total", "Queries answered with SERVFAIL", |c| {
+                c.servfail.to_string()
+            }),
+            (
+                "query_duration_seconds_sum",
+                "Time spent answering queries",
+                |c| c.latency.as_secs_f64().to_string(),
+            ),
+        ];
+
+        for (name, help, value) in metrics {
+            let _ = writeln!(out, "# HELP vodo_{} {}.", name, help);
+            let _ = writeln!(out, "# TYPE vodo_{} counter", name);
+            let _ = writeln!(out, "vodo_{} {}", name, value(&global));
+
+            let _ = writeln!(out, "# HELP vodo_zone_{} {}, by zone.", name, help);
+            let _ = writeln!(out, "# TYPE vodo_zone_{} counter", name);
+            for (zone, counters) in &buckets {
+                let _ = writeln!(
+                    out,
+                    "vodo_zone_{}{{zone=\"{}\"}} {}",
+                    name,
+                    zone,
+                    value(counters)
+                );
+            }
+
+            let _ = writeln!(
+                out,
+                "# HELP vodo_resolution_{} {}, by resolution path.",
+                name, help
+            );
+            let _ = writeln!(out, "# TYPE vodo_resolution_{} counter", name);
+            for (resolution, counters) in &resolutions {
+                let _ = writeln!(
+                    out,
+                    "vodo_resolution_{}{{resolution=\"{}\"}} {}",
+                    name,
+                    resolution,
+                    value(counters)
+                );
+            }
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP vodo_queue_depth Queries waiting to be answered."
+        );
+        let _ = writeln!(out, "# TYPE vodo_queue_depth gauge");
+        let _ = writeln!(out, "vodo_queue_depth {}", self.queue_depth());
+        let _ = writeln!(
+            out,
+            "# HELP vodo_queue_drops_total Queries dropped because too many were waiting."
+        );
+        let _ = writeln!(out, "# TYPE vodo_queue_drops_total counter");
+        let _ = writeln!(out, "vodo_queue_drops_total {}", self.queue_drops());
+        let _ = writeln!(
+            out,
+            "# HELP vodo_retries_total Client retries answered with the original query."
+        );
+        let _ = writeln!(out, "# TYPE vodo_retries_total counter");
+        let _ = writeln!(out, "vodo_retries_total {}", self.retries());
+        let _ = writeln!(out, "# HELP vodo_tcp_connections Open TCP connections.");
+        let _ = writeln!(out, "# TYPE vodo_tcp_connections gauge");
+        let _ = writeln!(out, "vodo_tcp_connections {}", self.tcp_connections());
+
+        let _ = writeln!(
+            out,
+            "# HELP vodo_socket_errors_total Errors of the listening sockets, by class."
+        );
+        let _ = writeln!(out, "# TYPE vodo_socket_errors_total counter");
+        for (class, errors) in self.socket_errors() {
+            let _ = writeln!(
+                out,
+                "vodo_socket_errors_total{{class=\"{}\"}} {}",
+                class, errors
+            );
+        }
+        let _ = writeln!(
+            out,
+            "# HELP vodo_socket_rebinds_total Broken listening sockets bound again."
+        );
+        let _ = writeln!(out, "# TYPE vodo_socket_rebinds_total counter");
+        let _ = writeln!(out, "vodo_socket_rebinds_total {}", self.socket_rebinds());
+
+        let _ = writeln!(
+            out,
+            "# HELP vodo_requests_total Messages received, by opcode."
+        );
+        let _ = writeln!(out, "# TYPE vodo_requests_total counter");
+        for (opcode, requests) in self.opcodes() {
+            let _ = writeln!(
+                out,
+                "vodo_requests_total{{opcode=\"{}\"}} {}",
+                opcode, requests
+            );
+        }
+
+        let memory = self.memory_usage();
+        let _ = writeln!(
+            out,
+            "# HELP vodo_memory_bytes Approximate memory used, by component."
+        );
+        let _ = writeln!(out, "# TYPE vodo_memory_bytes gauge");
+        for (component, bytes) in [
+            ("cache", memory.cache),
+            ("zones", memory.zones),
+            ("pending", memory.pending),
+            ("connections", memory.connections),
+        ] {
+            let _ = writeln!(
+                out,
+                "vodo_memory_bytes{{component=\"{}\"}} {}",
+                component, bytes
+            );
+        }
+
+        if profiling::is_enabled() {
+            let _ = writeln!(
+                out,
+                "# HELP vodo_stage_duration_seconds Time spent in each stage of answering queries."
+            );
+            let _ = writeln!(out, "# TYPE vodo_stage_duration_seconds histogram");
+            for (stage, snapshot) in profiling::stages() {
+                for (bound, count) in BUCKET_BOUNDS.iter().zip(snapshot.cumulative()) {
+                    let _ = writeln!(
+                        out,
+                        "vodo_stage_duration_seconds_bucket{{stage=\"{}\",le=\"{}\"}} {}",
+                        stage,
+                        *bound as f64 / 1_000_000.0,
+                        count
+                    );
+                }
+                let _ = writeln!(
+                    out,
+                    "vodo_stage_duration_seconds_bucket{{stage=\"{}\",le=\"+Inf\"}} {}",
+                    stage, snapshot.count
+                );
+                let _ = writeln!(
+                    out,
+                    "vodo_stage_duration_seconds_sum{{stage=\"{}\"}} {}",
+                    stage,
+                    snapshot.sum.as_secs_f64()
+                );
+                let _ = writeln!(
+                    out,
+                    "vodo_stage_duration_seconds_count{{stage=\"{}\"}} {}",
+                    stage, snapshot.count
+                );
+            }
+        }
+
+        out
+    }
+
+    /// Renders the statistics as a human readable table
+    pub fn to_table(&self) -> String {
+        let mut out = format!(
+            "{:<40} {:>10} {:>10} {:>12}\n",
+            "ZONE", "QUERIES", "NXDOMAIN", "AVG LATENCY"
+        );
+
+        let rows = std::iter::once(("(all)".to_string(), self.global())).chain(self.buckets());
+        for (zone, counters) in rows {
+            let _ = writeln!(
+                out,
+                "{:<40} {:>10} {:>9.1}% {:>10.1}ms",
+                zone,
+                counters.queries,
+                counters.nxdomain_rate() * 100.0,
+                counters.average_latency().as_secs_f64() * 1000.0
+            );
+        }
+        let resolutions: Vec<String> = self
+            .resolutions()
+            .iter()
+            .map(|(resolution, counters)| format!("{} {}", resolution, counters.queries))
+            .collect();
+        let _ = writeln!(out, "\nResolution: {}", resolutions.join(", "));
+        let _ = writeln!(
+            out,
+            "Queue: {} waiting, {} dropped, {} retries attached",
+            self.queue_depth(),
+            self.queue_drops(),
+            self.retries()
+        );
+        let errors: Vec<String> = self
+            .socket_errors()
+            .iter()
+            .map(|(class, errors)| format!("{} {}", class, errors))
+            .collect();
+        let _ = writeln!(
+            out,
+            "Socket errors: {} ({} rebinds)",
+            errors.join(", "),
+            self.socket_rebinds()
+        );
+        let opcodes: Vec<String> = self
+            .opcodes()
+            .iter()
+            .map(|(opcode, requests)| format!("{} {}", opcode, requests))
+            .collect();
+        let _ = writeln!(out, "Opcodes: {}", opcodes.join(", "));
+        let _ = writeln!(out, "Memory: {}", self.memory_usage());
+        if profiling::is_enabled() {
+            let stages: Vec<String> = profiling::stages()
+                .iter()
+                .map(|(stage, snapshot)| {
+                    format!(
+                        "{} {:.1}us avg",
+                        stage,
+                        snapshot.average().as_secs_f64() * 1_000_000.0
+                    )
+                })
+                .collect();
+            let _ = writeln!(out, "Stages: {}", stages.join(", "));
+        }
+
+        for (title, top) in [
+            ("Top queried", self.top_queried(TABLE_TOP_NAMES)),
+            ("Top blocked", self.top_blocked(TABLE_TOP_NAMES)),
+        ] {
+            let top: Vec<String> = top
+                .iter()
+                .map(|(name, count)| format!("{} {}", name, count))
+                .collect();
+            let _ = writeln!(out, "{}: {}", title, top.join(", "));
+        }
+
+        out
+    }
+
+    /// Renders the `n` most queried and most blocked names with their approximate counts,
+    /// one per line
+    pub fn to_top(&self, n: usize) -> String {
+        let mut out = String::new();
+        for (title, top) in [
+            ("QUERIED", self.top_queried(n)),
+            ("BLOCKED", self.top_blocked(n)),
+        ] {
+            let _ = writeln!(out, "{:<60} {:>10}", title, "COUNT");
+            for (name, count) in top {
+                let _ = writeln!(out, "{:<60} {:>10}", name, count);
+            }
+            out.push('\n');
+        }
+
+        out
+    }
+}
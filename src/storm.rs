@@ -0,0 +1,234 @@
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::warn;
+
+use crate::clock::{Clock, SystemClock};
+use crate::privacy;
+use crate::resultcode::ResultCode;
+
+/// Window over which the resolutions under each suffix are counted
+const WINDOW: Duration = Duration::from_secs(10);
+/// Time a suffix stays mitigated once a storm is detected, extended while it goes on
+const MITIGATION: Duration = Duration::from_secs(60);
+/// Resolutions per second still let through under a mitigated suffix, so that the names
+/// that do exist there keep resolving (and get cached)
+const PASSTHROUGH_PER_SECOND: u32 = 5;
+/// Number of suffixes tracked before those without recent activity are forgotten
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// Resolutions of the names under a suffix
+#[derive(Clone, Copy, Debug)]
+struct Suffix {
+    window_start: Instant,
+    resolutions: u32,
+    nxdomain: u32,
+    /// End of the mitigation, if the suffix is mitigated
+    mitigated_until: Option<Instant>,
+    /// Queries answered locally since the mitigation started or was extended
+    synthesized: u32,
+    /// Start of the current second, and the resolutions let through during it
+    passthrough: (Instant, u32),
+}
+
+/// The `StormGuard` struct detects random subdomain attacks (also known as NXDOMAIN floods
+/// or water torture): storms of queries for random labels under a single domain, which all
+/// miss the cache and are resolved by the authoritative servers of the victim. When most of
+/// the resolutions under a suffix (the name without its first label) end with NXDOMAIN,
+/// the suffix is mitigated: only a few resolutions per second are let through, and the
+/// other queries are answered NXDOMAIN locally, sparing both the server and the victim.
+pub struct StormGuard {
+    suffixes: Mutex<HashMap<String, Suffix, RandomState>>,
+    /// Ratio of NXDOMAIN answers above which a suffix is mitigated, disabled if zero
+    ratio: f64,
+    /// Resolutions in a window below which a suffix is never mitigated
+    min_resolutions: u32,
+    clock: Arc<dyn Clock>,
+}
+
+impl StormGuard {
+    pub fn new(ratio: f64, min_resolutions: u32) -> StormGuard {
+        StormGuard::with_clock(ratio, min_resolutions, Arc::new(SystemClock))
+    }
+
+    /// Creates a guard whose windows follow the given clock
+    pub fn with_clock(ratio: f64, min_resolutions: u32, clock: Arc<dyn Clock>) -> StormGuard {
+        StormGuard {
+            suffixes: Mutex::new(HashMap::with_hasher(RandomState::new())),
+            ratio,
+            min_resolutions,
+            clock,
+        }
+    }
+
+    /// Returns true if a name can be resolved, or false if it should be answered NXDOMAIN
+    /// locally as its suffix is mitigated
+    pub fn admit(&self, qname: &str) -> bool {
+        let Some(suffix) = suffix(qname) else {
+            return true;
+        };
+        let now = self.clock.now();
+        let mut suffixes = self.suffixes.lock().unwrap();
+        let Some(entry) = suffixes.get_mut(suffix) else {
+            return true;
+        };
+        let Some(until) = entry.mitigated_until else {
+            return true;
+        };
+
+        if until <= now {
+            // The mitigation goes on for as long as the storm does.
+            if entry.synthesized >= self.min_resolutions {
+                entry.mitigated_until = Some(now + MITIGATION);
+                entry.synthesized = 0;
+            } else {
+                warn!(
+                    "Query storm under {} is over, lifting the mitigation",
+                    privacy::name(suffix)
+                );
+                entry.mitigated_until = None;
+                entry.window_start = now;
+                entry.resolutions = 0;
+                entry.nxdomain = 0;
+                return true;
+            }
+        }
+
+        let (second, passed) = &mut entry.passthrough;
+        if now.duration_since(*second) >= Duration::from_secs(1) {
+            *second = now;
+            *passed = 0;
+        }
+        if *passed < PASSTHROUGH_PER_SECOND {
+            *passed += 1;
+            return true;
+        }
+
+        entry.synthesized = entry.synthesized.saturating_add(1);
+        false
+    }
+
+    /// Records how the resolution of a name ended, mitigating its suffix when most of the
+    /// recent resolutions under it ended with NXDOMAIN
+    pub fn record(&self, qname: &str, rescode: ResultCode) {
+        if self.ratio <= 0.0 {
+            return;
+        }
+        let Some(suffix) = suffix(qname) else {
+            return;
+        };
+
+        let now = self.clock.now();
+        let mut suffixes = self.suffixes.lock().unwrap();
+        if suffixes.len() >= PRUNE_THRESHOLD && !suffixes.contains_key(suffix) {
+            suffixes.retain(|_, entry| {
+                entry.mitigated_until.is_some() || now.duration_since(entry.window_start) < WINDOW
+            });
+        }
+
+        let entry = suffixes.entry(suffix.to_string()).or_insert(Suffix {
+            window_start: now,
+            resolutions: 0,
+            nxdomain: 0,
+            mitigated_until: None,
+            synthesized: 0,
+            passthrough: (now, 0),
+        });
+        if entry.mitigated_until.is_some() {
+            return;
+        }
+        if now.duration_since(entry.window_start) >= WINDOW {
+            entry.window_start = now;
+            entry.resolutions = 0;
+            entry.nxdomain = 0;
+        }
+
+        entry.resolutions += 1;
+        if rescode == ResultCode::NXDOMAIN {
+            entry.nxdomain += 1;
+        }
+        if entry.resolutions >= self.min_resolutions
+            && entry.nxdomain as f64 >= entry.resolutions as f64 * self.ratio
+        {
+            warn!(
+                "Query storm under {}: {} of {} resolutions ended with NXDOMAIN, mitigating it",
+                privacy::name(suffix),
+                entry.nxdomain,
+                entry.resolutions
+            );
+            entry.mitigated_until = Some(now + MITIGATION);
+            entry.synthesized = 0;
+            entry.passthrough = (now, 0);
+        }
+    }
+
+    /// Returns the suffixes being mitigated
+    pub fn mitigated(&self) -> Vec<String> {
+        let now = self.clock.now();
+        let mut mitigated: Vec<String> = self
+            .suffixes
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, entry)| entry.mitigated_until.is_some_and(|until| until > now))
+            .map(|(suffix, _)| suffix.clone())
+            .collect();
+        mitigated.sort();
+        mitigated
+    }
+}
+
+/// Returns the suffix a name is tracked under: the name without its first label, when that
+/// leaves at least two labels, so that top-level domains are never mitigated as a whole
+fn suffix(qname: &str) -> Option<&str> {
+    let (_, suffix) = qname.split_once('.')?;
+    suffix.contains('.').then_some(suffix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+
+    #[test]
+    fn mitigates_nxdomain_storms_under_a_suffix() {
+        let clock = Arc::new(ManualClock::new());
+        let guard = StormGuard::with_clock(0.9, 100, clock.clone());
+
+        for i in 0..99 {
+            let name = format!("{}.victim.com", i);
+            assert!(guard.admit(&name));
+            guard.record(&name, ResultCode::NXDOMAIN);
+        }
+        guard.record("www.victim.com", ResultCode::NOERROR);
+        assert_eq!(guard.mitigated(), vec!["victim.com"]);
+
+        // A few resolutions per second are still let through.
+        let admitted = (0..20)
+            .filter(|i| guard.admit(&format!("x{}.victim.com", i)))
+            .count();
+        assert_eq!(admitted, PASSTHROUGH_PER_SECOND as usize);
+        assert!(guard.admit("www.example.com"));
+
+        // The mitigation is lifted once the storm is over.
+        clock.advance(MITIGATION);
+        assert!(guard.admit("www.victim.com"));
+        assert!(guard.mitigated().is_empty());
+    }
+
+    #[test]
+    fn ignores_suffixes_resolving_normally() {
+        let guard = StormGuard::new(0.9, 10);
+        for i in 0..50 {
+            let rescode = match i % 2 {
+                0 => ResultCode::NXDOMAIN,
+                _ => ResultCode::NOERROR,
+            };
+            guard.record(&format!("{}.example.com", i), rescode);
+            guard.record(&format!("host{}.com", i), ResultCode::NXDOMAIN);
+        }
+        assert!(guard.mitigated().is_empty());
+    }
+}
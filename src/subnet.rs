@@ -0,0 +1,87 @@
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum SubnetError {
+    #[error("Invalid address in subnet \"{0}\"")]
+    InvalidAddress(String),
+    #[error("Invalid prefix length in subnet \"{0}\"")]
+    InvalidPrefix(String),
+}
+
+/// An IPv4 or IPv6 subnet in the CIDR notation, e.g. `192.168.1.0/24`.
+/// A single address is a subnet with the longest prefix.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Subnet {
+    pub addr: IpAddr,
+    pub prefix: u8,
+}
+
+impl Subnet {
+    /// Whether the address belongs to the subnet
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        match (self.addr, addr) {
+            (IpAddr::V4(net), IpAddr::V4(addr)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(addr) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(addr)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(addr) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Subnet {
+    type Err = SubnetError;
+
+    fn from_str(value: &str) -> Result<Subnet, SubnetError> {
+        let (addr, prefix) = match value.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (value, None),
+        };
+
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| SubnetError::InvalidAddress(value.to_string()))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse()
+                .ok()
+                .filter(|prefix| *prefix <= max)
+                .ok_or_else(|| SubnetError::InvalidPrefix(value.to_string()))?,
+            None => max,
+        };
+
+        Ok(Subnet { addr, prefix })
+    }
+}
+
+/// A setting with a default value, overridden for clients in given subnets.
+/// When several subnets contain a client, the most specific one wins.
+#[derive(Clone, Debug)]
+pub struct SubnetMap<T> {
+    pub default: T,
+    pub subnets: Vec<(Subnet, T)>,
+}
+
+impl<T> SubnetMap<T> {
+    pub fn new(default: T, subnets: Vec<(Subnet, T)>) -> SubnetMap<T> {
+        SubnetMap { default, subnets }
+    }
+
+    /// Returns the value that applies to the address
+    pub fn get(&self, addr: IpAddr) -> &T {
+        self.subnets
+            .iter()
+            .filter(|(subnet, _)| subnet.contains(addr))
+            .max_by_key(|(subnet, _)| subnet.prefix)
+            .map_or(&self.default, |(_, value)| value)
+    }
+}
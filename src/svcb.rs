@@ -0,0 +1,180 @@
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use crate::buffer::{Buffer, BufferError};
+
+/// SvcParamKeys, see https://www.iana.org/assignments/dns-svcb/dns-svcb.xhtml
+const ALPN: u16 = 1;
+const PORT: u16 = 3;
+const IPV4HINT: u16 = 4;
+const IPV6HINT: u16 = 6;
+
+/// A parameter of a SVCB or HTTPS record, carried in its data as a {key, length, value}
+/// triplet: see https://datatracker.ietf.org/doc/html/rfc9460#section-2.2
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum SvcParam {
+    /// Protocols supported by the service, e.g. `h2` and `h3`
+    Alpn(Vec<String>),
+    /// Port the service listens on, if not the default one of its scheme
+    Port(u16),
+    /// Addresses of the target, which clients can connect to before resolving it
+    Ipv4Hint(Vec<Ipv4Addr>),
+    Ipv6Hint(Vec<Ipv6Addr>),
+    /// Any other parameter, or a known one with a malformed value, kept as is so that the
+    /// record can be passed along
+    Unknown {
+        key: u16,
+        value: Vec<u8>,
+    },
+}
+
+impl SvcParam {
+    pub fn key(&self) -> u16 {
+        match self {
+            SvcParam::Alpn(_) => ALPN,
+            SvcParam::Port(_) => PORT,
+            SvcParam::Ipv4Hint(_) => IPV4HINT,
+            SvcParam::Ipv6Hint(_) => IPV6HINT,
+            SvcParam::Unknown { key, .. } => *key,
+        }
+    }
+
+    /// Returns an estimate of the memory used by the parameter, including the data it owns
+    pub fn approximate_size(&self) -> usize {
+        let owned = match self {
+            SvcParam::Alpn(ids) => ids.iter().map(|id| size_of::<String>() + id.len()).sum(),
+            SvcParam::Port(_) => 0,
+            SvcParam::Ipv4Hint(addrs) => size_of_val(addrs.as_slice()),
+            SvcParam::Ipv6Hint(addrs) => size_of_val(addrs.as_slice()),
+            SvcParam::Unknown { value, .. } => value.len(),
+        };
+
+        size_of::<SvcParam>() + owned
+    }
+
+    /// Decodes the value of a parameter with the given key
+    fn decode(key: u16, value: &[u8]) -> SvcParam {
+        let param = match key {
+            ALPN => {
+                let mut ids = Vec::new();
+                let mut rest = value;
+                while let Some((&len, tail)) = rest.split_first() {
+                    let Some(id) = tail.get(..len as usize).filter(|id| !id.is_empty()) else {
+                        ids.clear();
+                        break;
+                    };
+                    ids.push(String::from_utf8_lossy(id).into_owned());
+                    rest = &tail[len as usize..];
+                }
+                (!ids.is_empty()).then_some(SvcParam::Alpn(ids))
+            }
+            PORT => <[u8; 2]>::try_from(value)
+                .ok()
+                .map(|port| SvcParam::Port(u16::from_be_bytes(port))),
+            IPV4HINT if !value.is_empty() && value.len().is_multiple_of(4) => {
+                Some(SvcParam::Ipv4Hint(
+                    value
+                        .chunks(4)
+                        .map(|addr| Ipv4Addr::from(<[u8; 4]>::try_from(addr).unwrap()))
+                        .collect(),
+                ))
+            }
+            IPV6HINT if !value.is_empty() && value.len().is_multiple_of(16) => {
+                Some(SvcParam::Ipv6Hint(
+                    value
+                        .chunks(16)
+                        .map(|addr| Ipv6Addr::from(<[u8; 16]>::try_from(addr).unwrap()))
+                        .collect(),
+                ))
+            }
+            _ => None,
+        };
+
+        param.unwrap_or_else(|| SvcParam::Unknown {
+            key,
+            value: value.to_vec(),
+        })
+    }
+
+    /// Encodes the value of the parameter, without its key and length
+    fn encode(&self) -> Result<Vec<u8>, BufferError> {
+        let mut value = Vec::new();
+        match self {
+            SvcParam::Alpn(ids) => {
+                for id in ids {
+                    let len = u8::try_from(id.len()).map_err(|_| BufferError::StringTooLong)?;
+                    value.push(len);
+                    value.extend(id.as_bytes());
+                }
+            }
+            SvcParam::Port(port) => value.extend(port.to_be_bytes()),
+            SvcParam::Ipv4Hint(addrs) => addrs.iter().for_each(|addr| value.extend(addr.octets())),
+            SvcParam::Ipv6Hint(addrs) => addrs.iter().for_each(|addr| value.extend(addr.octets())),
+            SvcParam::Unknown { value: data, .. } => value.extend(data),
+        }
+
+        Ok(value)
+    }
+}
+
+/// Presentation format of a parameter, as in zone files: see
+/// https://datatracker.ietf.org/doc/html/rfc9460#section-2.1
+impl fmt::Display for SvcParam {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SvcParam::Alpn(ids) => write!(f, "alpn={}", ids.join(",")),
+            SvcParam::Port(port) => write!(f, "port={}", port),
+            SvcParam::Ipv4Hint(addrs) => write!(f, "ipv4hint={}", list(addrs)),
+            SvcParam::Ipv6Hint(addrs) => write!(f, "ipv6hint={}", list(addrs)),
+            SvcParam::Unknown { key, value } if value.is_empty() => write!(f, "key{}", key),
+            SvcParam::Unknown { key, value } => {
+                write!(f, "key{}=\"", key)?;
+                for &byte in value {
+                    match byte {
+                        b'"' | b'\\' => write!(f, "\\{}", byte as char)?,
+                        0x21..=0x7e => write!(f, "{}", byte as char)?,
+                        _ => write!(f, "\\{:03}", byte)?,
+                    }
+                }
+                write!(f, "\"")
+            }
+        }
+    }
+}
+
+/// Joins the values of a parameter with commas
+fn list<T: ToString>(values: &[T]) -> String {
+    values
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Reads the parameters filling the `len` bytes left of the data of a SVCB or HTTPS record
+pub fn read_params(buffer: &mut Buffer, len: usize) -> Result<Vec<SvcParam>, BufferError> {
+    let end = buffer.pos() + len;
+    let mut params = Vec::new();
+    while buffer.pos() < end {
+        let key = buffer.read_u16()?;
+        let value_len = buffer.read_u16()?;
+        let value = buffer.read_bytes(value_len as usize)?;
+        params.push(SvcParam::decode(key, &value));
+    }
+
+    Ok(params)
+}
+
+/// Writes parameters in the increasing order of their keys, as RFC 9460 requires
+pub fn write_params(buffer: &mut Buffer, params: &[SvcParam]) -> Result<(), BufferError> {
+    let mut params: Vec<&SvcParam> = params.iter().collect();
+    params.sort_by_key(|param| param.key());
+    for param in params {
+        let value = param.encode()?;
+        buffer.write_u16(param.key())?;
+        buffer.write_u16(value.len() as u16)?;
+        buffer.write_bytes(&value)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,651 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{IpAddr, Shutdown, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+
+use crate::buffer::BufferError;
+use crate::conformance::{self, Verdict};
+use crate::correlation;
+use crate::handler::{contain_panic, parse_query, respond, Context};
+use crate::journal;
+use crate::listener::TcpLimits;
+use crate::opt::{EdnsOption, OptRecord};
+use crate::packet::DnsPacket;
+use crate::privacy;
+use crate::profiling::{self, Stage};
+use crate::question::QueryType;
+use crate::resultcode::ResultCode;
+use crate::socket::{self, Backoff, ErrorClass};
+use crate::subnet::Subnet;
+use crate::transfer::{axfr_messages, ixfr_messages, TransferLimits, ZoneTransfers};
+
+/// UDP payload size advertised in the OPT records of TCP responses
+const TCP_PAYLOAD_SIZE: u16 = 1232;
+
+/// The TCP server answers queries sent over TCP, where each message is prefixed with its
+/// length as a 16 bits integer (see https://datatracker.ietf.org/doc/html/rfc1035#section-4.2.2).
+/// Each connection is handled by its own thread, which answers the queries pipelined on it
+/// concurrently. Errors accepting connections are retried
+/// with a backoff, e.g. when the process runs out of file descriptors, and the listener is
+/// bound again when it breaks.
+///
+/// Connections from clients that aren't allowed to use the server are closed right away.
+///
+/// Hosted zones are also transferred to the clients asking for them with AXFR, within
+/// the transfer limits and the `allow-transfer` ACL of each zone.
+pub struct TcpServer {
+    context: Arc<Context>,
+    limits: TcpLimits,
+    /// Clients allowed to send queries, everyone if empty
+    allow: Vec<Subnet>,
+    connections: AtomicUsize,
+    transfer_limits: TransferLimits,
+    /// Transfer settings of the hosted zones that have some, by origin
+    zone_transfers: BTreeMap<String, ZoneTransfers>,
+    /// Number of transfers in progress to each client
+    transfers: Mutex<HashMap<IpAddr, usize>>,
+}
+
+impl TcpServer {
+    pub fn new(
+        context: Arc<Context>,
+        limits: TcpLimits,
+        transfer_limits: TransferLimits,
+    ) -> TcpServer {
+        TcpServer {
+            context,
+            limits,
+            allow: Vec::new(),
+            connections: AtomicUsize::new(0),
+            transfer_limits,
+            zone_transfers: BTreeMap::new(),
+            transfers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Only transfers the zones with an `allow-transfer` ACL to the clients it allows
+    pub fn with_zone_transfers(
+        mut self,
+        zone_transfers: BTreeMap<String, ZoneTransfers>,
+    ) -> TcpServer {
+        self.zone_transfers = zone_transfers;
+        self
+    }
+
+    /// Only answers the clients in the given subnets, if any
+    pub fn with_allow(mut self, allow: Vec<Subnet>) -> TcpServer {
+        self.allow = allow;
+        self
+    }
+
+    /// Accepts connections forever, closing right away those above the limit, and using `bind`
+    /// to bind the listener again when it breaks
+    pub fn serve(self, listener: TcpListener, bind: impl Fn() -> io::Result<TcpListener>) {
+        let server = Arc::new(self);
+        let mut listener = Some(listener);
+        let mut backoff = Backoff::new();
+
+        loop {
+            let Some(current) = &listener else {
+                listener = Some(socket::rebind("TCP", &bind, &mut backoff));
+                server.context.stats.record_socket_rebind();
+                continue;
+            };
+
+            let stream = match current.accept() {
+                Ok((stream, _)) => {
+                    backoff.reset();
+                    stream
+                }
+                Err(e) => {
+                    let class = socket::classify(&e);
+                    server.context.stats.record_socket_error(class);
+                    let delay = backoff.delay(class);
+                    warn!(
+                        "Failed to accept TCP connection ({} error), retrying in {:?}: {}",
+                        class.as_str(),
+                        delay,
+                        e
+                    );
+                    // The broken listener is closed, so that its port can be bound again.
+                    if class == ErrorClass::Fatal {
+                        listener = None;
+                    }
+                    thread::sleep(delay);
+                    continue;
+                }
+            };
+
+            if let Some(client) = stream.peer_addr().ok().map(|peer| peer.ip()) {
+                if !server.allows(client) {
+                    info!(
+                        "Closing TCP connection from {}, not allowed on this listener",
+                        privacy::client(client)
+                    );
+                    continue;
+                }
+            }
+
+            let connections = server.connections.fetch_add(1, Ordering::SeqCst);
+            if connections >= server.limits.max_connections {
+                if let Ok(peer) = stream.peer_addr() {
+                    warn!(
+                        "Too many TCP connections, closing {}",
+                        privacy::client(peer.ip())
+                    );
+                }
+                server.connections.fetch_sub(1, Ordering::SeqCst);
+                continue;
+            }
+            server.context.stats.set_tcp_connections(connections + 1);
+
+            let server = server.clone();
+            thread::spawn(move || {
+                if let Err(e) = server.handle_connection(stream) {
+                    warn!("TCP connection error: {}", e);
+                }
+                let connections = server.connections.fetch_sub(1, Ordering::SeqCst);
+                server.context.stats.set_tcp_connections(connections - 1);
+            });
+        }
+    }
+
+    /// Whether a client is allowed to send queries
+    fn allows(&self, client: IpAddr) -> bool {
+        self.allow.is_empty() || self.allow.iter().any(|subnet| subnet.contains(client))
+    }
+
+    /// Answers the queries sent on a connection until the client closes it, stays idle for
+    /// too long, or reaches the maximum number of queries. Queries are answered concurrently,
+    /// up to the pipelining limit, and each response is sent as soon as it's ready, possibly
+    /// out of order (see https://datatracker.ietf.org/doc/html/rfc7766#section-6.2.1.1).
+    /// Responses still pending when the client stops sending queries are sent before the
+    /// connection is closed.
+    fn handle_connection(&self, mut stream: TcpStream) -> Result<(), BufferError> {
+        stream.set_write_timeout(Some(self.limits.idle_timeout))?;
+        let client = stream.peer_addr()?.ip();
+        // Responses are written whole, one at a time, by the threads answering the queries.
+        let writer = Mutex::new(stream.try_clone()?);
+        let pending = Pending::new(self.limits.max_pipelined);
+
+        thread::scope(|scope| {
+            for _ in 0..self.limits.max_queries {
+                // The whole query must arrive before the deadline, not just each of its bytes.
+                let deadline = Instant::now() + self.limits.idle_timeout;
+
+                let mut len = [0; 2];
+                match read_exact_until(&mut stream, &mut len, deadline) {
+                    Ok(()) => {}
+                    Err(e) if is_closed(&e) => {
+                        info!("Closing idle TCP connection: {}", e);
+                        return Ok(());
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+
+                let mut query = vec![0; u16::from_be_bytes(len) as usize];
+                read_exact_until(&mut stream, &mut query, deadline)?;
+                let id = correlation::new_id();
+                let _correlation = correlation::enter(id);
+
+                let Some(request) = contain_panic(&query, || parse_query(&query, client)) else {
+                    return Ok(());
+                };
+                let request = request?;
+
+                // Transfers hold the connection until they're over.
+                if conformance::check(&request) == Verdict::Accept && is_transfer(&request) {
+                    self.transfer(&mut writer.lock().unwrap(), &request, client)?;
+                    continue;
+                }
+
+                let slot = pending.start();
+                let writer = &writer;
+                scope.spawn(move || {
+                    let _slot = slot;
+                    let _correlation = correlation::enter(id);
+                    if let Err(e) = self.answer(writer, &query, request, client) {
+                        warn!("TCP connection error: {}", e);
+                        // The connection is closed, which also stops reading queries from it.
+                        let _ = writer.lock().unwrap().shutdown(Shutdown::Both);
+                    }
+                });
+            }
+
+            info!(
+                "Closing TCP connection after {} queries",
+                self.limits.max_queries
+            );
+
+            Ok(())
+        })
+    }
+
+    /// Answers a query received on a connection and sends the response. The connection is
+    /// closed when answering the query panics.
+    fn answer(
+        &self,
+        writer: &Mutex<TcpStream>,
+        query: &[u8],
+        request: DnsPacket,
+        client: IpAddr,
+    ) -> Result<(), BufferError> {
+        let keepalive_requested = wants_keepalive(&request);
+
+        let Some(response) = contain_panic(query, || respond(request, client, &self.context))
+        else {
+            return Err(BufferError::IoError(ErrorKind::ConnectionAborted.into()));
+        };
+        let Some(mut packet) = response? else {
+            return Ok(());
+        };
+
+        // Clients asking for it are told how long idle connections are kept open.
+        if keepalive_requested {
+            add_keepalive(&mut packet, self.limits.idle_timeout);
+        }
+
+        let response = profiling::time(Stage::Serialize, || packet.to_bytes())?;
+        let mut message = Vec::with_capacity(response.len() + 2);
+        message.extend_from_slice(&(response.len() as u16).to_be_bytes());
+        message.extend_from_slice(&response);
+        let mut writer = writer.lock().unwrap();
+        profiling::time(Stage::Send, || writer.write_all(&message))?;
+
+        Ok(())
+    }
+}
+
+/// Counts the queries of a connection being answered, so that a client can't pipeline more
+/// than the limit: reading the next query waits for one of them to be answered.
+struct Pending {
+    max: usize,
+    count: Mutex<usize>,
+    answered: Condvar,
+}
+
+impl Pending {
+    fn new(max: usize) -> Pending {
+        Pending {
+            max: max.max(1),
+            count: Mutex::new(0),
+            answered: Condvar::new(),
+        }
+    }
+
+    /// Waits until fewer queries than the limit are being answered, and counts one more
+    /// until the returned slot is dropped
+    fn start(&self) -> PendingSlot<'_> {
+        let mut count = self.count.lock().unwrap();
+        while *count >= self.max {
+            count = self.answered.wait(count).unwrap();
+        }
+        *count += 1;
+        PendingSlot { pending: self }
+    }
+}
+
+/// A query being answered, counted against the pipelining limit until dropped
+struct PendingSlot<'a> {
+    pending: &'a Pending,
+}
+
+impl Drop for PendingSlot<'_> {
+    fn drop(&mut self) {
+        *self.pending.count.lock().unwrap() -= 1;
+        self.pending.answered.notify_one();
+    }
+}
+
+impl TcpServer {
+    /// Transfers a hosted zone to a client, or answers with an error when the zone isn't
+    /// hosted, the transfer limits are reached, or the zone is too large. IXFR requests get
+    /// the changes since the version of the client when the journal has them, and the whole
+    /// zone otherwise.
+    fn transfer(
+        &self,
+        stream: &mut TcpStream,
+        request: &DnsPacket,
+        client: IpAddr,
+    ) -> Result<(), BufferError> {
+        let origin = &request.questions[0].name.to_ascii_lowercase();
+        let (zone, changes) = {
+            let authority = self.context.authority.read().unwrap();
+            let zone = authority
+                .zones
+                .iter()
+                .find(|zone| zone.origin == *origin)
+                .cloned();
+            let changes = ixfr_serial(request).and_then(|serial| {
+                match zone.as_ref()?.serial() == Some(serial) {
+                    true => Some(Vec::new()),
+                    false => authority.journal.changes_since(origin, serial),
+                }
+            });
+            (zone, changes)
+        };
+        let Some(zone) = zone else {
+            return send_error(stream, request, ResultCode::NOTAUTH);
+        };
+        if let Some(transfers) = self.zone_transfers.get(origin) {
+            if !transfers.allows(client) {
+                warn!(
+                    "Refusing transfer of zone {} to {}, not in allow-transfer",
+                    origin,
+                    privacy::client(client)
+                );
+                return send_error(stream, request, ResultCode::REFUSED);
+            }
+        }
+
+        let Some(_slot) = self.start_transfer(client) else {
+            warn!(
+                "Too many zone transfers, refusing {} to {}",
+                origin,
+                privacy::client(client)
+            );
+            return send_error(stream, request, ResultCode::REFUSED);
+        };
+
+        let messages = match &changes {
+            Some(changes) => ixfr_messages(&zone, changes, request),
+            None => axfr_messages(&zone, request),
+        };
+        let messages = match messages {
+            Ok(messages) => messages,
+            Err(e) => {
+                warn!("Failed to transfer zone {}: {}", origin, e);
+                return send_error(stream, request, ResultCode::SERVFAIL);
+            }
+        };
+        let size: usize = messages.iter().map(Vec::len).sum();
+        if size > self.transfer_limits.max_size {
+            warn!(
+                "Zone {} is too large to transfer ({} bytes), refusing it to {}",
+                origin,
+                size,
+                privacy::client(client)
+            );
+            return send_error(stream, request, ResultCode::REFUSED);
+        }
+
+        info!(
+            "Transferring zone {} ({} bytes) to {}",
+            origin,
+            size,
+            privacy::client(client)
+        );
+        let start = Instant::now();
+        let mut sent = 0;
+        for message in messages {
+            stream.write_all(&(message.len() as u16).to_be_bytes())?;
+            stream.write_all(&message)?;
+            sent += message.len() as u64;
+
+            // The transfer waits for as long as it's ahead of the allowed rate.
+            if let Some(rate) = self.transfer_limits.rate {
+                let due = Duration::from_secs_f64(sent as f64 / rate as f64);
+                thread::sleep(due.saturating_sub(start.elapsed()));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Counts a transfer to a client as in progress until the returned slot is dropped,
+    /// or returns `None` if it would exceed the limits
+    fn start_transfer(&self, client: IpAddr) -> Option<TransferSlot<'_>> {
+        let mut transfers = self.transfers.lock().unwrap();
+        let total: usize = transfers.values().sum();
+        let per_client = transfers.get(&client).copied().unwrap_or(0);
+        if total >= self.transfer_limits.max_transfers
+            || per_client >= self.transfer_limits.max_transfers_per_client
+        {
+            return None;
+        }
+
+        *transfers.entry(client).or_insert(0) += 1;
+        Some(TransferSlot {
+            server: self,
+            client,
+        })
+    }
+}
+
+/// A transfer in progress, counted against the limits until dropped
+struct TransferSlot<'a> {
+    server: &'a TcpServer,
+    client: IpAddr,
+}
+
+impl Drop for TransferSlot<'_> {
+    fn drop(&mut self) {
+        let mut transfers = self.server.transfers.lock().unwrap();
+        if let Some(count) = transfers.get_mut(&self.client) {
+            *count -= 1;
+            if *count == 0 {
+                transfers.remove(&self.client);
+            }
+        }
+    }
+}
+
+/// Whether the query asks for a zone transfer
+fn is_transfer(request: &DnsPacket) -> bool {
+    matches!(
+        request.questions.as_slice(),
+        [question] if matches!(question.qtype, QueryType::AXFR | QueryType::IXFR)
+    )
+}
+
+/// Returns the serial of the version of the zone the client has, for IXFR requests
+fn ixfr_serial(request: &DnsPacket) -> Option<u32> {
+    if request.questions.first()?.qtype != QueryType::IXFR {
+        return None;
+    }
+    request.authorities.first().and_then(journal::serial)
+}
+
+/// Answers a query with an error and no records
+fn send_error(
+    stream: &mut TcpStream,
+    request: &DnsPacket,
+    rescode: ResultCode,
+) -> Result<(), BufferError> {
+    let mut packet = DnsPacket::new();
+    packet.header.id = request.header.id;
+    packet.header.response = true;
+    packet.header.rescode = rescode;
+    packet.questions = request.questions.clone();
+
+    let response = packet.to_bytes()?;
+    stream.write_all(&(response.len() as u16).to_be_bytes())?;
+    stream.write_all(&response)?;
+
+    Ok(())
+}
+
+/// Fills the buffer from the stream, failing with `TimedOut` when the deadline passes
+fn read_exact_until(
+    stream: &mut TcpStream,
+    buf: &mut [u8],
+    deadline: Instant,
+) -> std::io::Result<()> {
+    let mut pos = 0;
+
+    while pos < buf.len() {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(ErrorKind::TimedOut.into());
+        }
+        stream.set_read_timeout(Some(remaining))?;
+
+        match stream.read(&mut buf[pos..])? {
+            0 => return Err(ErrorKind::UnexpectedEof.into()),
+            n => pos += n,
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether an error means the client is gone or idle, rather than something going wrong
+fn is_closed(e: &std::io::Error) -> bool {
+    matches!(
+        e.kind(),
+        ErrorKind::UnexpectedEof
+            | ErrorKind::TimedOut
+            | ErrorKind::WouldBlock
+            | ErrorKind::ConnectionReset
+    )
+}
+
+/// Whether the query has an OPT record with the edns-tcp-keepalive option
+fn wants_keepalive(request: &DnsPacket) -> bool {
+    request.opt().is_some_and(|opt| opt.keepalive().is_some())
+}
+
+/// Adds the edns-tcp-keepalive option, whose timeout is in units of 100 milliseconds, to the
+/// OPT record of a response
+fn add_keepalive(packet: &mut DnsPacket, idle_timeout: Duration) {
+    let timeout = (idle_timeout.as_millis() / 100).min(u16::MAX as u128) as u16;
+    let opt = packet
+        .opt()
+        .unwrap_or_else(|| OptRecord::new(TCP_PAYLOAD_SIZE))
+        .with_option(EdnsOption::Keepalive(Some(timeout)));
+
+    packet.set_opt(opt);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler;
+    use crate::question::DnsQuestion;
+    use crate::record::DnsRecord;
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    /// Starts a server answering `host.local` from an override, and returns its address
+    fn start(limits: TcpLimits) -> SocketAddr {
+        let context = handler::tests::context();
+        context
+            .authority
+            .write()
+            .unwrap()
+            .overrides
+            .push(DnsRecord::A {
+                domain: "host.local".to_string(),
+                addr: Ipv4Addr::new(192, 0, 2, 1),
+                ttl: 300,
+            });
+        let transfer_limits = TransferLimits {
+            max_transfers: 1,
+            max_transfers_per_client: 1,
+            rate: None,
+            max_size: 0,
+        };
+        let server = TcpServer::new(Arc::new(context), limits, transfer_limits);
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || server.serve(listener, move || TcpListener::bind(addr)));
+
+        addr
+    }
+
+    fn limits() -> TcpLimits {
+        TcpLimits {
+            max_connections: 10,
+            max_queries: 10,
+            max_pipelined: 1,
+            idle_timeout: Duration::from_secs(5),
+        }
+    }
+
+    fn connect(addr: SocketAddr) -> TcpStream {
+        let stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        stream
+    }
+
+    /// Whether the server closed the connection, rather than leaving it open
+    fn is_closed_by_server(stream: &mut TcpStream) -> bool {
+        match stream.read(&mut [0; 1]) {
+            Ok(n) => n == 0,
+            Err(e) => e.kind() == ErrorKind::ConnectionReset,
+        }
+    }
+
+    /// Sends a query for `host.local` and reads the response
+    fn query(stream: &mut TcpStream, id: u16) -> DnsPacket {
+        let mut request = DnsPacket::new();
+        request.header.id = id;
+        request
+            .questions
+            .push(DnsQuestion::new("host.local".to_string(), QueryType::A));
+        let query = request.to_bytes().unwrap();
+        stream
+            .write_all(&(query.len() as u16).to_be_bytes())
+            .unwrap();
+        stream.write_all(&query).unwrap();
+
+        let mut len = [0; 2];
+        stream.read_exact(&mut len).unwrap();
+        let mut response = vec![0; u16::from_be_bytes(len) as usize];
+        stream.read_exact(&mut response).unwrap();
+        DnsPacket::parse(&response).unwrap()
+    }
+
+    #[test]
+    fn closes_connections_over_the_limit() {
+        let addr = start(TcpLimits {
+            max_connections: 1,
+            ..limits()
+        });
+
+        // The first connection is answered, and stays open while the second one is closed.
+        let mut first = connect(addr);
+        assert_eq!(query(&mut first, 1).header.id, 1);
+        let mut second = connect(addr);
+        assert!(is_closed_by_server(&mut second));
+        assert_eq!(query(&mut first, 2).header.id, 2);
+    }
+
+    #[test]
+    fn closes_idle_connections() {
+        let idle_timeout = Duration::from_millis(200);
+        let addr = start(TcpLimits {
+            idle_timeout,
+            ..limits()
+        });
+
+        // Sending part of a query doesn't keep the connection open past the deadline.
+        let mut stream = connect(addr);
+        let start = Instant::now();
+        stream.write_all(&[0]).unwrap();
+        assert!(is_closed_by_server(&mut stream));
+        assert!(start.elapsed() >= idle_timeout);
+    }
+
+    #[test]
+    fn closes_connections_after_the_maximum_of_queries() {
+        let addr = start(TcpLimits {
+            max_queries: 2,
+            ..limits()
+        });
+
+        let mut stream = connect(addr);
+        for id in 1..=2 {
+            let response = query(&mut stream, id);
+            assert_eq!(response.header.id, id);
+            assert_eq!(response.answers.len(), 1);
+        }
+        assert!(is_closed_by_server(&mut stream));
+    }
+}
@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+
+/// Number of names tracked by default, many more than are ever shown so that the counts
+/// of the top names are close to exact
+const DEFAULT_CAPACITY: usize = 1000;
+
+/// The `TopN` struct counts the most frequent names of a stream using the Space-Saving
+/// algorithm: see https://www.cs.ucsb.edu/sites/default/files/documents/2005-23.pdf
+///
+/// At most `capacity` names are tracked. When a name that isn't tracked comes in, it takes
+/// the place of the least counted one, inheriting its count: counts are overestimated by at
+/// most the count of the evicted name, but any name seen more than `1 / capacity` of the
+/// time is guaranteed to be tracked. Memory stays bounded however many names are seen.
+#[derive(Debug)]
+pub struct TopN {
+    capacity: usize,
+    /// Count of each tracked name, and by how much it may be overestimated
+    counts: HashMap<String, (u64, u64)>,
+}
+
+impl Default for TopN {
+    fn default() -> Self {
+        TopN::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl TopN {
+    pub fn new(capacity: usize) -> TopN {
+        TopN {
+            capacity: capacity.max(1),
+            counts: HashMap::new(),
+        }
+    }
+
+    /// Counts an occurrence of a name
+    pub fn record(&mut self, name: &str) {
+        if let Some((count, _)) = self.counts.get_mut(name) {
+            *count += 1;
+            return;
+        }
+
+        let mut entry = (1, 0);
+        if self.counts.len() >= self.capacity {
+            let evicted = self
+                .counts
+                .iter()
+                .min_by_key(|(_, (count, _))| *count)
+                .map(|(name, (count, _))| (name.clone(), *count));
+            if let Some((evicted, count)) = evicted {
+                self.counts.remove(&evicted);
+                entry = (count + 1, count);
+            }
+        }
+        self.counts.insert(name.to_string(), entry);
+    }
+
+    /// Returns the `n` most counted names with their counts, the most counted first
+    pub fn top(&self, n: usize) -> Vec<(String, u64)> {
+        let mut top: Vec<(String, u64)> = self
+            .counts
+            .iter()
+            .map(|(name, (count, _))| (name.clone(), *count))
+            .collect();
+        top.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then_with(|| a.cmp(b)));
+        top.truncate(n);
+        top
+    }
+
+    /// Returns how much the count of a tracked name may be overestimated
+    pub fn error(&self, name: &str) -> Option<u64> {
+        self.counts.get(name).map(|(_, error)| *error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_names_exactly_within_capacity() {
+        let mut top = TopN::new(3);
+        for name in ["a.com", "b.com", "a.com", "c.com", "a.com", "b.com"] {
+            top.record(name);
+        }
+
+        assert_eq!(
+            top.top(2),
+            vec![("a.com".to_string(), 3), ("b.com".to_string(), 2)]
+        );
+        assert_eq!(top.error("c.com"), Some(0));
+    }
+
+    #[test]
+    fn keeps_frequent_names_beyond_capacity() {
+        let mut top = TopN::new(2);
+        for i in 0..100 {
+            top.record("popular.com");
+            top.record(&format!("rare{}.com", i));
+        }
+
+        assert_eq!(top.top(1), vec![("popular.com".to_string(), 100)]);
+        assert_eq!(top.error("popular.com"), Some(0));
+        // The last rare name inherited the count of the one it evicted, and so on.
+        assert_eq!(top.top(2)[1], ("rare99.com".to_string(), 100));
+        assert_eq!(top.error("rare99.com"), Some(99));
+    }
+}
@@ -0,0 +1,408 @@
+use std::io::{Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::time::Duration;
+
+use rand::Rng;
+
+use crate::buffer::{Buffer, BufferError};
+use crate::journal::{self, Change};
+use crate::packet::DnsPacket;
+use crate::question::{DnsQuestion, QueryType};
+use crate::record::DnsRecord;
+use crate::resultcode::ResultCode;
+use crate::subnet::Subnet;
+use crate::zone::Zone;
+
+/// Time to wait for a primary server to accept a connection or send data
+const TRANSFER_TIMEOUT: Duration = Duration::from_secs(10);
+/// Size the messages of outgoing transfers are kept under, well below the 64 KiB maximum
+const TRANSFER_MESSAGE_SIZE: usize = 16 * 1024;
+
+/// Limits applied to outgoing zone transfers, so that misbehaving secondaries can't drain
+/// the server by transferring large zones over and over
+#[derive(Clone, Copy, Debug)]
+pub struct TransferLimits {
+    /// Maximum number of transfers at the same time
+    pub max_transfers: usize,
+    /// Maximum number of transfers at the same time to the same client
+    pub max_transfers_per_client: usize,
+    /// Maximum number of bytes sent per second by each transfer, if limited
+    pub rate: Option<u64>,
+    /// Maximum size of a transfer in bytes, beyond which it is refused
+    pub max_size: usize,
+}
+
+/// Transfer settings of a hosted zone: the secondaries allowed to transfer it, and those
+/// notified when it changes besides them
+#[derive(Clone, Debug, Default)]
+pub struct ZoneTransfers {
+    /// Clients allowed to transfer the zone, everyone if empty
+    pub allow_transfer: Vec<Subnet>,
+    /// Secondaries sent a NOTIFY when the serial of the zone changes
+    pub also_notify: Vec<SocketAddr>,
+}
+
+impl ZoneTransfers {
+    /// Whether a client is allowed to transfer the zone
+    pub fn allows(&self, client: IpAddr) -> bool {
+        self.allow_transfer.is_empty()
+            || self
+                .allow_transfer
+                .iter()
+                .any(|subnet| subnet.contains(client))
+    }
+}
+
+/// `TransferError` is an enum that represents the errors that can occur while
+/// transferring a zone from a primary server
+#[derive(thiserror::Error, Debug)]
+pub enum TransferError {
+    #[error("Primary refused the transfer of {0}: {1:?}")]
+    Refused(String, ResultCode),
+    #[error("Transfer of {0} is malformed: {1}")]
+    Malformed(String, &'static str),
+    #[error("Buffer error: {0}")]
+    BufferError(#[from] BufferError),
+    #[error("I/O error: {0}")]
+    IoError(#[from] std::io::Error),
+}
+
+/// Returns the serial of the SOA record of a zone, as served by its primary
+pub fn query_serial(primary: SocketAddr, origin: &str) -> Result<u32, TransferError> {
+    let mut stream = send_query(primary, origin, QueryType::SOA, None)?;
+    let response = read_message(&mut stream, origin)?;
+
+    response
+        .answers
+        .iter()
+        .find_map(|record| match record {
+            DnsRecord::SOA { serial, .. } => Some(*serial),
+            _ => None,
+        })
+        .ok_or_else(|| TransferError::Malformed(origin.to_string(), "no SOA record"))
+}
+
+/// Transfers a whole zone from its primary with AXFR:
+/// see https://datatracker.ietf.org/doc/html/rfc5936
+///
+/// The zone is sent as a sequence of messages whose answers start with the SOA record
+/// and end with it again.
+pub fn axfr(primary: SocketAddr, origin: &str) -> Result<Zone, TransferError> {
+    let mut stream = send_query(primary, origin, QueryType::AXFR, None)?;
+    let malformed = |reason| TransferError::Malformed(origin.to_string(), reason);
+
+    let mut records: Vec<DnsRecord> = Vec::new();
+    loop {
+        let response = read_message(&mut stream, origin)?;
+        if response.answers.is_empty() {
+            return Err(malformed("empty message"));
+        }
+
+        for record in response.answers {
+            let is_soa = record.qtype() == QueryType::SOA;
+            if records.is_empty() && !is_soa {
+                return Err(malformed("first record is not the SOA"));
+            }
+            if is_soa && !records.is_empty() {
+                return Ok(Zone {
+                    origin: origin.to_string(),
+                    records,
+                });
+            }
+            records.push(record);
+        }
+    }
+}
+
+/// Brings a zone up to date with the version of its primary with IXFR, which only sends the
+/// changes since the version we have: see https://datatracker.ietf.org/doc/html/rfc1995
+///
+/// The primary answers with the SOA record of its version, followed by each change: the SOA
+/// of the old version, the records removed, the SOA of the new version and the records added,
+/// and the SOA of its version again. It can also send the whole zone instead, as with AXFR,
+/// or only its SOA when the zone is up to date.
+pub fn ixfr(primary: SocketAddr, zone: &Zone) -> Result<Zone, TransferError> {
+    let origin = zone.origin.as_str();
+    let malformed = |reason| TransferError::Malformed(origin.to_string(), reason);
+    let current = zone.soa().ok_or_else(|| malformed("no SOA record"))?;
+    let mut stream = send_query(primary, origin, QueryType::IXFR, Some(current.clone()))?;
+
+    let mut records: Vec<DnsRecord> = Vec::new();
+    let mut changes: Vec<Change> = Vec::new();
+    let mut adding = false;
+    loop {
+        let response = read_message(&mut stream, origin)?;
+        if response.answers.is_empty() {
+            return Err(malformed("empty message"));
+        }
+
+        let mut answers = response.answers.into_iter();
+        while let Some(record) = answers.next() {
+            let soa_serial = journal::serial(&record);
+            let Some(latest) = records.first() else {
+                if soa_serial.is_none() {
+                    return Err(malformed("first record is not the SOA"));
+                }
+                records.push(record);
+                continue;
+            };
+            let latest_serial = journal::serial(latest);
+
+            // A second record that isn't an SOA means the whole zone is sent, as with AXFR.
+            if records.len() == 1 && changes.is_empty() && soa_serial.is_none() {
+                records.push(record);
+                return finish_axfr(&mut stream, origin, records, answers.collect());
+            }
+
+            match (soa_serial, changes.last_mut()) {
+                (Some(_), _) if adding && soa_serial == latest_serial => {
+                    let mut updated = zone.clone();
+                    for change in &changes {
+                        if journal::serial(&change.from) != updated.serial() {
+                            return Err(malformed("changes don't apply to the current version"));
+                        }
+                        updated.apply(change);
+                    }
+                    return Ok(updated);
+                }
+                (Some(_), Some(change)) if !adding => {
+                    change.to = record;
+                    adding = true;
+                }
+                (Some(_), _) => {
+                    changes.push(Change {
+                        from: record,
+                        to: latest.clone(),
+                        removed: Vec::new(),
+                        added: Vec::new(),
+                    });
+                    adding = false;
+                }
+                (None, Some(change)) if adding => change.added.push(record),
+                (None, Some(change)) => change.removed.push(record),
+                (None, None) => return Err(malformed("record outside of a change")),
+            }
+        }
+
+        // A lone SOA record means the zone is already up to date.
+        if records.len() == 1 && changes.is_empty() {
+            if journal::serial(&records[0]) == zone.serial() {
+                return Ok(zone.clone());
+            }
+            return Err(malformed("lone SOA record of another version"));
+        }
+    }
+}
+
+/// Reads the rest of a transfer sent as with AXFR, after its first records and starting
+/// with the records left in the message being read
+fn finish_axfr(
+    stream: &mut TcpStream,
+    origin: &str,
+    mut records: Vec<DnsRecord>,
+    mut pending: Vec<DnsRecord>,
+) -> Result<Zone, TransferError> {
+    loop {
+        for record in pending {
+            if record.qtype() == QueryType::SOA {
+                return Ok(Zone {
+                    origin: origin.to_string(),
+                    records,
+                });
+            }
+            records.push(record);
+        }
+        pending = read_message(stream, origin)?.answers.into_vec();
+    }
+}
+
+/// Serializes the messages transferring a zone with AXFR, in answer to the given request:
+/// the SOA record, all the other records, and the SOA record again, split in messages
+/// kept under 16 KiB. Fails if the zone has no SOA record.
+pub fn axfr_messages(zone: &Zone, request: &DnsPacket) -> Result<Vec<Vec<u8>>, TransferError> {
+    let soa = zone
+        .soa()
+        .ok_or_else(|| TransferError::Malformed(zone.origin.clone(), "no SOA record"))?;
+    let records = std::iter::once(soa)
+        .chain(
+            zone.records
+                .iter()
+                .filter(|record| record.qtype() != QueryType::SOA),
+        )
+        .chain(std::iter::once(soa));
+
+    messages(records, request)
+}
+
+/// Serializes the messages transferring the changes of a zone with IXFR, in answer to the
+/// given request: the SOA record of the zone, each change, and the SOA record again.
+/// Without changes, the zone is up to date and only its SOA record is sent.
+pub fn ixfr_messages(
+    zone: &Zone,
+    changes: &[Change],
+    request: &DnsPacket,
+) -> Result<Vec<Vec<u8>>, TransferError> {
+    let soa = zone
+        .soa()
+        .ok_or_else(|| TransferError::Malformed(zone.origin.clone(), "no SOA record"))?;
+    if changes.is_empty() {
+        return messages(std::iter::once(soa), request);
+    }
+
+    let records = std::iter::once(soa)
+        .chain(changes.iter().flat_map(|change| {
+            std::iter::once(&change.from)
+                .chain(&change.removed)
+                .chain(std::iter::once(&change.to))
+                .chain(&change.added)
+        }))
+        .chain(std::iter::once(soa));
+
+    messages(records, request)
+}
+
+/// Serializes records in messages answering the given request, kept under 16 KiB. Records
+/// are written one after the other into the message being built, and one that takes it over
+/// the limit is moved to the next message.
+fn messages<'a>(
+    records: impl Iterator<Item = &'a DnsRecord>,
+    request: &DnsPacket,
+) -> Result<Vec<Vec<u8>>, TransferError> {
+    let new_message = |first: bool| -> Result<Buffer, BufferError> {
+        let mut packet = DnsPacket::new();
+        packet.header.id = request.header.id;
+        packet.header.response = true;
+        packet.header.authoritative_answer = true;
+        // Only the first message repeats the question.
+        if first {
+            packet.questions = request.questions.clone();
+        }
+        let mut buffer = Buffer::growable();
+        packet.write(&mut buffer)?;
+        Ok(buffer)
+    };
+
+    let mut messages = Vec::new();
+    let mut buffer = new_message(true)?;
+    let mut answers = 0;
+    for record in records {
+        let start = buffer.pos();
+        record.write(&mut buffer)?;
+        if buffer.pos() > TRANSFER_MESSAGE_SIZE && answers > 0 {
+            buffer.pos = start;
+            messages.push(finish_message(buffer, answers)?);
+            buffer = new_message(false)?;
+            record.write(&mut buffer)?;
+            answers = 0;
+        }
+        answers += 1;
+    }
+    messages.push(finish_message(buffer, answers)?);
+
+    Ok(messages)
+}
+
+/// Sets the number of answers in the header of a message, and returns its bytes
+fn finish_message(mut buffer: Buffer, answers: u16) -> Result<Vec<u8>, BufferError> {
+    // The answer count follows the ID, the flags and the question count.
+    buffer.set_u16(6, answers)?;
+    let mut bytes = buffer.buf;
+    bytes.truncate(buffer.pos);
+
+    Ok(bytes)
+}
+
+/// Connects to a primary over TCP and sends it a query, with the SOA record of the version
+/// we have in the authority section for IXFR
+fn send_query(
+    primary: SocketAddr,
+    qname: &str,
+    qtype: QueryType,
+    soa: Option<DnsRecord>,
+) -> Result<TcpStream, TransferError> {
+    let mut stream = TcpStream::connect_timeout(&primary, TRANSFER_TIMEOUT)?;
+    stream.set_read_timeout(Some(TRANSFER_TIMEOUT))?;
+    stream.set_write_timeout(Some(TRANSFER_TIMEOUT))?;
+
+    let mut packet = DnsPacket::new();
+    packet.header.id = rand::thread_rng().gen();
+    packet
+        .questions
+        .push(DnsQuestion::new(qname.to_string(), qtype));
+    packet.authorities.extend(soa);
+
+    let request = packet.to_bytes()?;
+    stream.write_all(&(request.len() as u16).to_be_bytes())?;
+    stream.write_all(&request)?;
+
+    Ok(stream)
+}
+
+/// Reads a message prefixed with its length from a primary, failing if it is an error
+fn read_message(stream: &mut TcpStream, origin: &str) -> Result<DnsPacket, TransferError> {
+    let mut len = [0; 2];
+    stream.read_exact(&mut len)?;
+    let mut message = vec![0; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut message)?;
+
+    let response = DnsPacket::parse(&message)?;
+    if response.header.rescode != ResultCode::NOERROR {
+        return Err(TransferError::Refused(
+            origin.to_string(),
+            response.header.rescode,
+        ));
+    }
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn splits_transfers_in_messages() {
+        let mut records = vec![DnsRecord::SOA {
+            domain: "example.com".to_string(),
+            mname: "ns1.example.com".to_string(),
+            rname: "hostmaster.example.com".to_string(),
+            serial: 1,
+            refresh: 3600,
+            retry: 600,
+            expire: 86400,
+            minimum: 300,
+            ttl: 300,
+        }];
+        records.extend((0..2000).map(|i| DnsRecord::A {
+            domain: format!("host{}.example.com", i),
+            addr: Ipv4Addr::new(192, 0, 2, 1),
+            ttl: 300,
+        }));
+        let zone = Zone {
+            origin: "example.com".to_string(),
+            records,
+        };
+        let mut request = DnsPacket::new();
+        request.header.id = 1234;
+        request
+            .questions
+            .push(DnsQuestion::new("example.com".to_string(), QueryType::AXFR));
+
+        let messages = axfr_messages(&zone, &request).unwrap();
+        assert!(messages.len() > 1);
+        let packets: Vec<DnsPacket> = messages
+            .iter()
+            .map(|message| DnsPacket::parse(message).unwrap())
+            .collect();
+        assert!(messages.iter().all(|m| m.len() <= TRANSFER_MESSAGE_SIZE));
+        assert_eq!(packets[0].questions, request.questions);
+        assert!(packets[1..].iter().all(|p| p.questions.is_empty()));
+
+        let transferred: Vec<&DnsRecord> = packets.iter().flat_map(|p| &p.answers).collect();
+        assert_eq!(transferred.len(), 2002);
+        assert_eq!(transferred[0], &zone.records[0]);
+        assert_eq!(transferred[2001], &zone.records[0]);
+        assert_eq!(transferred[1000], &zone.records[1000]);
+    }
+}
@@ -0,0 +1,167 @@
+use std::io;
+use std::net::{IpAddr, UdpSocket};
+use std::sync::{Arc, RwLock};
+use std::thread;
+
+use log::{info, warn};
+
+use crate::buffer::BufferError;
+use crate::correlation;
+use crate::handler::{
+    contain_panic, handle_query, receive_query, try_fast_path, Context, UdpQuery,
+};
+use crate::listener::UdpLimits;
+use crate::privacy;
+use crate::queue::Queue;
+use crate::socket::{self, Backoff, ErrorClass};
+use crate::subnet::Subnet;
+
+/// The UDP server receives queries on a single thread, which answers those whose answer is
+/// cached right away, and hands the others over to a pool of workers through a bounded
+/// queue. When queries arrive faster than the workers can answer them, the overflow policy
+/// of the queue decides which are dropped (or whether reception waits), rather than letting
+/// memory and latency grow without bounds. A query causing a panic is logged and left
+/// unanswered, without stopping the thread handling it.
+///
+/// Errors of the socket are retried with a backoff, so that a persistent one can't make the
+/// receiving thread spin, and the socket is bound again when it breaks.
+///
+/// Queries from clients that aren't allowed to use the server are dropped.
+pub struct UdpServer {
+    context: Arc<Context>,
+    queue: Queue<UdpQuery>,
+    workers: usize,
+    /// Clients allowed to send queries, everyone if empty
+    allow: Vec<Subnet>,
+    /// The listening socket, shared with the workers to send the responses,
+    /// missing while it is bound again
+    socket: RwLock<Option<Arc<UdpSocket>>>,
+}
+
+impl UdpServer {
+    pub fn new(context: Arc<Context>, limits: UdpLimits) -> UdpServer {
+        UdpServer {
+            context,
+            queue: Queue::new(limits.queue_size, limits.queue_policy),
+            workers: limits.workers.max(1),
+            allow: Vec::new(),
+            socket: RwLock::new(None),
+        }
+    }
+
+    /// Only answers the clients in the given subnets, if any
+    pub fn with_allow(mut self, allow: Vec<Subnet>) -> UdpServer {
+        self.allow = allow;
+        self
+    }
+
+    /// Receives and answers queries forever, using `bind` to bind the socket again when it breaks
+    pub fn serve(self, socket: UdpSocket, bind: impl Fn() -> io::Result<UdpSocket>) {
+        *self.socket.write().unwrap() = Some(Arc::new(socket));
+        let server = Arc::new(self);
+
+        for _ in 0..server.workers {
+            let server = server.clone();
+            thread::spawn(move || server.work());
+        }
+
+        let mut backoff = Backoff::new();
+        loop {
+            let Some(socket) = server.socket() else {
+                let socket = socket::rebind("UDP", &bind, &mut backoff);
+                *server.socket.write().unwrap() = Some(Arc::new(socket));
+                server.context.stats.record_socket_rebind();
+                continue;
+            };
+
+            let query = match receive_query(&socket) {
+                Ok(query) => {
+                    backoff.reset();
+                    query
+                }
+                Err(e) => {
+                    let class = match &e {
+                        BufferError::IoError(e) => socket::classify(e),
+                        _ => ErrorClass::Transient,
+                    };
+                    server.context.stats.record_socket_error(class);
+                    let delay = backoff.delay(class);
+                    match class {
+                        ErrorClass::Transient => info!("Failed to receive a query: {}", e),
+                        _ => warn!(
+                            "Failed to receive a query ({} error), retrying in {:?}: {}",
+                            class.as_str(),
+                            delay,
+                            e
+                        ),
+                    }
+                    // The broken socket is closed, so that its port can be bound again.
+                    if class == ErrorClass::Fatal {
+                        *server.socket.write().unwrap() = None;
+                    }
+                    thread::sleep(delay);
+                    continue;
+                }
+            };
+
+            if !server.allows(query.src.ip()) {
+                info!(
+                    "Dropping query from {}, not allowed on this listener",
+                    privacy::client(query.src.ip())
+                );
+                continue;
+            }
+
+            let correlation = correlation::enter(query.correlation);
+            let fast_path = contain_panic(&query.data, || {
+                try_fast_path(&socket, &query, &server.context)
+            });
+            match fast_path {
+                Some(Ok(true)) | None => continue,
+                Some(Ok(false)) => {}
+                Some(Err(e)) => {
+                    warn!("An error occurred: {}", e);
+                    continue;
+                }
+            }
+
+            drop(correlation);
+
+            if !server.queue.push(query) {
+                warn!("Too many queries waiting, dropping one");
+                server.context.stats.record_queue_drop();
+            }
+            server.context.stats.set_queue_depth(server.queue.len());
+        }
+    }
+
+    /// Whether a client is allowed to send queries
+    fn allows(&self, client: IpAddr) -> bool {
+        self.allow.is_empty() || self.allow.iter().any(|subnet| subnet.contains(client))
+    }
+
+    /// Returns the listening socket, unless it is being bound again
+    fn socket(&self) -> Option<Arc<UdpSocket>> {
+        self.socket.read().unwrap().clone()
+    }
+
+    /// Answers the queries waiting in the queue, forever
+    fn work(&self) {
+        loop {
+            let query = self.queue.pop();
+            self.context.stats.set_queue_depth(self.queue.len());
+
+            let Some(socket) = self.socket() else {
+                warn!("Dropping a query, the UDP socket is being bound again");
+                continue;
+            };
+            let _correlation = correlation::enter(query.correlation);
+            let data = query.data.clone();
+            if let Some(Err(e)) =
+                contain_panic(&data, || handle_query(&socket, query, &self.context))
+            {
+                warn!("An error occurred: {}", e);
+            }
+        }
+    }
+}
@@ -0,0 +1,535 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use crate::question::QueryType;
+use crate::svcb::SvcParam;
+
+/// Maximum length of a single label, as defined in RFC 1035
+const MAX_LABEL_LEN: usize = 63;
+/// Maximum length of a domain name in presentation format (without the trailing dot)
+const MAX_NAME_LEN: usize = 253;
+/// Maximum TTL value, as defined in RFC 2181:
+/// see https://tools.ietf.org/html/rfc2181#section-8
+const MAX_TTL: u32 = 0x7FFF_FFFF;
+
+/// `ValidationError` is an enum that represents the ways in which record data
+/// in presentation format can be malformed
+#[derive(thiserror::Error, Debug)]
+pub enum ValidationError {
+    #[error("Name \"{0}\" exceeds {MAX_NAME_LEN} characters of length")]
+    NameTooLong(String),
+    #[error("Label \"{0}\" exceeds {MAX_LABEL_LEN} characters of length")]
+    LabelTooLong(String),
+    #[error("Name \"{0}\" contains an empty label")]
+    EmptyLabel(String),
+    #[error("Name \"{0}\" contains invalid character {1:?}")]
+    InvalidCharacter(String, char),
+    #[error("Invalid IPv4 address \"{0}\"")]
+    InvalidIpv4(String),
+    #[error("Invalid IPv6 address \"{0}\"")]
+    InvalidIpv6(String),
+    #[error("Invalid TTL \"{0}\", expected a duration between 0 and {MAX_TTL} seconds")]
+    InvalidTtl(String),
+    #[error("Invalid MX priority \"{0}\", expected a number between 0 and 65535")]
+    InvalidPriority(String),
+    #[error("Invalid {0} \"{1}\", expected a number between 0 and 255")]
+    InvalidByte(&'static str, String),
+    #[error("Invalid {0} \"{1}\", expected a number between 0 and 65535")]
+    InvalidShort(&'static str, String),
+    #[error("Invalid {0} \"{1}\", expected a number between 0 and 4294967295")]
+    InvalidLong(&'static str, String),
+    #[error("Invalid length \"{0}\"")]
+    InvalidLength(String),
+    #[error("Invalid hexadecimal data \"{0}\"")]
+    InvalidHex(String),
+    #[error("Invalid base64 data \"{0}\"")]
+    InvalidBase64(String),
+    #[error("Unknown certificate type \"{0}\"")]
+    InvalidCertificateType(String),
+    #[error("Invalid generic data, expected \\# <length> <hex data>")]
+    InvalidGenericData,
+    #[error("Character string \"{0}\" exceeds 255 characters of length")]
+    StringTooLong(String),
+    #[error("Invalid CAA property tag \"{0}\", expected 1 to 255 letters and digits")]
+    InvalidCaaTag(String),
+    #[error("Invalid timestamp \"{0}\", expected YYYYMMDDHHmmSS or seconds since the epoch")]
+    InvalidTimestamp(String),
+    #[error("Unknown record type \"{0}\"")]
+    InvalidType(String),
+    #[error("Invalid service parameter \"{0}\"")]
+    InvalidSvcParam(String),
+    #[error("Invalid location: {0}")]
+    InvalidLocation(&'static str),
+    #[error("Unsupported record type \"{0}\"")]
+    UnsupportedType(String),
+    #[error("Unbalanced parentheses")]
+    UnbalancedParentheses,
+    #[error("Missing {0}")]
+    MissingField(&'static str),
+    #[error("Unexpected trailing data \"{0}\"")]
+    TrailingData(String),
+    #[error("Unsupported directive \"{0}\"")]
+    UnsupportedDirective(String),
+    #[error("Too many nested $INCLUDE directives")]
+    IncludeTooDeep,
+    #[error("Invalid $GENERATE range \"{0}\", expected <start>-<stop>[/<step>]")]
+    InvalidRange(String),
+    #[error("Invalid $GENERATE modifier \"{0}\", expected ${{<offset>[,<width>[,<base>]]}}")]
+    InvalidModifier(String),
+}
+
+/// Validates a domain name in presentation format (without the trailing dot):
+/// the name must fit the wire format limits and only contain letters, digits,
+/// hyphens and underscores, or be a wildcard label.
+pub fn validate_name(name: &str) -> Result<(), ValidationError> {
+    // The root domain is represented by the empty string
+    if name.is_empty() {
+        return Ok(());
+    }
+    if name.len() > MAX_NAME_LEN {
+        return Err(ValidationError::NameTooLong(name.to_string()));
+    }
+
+    for label in name.split('.') {
+        if label.is_empty() {
+            return Err(ValidationError::EmptyLabel(name.to_string()));
+        }
+        if label.len() > MAX_LABEL_LEN {
+            return Err(ValidationError::LabelTooLong(label.to_string()));
+        }
+        if label == "*" {
+            continue;
+        }
+        if let Some(c) = label
+            .chars()
+            .find(|c| !(c.is_ascii_alphanumeric() || *c == '-' || *c == '_'))
+        {
+            return Err(ValidationError::InvalidCharacter(name.to_string(), c));
+        }
+    }
+
+    Ok(())
+}
+
+/// Parses an IPv4 address literal
+pub fn parse_ipv4(value: &str) -> Result<Ipv4Addr, ValidationError> {
+    value
+        .parse()
+        .map_err(|_| ValidationError::InvalidIpv4(value.to_string()))
+}
+
+/// Parses an IPv6 address literal
+pub fn parse_ipv6(value: &str) -> Result<Ipv6Addr, ValidationError> {
+    value
+        .parse()
+        .map_err(|_| ValidationError::InvalidIpv6(value.to_string()))
+}
+
+/// Parses a TTL in seconds, or with the units of BIND (e.g. `1h30m`), which must fit in 31 bits
+pub fn parse_ttl(value: &str) -> Result<u32, ValidationError> {
+    let invalid = || ValidationError::InvalidTtl(value.to_string());
+    if value.is_empty() {
+        return Err(invalid());
+    }
+
+    let mut ttl: u64 = 0;
+    let mut number: Option<u64> = None;
+    for c in value.chars() {
+        match c.to_digit(10) {
+            Some(digit) => number = Some(number.unwrap_or(0) * 10 + digit as u64),
+            None => {
+                let unit = match c.to_ascii_lowercase() {
+                    's' => 1,
+                    'm' => 60,
+                    'h' => 3600,
+                    'd' => 86400,
+                    'w' => 604800,
+                    _ => return Err(invalid()),
+                };
+                ttl += number.take().ok_or_else(invalid)? * unit;
+            }
+        }
+        if number.unwrap_or(0) > MAX_TTL as u64 || ttl > MAX_TTL as u64 {
+            return Err(invalid());
+        }
+    }
+    ttl += number.unwrap_or(0);
+
+    match u32::try_from(ttl) {
+        Ok(ttl) if ttl <= MAX_TTL => Ok(ttl),
+        _ => Err(invalid()),
+    }
+}
+
+/// Parses the preference of an MX record
+pub fn parse_priority(value: &str) -> Result<u16, ValidationError> {
+    value
+        .parse()
+        .map_err(|_| ValidationError::InvalidPriority(value.to_string()))
+}
+
+/// Parses a single byte field of some record data, such as a flag or an algorithm number
+pub fn parse_u8(value: &str, field: &'static str) -> Result<u8, ValidationError> {
+    value
+        .parse()
+        .map_err(|_| ValidationError::InvalidByte(field, value.to_string()))
+}
+
+/// Parses a two bytes field of some record data, such as a weight or a key tag
+pub fn parse_u16(value: &str, field: &'static str) -> Result<u16, ValidationError> {
+    value
+        .parse()
+        .map_err(|_| ValidationError::InvalidShort(field, value.to_string()))
+}
+
+/// Parses a four bytes field of some record data, such as a serial number
+pub fn parse_u32(value: &str, field: &'static str) -> Result<u32, ValidationError> {
+    value
+        .parse()
+        .map_err(|_| ValidationError::InvalidLong(field, value.to_string()))
+}
+
+/// Parses the length of some record data
+pub fn parse_length(value: &str) -> Result<usize, ValidationError> {
+    match value.parse::<u16>() {
+        Ok(len) => Ok(usize::from(len)),
+        Err(_) => Err(ValidationError::InvalidLength(value.to_string())),
+    }
+}
+
+/// Parses a string of hexadecimal digits into bytes
+pub fn parse_hex(value: &str) -> Result<Vec<u8>, ValidationError> {
+    let invalid = || ValidationError::InvalidHex(value.to_string());
+    if !value.len().is_multiple_of(2) || !value.is_ascii() {
+        return Err(invalid());
+    }
+
+    (0..value.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&value[i..i + 2], 16).map_err(|_| invalid()))
+        .collect()
+}
+
+/// Parses base64 encoded data, as defined in RFC 4648:
+/// see https://tools.ietf.org/html/rfc4648#section-4
+pub fn parse_base64(value: &str) -> Result<Vec<u8>, ValidationError> {
+    let invalid = || ValidationError::InvalidBase64(value.to_string());
+
+    let data = value.trim_end_matches('=');
+    if !value.len().is_multiple_of(4) || value.len() - data.len() > 2 {
+        return Err(invalid());
+    }
+
+    let mut bytes = Vec::with_capacity(data.len() * 3 / 4);
+    let mut acc: u32 = 0;
+    let mut bits = 0;
+    for c in data.bytes() {
+        let sextet = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return Err(invalid()),
+        };
+
+        acc = (acc << 6) | u32::from(sextet);
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((acc >> bits) as u8);
+        }
+    }
+
+    Ok(bytes)
+}
+
+/// Parses a certificate type, given either as a number or as a mnemonic:
+/// see https://tools.ietf.org/html/rfc4398#section-2.1
+pub fn parse_cert_type(value: &str) -> Result<u16, ValidationError> {
+    let cert_type = match value.to_uppercase().as_str() {
+        "PKIX" => 1,
+        "SPKI" => 2,
+        "PGP" => 3,
+        "IPKIX" => 4,
+        "ISPKI" => 5,
+        "IPGP" => 6,
+        "ACPKIX" => 7,
+        "IACPKIX" => 8,
+        "URI" => 253,
+        "OID" => 254,
+        _ => value
+            .parse()
+            .map_err(|_| ValidationError::InvalidCertificateType(value.to_string()))?,
+    };
+
+    Ok(cert_type)
+}
+
+/// Checks that a character string fits in its single length byte
+pub fn parse_character_string(value: String) -> Result<String, ValidationError> {
+    if value.len() > 0xFF {
+        return Err(ValidationError::StringTooLong(value));
+    }
+
+    Ok(value)
+}
+
+/// Parses the property tag of a CAA record, made of letters and digits:
+/// see https://tools.ietf.org/html/rfc8659#section-4.1
+pub fn parse_caa_tag(value: String) -> Result<String, ValidationError> {
+    if value.is_empty() || value.len() > 0xFF || !value.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(ValidationError::InvalidCaaTag(value));
+    }
+
+    Ok(value)
+}
+
+/// Parses the timestamp of a signature, given as YYYYMMDDHHmmSS in UTC or as seconds since
+/// the epoch: see https://tools.ietf.org/html/rfc4034#section-3.2
+pub fn parse_timestamp(value: &str) -> Result<u32, ValidationError> {
+    let invalid = || ValidationError::InvalidTimestamp(value.to_string());
+    if value.len() != 14 {
+        return value.parse().map_err(|_| invalid());
+    }
+
+    let field = |range: std::ops::Range<usize>| -> Result<i64, ValidationError> {
+        value[range].parse().map_err(|_| invalid())
+    };
+    let (year, month, day) = (field(0..4)?, field(4..6)?, field(6..8)?);
+    let (hour, minute, second) = (field(8..10)?, field(10..12)?, field(12..14)?);
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 59
+    {
+        return Err(invalid());
+    }
+
+    // Days since the epoch of the date, from the algorithm of Howard Hinnant.
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+
+    // Timestamps are serial numbers, so they wrap around rather than overflow.
+    Ok((days * 86400 + hour * 3600 + minute * 60 + second).rem_euclid(1 << 32) as u32)
+}
+
+/// Parses a record type, given as its mnemonic (e.g. `AAAA`) or as `TYPE<N>`
+pub fn parse_type(value: &str) -> Result<u16, ValidationError> {
+    value
+        .parse::<QueryType>()
+        .map(QueryType::to_num)
+        .map_err(|_| ValidationError::InvalidType(value.to_string()))
+}
+
+/// Parses a parameter of a SVCB or HTTPS record, given as <KEY>[=<VALUE>]:
+/// see https://tools.ietf.org/html/rfc9460#section-2.1
+pub fn parse_svc_param(value: &str) -> Result<SvcParam, ValidationError> {
+    let invalid = || ValidationError::InvalidSvcParam(value.to_string());
+
+    let (name, data) = value.split_once('=').unwrap_or((value, ""));
+    // Values can be quoted, e.g. when they hold spaces.
+    let data = data
+        .strip_prefix('"')
+        .and_then(|data| data.strip_suffix('"'))
+        .unwrap_or(data);
+    let key = svc_param_key(name).ok_or_else(invalid)?;
+    let list = || data.split(',').filter(|item| !item.is_empty());
+
+    let param = match key {
+        1 => SvcParam::Alpn(list().map(str::to_string).collect()),
+        3 => SvcParam::Port(data.parse().map_err(|_| invalid())?),
+        4 => SvcParam::Ipv4Hint(list().map(parse_ipv4).collect::<Result<_, _>>()?),
+        6 => SvcParam::Ipv6Hint(list().map(parse_ipv6).collect::<Result<_, _>>()?),
+        // The keys a client must support to use the record
+        0 => SvcParam::Unknown {
+            key,
+            value: list()
+                .map(|name| svc_param_key(name).map(u16::to_be_bytes))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(invalid)?
+                .concat(),
+        },
+        // The Encrypted ClientHello configuration, in base64
+        5 => SvcParam::Unknown {
+            key,
+            value: parse_base64(data)?,
+        },
+        _ => SvcParam::Unknown {
+            key,
+            value: data.as_bytes().to_vec(),
+        },
+    };
+    match &param {
+        SvcParam::Alpn(ids) if ids.is_empty() || ids.iter().any(|id| id.len() > 0xFF) => {
+            Err(invalid())
+        }
+        SvcParam::Ipv4Hint(addrs) if addrs.is_empty() => Err(invalid()),
+        SvcParam::Ipv6Hint(addrs) if addrs.is_empty() => Err(invalid()),
+        _ => Ok(param),
+    }
+}
+
+/// Returns the key of a service parameter from its name, e.g. `alpn` or `key65000`
+fn svc_param_key(name: &str) -> Option<u16> {
+    let key = match name {
+        "mandatory" => 0,
+        "alpn" => 1,
+        "no-default-alpn" => 2,
+        "port" => 3,
+        "ipv4hint" => 4,
+        "ech" => 5,
+        "ipv6hint" => 6,
+        _ => name.strip_prefix("key")?.parse().ok()?,
+    };
+
+    Some(key)
+}
+
+/// The wire representation of a location, as defined in RFC 1876:
+/// see https://tools.ietf.org/html/rfc1876#section-2
+pub struct Location {
+    pub size: u8,
+    pub horiz_pre: u8,
+    pub vert_pre: u8,
+    pub latitude: u32,
+    pub longitude: u32,
+    pub altitude: u32,
+}
+
+/// Parses a location in the presentation format of RFC 1876:
+/// `d1 [m1 [s1]] {"N"|"S"} d2 [m2 [s2]] {"E"|"W"} alt["m"] [siz["m"] [hp["m"] [vp["m"]]]]`
+/// see https://tools.ietf.org/html/rfc1876#section-3
+pub fn parse_loc(fields: &[String]) -> Result<Location, ValidationError> {
+    let mut fields = fields.iter().map(String::as_str);
+
+    let latitude = parse_coordinate(&mut fields, ('N', 'S'), 90)?;
+    let longitude = parse_coordinate(&mut fields, ('E', 'W'), 180)?;
+
+    let altitude = fields
+        .next()
+        .and_then(parse_meters)
+        .filter(|alt| (-100_000.0..=42_849_672.95).contains(alt))
+        .ok_or(ValidationError::InvalidLocation("altitude"))?;
+
+    // Size and precisions default to 1m, 10km and 10m respectively.
+    let mut precision = |default, field| match fields.next() {
+        Some(value) => parse_meters(value)
+            .and_then(encode_precision)
+            .ok_or(ValidationError::InvalidLocation(field)),
+        None => Ok(default),
+    };
+    let size = precision(0x12, "size")?;
+    let horiz_pre = precision(0x16, "horizontal precision")?;
+    let vert_pre = precision(0x13, "vertical precision")?;
+
+    if fields.next().is_some() {
+        return Err(ValidationError::InvalidLocation("trailing data"));
+    }
+
+    Ok(Location {
+        size,
+        horiz_pre,
+        vert_pre,
+        latitude,
+        longitude,
+        // Altitudes are stored in centimeters above a base of 100km below the WGS 84 spheroid.
+        altitude: ((altitude * 100.0).round() + 10_000_000.0) as u32,
+    })
+}
+
+/// Parses a latitude or longitude in degrees, minutes and seconds followed by the hemisphere,
+/// and returns it in thousandths of a second of arc offset by 2^31 (the equator or prime meridian).
+fn parse_coordinate<'a>(
+    fields: &mut impl Iterator<Item = &'a str>,
+    (positive, negative): (char, char),
+    max_degrees: u32,
+) -> Result<u32, ValidationError> {
+    let invalid = || ValidationError::InvalidLocation("coordinate");
+
+    let mut parts = Vec::new();
+    let sign = loop {
+        match fields.next() {
+            Some(field) if field.len() == 1 && field.starts_with(positive) => break 1,
+            Some(field) if field.len() == 1 && field.starts_with(negative) => break -1,
+            Some(field) if parts.len() < 3 => parts.push(field),
+            _ => return Err(invalid()),
+        }
+    };
+
+    let degrees: u32 = parts
+        .first()
+        .and_then(|d| d.parse().ok())
+        .filter(|d| *d <= max_degrees)
+        .ok_or_else(invalid)?;
+    let minutes: u32 = match parts.get(1) {
+        Some(m) => m.parse().ok().filter(|m| *m < 60).ok_or_else(invalid)?,
+        None => 0,
+    };
+    let seconds: f64 = match parts.get(2) {
+        Some(s) => s
+            .parse()
+            .ok()
+            .filter(|s| (0.0..60.0).contains(s))
+            .ok_or_else(invalid)?,
+        None => 0.0,
+    };
+
+    let value = i64::from((degrees * 60 + minutes) * 60) * 1000 + (seconds * 1000.0).round() as i64;
+    if value > i64::from(max_degrees) * 3_600_000 {
+        return Err(invalid());
+    }
+
+    Ok(((1i64 << 31) + sign * value) as u32)
+}
+
+/// Parses a distance in meters with an optional "m" suffix
+fn parse_meters(value: &str) -> Option<f64> {
+    value.strip_suffix('m').unwrap_or(value).parse().ok()
+}
+
+/// Encodes a precision in meters as a mantissa and a power of ten in centimeters
+fn encode_precision(meters: f64) -> Option<u8> {
+    if !(0.0..=90_000_000.0).contains(&meters) {
+        return None;
+    }
+
+    let mut mantissa = (meters * 100.0).round() as u64;
+    let mut exponent = 0;
+    while mantissa >= 10 {
+        mantissa /= 10;
+        exponent += 1;
+    }
+
+    Some(((mantissa as u8) << 4) | exponent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn loc(value: &str) -> Result<Location, ValidationError> {
+        parse_loc(
+            &value
+                .split_whitespace()
+                .map(String::from)
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    #[test]
+    fn rejects_coordinates_out_of_range() {
+        let location = loc("52 22 23.000 N 4 53 32.000 E -2.00m").unwrap();
+        assert_eq!(location.latitude, (1 << 31) + 188_543_000);
+        assert_eq!(location.longitude, (1 << 31) + 17_612_000);
+
+        assert!(loc("90 0 0 S 180 0 0 W 0m").is_ok());
+        assert!(loc("90 0 1 N 0 E 0m").is_err());
+        assert!(loc("99999999 0 0 N 0 E 0m").is_err());
+        assert!(loc("0 N 4294967295 E 0m").is_err());
+    }
+}
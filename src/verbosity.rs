@@ -0,0 +1,128 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::RwLock;
+
+use log::{LevelFilter, Metadata};
+
+/// Crate the modules are named relative to
+const CRATE: &str = "vodo";
+
+/// Level of the lines logged by the modules without a level of their own, as an index into
+/// `LEVELS`, everything by default
+static LEVEL: AtomicUsize = AtomicUsize::new(LevelFilter::Trace as usize);
+
+/// Levels of the modules given one, by module path (e.g. `vodo::handler`)
+static MODULES: RwLock<BTreeMap<String, LevelFilter>> = RwLock::new(BTreeMap::new());
+
+/// Level filters, in the order of their numeric values
+const LEVELS: [LevelFilter; 6] = [
+    LevelFilter::Off,
+    LevelFilter::Error,
+    LevelFilter::Warn,
+    LevelFilter::Info,
+    LevelFilter::Debug,
+    LevelFilter::Trace,
+];
+
+/// Returns the level of the modules without a level of their own
+pub fn level() -> LevelFilter {
+    LEVELS[LEVEL.load(Ordering::Relaxed)]
+}
+
+/// Sets the level of the modules without a level of their own, e.g. to silence the logs
+/// of every query but while debugging a live issue
+pub fn set_level(level: LevelFilter) {
+    LEVEL.store(level as usize, Ordering::Relaxed);
+    update_max_level();
+}
+
+/// Sets the level of a module and its submodules, given relative to the crate (`handler`)
+/// or as a full path (`vodo::handler`), overriding the level of the others
+pub fn set_module_level(module: &str, level: LevelFilter) {
+    MODULES.write().unwrap().insert(module_path(module), level);
+    update_max_level();
+}
+
+/// Removes the level of a module, which then logs at the level of the others. Returns false
+/// if the module had no level of its own.
+pub fn clear_module_level(module: &str) -> bool {
+    let removed = MODULES.write().unwrap().remove(&module_path(module));
+    update_max_level();
+    removed.is_some()
+}
+
+/// Whether a line is logged: at the level of the most specific module with a level of its
+/// own containing the line, or at the level of the others
+pub fn enabled(metadata: &Metadata) -> bool {
+    let target = metadata.target();
+    let modules = MODULES.read().unwrap();
+    let level = modules
+        .iter()
+        .filter(|(module, _)| {
+            target == module.as_str()
+                || target
+                    .strip_prefix(module.as_str())
+                    .is_some_and(|rest| rest.starts_with("::"))
+        })
+        .max_by_key(|(module, _)| module.len())
+        .map_or_else(level, |(_, level)| *level);
+
+    metadata.level() <= level
+}
+
+/// Describes the levels, the general one first and then one line per module
+pub fn describe() -> String {
+    let mut description = format!("{}\n", level());
+    for (module, level) in MODULES.read().unwrap().iter() {
+        description.push_str(&format!("{} {}\n", module, level));
+    }
+    description
+}
+
+/// Returns the full path of a module given relative to the crate
+fn module_path(module: &str) -> String {
+    match module == CRATE || module.starts_with(&format!("{}::", CRATE)) {
+        true => module.to_string(),
+        false => format!("{}::{}", CRATE, module),
+    }
+}
+
+/// Lets the `log` macros skip the lines no module logs before formatting them
+fn update_max_level() {
+    let modules = MODULES.read().unwrap();
+    let max = modules.values().copied().fold(level(), Ord::max);
+    log::set_max_level(max);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+
+    #[test]
+    fn filters_by_most_specific_module() {
+        set_level(LevelFilter::Warn);
+        set_module_level("handler", LevelFilter::Trace);
+        set_module_level("vodo::handler::fast", LevelFilter::Error);
+
+        let enabled = |target: &str, level: Level| {
+            enabled(&Metadata::builder().target(target).level(level).build())
+        };
+        assert!(enabled("vodo::handler", Level::Trace));
+        assert!(!enabled("vodo::handlers", Level::Info));
+        assert!(!enabled("vodo::handler::fast", Level::Warn));
+        assert!(enabled("vodo::cache", Level::Warn));
+        assert!(!enabled("vodo::cache", Level::Info));
+        assert_eq!(
+            describe(),
+            "WARN\nvodo::handler TRACE\nvodo::handler::fast ERROR\n"
+        );
+
+        assert!(clear_module_level("vodo::handler"));
+        assert!(!clear_module_level("handler"));
+        assert!(!enabled("vodo::handler", Level::Info));
+
+        clear_module_level("handler::fast");
+        set_level(LevelFilter::Trace);
+    }
+}
@@ -0,0 +1,23 @@
+use log::{info, warn};
+
+use crate::cache::Cache;
+use crate::handler::recursive_lookup;
+use crate::infra::InfraCache;
+use crate::question::QueryType;
+
+/// Resolves the A and AAAA records of every domain of a seed list, so that they are cached
+/// by the time clients ask for them. The server calls it once on startup, from a thread of
+/// its own, so that clients are answered while the list is still being resolved.
+pub fn warm_up(names: &[String], cache: &Cache, infra: &InfraCache) {
+    info!("Warming up the cache with {} domains", names.len());
+
+    for name in names {
+        for qtype in [QueryType::A, QueryType::AAAA] {
+            if let Err(e) = recursive_lookup(name, qtype, cache, infra) {
+                warn!("Failed to warm up {:?} {}: {}", qtype, name, e);
+            }
+        }
+    }
+
+    info!("Cache warm-up completed");
+}
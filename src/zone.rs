@@ -0,0 +1,1232 @@
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+
+use crate::journal::{self, Change, Journal};
+use crate::packet::{DnsPacket, Records};
+use crate::question::{QueryClass, QueryType};
+use crate::record::DnsRecord;
+use crate::resultcode::ResultCode;
+use crate::reverse;
+use crate::stats::RECURSIVE_BUCKET;
+use crate::validate::{
+    parse_base64, parse_caa_tag, parse_cert_type, parse_character_string, parse_hex, parse_ipv4,
+    parse_ipv6, parse_length, parse_loc, parse_priority, parse_svc_param, parse_timestamp,
+    parse_ttl, parse_type, parse_u16, parse_u32, parse_u8, validate_name, ValidationError,
+};
+
+/// TTL used for records that don't specify one
+const DEFAULT_TTL: u32 = 3600;
+/// TTL used for records coming from a hosts file
+const HOSTS_TTL: u32 = 300;
+/// Statistics bucket of the queries answered from the hosts file
+const HOSTS_BUCKET: &str = "hosts";
+
+/// How deeply `$INCLUDE` directives can be nested, which also stops files including themselves
+const MAX_INCLUDE_DEPTH: usize = 8;
+
+/// `ZoneError` is an enum that represents the errors that can occur while loading
+/// zone files and hosts files
+#[derive(thiserror::Error, Debug)]
+pub enum ZoneError {
+    #[error("{file}:{line}: {kind}")]
+    Invalid {
+        file: String,
+        line: usize,
+        kind: ValidationError,
+    },
+    #[error("{file}:{line}: Cannot include \"{path}\": {error}")]
+    Include {
+        file: String,
+        line: usize,
+        path: String,
+        error: std::io::Error,
+    },
+    #[error("Zone origin \"{0}\" is invalid: {1}")]
+    InvalidOrigin(String, ValidationError),
+    #[error("I/O error: {0}")]
+    IoError(#[from] std::io::Error),
+}
+
+/// A zone hosted by the server, loaded from a master file as described in RFC 1035:
+/// see https://tools.ietf.org/html/rfc1035#section-5
+#[derive(Clone, Debug)]
+pub struct Zone {
+    pub origin: String,
+    pub records: Vec<DnsRecord>,
+}
+
+impl Zone {
+    /// Loads the zone for `origin` from a master file. Each line holds a single record in the
+    /// form `<name> [<ttl>] [IN] <type> <rdata>`, where `@` stands for the origin, names not
+    /// ending with a dot are relative to the origin, and an omitted name repeats the previous one.
+    /// Every record is validated, and errors point to the offending file and line.
+    ///
+    /// The directives of RFC 1035 and RFC 2308 are supported: `$ORIGIN <name>` changes the
+    /// origin of relative names, `$TTL <ttl>` the TTL of records that don't specify one, and
+    /// `$INCLUDE <path> [<origin>]` loads the records of another file, relative to the directory
+    /// of the including one. BIND's `$GENERATE` generates records from a template.
+    pub fn load(origin: &str, path: &Path) -> Result<Zone, ZoneError> {
+        let origin = normalize(origin);
+        validate_name(&origin).map_err(|e| ZoneError::InvalidOrigin(origin.clone(), e))?;
+
+        let mut records = Vec::new();
+        let state = MasterFile {
+            origin: origin.clone(),
+            owner: origin.clone(),
+            ttl: DEFAULT_TTL,
+        };
+        read_master_file(path, state, &mut records, 0)?;
+
+        Ok(Zone { origin, records })
+    }
+
+    /// Returns the problems of the zone that don't prevent it from being loaded, but are
+    /// likely mistakes: a missing or duplicated SOA record at the apex, or SOA values
+    /// that don't make sense, as recommended in RFC 1912:
+    /// see https://tools.ietf.org/html/rfc1912#section-2.2
+    pub fn check(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        let soas: Vec<_> = self
+            .records
+            .iter()
+            .filter(|record| record.qtype() == QueryType::SOA)
+            .collect();
+        if soas.len() > 1 {
+            problems.push(format!("{} SOA records found, expected one", soas.len()));
+        }
+
+        match soas.first() {
+            Some(DnsRecord::SOA {
+                domain,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+                ..
+            }) => {
+                if *domain != self.origin {
+                    problems.push(format!(
+                        "SOA record owned by {} instead of the apex",
+                        domain
+                    ));
+                }
+                if *serial == 0 {
+                    problems.push("SOA serial is 0".to_string());
+                }
+                if retry >= refresh {
+                    problems.push(format!(
+                        "SOA retry ({}) is not lower than refresh ({})",
+                        retry, refresh
+                    ));
+                }
+                if u64::from(*expire) <= u64::from(*refresh) + u64::from(*retry) {
+                    problems.push(format!(
+                        "SOA expire ({}) is not greater than refresh + retry ({})",
+                        expire,
+                        u64::from(*refresh) + u64::from(*retry)
+                    ));
+                }
+                if *minimum > 86400 {
+                    problems.push(format!(
+                        "SOA minimum ({}) exceeds one day, negative answers would be cached too long",
+                        minimum
+                    ));
+                }
+            }
+            _ => problems.push("no SOA record found".to_string()),
+        }
+
+        problems
+    }
+
+    /// Returns true if the name is the origin of the zone or one of its subdomains
+    pub fn contains(&self, name: &str) -> bool {
+        is_subdomain(name, &self.origin)
+    }
+
+    /// Returns the SOA record to send in the authority section of negative answers
+    /// (NXDOMAIN and NODATA), so that resolvers can cache them. Its TTL is the lower of
+    /// its own and the SOA minimum, which is the TTL of negative answers:
+    /// see https://tools.ietf.org/html/rfc2308#section-3
+    pub fn negative_soa(&self) -> Option<DnsRecord> {
+        match self.soa()? {
+            record @ DnsRecord::SOA { minimum, ttl, .. } => {
+                Some(record.with_ttl((*ttl).min(*minimum)))
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the SOA record at the apex of the zone
+    pub fn soa(&self) -> Option<&DnsRecord> {
+        self.records.iter().find(|record| {
+            record.qtype() == QueryType::SOA && record.domain() == Some(&self.origin)
+        })
+    }
+
+    /// Returns the serial of the zone, from its SOA record
+    pub fn serial(&self) -> Option<u32> {
+        self.soa().and_then(journal::serial)
+    }
+
+    /// Increments the serial of the zone, after its records were changed
+    pub fn increment_serial(&mut self) {
+        let origin = &self.origin;
+        for record in &mut self.records {
+            if let DnsRecord::SOA { domain, serial, .. } = record {
+                if domain == origin {
+                    *serial = serial.wrapping_add(1);
+                }
+            }
+        }
+    }
+
+    /// Applies a change to the zone, making it the new version
+    pub fn apply(&mut self, change: &Change) {
+        self.records
+            .retain(|record| record.qtype() != QueryType::SOA && !change.removed.contains(record));
+        self.records.insert(0, change.to.clone());
+        self.records.extend(change.added.iter().cloned());
+    }
+}
+
+/// State of the parser of a master file, changed by its directives
+#[derive(Clone, Debug)]
+struct MasterFile {
+    /// Origin relative names are resolved against
+    origin: String,
+    /// Owner of the previous record, repeated by records that omit it
+    owner: String,
+    /// TTL of the records that don't specify one
+    ttl: u32,
+}
+
+/// Reads the records of a master file, starting from the given state, into `records`.
+/// Included files start from the state of the including file at that point, and what they
+/// change (origin, owner and TTL) doesn't apply to the including file.
+fn read_master_file(
+    path: &Path,
+    mut state: MasterFile,
+    records: &mut Vec<DnsRecord>,
+    nesting: usize,
+) -> Result<(), ZoneError> {
+    let file = path.display().to_string();
+    let contents = fs::read_to_string(path)?;
+
+    // Records can span multiple lines when enclosed in parentheses, so fields are
+    // accumulated until they are balanced, and errors point to the first line.
+    let mut fields = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    let mut repeat_owner = false;
+    for (i, line) in contents.lines().enumerate() {
+        let (line_fields, parens) = tokenize(line);
+        if depth == 0 {
+            if line_fields.is_empty() && parens == 0 {
+                continue;
+            }
+            start = i + 1;
+            repeat_owner = line.starts_with(char::is_whitespace);
+        }
+
+        fields.extend(line_fields);
+        depth += parens;
+        if depth > 0 {
+            continue;
+        }
+
+        let invalid = |kind| ZoneError::Invalid {
+            file: file.clone(),
+            line: start,
+            kind,
+        };
+        if depth < 0 {
+            return Err(invalid(ValidationError::UnbalancedParentheses));
+        }
+
+        let fields = std::mem::take(&mut fields);
+        let directive = fields
+            .first()
+            .filter(|field| !repeat_owner && field.starts_with('$'))
+            .map(|field| field.to_uppercase());
+        match directive.as_deref() {
+            None => {
+                let record = parse_record(
+                    fields,
+                    repeat_owner,
+                    &state.origin,
+                    &mut state.owner,
+                    state.ttl,
+                )
+                .map_err(invalid)?;
+                records.push(record);
+            }
+            Some("$ORIGIN") => {
+                let origin = directive_argument(fields, "origin").map_err(invalid)?;
+                state.origin = absolute_name(&origin, &state.origin).map_err(invalid)?;
+            }
+            Some("$TTL") => {
+                let ttl = directive_argument(fields, "TTL").map_err(invalid)?;
+                state.ttl = parse_ttl(&ttl).map_err(invalid)?;
+            }
+            Some("$GENERATE") => records.extend(generate(fields, &state).map_err(invalid)?),
+            Some("$INCLUDE") => {
+                if nesting >= MAX_INCLUDE_DEPTH {
+                    return Err(invalid(ValidationError::IncludeTooDeep));
+                }
+
+                let mut fields = fields.into_iter().skip(1);
+                let included = fields
+                    .next()
+                    .ok_or(ValidationError::MissingField("path"))
+                    .map_err(invalid)?;
+                let mut included_state = state.clone();
+                if let Some(origin) = fields.next() {
+                    let origin = absolute_name(&origin, &state.origin).map_err(invalid)?;
+                    included_state.owner = origin.clone();
+                    included_state.origin = origin;
+                }
+                if let Some(extra) = fields.next() {
+                    return Err(invalid(ValidationError::TrailingData(extra)));
+                }
+
+                let included = path.parent().unwrap_or(Path::new("")).join(included);
+                read_master_file(&included, included_state, records, nesting + 1).map_err(|e| {
+                    match e {
+                        ZoneError::IoError(error) => ZoneError::Include {
+                            file: file.clone(),
+                            line: start,
+                            path: included.display().to_string(),
+                            error,
+                        },
+                        e => e,
+                    }
+                })?;
+            }
+            Some(_) => {
+                return Err(invalid(ValidationError::UnsupportedDirective(
+                    fields[0].clone(),
+                )))
+            }
+        }
+    }
+
+    if depth != 0 {
+        return Err(ZoneError::Invalid {
+            file,
+            line: start,
+            kind: ValidationError::UnbalancedParentheses,
+        });
+    }
+
+    Ok(())
+}
+
+/// Returns the single argument of a directive
+fn directive_argument(fields: Vec<String>, name: &'static str) -> Result<String, ValidationError> {
+    let mut fields = fields.into_iter().skip(1);
+    let argument = fields.next().ok_or(ValidationError::MissingField(name))?;
+    if let Some(extra) = fields.next() {
+        return Err(ValidationError::TrailingData(extra));
+    }
+
+    Ok(argument)
+}
+
+/// Expands a `$GENERATE <start>-<stop>[/<step>] <owner> [<ttl>] [IN] <type> <rdata>` directive
+/// into the records it stands for: one for each number of the range, with `$` replaced by the
+/// number in the owner and the data, see
+/// https://bind9.readthedocs.io/en/latest/chapter3.html#bind-master-file-extension-the-generate-directive
+///
+/// `${<offset>[,<width>[,<base>]]}` adds an offset to the number, and formats it with at least
+/// `width` digits in base `d` (decimal), `o` (octal), `x` or `X` (hexadecimal).
+/// `\$` stands for a literal `$`.
+fn generate(fields: Vec<String>, state: &MasterFile) -> Result<Vec<DnsRecord>, ValidationError> {
+    let mut fields = fields.into_iter().skip(1);
+    let range = fields
+        .next()
+        .ok_or(ValidationError::MissingField("range"))?;
+    let invalid_range = || ValidationError::InvalidRange(range.clone());
+
+    let (bounds, step) = match range.split_once('/') {
+        Some((bounds, step)) => (bounds, step.parse().map_err(|_| invalid_range())?),
+        None => (range.as_str(), 1),
+    };
+    let (start, stop) = bounds.split_once('-').ok_or_else(invalid_range)?;
+    let start: u32 = start.parse().map_err(|_| invalid_range())?;
+    let stop: u32 = stop.parse().map_err(|_| invalid_range())?;
+    if start > stop || step == 0 {
+        return Err(invalid_range());
+    }
+
+    let template: Vec<String> = fields.collect();
+    let mut records = Vec::new();
+    let mut owner = state.owner.clone();
+    for n in (start..=stop).step_by(step) {
+        let fields = template
+            .iter()
+            .map(|field| substitute(field, n))
+            .collect::<Result<Vec<_>, _>>()?;
+        records.push(parse_record(
+            fields,
+            false,
+            &state.origin,
+            &mut owner,
+            state.ttl,
+        )?);
+    }
+
+    Ok(records)
+}
+
+/// Replaces `$` and its modifiers with the given number in a field of a `$GENERATE` template
+fn substitute(field: &str, n: u32) -> Result<String, ValidationError> {
+    let invalid = || ValidationError::InvalidModifier(field.to_string());
+    let mut out = String::new();
+    let mut chars = field.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&'$') => out.extend(chars.next()),
+            '$' if chars.peek() == Some(&'{') => {
+                chars.next();
+                let mut modifier = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => modifier.push(c),
+                        None => return Err(invalid()),
+                    }
+                }
+
+                let mut parts = modifier.split(',');
+                let offset: i64 = parts
+                    .next()
+                    .unwrap_or_default()
+                    .parse()
+                    .map_err(|_| invalid())?;
+                let width: usize = match parts.next() {
+                    Some(width) => width.parse().map_err(|_| invalid())?,
+                    None => 0,
+                };
+                let value = u32::try_from(n as i64 + offset).map_err(|_| invalid())?;
+                let formatted = match parts.next().unwrap_or("d") {
+                    "d" => format!("{:0width$}", value, width = width),
+                    "o" => format!("{:0width$o}", value, width = width),
+                    "x" => format!("{:0width$x}", value, width = width),
+                    "X" => format!("{:0width$X}", value, width = width),
+                    _ => return Err(invalid()),
+                };
+                if parts.next().is_some() {
+                    return Err(invalid());
+                }
+                out.push_str(&formatted);
+            }
+            '$' => out.push_str(&n.to_string()),
+            c => out.push(c),
+        }
+    }
+
+    Ok(out)
+}
+
+/// Loads overrides from a file in the `/etc/hosts` format, where each line holds
+/// an IP address followed by one or more names. The reverse lookups of the addresses are
+/// answered with the names too, without a reverse zone for them.
+pub fn load_hosts(path: &Path) -> Result<Vec<DnsRecord>, ZoneError> {
+    let file = path.display().to_string();
+    let contents = fs::read_to_string(path)?;
+
+    let mut records = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        let invalid = |kind| ZoneError::Invalid {
+            file: file.clone(),
+            line: i + 1,
+            kind,
+        };
+
+        let line = line.split('#').next().unwrap_or_default();
+        let mut fields = line.split_whitespace();
+        let Some(ip) = fields.next() else {
+            continue;
+        };
+
+        let addr = if ip.contains(':') {
+            IpAddr::V6(parse_ipv6(ip).map_err(invalid)?)
+        } else {
+            IpAddr::V4(parse_ipv4(ip).map_err(invalid)?)
+        };
+
+        let mut names = fields.peekable();
+        if names.peek().is_none() {
+            return Err(invalid(ValidationError::MissingField("host name")));
+        }
+        for name in names {
+            let domain = normalize(name);
+            validate_name(&domain).map_err(invalid)?;
+
+            let ptr = DnsRecord::PTR {
+                domain: reverse::ptr_name(addr),
+                host: domain.clone(),
+                ttl: HOSTS_TTL,
+            };
+            if !records.contains(&ptr) {
+                records.push(ptr);
+            }
+            records.push(match addr {
+                IpAddr::V4(addr) => DnsRecord::A {
+                    domain,
+                    addr,
+                    ttl: HOSTS_TTL,
+                },
+                IpAddr::V6(addr) => DnsRecord::AAAA {
+                    domain,
+                    addr,
+                    ttl: HOSTS_TTL,
+                },
+            });
+        }
+    }
+
+    Ok(records)
+}
+
+/// Loads a list of domains, such as the warm-up seed list or the allowlist.
+/// Each line holds a single domain, and lines starting with `#` are comments.
+pub fn load_name_list(path: &Path) -> Result<Vec<String>, ZoneError> {
+    let file = path.display().to_string();
+    let contents = fs::read_to_string(path)?;
+
+    let mut names = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        let name = line.trim();
+        if name.is_empty() || name.starts_with('#') {
+            continue;
+        }
+
+        let name = name.trim_end_matches('.').to_lowercase();
+        validate_name(&name).map_err(|kind| ZoneError::Invalid {
+            file: file.clone(),
+            line: i + 1,
+            kind,
+        })?;
+        names.push(name);
+    }
+
+    Ok(names)
+}
+
+/// The `Authority` struct holds the locally configured data: overrides, which
+/// take precedence over everything else, and the hosted zones, along with the
+/// journal of their changes.
+pub struct Authority {
+    pub overrides: Vec<DnsRecord>,
+    pub zones: Vec<Zone>,
+    pub journal: Journal,
+}
+
+impl Authority {
+    pub fn new(overrides: Vec<DnsRecord>, zones: Vec<Zone>) -> Authority {
+        Authority {
+            overrides,
+            zones,
+            journal: Journal::new(),
+        }
+    }
+
+    /// Adds a zone, replacing the zone with the same origin if there is one and
+    /// recording what changed in the journal
+    pub fn insert_zone(&mut self, zone: Zone) {
+        if let Some(old) = self.zones.iter().find(|old| old.origin == zone.origin) {
+            self.journal.record(old, &zone);
+        }
+        self.zones.retain(|old| old.origin != zone.origin);
+        self.zones.push(zone);
+    }
+
+    /// Removes the zone with the given origin, if there is one
+    pub fn remove_zone(&mut self, origin: &str) {
+        self.zones.retain(|zone| zone.origin != origin);
+        self.journal.remove(origin);
+    }
+
+    /// Answers a query from local data. Returns `None` when the name is neither
+    /// overridden nor part of a hosted zone, meaning that it must be resolved.
+    /// Names of a zone without records of the requested type get an empty answer (NODATA),
+    /// as do empty non-terminals (names with no records, but with subdomains that have some).
+    /// Negative answers carry the SOA record of the zone.
+    /// All local data is in the Internet class, queries in other classes never match it.
+    pub fn lookup(&self, qname: &str, qtype: QueryType, qclass: QueryClass) -> Option<DnsPacket> {
+        if qclass != QueryClass::IN {
+            return None;
+        }
+
+        let mut packet = DnsPacket::new();
+        packet.header.authoritative_answer = true;
+
+        let overrides = find_records(&self.overrides, qname, qtype);
+        if !overrides.is_empty() {
+            packet.answers = overrides;
+            return Some(packet);
+        }
+
+        let zone = self.find_zone(qname)?;
+        if !zone.records.iter().any(|record| {
+            record
+                .domain()
+                .is_some_and(|domain| is_subdomain(domain, qname))
+        }) {
+            packet.header.rescode = ResultCode::NXDOMAIN;
+            packet.authorities.extend(zone.negative_soa());
+            return Some(packet);
+        }
+
+        packet.answers = find_records(&zone.records, qname, qtype);
+        if packet.answers.is_empty() {
+            packet.answers = find_records(&zone.records, qname, QueryType::CNAME);
+        }
+        if packet.answers.is_empty() {
+            packet.authorities.extend(zone.negative_soa());
+        }
+
+        Some(packet)
+    }
+
+    /// Returns the statistics bucket of the queries for a name: the overrides,
+    /// the hosted zone containing the name, or recursion
+    pub fn stats_bucket(&self, qname: &str) -> &str {
+        if self
+            .overrides
+            .iter()
+            .any(|record| record.domain() == Some(qname))
+        {
+            return HOSTS_BUCKET;
+        }
+
+        match self.find_zone(qname) {
+            Some(zone) => &zone.origin,
+            None => RECURSIVE_BUCKET,
+        }
+    }
+
+    /// Returns an estimate of the memory used by the overrides and the hosted zones
+    pub fn memory_usage(&self) -> usize {
+        self.overrides
+            .iter()
+            .chain(self.zones.iter().flat_map(|zone| &zone.records))
+            .map(DnsRecord::approximate_size)
+            .sum()
+    }
+
+    /// Returns the most specific zone containing the name
+    pub fn find_zone(&self, qname: &str) -> Option<&Zone> {
+        self.zones
+            .iter()
+            .filter(|zone| zone.contains(qname))
+            .max_by_key(|zone| zone.origin.len())
+    }
+}
+
+/// Returns copies of the records owned by `qname` with the given type
+fn find_records(records: &[DnsRecord], qname: &str, qtype: QueryType) -> Records {
+    records
+        .iter()
+        .filter(|record| record.domain() == Some(qname) && record.qtype() == qtype)
+        .cloned()
+        .collect()
+}
+
+/// Returns true if `name` is equal to `parent` or one of its subdomains
+pub fn is_subdomain(name: &str, parent: &str) -> bool {
+    parent.is_empty()
+        || name == parent
+        || (name.ends_with(parent) && name[..name.len() - parent.len()].ends_with('.'))
+}
+
+/// Returns the ancestor of `name` one label below `parent`, e.g. `example.com` for
+/// `www.example.com` under `com`, or `name` itself if it isn't below `parent`
+pub fn child_towards<'a>(name: &'a str, parent: &str) -> &'a str {
+    if name == parent || !is_subdomain(name, parent) {
+        return name;
+    }
+
+    let prefix = match parent.is_empty() {
+        true => name,
+        false => &name[..name.len() - parent.len() - 1],
+    };
+    match prefix.rfind('.') {
+        Some(dot) => &name[dot + 1..],
+        None => name,
+    }
+}
+
+/// Lowercases a name and strips the trailing dot, matching how names are read from packets
+fn normalize(name: &str) -> String {
+    name.trim_end_matches('.').to_lowercase()
+}
+
+/// Splits a master file line into fields, dropping comments.
+/// Quoted strings are returned as a single field without the quotes, while quoted values
+/// within a field keep theirs. Parentheses are dropped as well, with their balance (opened
+/// minus closed) returned alongside.
+fn tokenize(line: &str) -> (Vec<String>, i32) {
+    let mut fields = Vec::new();
+    let mut parens = 0;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            ';' => break,
+            '(' => parens += 1,
+            ')' => parens -= 1,
+            '"' => {
+                let mut field = String::new();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => field.extend(chars.next()),
+                        c => field.push(c),
+                    }
+                }
+                fields.push(field);
+            }
+            c if c.is_whitespace() => {}
+            c => {
+                let mut field = String::from(c);
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace() && !";()".contains(*c)) {
+                    field.push(c);
+                    // Quoted values within a field, e.g. `key="a b"`, are kept as is.
+                    if c == '"' {
+                        while let Some(c) = chars.next() {
+                            field.push(c);
+                            match c {
+                                '"' => break,
+                                '\\' => field.extend(chars.next()),
+                                _ => {}
+                            }
+                        }
+                    }
+                }
+                fields.push(field);
+            }
+        }
+    }
+
+    (fields, parens)
+}
+
+/// Resolves a name in a master file against the origin
+fn absolute_name(name: &str, origin: &str) -> Result<String, ValidationError> {
+    let name = if name == "@" {
+        origin.to_string()
+    } else if name.ends_with('.') || origin.is_empty() {
+        normalize(name)
+    } else {
+        format!("{}.{}", normalize(name), origin)
+    };
+
+    validate_name(&name)?;
+
+    Ok(name)
+}
+
+/// Parses the fields of a single master file line into a record.
+/// `owner` holds the owner of the previous record, which is used when the line starts with
+/// whitespace, and is updated with the owner of the parsed record. `default_ttl` applies when
+/// the line doesn't specify a TTL.
+fn parse_record(
+    fields: Vec<String>,
+    repeat_owner: bool,
+    origin: &str,
+    owner: &mut String,
+    default_ttl: u32,
+) -> Result<DnsRecord, ValidationError> {
+    let mut fields = fields.into_iter();
+    let mut next = |field| fields.next().ok_or(ValidationError::MissingField(field));
+
+    if !repeat_owner {
+        *owner = absolute_name(&next("name")?, origin)?;
+    }
+    let domain = owner.clone();
+
+    // The TTL and the class are both optional, and can appear in any order.
+    let mut ttl = default_ttl;
+    let mut rtype = next("type")?;
+    for _ in 0..2 {
+        if rtype.eq_ignore_ascii_case("IN") {
+            rtype = next("type")?;
+        } else if rtype.starts_with(|c: char| c.is_ascii_digit()) {
+            ttl = parse_ttl(&rtype)?;
+            rtype = next("type")?;
+        }
+    }
+
+    let record = match rtype.to_uppercase().as_str() {
+        "A" => DnsRecord::A {
+            domain,
+            addr: parse_ipv4(&next("address")?)?,
+            ttl,
+        },
+        "AAAA" => DnsRecord::AAAA {
+            domain,
+            addr: parse_ipv6(&next("address")?)?,
+            ttl,
+        },
+        "NS" => DnsRecord::NS {
+            domain,
+            host: absolute_name(&next("host")?, origin)?,
+            ttl,
+        },
+        "PTR" => DnsRecord::PTR {
+            domain,
+            host: absolute_name(&next("host")?, origin)?,
+            ttl,
+        },
+        "CNAME" => DnsRecord::CNAME {
+            domain,
+            host: absolute_name(&next("host")?, origin)?,
+            ttl,
+        },
+        "NULL" => {
+            if next("generic data marker")? != "\\#" {
+                return Err(ValidationError::InvalidGenericData);
+            }
+            let len = parse_length(&next("data length")?)?;
+            let data = parse_hex(&fields.by_ref().collect::<String>())?;
+            if data.len() != len {
+                return Err(ValidationError::InvalidGenericData);
+            }
+
+            DnsRecord::NULL { domain, data, ttl }
+        }
+        "HINFO" => DnsRecord::HINFO {
+            domain,
+            cpu: parse_character_string(next("CPU")?)?,
+            os: parse_character_string(next("OS")?)?,
+            ttl,
+        },
+        "TXT" => {
+            // A record holds one or more strings, usually quoted.
+            let data = fields
+                .by_ref()
+                .map(parse_character_string)
+                .collect::<Result<Vec<_>, _>>()?;
+            if data.is_empty() {
+                return Err(ValidationError::MissingField("text"));
+            }
+
+            DnsRecord::TXT { domain, data, ttl }
+        }
+        "SOA" => DnsRecord::SOA {
+            domain,
+            mname: absolute_name(&next("primary name server")?, origin)?,
+            rname: absolute_name(&next("responsible mailbox")?, origin)?,
+            serial: parse_u32(&next("serial")?, "serial")?,
+            refresh: parse_ttl(&next("refresh")?)?,
+            retry: parse_ttl(&next("retry")?)?,
+            expire: parse_ttl(&next("expire")?)?,
+            minimum: parse_ttl(&next("minimum")?)?,
+            ttl,
+        },
+        "MX" => DnsRecord::MX {
+            domain,
+            priority: parse_priority(&next("priority")?)?,
+            host: absolute_name(&next("host")?, origin)?,
+            ttl,
+        },
+        "SRV" => DnsRecord::SRV {
+            domain,
+            priority: parse_u16(&next("priority")?, "priority")?,
+            weight: parse_u16(&next("weight")?, "weight")?,
+            port: parse_u16(&next("port")?, "port")?,
+            host: absolute_name(&next("target")?, origin)?,
+            ttl,
+        },
+        "LOC" => {
+            let loc = parse_loc(&fields.by_ref().collect::<Vec<_>>())?;
+
+            DnsRecord::LOC {
+                domain,
+                version: 0,
+                size: loc.size,
+                horiz_pre: loc.horiz_pre,
+                vert_pre: loc.vert_pre,
+                latitude: loc.latitude,
+                longitude: loc.longitude,
+                altitude: loc.altitude,
+                ttl,
+            }
+        }
+        "CERT" => DnsRecord::CERT {
+            domain,
+            cert_type: parse_cert_type(&next("certificate type")?)?,
+            key_tag: parse_u16(&next("key tag")?, "key tag")?,
+            algorithm: parse_u8(&next("algorithm")?, "algorithm")?,
+            // The base64 data may be split in multiple fields for readability.
+            certificate: parse_base64(&fields.by_ref().collect::<String>())?,
+            ttl,
+        },
+        "DS" => DnsRecord::DS {
+            domain,
+            key_tag: parse_u16(&next("key tag")?, "key tag")?,
+            algorithm: parse_u8(&next("algorithm")?, "algorithm")?,
+            digest_type: parse_u8(&next("digest type")?, "digest type")?,
+            // The hexadecimal digest may be split in multiple fields for readability.
+            digest: parse_hex(&fields.by_ref().collect::<String>())?,
+            ttl,
+        },
+        "RRSIG" => DnsRecord::RRSIG {
+            domain,
+            type_covered: parse_type(&next("type covered")?)?,
+            algorithm: parse_u8(&next("algorithm")?, "algorithm")?,
+            labels: parse_u8(&next("labels")?, "labels")?,
+            original_ttl: parse_ttl(&next("original TTL")?)?,
+            expiration: parse_timestamp(&next("expiration")?)?,
+            inception: parse_timestamp(&next("inception")?)?,
+            key_tag: parse_u16(&next("key tag")?, "key tag")?,
+            signer: absolute_name(&next("signer")?, origin)?,
+            // The base64 signature may be split in multiple fields for readability.
+            signature: parse_base64(&fields.by_ref().collect::<String>())?,
+            ttl,
+        },
+        "NSEC" => DnsRecord::NSEC {
+            domain,
+            next: absolute_name(&next("next name")?, origin)?,
+            types: fields
+                .by_ref()
+                .map(|qtype| parse_type(&qtype))
+                .collect::<Result<Vec<_>, _>>()?,
+            ttl,
+        },
+        "DNSKEY" => DnsRecord::DNSKEY {
+            domain,
+            flags: parse_u16(&next("flags")?, "flags")?,
+            protocol: parse_u8(&next("protocol")?, "protocol")?,
+            algorithm: parse_u8(&next("algorithm")?, "algorithm")?,
+            // The base64 key may be split in multiple fields for readability.
+            public_key: parse_base64(&fields.by_ref().collect::<String>())?,
+            ttl,
+        },
+        "SSHFP" => DnsRecord::SSHFP {
+            domain,
+            algorithm: parse_u8(&next("algorithm")?, "algorithm")?,
+            fp_type: parse_u8(&next("fingerprint type")?, "fingerprint type")?,
+            fingerprint: parse_hex(&fields.by_ref().collect::<String>())?,
+            ttl,
+        },
+        "TLSA" => DnsRecord::TLSA {
+            domain,
+            usage: parse_u8(&next("certificate usage")?, "certificate usage")?,
+            selector: parse_u8(&next("selector")?, "selector")?,
+            matching_type: parse_u8(&next("matching type")?, "matching type")?,
+            // The hexadecimal data may be split in multiple fields for readability.
+            data: parse_hex(&fields.by_ref().collect::<String>())?,
+            ttl,
+        },
+        "URI" => DnsRecord::URI {
+            domain,
+            priority: parse_u16(&next("priority")?, "priority")?,
+            weight: parse_u16(&next("weight")?, "weight")?,
+            target: next("target")?,
+            ttl,
+        },
+        "SVCB" | "HTTPS" => {
+            let priority = parse_u16(&next("priority")?, "priority")?;
+            let target = absolute_name(&next("target")?, origin)?;
+            let params = fields
+                .by_ref()
+                .map(|param| parse_svc_param(&param))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            match rtype.eq_ignore_ascii_case("SVCB") {
+                true => DnsRecord::SVCB {
+                    domain,
+                    priority,
+                    target,
+                    params,
+                    ttl,
+                },
+                false => DnsRecord::HTTPS {
+                    domain,
+                    priority,
+                    target,
+                    params,
+                    ttl,
+                },
+            }
+        }
+        "CAA" => DnsRecord::CAA {
+            domain,
+            flags: parse_u8(&next("flags")?, "flags")?,
+            tag: parse_caa_tag(next("tag")?)?,
+            value: next("value")?.into_bytes(),
+            ttl,
+        },
+        _ => return Err(ValidationError::UnsupportedType(rtype)),
+    };
+
+    if let Some(extra) = fields.next() {
+        return Err(ValidationError::TrailingData(extra));
+    }
+
+    Ok(record)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::svcb::SvcParam;
+    use std::path::PathBuf;
+
+    /// Writes master files to a fresh temporary directory, and returns its path
+    fn write_files(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("vodo-zone-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for (name, contents) in files {
+            fs::write(dir.join(name), contents).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn applies_origin_and_ttl_directives() {
+        let dir = write_files(
+            "directives",
+            &[(
+                "home.zone",
+                "\
+$TTL 1h
+@           NS    ns1
+ns1         A     192.168.1.1
+$ORIGIN lab.home.lan.
+$ttl 300
+printer  1d A     192.168.2.5
+",
+            )],
+        );
+        let zone = Zone::load("home.lan", &dir.join("home.zone")).unwrap();
+
+        assert_eq!(
+            zone.records,
+            vec![
+                DnsRecord::NS {
+                    domain: "home.lan".to_string(),
+                    host: "ns1.home.lan".to_string(),
+                    ttl: 3600,
+                },
+                DnsRecord::A {
+                    domain: "ns1.home.lan".to_string(),
+                    addr: "192.168.1.1".parse().unwrap(),
+                    ttl: 3600,
+                },
+                DnsRecord::A {
+                    domain: "printer.lab.home.lan".to_string(),
+                    addr: "192.168.2.5".parse().unwrap(),
+                    ttl: 86400,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_service_bindings() {
+        let dir = write_files(
+            "svcb",
+            &[(
+                "example.zone",
+                "\
+@     300 HTTPS 1 . alpn=\"h3,h2\" port=8443 ipv4hint=192.0.2.1,192.0.2.2 no-default-alpn
+_dns  300 SVCB  0 dns.example.com.
+",
+            )],
+        );
+        let zone = Zone::load("example.com", &dir.join("example.zone")).unwrap();
+
+        assert_eq!(
+            zone.records,
+            vec![
+                DnsRecord::HTTPS {
+                    domain: "example.com".to_string(),
+                    priority: 1,
+                    target: String::new(),
+                    params: vec![
+                        SvcParam::Alpn(vec!["h3".to_string(), "h2".to_string()]),
+                        SvcParam::Port(8443),
+                        SvcParam::Ipv4Hint(vec![
+                            "192.0.2.1".parse().unwrap(),
+                            "192.0.2.2".parse().unwrap()
+                        ]),
+                        SvcParam::Unknown {
+                            key: 2,
+                            value: Vec::new()
+                        },
+                    ],
+                    ttl: 300,
+                },
+                DnsRecord::SVCB {
+                    domain: "_dns.example.com".to_string(),
+                    priority: 0,
+                    target: "dns.example.com".to_string(),
+                    params: Vec::new(),
+                    ttl: 300,
+                },
+            ]
+        );
+        assert!(parse_svc_param("port=http").is_err());
+        assert!(parse_svc_param("alpn").is_err());
+    }
+
+    #[test]
+    fn parses_dnssec_records() {
+        let dir = write_files(
+            "dnssec",
+            &[(
+                "example.zone",
+                "\
+@    300 RRSIG A 13 2 300 20261101000000 20261015123005 60485 example.com. 3q2+7w==
+@    300 NSEC  www A NS SOA RRSIG NSEC TYPE65534
+",
+            )],
+        );
+        let zone = Zone::load("example.com", &dir.join("example.zone")).unwrap();
+
+        assert!(matches!(
+            &zone.records[0],
+            DnsRecord::RRSIG {
+                type_covered: 1,
+                expiration: 1793491200,
+                inception: 1792067405,
+                signer,
+                signature,
+                ..
+            } if signer == "example.com" && signature == &[0xde, 0xad, 0xbe, 0xef]
+        ));
+        assert!(matches!(
+            &zone.records[1],
+            DnsRecord::NSEC { next, types, .. }
+                if next == "www.example.com" && types == &[1, 2, 6, 46, 47, 65534]
+        ));
+    }
+
+    #[test]
+    fn includes_files_with_their_own_origin() {
+        let dir = write_files(
+            "include",
+            &[
+                (
+                    "home.zone",
+                    "\
+$INCLUDE hosts.inc lab
+nas         A     192.168.1.10
+",
+                ),
+                ("hosts.inc", "@ 60 A 192.168.2.1\nwww CNAME @\n"),
+                ("loop.zone", "$INCLUDE loop.zone\n"),
+            ],
+        );
+        let zone = Zone::load("home.lan", &dir.join("home.zone")).unwrap();
+
+        let owners: Vec<_> = zone.records.iter().filter_map(DnsRecord::domain).collect();
+        assert_eq!(owners, ["lab.home.lan", "www.lab.home.lan", "nas.home.lan"]);
+        assert!(matches!(
+            Zone::load("home.lan", &dir.join("loop.zone")),
+            Err(ZoneError::Invalid {
+                kind: ValidationError::IncludeTooDeep,
+                ..
+            })
+        ));
+        assert!(matches!(
+            Zone::load("home.lan", &dir.join("missing.zone")),
+            Err(ZoneError::IoError(_))
+        ));
+    }
+
+    #[test]
+    fn answers_reverse_lookups_of_hosts() {
+        let dir = write_files(
+            "hosts",
+            &[(
+                "hosts",
+                "192.168.1.10 nas.home.lan nas\n192.168.1.10 nas.home.lan\n",
+            )],
+        );
+        let authority = Authority::new(load_hosts(&dir.join("hosts")).unwrap(), Vec::new());
+
+        let packet = authority
+            .lookup("10.1.168.192.in-addr.arpa", QueryType::PTR, QueryClass::IN)
+            .unwrap();
+        let hosts: Vec<_> = packet
+            .answers
+            .iter()
+            .filter_map(|record| match record {
+                DnsRecord::PTR { host, .. } => Some(host.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(hosts, ["nas.home.lan", "nas"]);
+        assert!(authority
+            .lookup("11.1.168.192.in-addr.arpa", QueryType::PTR, QueryClass::IN)
+            .is_none());
+    }
+
+    #[test]
+    fn generates_records_from_templates() {
+        let dir = write_files(
+            "generate",
+            &[(
+                "reverse.zone",
+                "\
+$GENERATE 10-14/2 $ PTR host-${0,3,d}.home.lan.
+$GENERATE 1-2 dhcp-${9,2,x} 60 IN A 192.168.1.$
+",
+            )],
+        );
+        let zone = Zone::load("1.168.192.in-addr.arpa", &dir.join("reverse.zone")).unwrap();
+
+        let hosts: Vec<_> = zone
+            .records
+            .iter()
+            .filter_map(|record| match record {
+                DnsRecord::PTR { domain, host, .. } => Some(format!("{} {}", domain, host)),
+                DnsRecord::A { domain, addr, .. } => Some(format!("{} {}", domain, addr)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            hosts,
+            [
+                "10.1.168.192.in-addr.arpa host-010.home.lan",
+                "12.1.168.192.in-addr.arpa host-012.home.lan",
+                "14.1.168.192.in-addr.arpa host-014.home.lan",
+                "dhcp-0a.1.168.192.in-addr.arpa 192.168.1.1",
+                "dhcp-0b.1.168.192.in-addr.arpa 192.168.1.2",
+            ]
+        );
+        assert!(matches!(
+            substitute("${1,2,q}", 1),
+            Err(ValidationError::InvalidModifier(_))
+        ));
+    }
+
+    #[test]
+    fn finds_the_child_towards_a_name() {
+        assert_eq!(child_towards("www.example.com", ""), "com");
+        assert_eq!(child_towards("www.example.com", "com"), "example.com");
+        assert_eq!(
+            child_towards("www.example.com", "example.com"),
+            "www.example.com"
+        );
+        assert_eq!(
+            child_towards("www.example.com", "www.example.com"),
+            "www.example.com"
+        );
+        assert_eq!(
+            child_towards("www.example.com", "example.org"),
+            "www.example.com"
+        );
+    }
+}
@@ -0,0 +1,276 @@
+//! Runs the server under sustained mixed load, resolving from a simulated hierarchy of servers
+//! (see `vodo::simulation`), and checks that it doesn't leak: the memory it accounts for must
+//! stay under its cap, and its open file descriptors must go back to where they were once the
+//! load stops.
+//!
+//! The soak is long, so it's ignored by default. Run it with
+//! `VODO_SOAK_SECS=600 cargo test --release --test soak -- --ignored --nocapture`.
+
+use std::env;
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+use vodo::packet::DnsPacket;
+use vodo::question::{DnsQuestion, QueryType};
+use vodo::simulation::{Conditions, Hierarchy};
+
+/// Duration of the soak when `VODO_SOAK_SECS` isn't set
+const DEFAULT_DURATION: Duration = Duration::from_secs(60);
+/// Threads sending queries
+const CLIENTS: usize = 8;
+/// Cap on the memory of the server, in MiB, which the accounting must stay under
+const MEMORY_LIMIT_MIB: usize = 4;
+/// Share of the cap the accounting may overshoot it by between two checks of the server
+const MEMORY_SLACK: f64 = 0.25;
+/// Descriptors the server may hold open after the load stops beyond those it held before
+const DESCRIPTOR_SLACK: usize = 4;
+/// Time to wait for a response
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+const ZONE: &str = "$TTL 300
+@     IN SOA ns1 hostmaster 1 3600 600 86400 300
+@     IN NS  ns1
+ns1   IN A   127.0.0.1
+www   IN A   192.0.2.10
+alias IN CNAME www
+";
+
+/// The server under test, killed when dropped
+struct Server {
+    child: Child,
+    port: u16,
+    admin_port: u16,
+}
+
+impl Server {
+    fn start(hierarchy: &Hierarchy, zone: &str) -> Server {
+        let (port, admin_port) = (free_port(), free_port());
+        let child = Command::new(env!("CARGO_BIN_EXE_vodo"))
+            .args(["-p", &port.to_string()])
+            .args(["--admin-port", &admin_port.to_string()])
+            .args([
+                "--root-server",
+                &format!("{}:{}", hierarchy.root, hierarchy.port),
+            ])
+            .args(["--memory-limit", &MEMORY_LIMIT_MIB.to_string()])
+            .args(["-z", &format!("soak.test={}", zone)])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("failed to start the server");
+        let server = Server {
+            child,
+            port,
+            admin_port,
+        };
+
+        let start = Instant::now();
+        while server.admin("GET", "/stats").is_none() || query_udp(port, "www.soak.test").is_none()
+        {
+            assert!(start.elapsed() < Duration::from_secs(10), "server not up");
+            thread::sleep(Duration::from_millis(100));
+        }
+        // Logging every query would measure the terminal rather than the server.
+        server.admin("PUT", "/log-level/warn");
+        server
+    }
+
+    /// Sends a request to the admin API, and returns the body of the response
+    fn admin(&self, method: &str, path: &str) -> Option<String> {
+        let mut stream = TcpStream::connect((Ipv4Addr::LOCALHOST, self.admin_port)).ok()?;
+        write!(stream, "{} {} HTTP/1.1\r\n\r\n", method, path).ok()?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).ok()?;
+        Some(response.split_once("\r\n\r\n")?.1.to_string())
+    }
+
+    /// Returns the memory used by the server, as it accounts for it
+    fn memory(&self) -> usize {
+        let metrics = self.admin("GET", "/metrics").unwrap_or_default();
+        metrics
+            .lines()
+            .filter(|line| line.starts_with("vodo_memory_bytes{"))
+            .filter_map(|line| line.rsplit(' ').next()?.parse::<usize>().ok())
+            .sum()
+    }
+
+    /// Returns the number of file descriptors the server holds open, where /proc tells
+    fn descriptors(&self) -> Option<usize> {
+        let fds = fs::read_dir(format!("/proc/{}/fd", self.child.id())).ok()?;
+        Some(fds.count())
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Returns a port nothing listens on, over UDP and TCP
+fn free_port() -> u16 {
+    loop {
+        let tcp = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = tcp.local_addr().unwrap().port();
+        if UdpSocket::bind((Ipv4Addr::LOCALHOST, port)).is_ok() {
+            return port;
+        }
+    }
+}
+
+fn request(name: &str) -> Vec<u8> {
+    let mut packet = DnsPacket::new();
+    packet.header.id = rand::thread_rng().gen();
+    packet.header.recursion_desired = true;
+    packet
+        .questions
+        .push(DnsQuestion::new(name.to_string(), QueryType::A));
+    packet.to_bytes().unwrap()
+}
+
+fn query_udp(port: u16, name: &str) -> Option<DnsPacket> {
+    let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).ok()?;
+    socket.set_read_timeout(Some(TIMEOUT)).ok()?;
+    socket
+        .send_to(&request(name), (Ipv4Addr::LOCALHOST, port))
+        .ok()?;
+    let mut response = [0; 4096];
+    let len = socket.recv(&mut response).ok()?;
+    DnsPacket::parse(&response[..len]).ok()
+}
+
+/// Sends queries over a single TCP connection, which is then either closed or abandoned
+/// mid-message
+fn query_tcp(port: u16, names: &[String], abandon: bool) -> Option<()> {
+    let server = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    let mut stream = TcpStream::connect_timeout(&server, TIMEOUT).ok()?;
+    stream.set_read_timeout(Some(TIMEOUT)).ok()?;
+    for name in names {
+        let request = request(name);
+        stream
+            .write_all(&(request.len() as u16).to_be_bytes())
+            .ok()?;
+        stream.write_all(&request).ok()?;
+        let mut len = [0; 2];
+        stream.read_exact(&mut len).ok()?;
+        let mut response = vec![0; u16::from_be_bytes(len) as usize];
+        stream.read_exact(&mut response).ok()?;
+    }
+    if abandon {
+        stream.write_all(&[0, 64, 0x12]).ok()?;
+    }
+    Some(())
+}
+
+/// Sends a mix of queries until told to stop: answered from the zone, from the cache, by
+/// resolving new names, over UDP and TCP, and garbage
+fn client(port: u16, running: &AtomicBool, sent: &AtomicUsize) {
+    let mut rng = rand::thread_rng();
+    let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+
+    while running.load(Ordering::Relaxed) {
+        match rng.gen_range(0..100) {
+            0..=29 => {
+                let name =
+                    ["www.soak.test", "alias.soak.test", "none.soak.test"][rng.gen_range(0..3)];
+                query_udp(port, name);
+            }
+            30..=59 => {
+                query_udp(port, &format!("host{}.example.com", rng.gen_range(0..100)));
+            }
+            60..=84 => {
+                query_udp(port, &format!("new{}.example.com", rng.gen::<u64>()));
+            }
+            85..=94 => {
+                let names: Vec<String> = (0..rng.gen_range(1..4))
+                    .map(|_| format!("host{}.example.com", rng.gen_range(0..100)))
+                    .collect();
+                query_tcp(port, &names, rng.gen_bool(0.3));
+            }
+            _ => {
+                let garbage: Vec<u8> = (0..rng.gen_range(1..64)).map(|_| rng.gen()).collect();
+                let _ = socket.send_to(&garbage, (Ipv4Addr::LOCALHOST, port));
+            }
+        }
+        sent.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[test]
+#[ignore = "long running, run with --ignored"]
+fn stays_bounded_under_sustained_load() {
+    let duration = env::var("VODO_SOAK_SECS")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .map_or(DEFAULT_DURATION, Duration::from_secs);
+
+    let hierarchy = Hierarchy::start(Conditions {
+        latency: Duration::from_millis(2),
+        loss: 0.02,
+    })
+    .expect("failed to start the simulated hierarchy");
+    let zone = env::temp_dir().join(format!("vodo-soak-{}.zone", std::process::id()));
+    fs::write(&zone, ZONE).unwrap();
+    let server = Server::start(&hierarchy, zone.to_str().unwrap());
+    let descriptors_before = server.descriptors();
+
+    let running = Arc::new(AtomicBool::new(true));
+    let sent = Arc::new(AtomicUsize::new(0));
+    let clients: Vec<_> = (0..CLIENTS)
+        .map(|_| {
+            let (port, running, sent) = (server.port, running.clone(), sent.clone());
+            thread::spawn(move || client(port, &running, &sent))
+        })
+        .collect();
+
+    let limit = MEMORY_LIMIT_MIB * 1024 * 1024;
+    let mut peak = 0;
+    let start = Instant::now();
+    while start.elapsed() < duration {
+        thread::sleep(Duration::from_secs(1));
+        let memory = server.memory();
+        peak = peak.max(memory);
+        assert!(
+            memory as f64 <= limit as f64 * (1.0 + MEMORY_SLACK),
+            "memory accounted for at {} bytes, over the cap of {}",
+            memory,
+            limit
+        );
+    }
+
+    running.store(false, Ordering::Relaxed);
+    for client in clients {
+        client.join().unwrap();
+    }
+    println!(
+        "{} queries in {:?}, peak memory of {} bytes",
+        sent.load(Ordering::Relaxed),
+        duration,
+        peak
+    );
+
+    // Abandoned connections are closed once they time out.
+    if let Some(before) = descriptors_before {
+        let start = Instant::now();
+        let mut after = server.descriptors().unwrap_or_default();
+        while after > before + DESCRIPTOR_SLACK && start.elapsed() < Duration::from_secs(30) {
+            thread::sleep(Duration::from_secs(1));
+            after = server.descriptors().unwrap_or_default();
+        }
+        assert!(
+            after <= before + DESCRIPTOR_SLACK,
+            "{} descriptors open after the load, {} before",
+            after,
+            before
+        );
+    }
+    let _ = fs::remove_file(zone);
+}